@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use slpz::*;
+
+fuzz_target!(|data: &[u8]| {
+    // finds the metadata offset, scanning the events if the raw length is unwritten
+    let _ = extract_raw_section(data);
+    let _ = slp_file_info(data);
+    let _ = decompress(&mut Decompressor::new().unwrap(), data);
+
+    let mut compressor = Compressor::new(1).unwrap();
+    let compressed = compress(&mut compressor, data);
+
+    if validate_slp(data).issues.contains(&SlpIssue::PlaceholderRawLen) {
+        let warnings = compressed.expect("replays with an unwritten raw length compress").warnings;
+        assert!(warnings.contains(&CompressWarning::ZeroRawLen));
+
+        compressor.set_strictness(Strictness::Strict);
+        assert_eq!(compress(&mut compressor, data), Err(CompError::PlaceholderRawLen));
+    }
+});
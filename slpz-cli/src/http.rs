@@ -0,0 +1,261 @@
+//! A minimal HTTP/1.1 client for reading replays from URLs, behind the `http` feature.
+//!
+//! Only plain `http://` is supported. There is no TLS without a dependency, so `https://` URLs are refused.
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+pub struct Response {
+    /// The URL the body was read from, after redirects.
+    pub url: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl,
+    HttpsUnsupported,
+    Io(std::io::Error),
+    /// A status other than 200 OK, with its reason phrase.
+    Status(u16, String),
+    TooManyRedirects,
+    /// The body is larger than the size limit, in bytes.
+    TooLarge(u64),
+    InvalidResponse,
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::InvalidUrl => write!(f, "invalid URL"),
+            HttpError::HttpsUnsupported => write!(f, "https URLs are not supported, download the file first"),
+            HttpError::Io(e) => write!(f, "{}", e),
+            HttpError::Status(code, reason) => write!(f, "server responded {} {}", code, reason),
+            HttpError::TooManyRedirects => write!(f, "more than {} redirects", MAX_REDIRECTS),
+            HttpError::TooLarge(limit) => write!(f, "response is larger than {} bytes. See --max-size", limit),
+            HttpError::InvalidResponse => write!(f, "invalid HTTP response"),
+        }
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self { HttpError::Io(e) }
+}
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Downloads `url`, following redirects. Fails if the body is larger than `max_size` bytes,
+/// without reading more than that.
+pub fn get(url: &str, max_size: u64) -> Result<Response, HttpError> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        match get_once(&url, max_size)? {
+            Fetched::Body(content_type, body) => return Ok(Response { url, content_type, body }),
+            Fetched::Redirect(location) => url = resolve(&url, &location)?,
+        }
+    }
+    Err(HttpError::TooManyRedirects)
+}
+
+enum Fetched {
+    Body(Option<String>, Vec<u8>),
+    Redirect(String),
+}
+
+/// (host, port, path) of an http URL. IPv6 hosts are returned without their brackets.
+fn split_url(url: &str) -> Result<(&str, u16, &str), HttpError> {
+    if url.starts_with("https://") { return Err(HttpError::HttpsUnsupported) }
+    let rest = url.strip_prefix("http://").ok_or(HttpError::InvalidUrl)?;
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, port) = bracketed.split_once(']').ok_or(HttpError::InvalidUrl)?;
+            (host, port.strip_prefix(':').unwrap_or(port))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port),
+            None => (authority, ""),
+        },
+    };
+    let port = match port {
+        "" if !authority.ends_with(':') => 80,
+        port => port.parse().map_err(|_| HttpError::InvalidUrl)?,
+    };
+    if host.is_empty() || (host.contains(':') && !authority.starts_with('[')) { return Err(HttpError::InvalidUrl) }
+    Ok((host, port, path))
+}
+
+/// The host and port as written in a URL or Host header, omitting the default port.
+fn authority(host: &str, port: u16) -> String {
+    let host = if host.contains(':') { format!("[{}]", host) } else { host.to_string() };
+    if port == 80 { host } else { format!("{}:{}", host, port) }
+}
+
+/// Resolves a Location header against the URL that returned it.
+fn resolve(url: &str, location: &str) -> Result<String, HttpError> {
+    if is_url(location) { return Ok(location.to_string()) }
+    let (host, port, _) = split_url(url)?;
+    match location.starts_with('/') {
+        true => Ok(format!("http://{}{}", authority(host, port), location)),
+        false => Err(HttpError::InvalidResponse),
+    }
+}
+
+fn get_once(url: &str, max_size: u64) -> Result<Fetched, HttpError> {
+    use std::io::{BufRead, Read, Write};
+
+    let (host, port, path) = split_url(url)?;
+    let stream = std::net::TcpStream::connect((host, port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let path = if path.starts_with('?') { format!("/{}", path) } else { path.to_string() };
+    write!(
+        &stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: slpz/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        path, authority(host, port), slpz::CRATE_VERSION,
+    )?;
+
+    let mut reader = std::io::BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut status_line = line.trim_end().splitn(3, ' ');
+    if !status_line.next().is_some_and(|v| v.starts_with("HTTP/1.")) { return Err(HttpError::InvalidResponse) }
+    let status: u16 = status_line.next().and_then(|s| s.parse().ok()).ok_or(HttpError::InvalidResponse)?;
+    let reason = status_line.next().unwrap_or("").to_string();
+
+    let mut content_length = None;
+    let mut content_type = None;
+    let mut location = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 { return Err(HttpError::InvalidResponse) }
+        let header = line.trim_end();
+        if header.is_empty() { break }
+        let Some((name, value)) = header.split_once(':') else { return Err(HttpError::InvalidResponse) };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = Some(value.parse::<u64>().map_err(|_| HttpError::InvalidResponse)?),
+            "content-type" => content_type = Some(value.to_string()),
+            "location" => location = Some(value.to_string()),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => (),
+        }
+    }
+
+    match status {
+        200 => (),
+        301 | 302 | 303 | 307 | 308 => return location.map(Fetched::Redirect).ok_or(HttpError::InvalidResponse),
+        _ => return Err(HttpError::Status(status, reason)),
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size_hex = line.trim_end().split(';').next().unwrap_or("");
+            let size = u64::from_str_radix(size_hex, 16).map_err(|_| HttpError::InvalidResponse)?;
+            if size == 0 { break }
+            // the size is from the server, so may overflow
+            match (body.len() as u64).checked_add(size) {
+                Some(len) if len <= max_size => (),
+                _ => return Err(HttpError::TooLarge(max_size)),
+            }
+            let read = (&mut reader).take(size).read_to_end(&mut body)?;
+            if read as u64 != size { return Err(HttpError::InvalidResponse) }
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+    } else if let Some(len) = content_length {
+        if len > max_size { return Err(HttpError::TooLarge(max_size)) }
+        let read = reader.take(len).read_to_end(&mut body)?;
+        if read as u64 != len { return Err(HttpError::InvalidResponse) }
+    } else {
+        // one byte more than the limit tells a body at the limit from a larger one
+        reader.take(max_size.saturating_add(1)).read_to_end(&mut body)?;
+        if body.len() as u64 > max_size { return Err(HttpError::TooLarge(max_size)) }
+    }
+
+    Ok(Fetched::Body(content_type, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves one canned response per connection, in order. Returns the base URL.
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || for response in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 { line.clear() }
+            // the client may close early on oversized responses
+            let _ = (&stream).write_all(&response);
+        });
+        url
+    }
+
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut r = format!("HTTP/1.1 {}\r\n{}\r\n", status, headers).into_bytes();
+        r.extend_from_slice(body);
+        r
+    }
+
+    #[test]
+    fn downloads() {
+        let url = serve(vec![
+            response("302 Found", "Location: /replays/game.slpz\r\n", b""),
+            response("200 OK", "Content-Type: application/x-slippi-compressed\r\nContent-Length: 5\r\n", b"hello"),
+            response("200 OK", "Transfer-Encoding: chunked\r\n", b"3\r\nabc\r\n2;ext\r\nde\r\n0\r\n\r\n"),
+            response("200 OK", "", b"until closed"),
+        ]);
+
+        let r = get(&format!("{}/game", url), 100).unwrap();
+        assert_eq!(r.url, format!("{}/replays/game.slpz", url));
+        assert_eq!(r.content_type.as_deref(), Some("application/x-slippi-compressed"));
+        assert_eq!(r.body, b"hello");
+        assert_eq!(get(&url, 100).unwrap().body, b"abcde");
+        assert_eq!(get(&url, 100).unwrap().body, b"until closed");
+    }
+
+    #[test]
+    fn errors() {
+        let url = serve(vec![
+            response("404 Not Found", "Content-Length: 0\r\n", b""),
+            response("200 OK", "Content-Length: 1000\r\n", &[0; 1000]),
+            response("200 OK", "Transfer-Encoding: chunked\r\n", b"8\r\n12345678\r\n8\r\n12345678\r\n0\r\n\r\n"),
+            response("200 OK", "", &[0; 11]),
+            response("200 OK", "Transfer-Encoding: chunked\r\n", b"8\r\n12345678\r\nffffffffffffffff\r\n"),
+        ]);
+
+        assert!(matches!(get(&url, 10), Err(HttpError::Status(404, r)) if r == "Not Found"));
+        assert!(matches!(get(&url, 10), Err(HttpError::TooLarge(10))));
+        assert!(matches!(get(&url, 10), Err(HttpError::TooLarge(10))));
+        assert!(matches!(get(&url, 10), Err(HttpError::TooLarge(10))));
+        assert!(matches!(get(&url, 10), Err(HttpError::TooLarge(10))));
+
+        assert!(matches!(get("https://example.com/game.slpz", 10), Err(HttpError::HttpsUnsupported)));
+        assert!(matches!(get("http://:80/", 10), Err(HttpError::InvalidUrl)));
+        assert_eq!(split_url("http://example.com?a=1").unwrap(), ("example.com", 80, "?a=1"));
+        assert_eq!(split_url("http://[::1]/x").unwrap(), ("::1", 80, "/x"));
+        assert_eq!(split_url("http://[::1]:8080").unwrap(), ("::1", 8080, "/"));
+        assert!(matches!(split_url("http://::1/x"), Err(HttpError::InvalidUrl)));
+        assert!(matches!(split_url("http://[::1/x"), Err(HttpError::InvalidUrl)));
+        assert!(matches!(split_url("http://example.com:/x"), Err(HttpError::InvalidUrl)));
+        assert_eq!(resolve("http://[::1]:8080/a", "/b").unwrap(), "http://[::1]:8080/b");
+        assert_eq!(authority("example.com", 80), "example.com");
+    }
+}
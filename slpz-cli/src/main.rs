@@ -0,0 +1,2063 @@
+use slpz::*;
+
+#[cfg(feature = "http")]
+mod http;
+
+const HELP: &str =
+"Usage: slpz [OPTIONS] <input path>
+       slpz [OPTIONS] (-x | -d) --stdin-filenames
+       slpz [OPTIONS] [-o <output file>] [--max-size <BYTES>] <http URL>
+       slpz analyze [--json] [--fast | --small] <slp file>
+       slpz info <slp or slpz file>
+       slpz check [--json] [-v | --verbose] [--include-hidden] <path>
+       slpz train-dict --output <dict file> [--size <BYTES>] <slp files>...
+       slpz recompress [OPTIONS] [--force-recompress] <input path>
+       slpz repair --donor <slp file> [-o <output file>] <slp file>
+       slpz audit --journal <journal file> [--temp-suffix <SUFFIX>] <dir>
+       slpz profiles
+       slpz --print-default-config [--format toml | json]
+
+Options:
+  --fast                Prefer speed over compression. Level -1.
+  --small               Prefer compression over speed
+  --profile <NAME>      Apply a set of compression settings. See 'slpz profiles'.
+  --level <LEVEL>       Compression level, -131072 to 22. 0 stores without compressing. [Default: 3]
+                        Negative levels are faster, levels above 19 use a lot of memory.
+  --time-budget <DURATION>
+                        Compress files that would take longer than this, such as 10s, at level 1 instead.
+  --long                Enable zstd long distance matching.
+  --checksum            Add a zstd checksum. [Default]
+  --no-checksum         Do not add a zstd checksum.
+  --zstd-threads <N>    Compress each file with N zstd worker threads. [Default: 0]
+  --segments <N>        Split each file into N independently compressed segments,
+                        compressed and decompressed on a thread each. For large replays.
+  --memory-budget <BYTES>
+                        Use fewer threads so that the estimated memory stays under BYTES.
+                        --verbose prints the estimate.
+  --verify              Decompress each compressed file and check it matches the input.
+  --spot-check <PERCENT>
+                        Decompress and check a random PERCENT of compressed files before writing them,
+                        stopping at the first one that does not match.
+  --seed <N>            Seed for choosing the files to spot check, to check the same files again.
+  --clip <DURATION>     Only compress the start of each replay, such as 60s or 2m, for previews.
+                        Clips are written to <name>.clip.slpz, and cannot be used with --rm.
+  --clip-frames <N>     Only compress the first N frames of each replay.
+  -x, --compress
+  -d, --decompress
+  -r, --recursive       Compress/decompress all files in subdirectories.
+  --include-hidden      Process files and directories whose names start with '.'.
+  --follow-symlinks     Process symlinked files and directories. Symlinks are skipped by default.
+  -k, --keep            Keep files after compression/decompression. [Default]
+  --rm                  Move files to the trash after compression/decompression.
+  --rm-permanent        Delete files permanently after compression/decompression.
+  --verbose             Also log the options in use, and the sizes, compression ratio and throughput of each file.
+  -q, --quiet           Do not log each file to stdout. A summary line is still printed to stderr.
+  -qq, --very-quiet     Only print errors. Also given by passing -q twice.
+  --stats               Print the summary line to stderr even if it is not a terminal, or with -qq.
+                        It ends with the time taken and the throughput in MB/s of slp files.
+  --strip-trailer       Drop bytes after the end of the replay instead of keeping them.
+  --strict              Reject replays that need inference or repair, such as an unwritten raw length,
+                        unknown events, bytes after the end, or missing metadata.
+  --lenient             Also infer raw lengths that point past the end of the file.
+  --suffix <EXT>        Output file extension. [Default: slpz or slp]
+  --ext <SLP>=<SLPZ>    Also compress files ending in .SLP to files ending in .SLPZ, and decompress them back,
+                        such as --ext slp=slp.zst. Can be given more than once. Extensions ignore case.
+  --in-place            Allow the output to replace the input when using --suffix.
+  --out-dir, --output-dir <DIR>
+                        Write outputs to DIR, keeping their path relative to the input directory.
+                        DIR is created if needed.
+  --launcher-names      Name decompressed files like the Slippi Launcher (Game_YYYYMMDDTHHMMSS.slp) from
+                        their start time. When compressing, warn about names that do not match.
+  --to <FORMAT>         Decompress to slp or gzip (.slp.gz) files. [Default: slp]
+                        .slp.gz files are always read when compressing. Both require the 'gzip' feature.
+  --force               Overwrite existing slp files that hold a different replay when decompressing.
+  --rename-conflicts    Write to <name>.conflict-<n>.slp instead of overwriting a different replay.
+  -i, --interactive     Ask before overwriting existing output files, like 'cp -i'.
+                        Nothing is overwritten if stdin is not a terminal.
+  --no-overwrite        Skip files whose output already exists.
+  --skip-if-unchanged   Skip files whose slpz file already exists and holds the same replay.
+  --only-complete       Skip replays that did not end normally, such as crashes and quit outs:
+                        those without a Game End event, or that ended in a no contest.
+  --only-incomplete     Only process those replays. 'slpz info' shows how a replay ended.
+  --dedupe-names        Add a replay hash to the names of outputs that would have the same path,
+                        such as Game.slpz and game.slpz on Windows and macOS, instead of failing.
+  --settle-ms <MS>      Skip files modified within this many milliseconds. [Default: 2000]
+  --temp-suffix <SUFFIX>
+                        Suffix for partially written files. [Default: .tmp]
+  --journal <FILE>      Append a line to FILE for each file started, written, verified and removed,
+                        synced to disk, and check each output by reading it back before removing its input.
+                        After a crash, 'slpz audit --journal FILE <dir>' finds the files to look at.
+  --stdin-filenames     Read paths separated by newlines or NUL bytes from stdin, processing each as it arrives,
+                        until stdin closes. Paths that fail are logged and do not stop the others.
+                        Paths without an input extension, such as slpz's temp files, are skipped.
+                        For example 'inotifywait -m -e close_write --format %w%f DIR | slpz -x --rm --stdin-filenames'.
+  -c, --stdout          Write the output of a single file to stdout.
+  -n, --null-output     Compress/decompress without writing or removing any files, for benchmarking.
+                        The summary line gives the ratio and the throughput.
+  --original-size       Print the size of an slpz file once decompressed, without decompressing it.
+  --format-version [FILE]
+                        Print the slpz format versions this program reads and writes, and the version of FILE.
+  --print-default-config [--format toml | json]
+                        Print the default options as a commented config file, or as JSON.
+  --config <FILE>       Read options from a config file, as printed by --print-default-config.
+                        SLPZ_LEVEL and flags override it. [Default: slpz/config.toml in %APPDATA% on
+                        Windows, or in $XDG_CONFIG_HOME or ~/.config elsewhere, if it exists]
+  --progress            Show a progress bar on stderr when processing a single file.
+                        Requires the 'progress' feature.
+  --mime-header         Write to stdout, starting with Content-Type and Content-Length header lines.
+  --exec-before <CMD>   Run CMD with the shell before processing each file, skipping the file if CMD fails.
+                        {in} is replaced with the quoted input path.
+  --exec-after <CMD>    Run CMD with the shell after writing each output, counting the file as failed
+                        and keeping its input if CMD fails. {in}, {out}, and {ratio} are replaced.
+  --exec-ignore-failure Print a warning instead when an --exec-before or --exec-after command fails.
+  -o, --output <FILE>   Where to write the output of a single file or URL input. [Default: the URL's file name]
+                        Named pipes and devices such as /dev/stdout are written as they are. If the reader
+                        closes the pipe early, exits with status 141 without an error.
+  --output-fd <N>       Write the output of a single file to file descriptor N, opened by the parent process.
+                        It is written from its current offset, without a temp file. Unix only.
+  --input-fd <N>        Read a single input from file descriptor N instead of a path.
+                        Without --output-fd, the output is written to stdout. Unix only.
+  --output-handle <N>, --input-handle <N>
+                        The same for handles on Windows.
+  --max-size <BYTES>    Refuse URL downloads larger than this. [Default: 268435456]
+                        URL inputs require the 'http' feature, and only support http.
+  -h, --help
+  -v, --version
+
+Environment:
+  SLPZ_LEVEL            Default compression level, overriding the config file and overridden by flags.";
+
+fn main() {
+    let mut arg_strings = std::env::args();
+    arg_strings.next(); // skip exe name
+    let mut arg_strings = arg_strings.collect::<Vec<_>>();
+
+    if arg_strings.first().map(String::as_str) == Some("analyze") {
+        arg_strings.remove(0);
+        analyze_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("profiles") {
+        profiles_cmd();
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("--print-default-config") {
+        arg_strings.remove(0);
+        print_default_config_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("info") {
+        arg_strings.remove(0);
+        info_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("train-dict") {
+        arg_strings.remove(0);
+        train_dict_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("recompress") {
+        arg_strings.remove(0);
+        recompress_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("repair") {
+        arg_strings.remove(0);
+        repair_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("check") {
+        arg_strings.remove(0);
+        check_cmd(arg_strings);
+    }
+
+    if arg_strings.first().map(String::as_str) == Some("audit") {
+        arg_strings.remove(0);
+        audit_cmd(arg_strings);
+    }
+
+    let input_fd = take_inherited(&mut arg_strings, "--input-fd", "--input-handle");
+    let output_fd = take_inherited(&mut arg_strings, "--output-fd", "--output-handle");
+    if input_fd.is_some() || output_fd.is_some() {
+        inherited_cmd(arg_strings, input_fd, output_fd);
+    }
+
+    if take_flag(&mut arg_strings, &["--stdin-filenames"]) {
+        stdin_filenames_cmd(arg_strings);
+    }
+
+    // last arg is path, or the one before a trailing '-o <file>'
+    let len = arg_strings.len();
+    let trailing_output = len >= 3 && ["-o", "--output"].contains(&arg_strings[len - 2].as_str());
+    let input_path = match if trailing_output { Some(arg_strings.remove(len - 3)) } else { arg_strings.pop() } {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    if &input_path == "-h" || &input_path == "--help" {
+        println!("{}", HELP);
+        std::process::exit(0);
+    }
+
+    if &input_path == "-v" || &input_path == "--version" {
+        println!("slpz version {} - created by Alex Harrison (Aitch)", VERSION);
+        std::process::exit(0);
+    }
+
+    if &input_path == "--format-version" {
+        format_version_cmd(None);
+    }
+
+    if take_flag(&mut arg_strings, &["--original-size"]) {
+        original_size_cmd(&input_path);
+    }
+
+    if take_flag(&mut arg_strings, &["--format-version"]) {
+        format_version_cmd(Some(&input_path));
+    }
+
+    let to_stdout = take_flag(&mut arg_strings, &["-c", "--stdout"]);
+    let mime_header = take_flag(&mut arg_strings, &["--mime-header"]);
+    let stats = take_flag(&mut arg_strings, &["--stats"]);
+    let output = take_value(&mut arg_strings, &["-o", "--output"], "an output file");
+    let max_size = match take_value(&mut arg_strings, &["--max-size"], "a size in bytes").map(|s| s.parse()) {
+        None => DEFAULT_MAX_DOWNLOAD_SIZE,
+        Some(Ok(size)) => size,
+        Some(Err(_)) => {
+            eprintln!("Error: '--max-size' requires a size in bytes");
+            std::process::exit(1);
+        }
+    };
+    let options = parse_options(&arg_strings);
+
+    if options.null_output {
+        let conflict = [
+            (output.is_some(), "--output"),
+            (to_stdout, "--stdout"),
+            (mime_header, "--mime-header"),
+            (options.output_dir.is_some(), "--out-dir"),
+        ].into_iter().find(|(given, _)| *given);
+        if let Some((_, flag)) = conflict {
+            eprintln!("Error: '--null-output' cannot be used with '{}'", flag);
+            std::process::exit(1);
+        }
+    }
+
+    if input_path.starts_with("http://") || input_path.starts_with("https://") {
+        url_cmd(&options, &input_path, output, max_size);
+    }
+
+    if to_stdout || mime_header {
+        stdout_cmd(&options, &input_path, mime_header);
+    }
+
+    if let Some(out) = output {
+        output_cmd(&options, &input_path, std::path::Path::new(&out));
+    }
+
+    if options.log == LogLevel::Verbose { println!("options: {}", options); }
+    let start = std::time::Instant::now();
+    match target_path(&options, std::path::Path::new(&input_path), None) {
+        Ok(result) => {
+            if stats || (options.log.prints_summary() && std::io::IsTerminal::is_terminal(&std::io::stderr())) {
+                eprintln!("{}", result.summary_with_time(start.elapsed()));
+            }
+            if !result.spot_check_failed.is_empty() {
+                eprintln!("Error: stopped after {} failed spot check(s). Later files were not processed", result.spot_check_failed.len());
+                std::process::exit(1);
+            }
+        }
+        Err(e) => print_target_path_error(e, std::path::Path::new(&input_path)),
+    }
+}
+
+/// Prints the error for an input path that [`target_path`] could not process.
+fn print_target_path_error(e: TargetPathError, input_path: &std::path::Path) {
+    match e {
+        TargetPathError::PathNotFound => eprintln!("Error: input path '{}' not found", display_escaped(input_path)),
+        TargetPathError::PathInvalid => eprintln!("Error: input path '{}' not valid", display_escaped(input_path)),
+        TargetPathError::CompressOrDecompressAmbiguous => match file_format(input_path) {
+            Some(format) => eprintln!("Error: '{}' looks like {}, not a Slippi replay", display_escaped(input_path), format.description()),
+            None => eprintln!("Error: must pass either '-x' or '-d' flag for input path '{}'", display_escaped(input_path)),
+        },
+        TargetPathError::ZstdInitError => eprintln!("Error: zstd initiation failed"),
+        TargetPathError::InvalidOutputExtension => eprintln!("Error: invalid suffix. Suffixes must not be empty or contain path separators"),
+        TargetPathError::OutputDirInvalid => eprintln!("Error: output directory could not be created"),
+        TargetPathError::OutputDirConflict => eprintln!("Error: output directory is the input directory, so outputs would be processed again"),
+        TargetPathError::OutputCollision => eprintln!("Error: nothing was written. Pass --dedupe-names to add a replay hash to the colliding names"),
+        TargetPathError::JournalInvalid => eprintln!("Error: the journal could not be opened, or is not an slpz journal"),
+        TargetPathError::ClipRemovesInput => eprintln!("Error: '--clip' cannot be used with '--rm', which would replace each replay with its clip"),
+    }
+}
+
+/// The format of a file other than slp and slpz files, from its first bytes. See [`detect_format`].
+fn file_format(path: &std::path::Path) -> Option<KnownFormat> {
+    use std::io::Read;
+
+    let mut start = Vec::with_capacity(1024);
+    std::fs::File::open(path).ok()?.take(1024).read_to_end(&mut start).ok()?;
+    detect_format(&start).filter(|f| !matches!(f, KnownFormat::Slp | KnownFormat::Slpz))
+}
+
+/// Parses durations like `60s`, `1.5m` or `90`, in seconds without a unit.
+fn parse_duration(s: &str) -> Option<std::time::Duration> {
+    let (number, scale) = match s.strip_suffix('m') {
+        Some(minutes) => (minutes, 60.0),
+        None => (s.strip_suffix('s').unwrap_or(s), 1.0),
+    };
+    let secs = number.parse::<f64>().ok()? * scale;
+    std::time::Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Parses the options before the input path. Exits on invalid arguments.
+/// Flags override the environment, which overrides the config file. See [`Options::with_env`].
+fn parse_options(arg_strings: &[String]) -> Options {
+    let mut options = load_config(arg_strings).with_env();
+    let mut exec_before = None;
+    let mut exec_after = None;
+    let mut exec_ignore_failure = false;
+    let mut seed = None;
+
+    let mut i = 0;
+    while let Some(a) = arg_strings.get(i) {
+        match a.as_ref() {
+            "--fast" => options = options.profile(Profile::Fast),
+            "--small" => options = options.profile(Profile::Small),
+            // read by load_config
+            "--config" => { arg_value(arg_strings, &mut i, "a config file"); }
+            "--profile" => {
+                let name = arg_value(arg_strings, &mut i, "a profile name. See 'slpz profiles'");
+                match Profile::from_name(name) {
+                    Some(p) => options = options.profile(p),
+                    None => {
+                        eprintln!("Error: unknown profile '{}'. See 'slpz profiles'", name);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--level" => {
+                match arg_value(arg_strings, &mut i, "a compression level").parse() {
+                    Ok(level) if is_valid_level(level) => options.level = level,
+                    _ => {
+                        eprintln!("Error: '--level' requires a level between {} and {}", COMPRESS_LEVEL_MIN, COMPRESS_LEVEL_MAX);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--zstd-threads" => {
+                match arg_value(arg_strings, &mut i, "a number of threads").parse() {
+                    Ok(n) => options.zstd_threads = n,
+                    Err(_) => {
+                        eprintln!("Error: '--zstd-threads' requires a number of threads");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--segments" => {
+                match arg_value(arg_strings, &mut i, "a number of segments").parse() {
+                    Ok(n @ 1..) => options.segments = Some(n),
+                    _ => {
+                        eprintln!("Error: '--segments' requires a number of segments");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--clip" => match parse_duration(arg_value(arg_strings, &mut i, "a duration")) {
+                Some(d) => options.clip = Some(ClipRange::Duration(d)),
+                None => {
+                    eprintln!("Error: '--clip' requires a duration, such as 60s or 2m");
+                    std::process::exit(1);
+                }
+            },
+            "--time-budget" => match parse_duration(arg_value(arg_strings, &mut i, "a duration")) {
+                Some(d) => options.time_budget_per_file = Some(d),
+                None => {
+                    eprintln!("Error: '--time-budget' requires a duration, such as 10s or 1m");
+                    std::process::exit(1);
+                }
+            },
+            "--clip-frames" => match arg_value(arg_strings, &mut i, "a number of frames").parse() {
+                Ok(n) => options.clip = Some(ClipRange::Frames(n)),
+                Err(_) => {
+                    eprintln!("Error: '--clip-frames' requires a number of frames");
+                    std::process::exit(1);
+                }
+            },
+            "--spot-check" => match arg_value(arg_strings, &mut i, "a percentage").trim_end_matches('%').parse() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => options.spot_check = Some(SpotCheck { percent, seed: 0 }),
+                _ => {
+                    eprintln!("Error: '--spot-check' requires a percentage from 0 to 100");
+                    std::process::exit(1);
+                }
+            },
+            "--seed" => match arg_value(arg_strings, &mut i, "a number").parse() {
+                Ok(n) => seed = Some(n),
+                Err(_) => {
+                    eprintln!("Error: '--seed' requires a number");
+                    std::process::exit(1);
+                }
+            },
+            "--long" => options.long_distance_matching = true,
+            "--checksum" => options.zstd_checksum = true,
+            "--no-checksum" => options.zstd_checksum = false,
+            "--verify" => {
+                eprintln!("warning: '--verify' decompresses every compressed file, roughly doubling the time taken");
+                options.verify_after_compress = true;
+            }
+            "-x" | "--compress" => options.compress = Some(true),
+            "-d" | "--decompress" => options.compress = Some(false),
+            "-r" | "--recursive" => options.recursive = true,
+            "--include-hidden" => options.skip_hidden = false,
+            "--follow-symlinks" => options.follow_symlinks = true,
+            "-k" | "--keep" => options.keep = true,
+            "-n" | "--null-output" => options.null_output = true,
+            "--skip-if-unchanged" => options.skip_if_unchanged = true,
+            "--only-complete" => options.only_complete = Some(true),
+            "--only-incomplete" => options.only_complete = Some(false),
+            "--rm" => options.keep = false,
+            "--rm-permanent" => {
+                options.keep = false;
+                options.removal = RemovalMode::Permanent;
+            }
+            "--verbose" => options.log = LogLevel::Verbose,
+            "-q" | "--quiet" => options.log = match options.log {
+                LogLevel::Quiet | LogLevel::Silent => LogLevel::Silent,
+                _ => LogLevel::Quiet,
+            },
+            "-qq" | "--very-quiet" => options.log = LogLevel::Silent,
+            "--strip-trailer" => options.strip_trailer = true,
+            "--strict" | "--lenient" => {
+                let strictness = if a == "--strict" { Strictness::Strict } else { Strictness::Lenient };
+                if options.strictness != Strictness::Normal && options.strictness != strictness {
+                    eprintln!("Error: '--strict' and '--lenient' conflict");
+                    std::process::exit(1);
+                }
+                options.strictness = strictness;
+            }
+            "--suffix" => options.output_extension = Some(arg_value(arg_strings, &mut i, "an extension").into()),
+            "--ext" => {
+                let pair = arg_value(arg_strings, &mut i, "a pair of extensions like slp.zst=slpz");
+                if pair.split_once('=').and_then(|(slp, slpz)| options.extensions.add(slp, slpz)).is_none() {
+                    eprintln!("Error: '--ext' requires a pair of extensions like slp.zst=slpz");
+                    std::process::exit(1);
+                }
+            }
+            "--in-place" => options.in_place = true,
+            "--out-dir" | "--output-dir" => options.output_dir = Some(arg_value(arg_strings, &mut i, "a directory").into()),
+            "--force" => options.on_conflict = OnConflict::Overwrite,
+            "--rename-conflicts" => options.on_conflict = OnConflict::Rename,
+            "--dedupe-names" => options.dedupe_names = true,
+            "-i" | "--interactive" => options.confirm_overwrite = Some(interactive_overwrite()),
+            "--no-overwrite" => {
+                options.confirm_overwrite = Some(Hook::new(|out: &std::path::Path| {
+                    Err(HookError::Other(format!("{} exists", display_escaped(&out))))
+                }));
+            }
+            "--launcher-names" => options.launcher_names = true,
+            "--to" => match arg_value(arg_strings, &mut i, "slp or gzip") {
+                "slp" => options.gzip_output = false,
+                "gzip" if cfg!(feature = "gzip") => options.gzip_output = true,
+                "gzip" => {
+                    eprintln!("Error: '--to gzip' requires the 'gzip' feature");
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("Error: '--to' requires slp or gzip");
+                    std::process::exit(1);
+                }
+            },
+            "--force-recompress" => options.force_recompress = true,
+            "--exec-before" => exec_before = Some(arg_value(arg_strings, &mut i, "a command").to_string()),
+            "--exec-after" => exec_after = Some(arg_value(arg_strings, &mut i, "a command").to_string()),
+            "--exec-ignore-failure" => exec_ignore_failure = true,
+            "--progress" => {
+                if !cfg!(feature = "progress") {
+                    eprintln!("warning: slpz was built without the 'progress' feature, '--progress' is ignored");
+                }
+                options.progress = true;
+            }
+            "--memory-budget" => {
+                match arg_value(arg_strings, &mut i, "a size in bytes").parse() {
+                    Ok(bytes) => options.memory_budget = Some(bytes),
+                    Err(_) => {
+                        eprintln!("Error: '--memory-budget' requires a size in bytes");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--settle-ms" => {
+                match arg_value(arg_strings, &mut i, "a number of milliseconds").parse() {
+                    Ok(ms) => options.settle_ms = ms,
+                    Err(_) => {
+                        eprintln!("Error: '--settle-ms' requires a number of milliseconds");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--journal" => options.journal = Some(arg_value(arg_strings, &mut i, "a journal file").into()),
+            "--temp-suffix" => {
+                let suffix = arg_value(arg_strings, &mut i, "a suffix");
+                if suffix.is_empty() {
+                    eprintln!("Error: '--temp-suffix' requires a suffix");
+                    std::process::exit(1);
+                }
+                options.temp_suffix = suffix.to_string().into();
+            }
+            "-h" | "--help" => {
+                println!("{}", HELP);
+                std::process::exit(0);
+            }
+            "-v" | "--version" => {
+                println!("slpz version {} - created by Alex Harrison (Aitch)", VERSION);
+                std::process::exit(0);
+            }
+            a => eprintln!("unknown argument '{}'", a),
+        }
+
+        i += 1;
+    }
+
+    if options.clip.is_some() && !options.keep {
+        eprintln!("Error: '--clip' cannot be used with '--rm' or '--rm-permanent', which would replace each replay with its clip");
+        std::process::exit(1);
+    }
+
+    if let Some(ref mut spot_check) = options.spot_check {
+        spot_check.seed = match seed {
+            Some(seed) => seed,
+            None => {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+                let seed = now.as_nanos() as u64 ^ std::process::id() as u64;
+                if options.log != LogLevel::Silent {
+                    eprintln!("spot checking with seed {}. Pass '--seed {}' to check the same files again", seed, seed);
+                }
+                seed
+            }
+        };
+    }
+
+    if let Some(cmd) = exec_before {
+        options.pre_hook = Some(Hook::new(move |input: &std::path::Path| {
+            match shell_quote(input) {
+                Ok(input) => run_hook_command(&substitute(&cmd, &[("{in}", &input)]), exec_ignore_failure),
+                Err(e) => hook_failure(&cmd, e, exec_ignore_failure),
+            }
+        }));
+    }
+    if let Some(cmd) = exec_after {
+        options.post_hook = Some(Hook::new(move |result: &FileResult| {
+            let (input, output) = match (shell_quote(&result.input), shell_quote(&result.output)) {
+                (Ok(input), Ok(output)) => (input, output),
+                (Err(e), _) | (_, Err(e)) => return hook_failure(&cmd, e, exec_ignore_failure),
+            };
+            let cmd = substitute(&cmd, &[("{in}", &input), ("{out}", &output), ("{ratio}", &format!("{:.2}", result.ratio()))]);
+            run_hook_command(&cmd, exec_ignore_failure)
+        }));
+    }
+
+    options
+}
+
+/// Replaces each placeholder in one pass, so substituted text is never substituted again.
+fn substitute(template: &str, substitutions: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    'outer: while !rest.is_empty() {
+        for (placeholder, value) in substitutions {
+            if let Some(after) = rest.strip_prefix(placeholder) {
+                out.push_str(value);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Quotes a path as a single shell argument.
+///
+/// Paths that are not UTF-8 are an error, since a lossy copy would name a different file.
+fn shell_quote(path: &std::path::Path) -> Result<String, HookError> {
+    let s = path.to_str()
+        .ok_or_else(|| HookError::Other(format!("{} is not valid UTF-8 and cannot be passed to a hook", display_escaped(path))))?;
+    Ok(if cfg!(windows) {
+        // cmd expands %VAR% even inside quotes, so each '%' is escaped with '^' outside them
+        format!("\"{}\"", s.replace('"', "\"\"").replace('%', "\"^%\""))
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    })
+}
+
+/// Runs a command with the system shell, returning an error if it fails unless `ignore_failure` is set.
+fn run_hook_command(cmd: &str, ignore_failure: bool) -> Result<(), HookError> {
+    let status = if cfg!(windows) {
+        std::process::Command::new("cmd").arg("/C").arg(cmd).status()
+    } else {
+        std::process::Command::new("sh").arg("-c").arg(cmd).status()
+    };
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => hook_failure(cmd, HookError::ExitStatus(status.code()), ignore_failure),
+        Err(e) => hook_failure(cmd, HookError::Spawn(e.to_string()), ignore_failure),
+    }
+}
+
+/// Returns the error of a hook command, or only prints it if `ignore_failure` is set.
+fn hook_failure(cmd: &str, e: HookError, ignore_failure: bool) -> Result<(), HookError> {
+    if !ignore_failure { return Err(e) }
+    eprintln!("warning: '{}': {}", cmd, e);
+    Ok(())
+}
+
+/// Flags followed by a value, which is never itself a flag, such as the extension in `--suffix -c`.
+const VALUE_FLAGS: &[&str] = &[
+    "-o", "--output", "--max-size", "--config", "--profile", "--level", "--zstd-threads", "--segments", "--clip",
+    "--time-budget", "--clip-frames", "--spot-check", "--seed", "--suffix", "--ext", "--out-dir", "--output-dir", "--to",
+    "--exec-before", "--exec-after", "--memory-budget", "--settle-ms", "--journal", "--temp-suffix",
+];
+
+/// Returns the position of the first of the flags, skipping the values of [`VALUE_FLAGS`].
+fn flag_position(arg_strings: &[String], names: &[&str]) -> Option<usize> {
+    let mut i = 0;
+    while let Some(a) = arg_strings.get(i) {
+        if names.contains(&a.as_str()) { return Some(i) }
+        i += if VALUE_FLAGS.contains(&a.as_str()) { 2 } else { 1 };
+    }
+    None
+}
+
+/// Removes every occurrence of the flag from the arguments, returning true if there was one.
+fn take_flag(arg_strings: &mut Vec<String>, names: &[&str]) -> bool {
+    let mut found = false;
+    while let Some(i) = flag_position(arg_strings, names) {
+        arg_strings.remove(i);
+        found = true;
+    }
+    found
+}
+
+/// Removes a flag and its value, returning the value. Exits if the flag has no value.
+fn take_value(arg_strings: &mut Vec<String>, names: &[&str], expected: &str) -> Option<String> {
+    let i = flag_position(arg_strings, names)?;
+    if i + 1 >= arg_strings.len() {
+        eprintln!("Error: '{}' requires {}", arg_strings[i], expected);
+        std::process::exit(1);
+    }
+    let value = arg_strings.remove(i + 1);
+    arg_strings.remove(i);
+    Some(value)
+}
+
+/// Reads the options from the config file given with `--config`, or from [`default_config_path`] if it exists.
+///
+/// Exits if the file cannot be read or parsed. Without a config file, returns [`Options::DEFAULT`].
+fn load_config(arg_strings: &[String]) -> Options {
+    let path = match arg_strings.iter().rposition(|a| a == "--config") {
+        Some(i) => match arg_strings.get(i + 1) {
+            Some(path) => std::path::PathBuf::from(path),
+            None => {
+                eprintln!("Error: '--config' requires a config file");
+                std::process::exit(1);
+            }
+        },
+        None => match default_config_path().filter(|p| p.is_file()) {
+            Some(path) => path,
+            None => return Options::DEFAULT,
+        },
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Error: cannot read config file {}: {}", display_escaped(&path), e);
+            std::process::exit(1);
+        }
+    };
+    match text.parse::<Options>() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}: {}", display_escaped(&path), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `slpz/config.toml` in the user's config directory: `%APPDATA%` on Windows,
+/// and `$XDG_CONFIG_HOME` or `~/.config` elsewhere.
+fn default_config_path() -> Option<std::path::PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(std::path::PathBuf::from);
+    let dir = if cfg!(windows) {
+        var("APPDATA")?
+    } else {
+        var("XDG_CONFIG_HOME").or_else(|| Some(var("HOME")?.join(".config")))?
+    };
+    Some(dir.join("slpz").join("config.toml"))
+}
+
+const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 256 << 20;
+
+#[cfg(not(feature = "http"))]
+fn url_cmd(_: &Options, _: &str, _: Option<String>, _: u64) -> ! {
+    eprintln!("Error: URL inputs require building slpz with the 'http' feature");
+    std::process::exit(1);
+}
+
+/// Downloads a replay and compresses or decompresses it.
+///
+/// The direction is taken from -x or -d, then the Content-Type, then the URL's extension, then the content.
+#[cfg(feature = "http")]
+fn url_cmd(options: &Options, url: &str, output: Option<String>, max_size: u64) -> ! {
+    let response = match http::get(url, max_size) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error downloading {}: {}", url, e);
+            std::process::exit(1);
+        }
+    };
+
+    let name = url_file_name(&response.url);
+    let content_type = response.content_type.as_deref().and_then(|t| t.split(';').next()).map(str::trim);
+    let should_compress = match options.compress {
+        Some(c) => c,
+        None if content_type == Some(SLPZ_MIME_TYPE) => false,
+        None if content_type == Some(SLP_MIME_TYPE) => true,
+        None if options.extensions.direction(std::path::Path::new(name)) == Some(false) => false,
+        None if options.extensions.direction(std::path::Path::new(name)) == Some(true) => true,
+        None if is_slp(&response.body) => true,
+        None if is_slpz(&response.body) => false,
+        None => {
+            eprintln!("Error: must pass either '-x' or '-d' flag for '{}'", url);
+            std::process::exit(1);
+        }
+    };
+
+    let out = match output {
+        Some(o) => std::path::PathBuf::from(o),
+        None => {
+            let out = match options.output_extension {
+                Some(ref extension) => std::path::Path::new(name).with_extension(extension),
+                None => options.extensions.output_name(std::path::Path::new(name), should_compress),
+            };
+            match options.output_dir {
+                Some(ref dir) => dir.join(out),
+                None => out,
+            }
+        }
+    };
+    confirm_output(options, url, &out);
+
+    let result = if should_compress {
+        match options_compressor(options) {
+            Some(mut c) => options_compress(&mut c, options, &response.body).map_err(SlpzError::from),
+            None => Err(TargetPathError::ZstdInitError.into()),
+        }
+    } else {
+        match options_decompressor(options) {
+            Some(mut d) => decompress(&mut d, &response.body).map_err(SlpzError::from),
+            None => Err(TargetPathError::ZstdInitError.into()),
+        }
+    };
+    let data = match result {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Error {} {}: {}", if should_compress { "compressing" } else { "decompressing" }, url, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = atomic_write_with_suffix(&out, &data, &options.temp_suffix) {
+        eprintln!("Error writing {}: {}", display_escaped(&out), e);
+        std::process::exit(1);
+    }
+    if options.log.prints_files() { println!("{} {} to {}", if should_compress { "compressed" } else { "decompressed" }, url, display_escaped(&out)); }
+    std::process::exit(0);
+}
+
+/// The last path segment of a URL, or "replay" if it is empty or not a plain file name.
+#[cfg(feature = "http")]
+fn url_file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest.split_once('/').map_or("", |(_, p)| p));
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() && !name.starts_with('.') && !name.contains(['\\', ':']) => name,
+        _ => "replay",
+    }
+}
+
+/// Exit status when the reader of a pipe closes it before the whole output is written, as for a process killed by SIGPIPE.
+const EXIT_READER_CLOSED: i32 = 141;
+
+/// Reads a single input file and whether to compress it. Exits on errors.
+fn read_single_input(options: &Options, input_path: &str) -> (Vec<u8>, bool) {
+    let data = match std::fs::read(input_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+    let should_compress = single_input_compresses(options, input_path, &data);
+    (data, should_compress)
+}
+
+/// Whether to compress a single input, from `-x` or `-d` or its contents. Exits if neither decides.
+fn single_input_compresses(options: &Options, input_path: &str, data: &[u8]) -> bool {
+    match options.compress {
+        Some(c) => c,
+        None if is_slp(data) => true,
+        None if is_slpz(data) => false,
+        None => {
+            eprintln!("Error: must pass either '-x' or '-d' flag for input path '{}'", input_path);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compresses or decompresses a single file in memory. Exits on errors.
+fn convert_single_input(options: &Options, input_path: &str, data: &[u8], should_compress: bool) -> Vec<u8> {
+    let result = if should_compress {
+        let mut compressor = match options_compressor(options) {
+            Some(c) => c,
+            None => {
+                eprintln!("Error: zstd initiation failed");
+                std::process::exit(1);
+            }
+        };
+        options_compress(&mut compressor, options, data).map_err(SlpzError::from)
+    } else {
+        match Decompressor::new() {
+            Some(mut d) => decompress(&mut d, data).map_err(SlpzError::from),
+            None => Err(TargetPathError::ZstdInitError.into()),
+        }
+    };
+
+    match result {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("Error {} {}: {}", if should_compress { "compressing" } else { "decompressing" }, input_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compresses or decompresses a single file to stdout.
+/// Nothing else is written to stdout, so it can be piped.
+fn stdout_cmd(options: &Options, input_path: &str, mime_header: bool) -> ! {
+    use std::io::Write;
+
+    let (data, should_compress) = read_single_input(options, input_path);
+    let output = convert_single_input(options, input_path, &data, should_compress);
+
+    let mut stdout = std::io::stdout().lock();
+    let mut written = Ok(());
+    if mime_header {
+        let mime_type = if should_compress { SLPZ_MIME_TYPE } else { SLP_MIME_TYPE };
+        written = stdout.write_all(http_header(mime_type, output.len()).as_bytes());
+    }
+    match written.and_then(|_| stdout.write_all(&output)).and_then(|_| stdout.flush()) {
+        Ok(()) => std::process::exit(0),
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(EXIT_READER_CLOSED),
+        Err(e) => {
+            eprintln!("Error writing to stdout: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Whether writes to the path go to a reader instead of a file, such as a named pipe or `/dev/stdout`.
+fn is_stream_output(path: &std::path::Path) -> bool {
+    if path.starts_with("/dev") || path.starts_with("/proc") { return true }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata(path).is_ok_and(|m| {
+            let file_type = m.file_type();
+            file_type.is_fifo() || file_type.is_char_device() || file_type.is_socket()
+        })
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+/// Records whether the reader closed the pipe, which [`decompress_to_sink`] reports as any other write failure.
+struct StreamOutput<W> {
+    file: W,
+    reader_closed: bool,
+}
+
+impl<W> StreamOutput<W> {
+    fn check<T>(&mut self, result: std::io::Result<T>) -> std::io::Result<T> {
+        if result.as_ref().is_err_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) {
+            self.reader_closed = true;
+        }
+        result
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for StreamOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.file.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.file.flush();
+        self.check(result)
+    }
+}
+
+/// Compresses or decompresses a single file to `out`.
+///
+/// Named pipes and devices are opened and written as they are, without a temp file, overwrite checks,
+/// or the line for the file on stdout. Decompressed replays are written to them as they are rebuilt.
+/// Exits with [`EXIT_READER_CLOSED`] and no error if the reader closes the pipe early.
+fn output_cmd(options: &Options, input_path: &str, out: &std::path::Path) -> ! {
+    if std::path::Path::new(input_path).is_dir() {
+        eprintln!("Error: '-o' requires a single file. Use --out-dir for directories");
+        std::process::exit(1);
+    }
+    let (data, should_compress) = read_single_input(options, input_path);
+    let action = if should_compress { "compressed" } else { "decompressed" };
+
+    if !is_stream_output(out) {
+        confirm_output(options, input_path, out);
+        let output = convert_single_input(options, input_path, &data, should_compress);
+        if let Err(e) = atomic_write_with_suffix(out, &output, &options.temp_suffix) {
+            eprintln!("Error writing {}: {}", display_escaped(&out), e);
+            std::process::exit(1);
+        }
+        if options.log.prints_files() { println!("{} {} to {}", action, input_path, display_escaped(&out)); }
+        std::process::exit(0);
+    }
+
+    let file = match std::fs::OpenOptions::new().write(true).open(out) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Error opening {}: {}", display_escaped(&out), e);
+            std::process::exit(1);
+        }
+    };
+    write_stream(options, input_path, &data, should_compress, file, &display_escaped(&out).to_string());
+    std::process::exit(0);
+}
+
+/// Compresses or decompresses `data` to a stream as it is rebuilt. Exits if writing fails,
+/// with [`EXIT_READER_CLOSED`] and no error if the reader closed the pipe.
+fn write_stream(options: &Options, input_path: &str, data: &[u8], should_compress: bool, out: impl std::io::Write, out_name: &str) {
+    use std::io::Write;
+
+    let mut stream = StreamOutput { file: out, reader_closed: false };
+    let result = if should_compress {
+        let output = convert_single_input(options, input_path, data, should_compress);
+        stream.write_all(&output).and_then(|_| stream.flush()).map_err(|e| e.to_string())
+    } else {
+        match options_decompressor(options) {
+            Some(mut d) => decompress_to_sink(&mut d, data, &mut stream).map(|_| ()).map_err(|e| e.to_string()),
+            None => Err(TargetPathError::ZstdInitError.to_string()),
+        }
+    };
+
+    match result {
+        Ok(()) => {}
+        Err(_) if stream.reader_closed => std::process::exit(EXIT_READER_CLOSED),
+        Err(e) => {
+            eprintln!("Error writing {} to {}: {}", input_path, out_name, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A file descriptor on Unix or handle on Windows, opened by the parent process and passed with
+/// `--input-fd` and `--output-fd` or `--input-handle` and `--output-handle`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Inherited {
+    flag: &'static str,
+    number: u64,
+}
+
+impl std::fmt::Display for Inherited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if cfg!(windows) { write!(f, "handle {}", self.number) } else { write!(f, "fd {}", self.number) }
+    }
+}
+
+/// Removes `--<name>-fd N` on Unix or `--<name>-handle N` on Windows. Exits if N is not a number,
+/// or if the flag for the other platform is given.
+fn take_inherited(arg_strings: &mut Vec<String>, fd_flag: &'static str, handle_flag: &'static str) -> Option<Inherited> {
+    let (flag, other, expected) = match cfg!(windows) {
+        true => (handle_flag, fd_flag, "a handle"),
+        false => (fd_flag, handle_flag, "a file descriptor"),
+    };
+    if arg_strings.iter().any(|a| a == other) {
+        eprintln!("Error: '{}' is not supported on this platform. Use '{}'", other, flag);
+        std::process::exit(1);
+    }
+    let value = take_value(arg_strings, &[flag], expected)?;
+    match value.parse() {
+        Ok(number) => Some(Inherited { flag, number }),
+        Err(_) => {
+            eprintln!("Error: '{}' requires {}", flag, expected);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Wraps an inherited descriptor in a `File`, checking that it is open for reading or writing. Exits if not.
+fn open_inherited(inherited: Inherited, write: bool) -> std::fs::File {
+    use std::io::{Read, Write};
+
+    let file = inherited_file(inherited);
+    // empty reads and writes still fail on descriptors not opened for them
+    let checked = if write { (&file).write(&[]) } else { (&file).read(&mut []) };
+    if let Err(e) = checked {
+        eprintln!("Error: {} is not open for {}: {}", inherited, if write { "writing" } else { "reading" }, e);
+        std::process::exit(1);
+    }
+    file
+}
+
+#[cfg(unix)]
+fn inherited_file(inherited: Inherited) -> std::fs::File {
+    use std::os::fd::FromRawFd;
+
+    let fd = match std::os::fd::RawFd::try_from(inherited.number) {
+        Ok(fd) if fd > 2 => fd,
+        _ => {
+            eprintln!("Error: '{}' requires a descriptor above 2. 0 to 2 are stdin, stdout and stderr", inherited.flag);
+            std::process::exit(1);
+        }
+    };
+    if std::fs::metadata(format!("/dev/fd/{}", fd)).is_err() {
+        eprintln!("Error: {} is not open. The parent process must open it and leave it open for slpz", inherited);
+        std::process::exit(1);
+    }
+    // SAFETY: the descriptor is open, and was opened by the parent process, as slpz opens no files before
+    // parsing its arguments. It is not stdin, stdout or stderr, which std also uses. inherited_cmd checks
+    // that the input and output are different descriptors, so only this File closes it.
+    unsafe { std::fs::File::from_raw_fd(fd) }
+}
+
+#[cfg(windows)]
+fn inherited_file(inherited: Inherited) -> std::fs::File {
+    use std::os::windows::io::{AsRawHandle, BorrowedHandle, RawHandle};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetHandleInformation(handle: RawHandle, flags: *mut u32) -> i32;
+    }
+
+    // pseudo handles such as the current process are negative
+    let handle = match isize::try_from(inherited.number) {
+        Ok(n) if n > 0 => n as RawHandle,
+        _ => {
+            eprintln!("Error: '{}' requires a handle number above 0", inherited.flag);
+            std::process::exit(1);
+        }
+    };
+    let stdio = [std::io::stdin().as_raw_handle(), std::io::stdout().as_raw_handle(), std::io::stderr().as_raw_handle()];
+    if stdio.contains(&handle) {
+        eprintln!("Error: '{}' requires a handle other than stdin, stdout and stderr", inherited.flag);
+        std::process::exit(1);
+    }
+    let mut flags = 0;
+    // SAFETY: GetHandleInformation only looks the value up in the process's handle table, and fails if it is not
+    // an open handle. `flags` is a valid u32 to write to.
+    if unsafe { GetHandleInformation(handle, &mut flags) } == 0 {
+        eprintln!("Error: {} is not open. The parent process must open it as inheritable", inherited);
+        std::process::exit(1);
+    }
+    // SAFETY: the handle is open, and is not one of the stdio handles std uses. It is only borrowed to duplicate it,
+    // so the File owns and closes the duplicate, and the inherited handle is left open.
+    let borrowed = unsafe { BorrowedHandle::borrow_raw(handle) };
+    match borrowed.try_clone_to_owned() {
+        Ok(owned) => std::fs::File::from(owned),
+        Err(e) => {
+            eprintln!("Error: {} could not be duplicated: {}", inherited, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn inherited_file(inherited: Inherited) -> std::fs::File {
+    eprintln!("Error: '{}' is not supported on this platform", inherited.flag);
+    std::process::exit(1);
+}
+
+/// Compresses or decompresses a single input to a descriptor passed by the parent process, or from one.
+///
+/// Without an output descriptor the output goes to stdout. Nothing is derived from paths: the output is
+/// written as it is, without a temp file or extension. Inputs given as paths are still removed with `--rm`
+/// once the output is written.
+fn inherited_cmd(mut arg_strings: Vec<String>, input_fd: Option<Inherited>, output_fd: Option<Inherited>) -> ! {
+    let input_path = match input_fd {
+        Some(_) => None,
+        None => match arg_strings.pop() {
+            Some(p) => Some(p),
+            None => {
+                eprintln!("{}", HELP);
+                std::process::exit(1);
+            }
+        },
+    };
+    let given = input_fd.or(output_fd).unwrap();
+    if input_fd.is_some() && input_fd.map(|i| i.number) == output_fd.map(|o| o.number) {
+        eprintln!("Error: the input and output must be different, not both {}", given);
+        std::process::exit(1);
+    }
+    let mut conflicting = vec!["-o", "--output", "--mime-header", "--out-dir", "--output-dir", "-n", "--null-output"];
+    if output_fd.is_some() { conflicting.extend(["-c", "--stdout"]) }
+    if let Some(flag) = arg_strings.iter().find(|a| conflicting.contains(&a.as_str())) {
+        eprintln!("Error: '{}' cannot be used with '{}'", given.flag, flag);
+        std::process::exit(1);
+    }
+    take_flag(&mut arg_strings, &["-c", "--stdout"]);
+    let options = parse_options(&arg_strings);
+
+    let (input_name, data) = match (input_fd, &input_path) {
+        (Some(input_fd), _) => {
+            use std::io::Read;
+
+            let mut data = Vec::new();
+            if let Err(e) = open_inherited(input_fd, false).read_to_end(&mut data) {
+                eprintln!("Error reading {}: {}", input_fd, e);
+                std::process::exit(1);
+            }
+            (input_fd.to_string(), data)
+        }
+        (None, Some(path)) if std::path::Path::new(path).is_dir() => {
+            eprintln!("Error: '{}' requires a single file", given.flag);
+            std::process::exit(1);
+        }
+        (None, Some(path)) => match std::fs::read(path) {
+            Ok(data) => (path.clone(), data),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => unreachable!(),
+    };
+    let should_compress = single_input_compresses(&options, &input_name, &data);
+
+    let Some(output_fd) = output_fd else {
+        write_stream(&options, &input_name, &data, should_compress, std::io::stdout().lock(), "stdout");
+        std::process::exit(0);
+    };
+    let out_name = output_fd.to_string();
+    write_stream(&options, &input_name, &data, should_compress, open_inherited(output_fd, true), &out_name);
+    let action = if should_compress { "compressed" } else { "decompressed" };
+    if options.log.prints_files() { println!("{} {} to {}", action, input_name, out_name); }
+    if let (Some(path), false) = (input_path, options.keep) {
+        remove_source(&options, std::path::Path::new(&path), &out_name);
+    }
+    std::process::exit(0);
+}
+
+/// Processes paths read from stdin as they arrive, until stdin closes.
+fn stdin_filenames_cmd(mut arg_strings: Vec<String>) -> ! {
+    let stats = take_flag(&mut arg_strings, &["--stats"]);
+    let options = parse_options(&arg_strings);
+    let compress = match options.compress {
+        Some(c) => c,
+        None => {
+            eprintln!("Error: '--stdin-filenames' requires either '-x' or '-d'");
+            std::process::exit(1);
+        }
+    };
+    // progress bars for several files at once would overwrite each other
+    let options = Options { progress: false, ..options };
+    if options.log == LogLevel::Verbose { println!("options: {}", options); }
+
+    // as many threads as target_path uses, with room for a path waiting for each
+    let threads = if options.threading { 8 } else { 1 };
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<std::path::PathBuf>(threads);
+    let receiver = std::sync::Mutex::new(receiver);
+    let result = std::sync::Mutex::new(BatchResult::new(compress));
+    let stopped = std::sync::atomic::AtomicBool::new(false);
+
+    let start = std::time::Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let path = match receiver.lock().unwrap().recv() {
+                    Ok(path) => path,
+                    Err(_) => break,
+                };
+                if stopped.load(std::sync::atomic::Ordering::Relaxed) { continue }
+                let file_result = target_path(&options, &path, None).unwrap_or_else(|e| {
+                    print_target_path_error(e, &path);
+                    BatchResult { failed: vec![path], ..BatchResult::new(compress) }
+                });
+                if !file_result.spot_check_failed.is_empty() {
+                    stopped.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                result.lock().unwrap().merge(file_result);
+            });
+        }
+
+        let mut stdin = std::io::stdin().lock();
+        while !stopped.load(std::sync::atomic::Ordering::Relaxed) {
+            match read_path(&mut stdin) {
+                // such as the temp files and outputs slpz writes, when paths come from a file watcher
+                Ok(Some(path)) if options.extensions.direction(&path) != Some(compress) => continue,
+                Ok(Some(path)) => sender.send(path).expect("Sending failed"),
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Error: failed to read paths from stdin: {}", e);
+                    break;
+                }
+            }
+        }
+        drop(sender);
+    });
+
+    let result = result.into_inner().unwrap();
+    if stats || (options.log.prints_summary() && std::io::IsTerminal::is_terminal(&std::io::stderr())) {
+        eprintln!("{}", result.summary_with_time(start.elapsed()));
+    }
+    if !result.spot_check_failed.is_empty() {
+        eprintln!("Error: stopped after {} failed spot check(s). Later files were not processed", result.spot_check_failed.len());
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Reads the next non-empty path ending in a newline or NUL byte, or at the end of the input.
+/// Returns None at the end of the input.
+fn read_path(reader: &mut impl std::io::BufRead) -> std::io::Result<Option<std::path::PathBuf>> {
+    let mut bytes = Vec::new();
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if buf.is_empty() {
+            if bytes.is_empty() { return Ok(None) }
+            break;
+        }
+        match buf.iter().position(|&b| b == b'\n' || b == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&buf[..end]);
+                reader.consume(end + 1);
+                if !bytes.is_empty() { break }
+            }
+            None => {
+                let len = buf.len();
+                bytes.extend_from_slice(buf);
+                reader.consume(len);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    let path = std::path::PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(&bytes));
+    #[cfg(not(unix))]
+    let path = std::path::PathBuf::from(String::from_utf8_lossy(&bytes).into_owned());
+    Ok(Some(path))
+}
+
+/// Removes a single input once its output is written, as [`target_path`] does with [`Options::keep`] false.
+fn remove_source(options: &Options, path: &std::path::Path, out_name: &str) {
+    let removed = match options.removal {
+        RemovalMode::Trash => trash_file(path).map(|_| ()),
+        RemovalMode::Permanent => std::fs::remove_file(path),
+    };
+    match removed {
+        Ok(()) if !options.log.prints_files() => {}
+        Ok(()) if options.removal == RemovalMode::Trash => println!("moved {} to the trash", display_escaped(&path)),
+        Ok(()) => println!("removed {}", display_escaped(&path)),
+        Err(e) => {
+            eprintln!("Error: cannot remove source {}: {}. {} was written, so both exist", display_escaped(&path), e, out_name);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exits if `out` exists and should not be overwritten, asking first with `--interactive`.
+fn confirm_output(options: &Options, input: &str, out: &std::path::Path) {
+    if !out.exists() { return }
+    match options.confirm_overwrite {
+        Some(ref confirm) => if let Err(e) = confirm.run(out) {
+            if options.log.prints_files() { println!("skipped {} ({})", input, e); }
+            std::process::exit(1);
+        },
+        None if options.on_conflict == OnConflict::Skip => {
+            eprintln!("Error: {} exists. Pass --force to overwrite it, or -o to write elsewhere", display_escaped(&out));
+            std::process::exit(1);
+        }
+        None => (),
+    }
+}
+
+fn original_size_cmd(input_path: &str) -> ! {
+    let data = match std::fs::read(input_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    match slpz_original_size(&data) {
+        Ok(size) => println!("{}", size),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Prints the supported format versions, then the version of the file if given.
+/// Exits with 1 if the file is not an slpz file this program reads.
+fn format_version_cmd(input_path: Option<&str>) -> ! {
+    println!(
+        "slpz {} reads format versions {} to {} and writes version {}",
+        CRATE_VERSION, MIN_SUPPORTED_FORMAT_VERSION, SLPZ_FORMAT_VERSION, SLPZ_FORMAT_VERSION,
+    );
+    let Some(input_path) = input_path else { std::process::exit(0) };
+
+    let data = match std::fs::read(input_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    match format_version(&data) {
+        Some(version) if is_slpz(&data) => println!("{}: format version {}", input_path, version),
+        Some(version) => {
+            println!("{}: format version {} (not supported)", input_path, version);
+            std::process::exit(1);
+        }
+        None => {
+            println!("{}: not an slpz file", input_path);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+/// The header lines written by `--mime-header`.
+fn http_header(mime_type: &str, content_length: usize) -> String {
+    format!("Content-Type: {}\r\nContent-Length: {}\r\n\r\n", mime_type, content_length)
+}
+
+/// Returns the value following the flag at `i`, advancing `i`. Exits if there is none.
+fn arg_value<'a>(arg_strings: &'a [String], i: &mut usize, expected: &str) -> &'a str {
+    let flag = &arg_strings[*i];
+    *i += 1;
+    match arg_strings.get(*i) {
+        Some(v) => v,
+        None => {
+            eprintln!("Error: '{}' requires {}", flag, expected);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn profiles_cmd() -> ! {
+    for p in Profile::ALL {
+        println!("{:<10} {}", p.name(), p.description());
+    }
+    std::process::exit(0);
+}
+
+fn print_default_config_cmd(mut arg_strings: Vec<String>) -> ! {
+    let format = take_value(&mut arg_strings, &["--format"], "'toml' or 'json'");
+    if let Some(a) = arg_strings.first() {
+        eprintln!("Error: unknown argument '{}'", a);
+        std::process::exit(1);
+    }
+    match format.as_deref() {
+        None | Some("toml") => print!("{}", Options::DEFAULT.to_toml()),
+        Some("json") => println!("{}", Options::DEFAULT.to_json()),
+        Some(f) => {
+            eprintln!("Error: unknown config format '{}'. Expected 'toml' or 'json'", f);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+fn analyze_cmd(mut arg_strings: Vec<String>) -> ! {
+    let mut level = Options::from_env().level;
+    let mut json = false;
+
+    let input_path = match arg_strings.pop() {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    for a in arg_strings.iter() {
+        match a.as_ref() {
+            "--fast" => level = Profile::Fast.level(),
+            "--small" => level = Profile::Small.level(),
+            "--json" => json = true,
+            a => eprintln!("unknown argument '{}'", a),
+        }
+    }
+
+    let slp = match std::fs::read(&input_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut compressor = match Compressor::new(level) {
+        Some(c) => c,
+        None => {
+            eprintln!("Error: zstd initiation failed");
+            std::process::exit(1);
+        }
+    };
+
+    let report = match analyze(&mut compressor, &slp) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error analyzing {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print_analysis_table(&report);
+        if !report.unknown_events.is_empty() {
+            let unknown = report.unknown_events.iter().map(|c| format!("0x{:02X}", c)).collect::<Vec<_>>();
+            println!("warning: unknown events {}", unknown.join(", "));
+        }
+    }
+
+    std::process::exit(0);
+}
+
+fn info_cmd(mut arg_strings: Vec<String>) -> ! {
+    let input_path = match arg_strings.pop() {
+        Some(p) if arg_strings.is_empty() => p,
+        _ => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    let data = match std::fs::read(&input_path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    if is_slpz(&data) {
+        let header = match SlpzHeader::parse(&data) {
+            Ok(h) => h,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", &input_path, e);
+                std::process::exit(1);
+            }
+        };
+        println!("format:           slpz");
+        println!("version:          {}", header.version);
+        println!("flags:            0x{:08X}", header.flags);
+        match header.level {
+            Some(level) => println!("level:            {}", level),
+            None => println!("level:            unknown"),
+        }
+        println!("size:             {}", data.len());
+        println!("metadata size:    {}", header.compressed_events_offset - header.metadata_offset);
+        println!("compressed size:  {}", data.len() - header.compressed_events_offset);
+        println!("chunks:           {}", header.chunk_count);
+        if let Ok(sizes) = slpz_event_sizes(&data) {
+            let sizes = (0..=255u8)
+                .filter(|&command| sizes[command as usize] != 0)
+                .map(|command| format!("0x{:02X}={}", command, sizes[command as usize]))
+                .collect::<Vec<_>>();
+            println!("event sizes:      {}", sizes.join(" "));
+        }
+        if let Ok(size) = slpz_original_size(&data) {
+            println!("original size:    {}", size);
+        }
+    } else if is_slp(&data) {
+        println!("format:           slp");
+        println!("size:             {}", data.len());
+        if let Ok(counts) = event_counts(&data) {
+            println!("events:           {}", counts.iter().map(|&c| c as u64).sum::<u64>());
+        }
+    }
+
+    if let Ok(c) = completeness(&data) {
+        match c.game_end_method {
+            Some(GAME_END_NO_CONTEST) => println!("game end:         {} (no contest)", GAME_END_NO_CONTEST),
+            Some(method) => println!("game end:         {}", method),
+            None => println!("game end:         none"),
+        }
+        println!("complete:         {}", if c.is_complete() { "yes" } else { "no" });
+        if !c.raw_len_matches { println!("raw length:       does not match the events"); }
+    }
+
+    if let Some(Ok(metadata)) = read_metadata(&data).map(Metadata::parse) {
+        if let Some(start) = metadata.start_at() { println!("start:            {}", start); }
+        if let Some(frame) = metadata.last_frame() { println!("last frame:       {}", frame); }
+        if let Some(played_on) = metadata.played_on() { println!("played on:        {}", played_on); }
+        if let Some(nick) = metadata.console_nick() { println!("console:          {}", nick); }
+        for player in metadata.players() {
+            let characters = player.characters.iter()
+                .map(|&(c, _)| tables::character_name(c).unwrap_or("unknown"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let name = match (player.name, player.connect_code) {
+                (Some(name), Some(code)) => format!("{} ({}) ", name, code),
+                (Some(name), None) | (None, Some(name)) => format!("{} ", name),
+                (None, None) => String::new(),
+            };
+            println!("port {}:           {}{}", player.port + 1, name, characters);
+        }
+    }
+
+    match replay_id(&data) {
+        Ok(id) => println!("replay id:        {}", id.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        Err(e) => {
+            eprintln!("Error reading {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    }
+
+    std::process::exit(0);
+}
+
+/// Validates every replay in a path without writing anything. Exits with 1 if any file is corrupt.
+fn check_cmd(mut arg_strings: Vec<String>) -> ! {
+    let mut options = Options { recursive: true, ..Options::DEFAULT };
+    let mut json = false;
+    let mut verbose = false;
+
+    let input_path = match arg_strings.pop() {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    for a in arg_strings.iter() {
+        match a.as_ref() {
+            "--json" => json = true,
+            "-v" | "--verbose" => verbose = true,
+            "--include-hidden" => options.skip_hidden = false,
+            a => eprintln!("unknown argument '{}'", a),
+        }
+    }
+
+    let results = match check_path(&options, std::path::Path::new(&input_path)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error: {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    // category name -> files with that issue, in path order
+    let mut categories: Vec<(&'static str, Vec<&std::path::Path>)> = Vec::new();
+    for (path, report) in results.iter() {
+        for issue in report.issues.iter() {
+            match categories.iter_mut().find(|(name, _)| *name == issue.name()) {
+                Some((_, files)) => files.push(path),
+                None => categories.push((issue.name(), vec![path])),
+            }
+        }
+    }
+    categories.sort_by_key(|(name, _)| *name);
+    let corrupt = results.iter().filter(|(_, r)| !r.is_ok()).count();
+
+    if json {
+        let categories = categories.iter()
+            .map(|(name, files)| format!(
+                "{{\"category\":\"{}\",\"count\":{},\"files\":[{}]{}}}",
+                name, files.len(),
+                files.iter().map(|f| json_string(&f.to_string_lossy())).collect::<Vec<_>>().join(","),
+                json_path_bytes(files),
+            ))
+            .collect::<Vec<_>>()
+            .join(",");
+        println!("{{\"files\":{},\"corrupt\":{},\"categories\":[{}]}}", results.len(), corrupt, categories);
+    } else {
+        for (name, files) in categories.iter() {
+            println!("{} ({})", name, files.len());
+            for f in files.iter() {
+                println!("    {}", display_escaped(&f));
+            }
+        }
+        if verbose {
+            for (path, report) in results.iter() {
+                print_issue_details(path, report);
+            }
+        }
+        println!("checked {} files, {} corrupt", results.len(), corrupt);
+    }
+
+    std::process::exit(if corrupt == 0 { 0 } else { 1 });
+}
+
+/// Prints the report of a file with issues, then the offset of each issue with the bytes around it.
+fn print_issue_details(path: &std::path::Path, report: &SlpValidationReport) {
+    if report.issues.is_empty() { return; }
+    println!("{}: {}", display_escaped(&path), report);
+    if report.issues.iter().all(|i| i.offset().is_none()) { return; }
+    let data = match std::fs::read(path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Error: {}: {}", display_escaped(&path), e);
+            return;
+        }
+    };
+
+    for issue in report.issues.iter() {
+        let offset = match issue.offset() {
+            Some(o) => o,
+            None => continue,
+        };
+        match issue {
+            SlpIssue::UndeclaredEvent { command, last_event, .. }
+                | SlpIssue::TruncatedEvent { command, last_event, .. } => {
+                print!("    {} 0x{:02X} at 0x{:X}", issue.name(), command, offset);
+                match last_event {
+                    Some(e) => println!(", after 0x{:02X} at 0x{:X}", e.command, e.offset),
+                    None => println!(", first event"),
+                }
+            }
+            SlpIssue::RawLenOutOfBounds { raw_len, .. } =>
+                println!("    {} {}, events end at 0x{:X}", issue.name(), raw_len, offset),
+            _ => println!("    {} at 0x{:X}", issue.name(), offset),
+        }
+        println!("{}", hexdump_window(&data, offset));
+    }
+}
+
+/// A 16 byte hexdump line around `offset`, with a caret under the byte at `offset`.
+fn hexdump_window(data: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(8).min(data.len().saturating_sub(16));
+    let end = (start + 16).min(data.len());
+    let bytes = &data[start..end];
+
+    let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    let ascii = bytes.iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect::<String>();
+    let mut out = format!("      {:08x}  {:<47}  |{}|", start, hex, ascii);
+    if offset < end {
+        out.push_str(&format!("\n      {:8}  {:>width$}", "", "^^", width = (offset - start) * 3 + 2));
+    }
+    out
+}
+
+/// Recompresses slpz files in place with the compression options.
+fn recompress_cmd(mut arg_strings: Vec<String>) -> ! {
+    let input_path = match arg_strings.pop() {
+        Some(p) => p,
+        None => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+    let options = parse_options(&arg_strings);
+
+    match recompress_path(&options, std::path::Path::new(&input_path)) {
+        Ok(summary) => {
+            if options.log.prints_summary() {
+                let saved = summary.bytes_before as i64 - summary.bytes_after as i64;
+                print!("recompressed {} of {} files, saved {} bytes", summary.replaced, summary.files, saved);
+                if summary.failed > 0 { print!(", {} failed", summary.failed); }
+                println!();
+            }
+            std::process::exit(if summary.failed > 0 { 1 } else { 0 });
+        }
+        Err(e) => {
+            eprintln!("Error: {}: {}", &input_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Trains a zstd dictionary on the raw sections of slp files.
+fn train_dict_cmd(arg_strings: Vec<String>) -> ! {
+    let mut output = None;
+    let mut dict_size = 112640; // zstd's default
+    let mut inputs = Vec::new();
+
+    let mut i = 0;
+    while let Some(a) = arg_strings.get(i) {
+        match a.as_ref() {
+            "-o" | "--output" => output = Some(arg_value(&arg_strings, &mut i, "a dictionary file path")),
+            "--size" => {
+                match arg_value(&arg_strings, &mut i, "a dictionary size in bytes").parse() {
+                    Ok(size) => dict_size = size,
+                    Err(_) => {
+                        eprintln!("Error: '--size' requires a dictionary size in bytes");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            a => inputs.push(a),
+        }
+        i += 1;
+    }
+
+    let output = match output {
+        Some(o) => o,
+        None => {
+            eprintln!("Error: 'train-dict' requires '--output <dict file>'");
+            std::process::exit(1);
+        }
+    };
+
+    let mut slps = Vec::with_capacity(inputs.len());
+    for path in inputs.iter() {
+        match std::fs::read(path) {
+            Ok(slp) => slps.push(slp),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let samples = slps.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let dict = match train_slpz_dictionary(&samples, dict_size) {
+        Ok(d) => d,
+        Err(DictionaryError::InvalidSample { index }) => {
+            eprintln!("Error: {} is not a valid slp file", inputs[index]);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = atomic_write(std::path::Path::new(output), &dict) {
+        eprintln!("Error writing {}: {}", output, e);
+        std::process::exit(1);
+    }
+    println!("wrote {} byte dictionary trained on {} files to {}", dict.len(), samples.len(), output);
+    std::process::exit(0);
+}
+
+/// Replaces the damaged Event Payloads event of a replay with one from a donor replay.
+/// Checks a directory against a journal written with `--journal`. Exits with 1 if anything needs a look.
+fn audit_cmd(arg_strings: Vec<String>) -> ! {
+    let mut journal_path = None;
+    let mut temp_suffix = DEFAULT_TEMP_SUFFIX.to_string();
+    let mut inputs = Vec::new();
+
+    let mut i = 0;
+    while let Some(a) = arg_strings.get(i) {
+        match a.as_ref() {
+            "--journal" => journal_path = Some(arg_value(&arg_strings, &mut i, "a journal file")),
+            "--temp-suffix" => temp_suffix = arg_value(&arg_strings, &mut i, "a suffix").to_string(),
+            a => inputs.push(a),
+        }
+        i += 1;
+    }
+
+    let (journal_path, dir) = match (journal_path, inputs.as_slice()) {
+        (Some(j), &[dir]) => (j, dir),
+        (None, _) => {
+            eprintln!("Error: 'audit' requires '--journal <journal file>'");
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    let journal = match std::fs::read(journal_path).map(|j| parse_journal(&j)) {
+        Ok(Ok(journal)) => journal,
+        Ok(Err(e)) => {
+            eprintln!("Error: {}: {}", journal_path, e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error reading {}: {}", journal_path, e);
+            std::process::exit(1);
+        }
+    };
+    if journal.truncated { println!("note: the last line of the journal was cut off, and was ignored"); }
+    if journal.unknown_lines != 0 {
+        println!("note: {} lines of the journal are not records this version of slpz knows, and were ignored", journal.unknown_lines);
+    }
+
+    let report = audit(&journal, std::path::Path::new(dir), &temp_suffix);
+    for issue in report.issues.iter() {
+        println!("{}", issue);
+    }
+    println!("audit: files={} issues={}", report.files, report.issues.len());
+    std::process::exit(if report.issues.is_empty() { 0 } else { 1 });
+}
+
+fn repair_cmd(arg_strings: Vec<String>) -> ! {
+    let mut donor_path = None;
+    let mut output = None;
+    let mut inputs = Vec::new();
+
+    let mut i = 0;
+    while let Some(a) = arg_strings.get(i) {
+        match a.as_ref() {
+            "--donor" => donor_path = Some(arg_value(&arg_strings, &mut i, "an slp file path")),
+            "-o" | "--output" => output = Some(arg_value(&arg_strings, &mut i, "an output file path")),
+            a => inputs.push(a),
+        }
+        i += 1;
+    }
+
+    let (donor_path, input_path) = match (donor_path, inputs.as_slice()) {
+        (Some(d), &[input]) => (d, input),
+        (None, _) => {
+            eprintln!("Error: 'repair' requires '--donor <slp file>'");
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("{}", HELP);
+            std::process::exit(1);
+        }
+    };
+
+    let read = |path: &str| match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let broken = read(input_path);
+    let donor = read(donor_path);
+
+    let repaired = match repair_with_donor(&broken, &donor) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error: {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let output = match output {
+        Some(o) => std::path::PathBuf::from(o),
+        None => std::path::Path::new(input_path).with_extension("repaired.slp"),
+    };
+    if let Err(e) = atomic_write(&output, &repaired) {
+        eprintln!("Error writing {}: {}", display_escaped(&output), e);
+        std::process::exit(1);
+    }
+    println!("{} -> {}", input_path, display_escaped(&output));
+    std::process::exit(0);
+}
+
+/// A `path_bytes` field listing the bytes of each path that is not UTF-8 in base64, and null for the others,
+/// since `files` only holds them lossily. Empty if every path is UTF-8.
+fn json_path_bytes(files: &[&std::path::Path]) -> String {
+    if files.iter().all(|f| f.to_str().is_some()) { return String::new() }
+    let bytes = files.iter()
+        .map(|f| match f.to_str() {
+            Some(_) => "null".to_string(),
+            None => format!("\"{}\"", base64(f.as_os_str().as_encoded_bytes())),
+        })
+        .collect::<Vec<_>>();
+    format!(",\"path_bytes\":[{}]", bytes.join(","))
+}
+
+/// Standard base64 with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn print_analysis_table(report: &AnalysisReport) {
+    println!("{:<8} {:>10} {:>12} {:>12} {:>8}", "event", "count", "raw", "compressed", "ratio");
+    println!("{:<8} {:>10} {:>12} {:>12} {:>8.2}",
+        "order", "", report.order_raw_size, report.order_compressed_size, report.order_ratio());
+    for e in report.events.iter() {
+        println!("{:<8} {:>10} {:>12} {:>12} {:>8.2}",
+            format!("0x{:02X}", e.command), e.count, e.raw_size, e.compressed_size, e.ratio());
+    }
+    println!();
+    println!("{:<8} {:>10} {:>12} {:>12} {:>8.2}",
+        "events", "", report.events_raw_size, report.events_compressed_size, report.events_ratio());
+    println!("{:<8} {:>10} {:>12} {:>12} {:>8.2}",
+        "file", "", report.slp_size, report.slpz_size, report.ratio());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn hook_commands() {
+        assert_eq!(substitute("cp {in} {out} # {ratio}", &[("{in}", "a {out}"), ("{out}", "b")]), "cp a {out} b # {ratio}");
+        assert_eq!(substitute("ü{in}", &[("{in}", "x")]), "üx");
+
+        #[cfg(unix)]
+        {
+            assert_eq!(shell_quote("it's a.slp".as_ref()), Ok("'it'\\''s a.slp'".into()));
+            assert_eq!(run_hook_command("test $(printf %s 'it'\\''s') = \"it's\"", false), Ok(()));
+            assert_eq!(run_hook_command("exit 3", false), Err(HookError::ExitStatus(Some(3))));
+            assert_eq!(run_hook_command("exit 3", true), Ok(()));
+
+            use std::os::unix::ffi::OsStrExt;
+            let latin1 = std::path::Path::new(std::ffi::OsStr::from_bytes(b"\xE9.slp"));
+            assert_eq!(shell_quote(latin1), Err(HookError::Other("\u{FFFD}.slp is not valid UTF-8 and cannot be passed to a hook".into())));
+        }
+        #[cfg(windows)]
+        {
+            assert_eq!(shell_quote("100% \"a\".slp".as_ref()), Ok("\"100\"^%\" \"\"a\"\".slp\"".into()));
+        }
+    }
+
+    #[test]
+    fn hexdump() {
+        let data = (0u8..32).collect::<Vec<u8>>();
+        assert_eq!(
+            hexdump_window(&data, 10),
+            "      00000002  02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11  |................|\n                                        ^^",
+        );
+        assert!(hexdump_window(&data, 31).starts_with("      00000010  10 11"));
+        assert!(hexdump_window(b"abc", 1).starts_with("      00000000  61 62 63"));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn url_file_names() {
+        assert_eq!(url_file_name("http://example.com/replays/Game_1.slpz?dl=1"), "Game_1.slpz");
+        assert_eq!(url_file_name("http://example.com/"), "replay");
+        assert_eq!(url_file_name("http://example.com"), "replay");
+        assert_eq!(url_file_name("http://example.com/a/.."), "replay");
+        assert_eq!(url_file_name("http://example.com/C:game.slp"), "replay");
+    }
+
+    #[test]
+    fn stdout_flags() {
+        let mut a = args("-x --mime-header --level 5");
+        assert!(take_flag(&mut a, &["--mime-header"]));
+        assert!(!take_flag(&mut a, &["-c", "--stdout"]));
+        assert_eq!(a, args("-x --level 5"));
+
+        let mut a = args("--suffix -c -x -c --exec-after --stdout");
+        assert!(take_flag(&mut a, &["-c", "--stdout"]));
+        assert_eq!(a, args("--suffix -c -x --exec-after --stdout"));
+        assert_eq!(take_value(&mut a, &["--suffix"], "an extension").as_deref(), Some("-c"));
+
+        assert_eq!(
+            http_header(SLPZ_MIME_TYPE, 1234),
+            "Content-Type: application/x-slippi-compressed\r\nContent-Length: 1234\r\n\r\n",
+        );
+    }
+
+    #[test]
+    fn profile_merging() {
+        assert_eq!(parse_options(&args("--time-budget 1.5m")).time_budget_per_file, Some(std::time::Duration::from_secs(90)));
+        let options = parse_options(&args("--profile archive --zstd-threads 4"));
+        assert_eq!(options.level, 19);
+        assert!(options.long_distance_matching);
+        assert!(options.zstd_checksum);
+        assert!(options.verify_after_compress);
+        assert_eq!(options.zstd_threads, 4);
+        assert_eq!(parse_options(&args("--memory-budget 1000000")).memory_budget, Some(1000000));
+        assert!(parse_options(&args("-n")).null_output && parse_options(&args("--null-output")).null_output);
+        assert_eq!(parse_options(&args("--segments 4")).segments, Some(4));
+        assert_eq!(parse_options(&args("--output-dir out")).output_dir, Some("out".into()));
+        assert_eq!(parse_options(&args("--temp-suffix .part")).temp_suffix, ".part");
+        assert!(!parse_options(&args("--to slp")).gzip_output);
+        assert_eq!(parse_options(&args("--lenient")).strictness, Strictness::Lenient);
+        assert_eq!(parse_options(&args("--strict --strict")).strictness, Strictness::Strict);
+        let no_overwrite = parse_options(&args("--no-overwrite")).confirm_overwrite.unwrap();
+        assert_eq!(no_overwrite.run("game.slp".as_ref()), Err(HookError::Other("game.slp exists".into())));
+        assert!(parse_options(&args("-i")).confirm_overwrite.is_some());
+        assert_eq!(parse_options(&args("")).log, LogLevel::Normal);
+        assert_eq!(parse_options(&args("--verbose")).log, LogLevel::Verbose);
+        assert_eq!(parse_options(&args("-q")).log, LogLevel::Quiet);
+        assert_eq!(parse_options(&args("-q --quiet")).log, LogLevel::Silent);
+        assert_eq!(parse_options(&args("-qq")).log, LogLevel::Silent);
+        assert_eq!(
+            parse_options(&args("--seed 7 --spot-check 1.5%")).spot_check,
+            Some(SpotCheck { percent: 1.5, seed: 7 }),
+        );
+
+        let options = parse_options(&args("--profile realtime --level 2 --checksum"));
+        assert_eq!(options.level, 2);
+        assert!(options.zstd_checksum);
+        assert_eq!(options.window_log, 18);
+    }
+
+    #[test]
+    fn clip_durations() {
+        assert_eq!(parse_duration("60s"), Some(std::time::Duration::from_secs(60)));
+        assert_eq!(parse_duration("1.5m"), Some(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_duration("90"), Some(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_options(&args("--clip-frames 3600")).clip, Some(ClipRange::Frames(3600)));
+        assert_eq!(parse_options(&args("--clip 60s")).clip.map(ClipRange::frames), Some(3600));
+    }
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+        assert_eq!(json_string("\u{1b}[31m\u{9b}"), "\"\\u001b[31m\\u009b\"");
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo\xff"), "Zm9v/w==");
+    }
+
+    #[test]
+    fn stdin_paths() {
+        let mut input = std::io::BufReader::with_capacity(4, &b"a.slp\n\nb c.slp\0long/name.slp\n\0last"[..]);
+        let mut paths = Vec::new();
+        while let Some(p) = read_path(&mut input).unwrap() { paths.push(p) }
+        assert_eq!(paths, ["a.slp", "b c.slp", "long/name.slp", "last"].map(std::path::PathBuf::from));
+    }
+}
@@ -0,0 +1,87 @@
+//! Options read from config files, and their precedence: flags, then `SLPZ_LEVEL`, then the config file.
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_config_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The options line printed with `--verbose`, for a run that writes nothing.
+fn options_line(dir: &std::path::Path, level: Option<&str>, args: &[&str]) -> String {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"));
+    command.args(["-x", "-n", "--verbose", "--settle-ms", "0"]).args(args).arg(dir.join("game.slp"));
+    // the default config path is in the test directory
+    command.env("XDG_CONFIG_HOME", dir).env("APPDATA", dir).env_remove("SLPZ_LEVEL");
+    if let Some(level) = level { command.env("SLPZ_LEVEL", level); }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap().lines().next().unwrap().to_string()
+}
+
+#[test]
+fn precedence() {
+    let dir = test_dir("precedence");
+    std::fs::write(dir.join("game.slp"), test_slp()).unwrap();
+    let config = dir.join("custom.toml");
+    std::fs::write(&config, "level = 9\nrecursive = true\n").unwrap();
+    let config = config.to_str().unwrap();
+
+    assert!(options_line(&dir, None, &[]).starts_with("options: level=3, keep=true, recursive=false"));
+    assert!(options_line(&dir, None, &["--config", config]).starts_with("options: level=9, keep=true, recursive=true"));
+    assert!(options_line(&dir, Some("5"), &["--config", config]).starts_with("options: level=5, keep=true, recursive=true"));
+    assert!(options_line(&dir, Some("5"), &["--config", config, "--level", "7"]).starts_with("options: level=7,"));
+
+    // the default path
+    std::fs::create_dir(dir.join("slpz")).unwrap();
+    std::fs::write(dir.join("slpz/config.toml"), "level = 11\n").unwrap();
+    assert!(options_line(&dir, None, &[]).starts_with("options: level=11, keep=true, recursive=false"));
+    assert!(options_line(&dir, None, &["--config", config]).starts_with("options: level=9,"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_config() {
+    let dir = test_dir("invalid");
+    std::fs::write(dir.join("game.slp"), test_slp()).unwrap();
+    std::fs::write(dir.join("bad.toml"), "level = 9\nlevle = 3\n").unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "-n", "--config"])
+        .arg(dir.join("bad.toml"))
+        .arg(dir.join("game.slp"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 2: unknown option 'levle'"));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "-n", "--config"])
+        .arg(dir.join("missing.toml"))
+        .arg(dir.join("game.slp"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot read config file"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
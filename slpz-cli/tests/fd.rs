@@ -0,0 +1,99 @@
+//! Reading and writing descriptors opened by the parent process with `--input-fd` and `--output-fd`.
+#![cfg(unix)]
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_fd_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs slpz with `args` from the shell in `dir`, which opens the descriptors given in `redirects`, such as `3>out`.
+fn slpz_with(dir: &std::path::Path, args: &str, redirects: &str) -> std::process::Output {
+    std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("\"$0\" {} {}", args, redirects))
+        .arg(env!("CARGO_BIN_EXE_slpz"))
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn output_fd() {
+    let dir = test_dir("output");
+    let slp = test_slp();
+    let slpz = slpz::compress_unchecked(&mut slpz::Compressor::new(3).unwrap(), &slp).unwrap();
+    std::fs::write(dir.join("game.slpz"), &slpz).unwrap();
+
+    let output = slpz_with(&dir, "-d --output-fd 3 game.slpz", "3>out");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "decompressed game.slpz to fd 3\n");
+    assert_eq!(std::fs::read(dir.join("out")).unwrap(), slp);
+    // no temp file or output named from the input
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    // written from the offset the parent left it at
+    let output = slpz_with(&dir, "-d --output-fd 3 game.slpz", "3>>out");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read(dir.join("out")).unwrap(), [&slp[..], &slp[..]].concat());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn input_fd() {
+    let dir = test_dir("input");
+    let slp = test_slp();
+    std::fs::write(dir.join("game.slp"), &slp).unwrap();
+
+    let output = slpz_with(&dir, "-x --input-fd 3 --output-fd 4", "3<game.slp 4>out");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let slpz = std::fs::read(dir.join("out")).unwrap();
+    assert_eq!(slpz::decompress(&mut slpz::Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+
+    // to stdout without --output-fd
+    let output = slpz_with(&dir, "-x --input-fd 3", "3<game.slp");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, slpz);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_fds() {
+    let dir = test_dir("invalid");
+    std::fs::write(dir.join("game.slp"), test_slp()).unwrap();
+
+    let failure = |args: &str, redirects: &str| {
+        let output = slpz_with(&dir, args, redirects);
+        assert_eq!(output.status.code(), Some(1));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert!(failure("-x --output-fd 7 game.slp", "").contains("fd 7 is not open"));
+    assert!(failure("-x --output-fd 3 game.slp", "3<game.slp").contains("fd 3 is not open for writing"));
+    assert!(failure("-x --input-fd 3 --output-fd 4", "3>in 4>out").contains("fd 3 is not open for reading"));
+    assert!(failure("-x --output-fd 1 game.slp", "").contains("requires a descriptor above 2"));
+    assert!(failure("-x --output-fd 3 -o out game.slp", "3>out").contains("cannot be used with '-o'"));
+    assert!(failure("-x --output-handle 3 game.slp", "").contains("not supported on this platform"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,99 @@
+//! Decompressing to a named pipe with `-o`, as Dolphin reads replays for playback.
+#![cfg(unix)]
+
+use std::io::Read;
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+/// A replay of about 1.5MB, so the pipe fills before the reader closes it.
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..300_000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+/// Returns the directory, the path of `game.slpz` in it, and the path of a named pipe in it.
+fn setup(name: &str, slp: &[u8]) -> (std::path::PathBuf, std::path::PathBuf, std::path::PathBuf) {
+    let dir = std::env::temp_dir().join(format!("slpz_fifo_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let slpz = slpz::compress_unchecked(&mut slpz::Compressor::new(3).unwrap(), slp).unwrap();
+    let input = dir.join("game.slpz");
+    std::fs::write(&input, slpz).unwrap();
+
+    let fifo = dir.join("playback.fifo");
+    assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+    (dir, input, fifo)
+}
+
+/// Runs `slpz -d <input> -o <out>`.
+fn decompress_to(input: &std::path::Path, out: &std::path::Path) -> std::process::Output {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .arg("-d")
+        .arg(input)
+        .arg("-o")
+        .arg(out)
+        .output()
+        .unwrap();
+    // if slpz exits without opening the pipe, the reader would wait for a writer forever
+    drop(std::fs::OpenOptions::new().read(true).write(true).open(out));
+    output
+}
+
+#[test]
+fn decompress_to_fifo() {
+    let slp = test_slp();
+    let (dir, input, fifo) = setup("whole", &slp);
+
+    let reader = {
+        let fifo = fifo.clone();
+        std::thread::spawn(move || {
+            let mut read = Vec::new();
+            std::fs::File::open(fifo).unwrap().read_to_end(&mut read).unwrap();
+            read
+        })
+    };
+    let output = decompress_to(&input, &fifo);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+    assert_eq!(reader.join().unwrap(), slp);
+
+    // written through, not replaced with a file
+    use std::os::unix::fs::FileTypeExt;
+    assert!(std::fs::metadata(&fifo).unwrap().file_type().is_fifo());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reader_closes_early() {
+    let slp = test_slp();
+    let (dir, input, fifo) = setup("closed", &slp);
+
+    let reader = {
+        let fifo = fifo.clone();
+        std::thread::spawn(move || {
+            let mut read = vec![0; 4096];
+            std::fs::File::open(fifo).unwrap().read_exact(&mut read).unwrap();
+            read
+        })
+    };
+    let output = decompress_to(&input, &fifo);
+    assert_eq!(reader.join().unwrap(), slp[..4096]);
+    assert_eq!(output.status.code(), Some(141));
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,76 @@
+//! The lines the slpz program prints for each file and the summary line.
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_log_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn verbose_throughput() {
+    let dir = test_dir("throughput");
+    std::fs::write(dir.join("a.slp"), test_slp()).unwrap();
+    std::fs::write(dir.join("b.slp"), test_slp()).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "--verbose", "--stats", "--settle-ms", "0"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 3, "{}", stdout);
+    assert_eq!(stdout.lines().next(), Some("options: level=3, keep=true, recursive=false, log=verbose, compress=true, settle_ms=0"));
+    for line in stdout.lines().skip(1) {
+        assert!(line.starts_with("compressed ") && line.contains(" MB/s in ") && line.ends_with(" ms)"), "{}", line);
+    }
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("summary: compressed=2 ") && stderr.contains(" seconds=") && stderr.contains(" throughput_mbs="), "{}", stderr);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recompress_failures() {
+    let dir = test_dir("recompress");
+    std::fs::write(dir.join("a.slp"), test_slp()).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "--settle-ms", "0"])
+        .arg(dir.join("a.slp"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let slpz = std::fs::read(dir.join("a.slpz")).unwrap();
+    std::fs::write(dir.join("b.slpz"), &slpz[..slpz.len() - 1]).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["recompress", "--force-recompress"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("recompressed 1 of 2 files") && stdout.trim_end().ends_with(", 1 failed"), "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error recompressing "));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
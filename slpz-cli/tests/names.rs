@@ -0,0 +1,109 @@
+//! File names with control characters and invalid UTF-8 in log lines and JSON output.
+#![cfg(unix)]
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_names_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn slpz(args: &[&std::ffi::OsStr]) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_slpz")).args(args).output().unwrap()
+}
+
+#[test]
+fn control_characters_escaped() {
+    let dir = test_dir("control");
+    std::fs::write(dir.join("two\nlines\x1b[31m.slp"), test_slp()).unwrap();
+
+    let output = slpz(&["-x".as_ref(), "--settle-ms".as_ref(), "0".as_ref(), dir.as_os_str()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, format!("compressed {}\n", dir.join("two\\nlines\\u{1b}[31m.slp").display()));
+    assert!(dir.join("two\nlines\x1b[31m.slpz").exists());
+
+    // as JSON strings
+    let truncated = dir.join("cut\x1b.slp");
+    std::fs::write(&truncated, &test_slp()[..100]).unwrap();
+    let output = slpz(&["check".as_ref(), "--json".as_ref(), dir.as_os_str()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("\"files\":[\"{}/cut\\u001b.slp\"]", dir.display())), "{}", stdout);
+    assert!(!stdout.contains("path_bytes"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// macOS and other platforms may reject names that are not UTF-8
+#[cfg(target_os = "linux")]
+#[test]
+fn invalid_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = test_dir("utf8");
+    let name = std::ffi::OsStr::from_bytes(b"bad\xff.slp");
+    std::fs::write(dir.join(name), &test_slp()[..100]).unwrap();
+    std::fs::write(dir.join("good.slp"), &test_slp()[..100]).unwrap();
+
+    let output = slpz(&["check".as_ref(), "--json".as_ref(), dir.as_os_str()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let bytes = dir.join(name).into_os_string();
+    assert!(stdout.contains(&format!(
+        "\"files\":[\"{}/bad\u{fffd}.slp\",\"{}/good.slp\"],\"path_bytes\":[\"{}\",null]",
+        dir.display(), dir.display(), base64(bytes.as_bytes()),
+    )), "{}", stdout);
+
+    let output = slpz(&["check".as_ref(), dir.as_os_str()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("    {}/bad\u{fffd}.slp\n", dir.display())), "{}", stdout);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bits = data.iter().flat_map(|b| (0..8).rev().map(move |i| b >> i & 1)).collect::<Vec<_>>();
+    while bits.len() % 6 != 0 { bits.push(0) }
+    let mut out = bits.chunks(6).map(|c| ALPHABET[c.iter().fold(0, |n, &b| n << 1 | b) as usize] as char).collect::<String>();
+    while out.len() % 4 != 0 { out.push('=') }
+    out
+}
+
+#[test]
+fn extension_mapping() {
+    let dir = test_dir("ext");
+    std::fs::write(dir.join("GAME.SLP"), test_slp()).unwrap();
+
+    let output = slpz(&["-x".as_ref(), "--ext".as_ref(), "slp=slp.zst".as_ref(), "--settle-ms".as_ref(), "0".as_ref(), dir.as_os_str()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("GAME.slp.zst").exists());
+
+    let output = slpz(&["-d".as_ref(), "--ext".as_ref(), "slp=slp.zst".as_ref(), "--settle-ms".as_ref(), "0".as_ref(), dir.as_os_str()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read(dir.join("GAME.slp")).unwrap(), test_slp());
+
+    let output = slpz(&["-x".as_ref(), "--ext".as_ref(), "slp".as_ref(), dir.as_os_str()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'--ext' requires a pair"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
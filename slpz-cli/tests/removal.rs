@@ -0,0 +1,77 @@
+//! Removing inputs with --rm and --rm-permanent.
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_removal_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// the trash is found from the environment, so is only set for the child process
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn trash() {
+    let dir = test_dir("trash");
+    let home = dir.join("home");
+    std::fs::write(dir.join("c.slp"), test_slp()).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "--rm", "--settle-ms", "0"])
+        .arg(dir.join("c.slp"))
+        .env("HOME", &home)
+        .env_remove("XDG_DATA_HOME")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.join("c.slp").exists());
+    assert!(dir.join("c.slpz").exists());
+    assert_eq!(std::fs::read(home.join(".local/share/Trash/files/c.slp")).unwrap(), test_slp());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn clip_keeps_input() {
+    let dir = test_dir("clip");
+    std::fs::write(dir.join("game.slp"), test_slp()).unwrap();
+
+    for rm in ["--rm", "--rm-permanent"] {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+            .args(["-x", "--clip-frames", "10", rm, "--settle-ms", "0"])
+            .arg(&dir)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("'--clip' cannot be used with '--rm'"));
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "--clip-frames", "10", "--settle-ms", "0"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), test_slp());
+    assert!(dir.join("game.clip.slpz").exists());
+    assert!(!dir.join("game.slpz").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,95 @@
+//! Paths fed to `slpz --stdin-filenames` through a pipe that stays open between files.
+
+use std::io::{BufRead, Read, Write};
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+fn test_slp() -> Vec<u8> {
+    let mut raw = vec![0x35, 10, 0x36, 0, 4, 0x37, 0, 4, 0x39, 0, 2];
+    raw.extend_from_slice(&[0x36, 3, 18, 0, 0]);
+    for frame in 0..1000u32 {
+        raw.push(0x37);
+        raw.extend_from_slice(&frame.to_be_bytes());
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("slpz_stdin_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn slow_pipe() {
+    let dir = test_dir("slow");
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .args(["-x", "--rm-permanent", "--stats", "--stdin-filenames"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+
+    // each file is processed while the pipe is still open
+    for name in ["a.slp", "b.slp"] {
+        let path = dir.join(name);
+        std::fs::write(&path, test_slp()).unwrap();
+        writeln!(stdin, "{}", path.display()).unwrap();
+        stdin.flush().unwrap();
+
+        line.clear();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("compressed {}\n", path.display()));
+        line.clear();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, format!("removed {}\n", path.display()));
+        assert!(dir.join(name).with_extension("slpz").exists());
+        assert!(!path.exists());
+    }
+
+    // slpz's own temp files and outputs, as inotifywait reports them, are skipped without a message
+    write!(stdin, "{}\n{}\n", dir.join("a.slpz.tmp").display(), dir.join("a.slpz").display()).unwrap();
+
+    // a missing file does not stop the ones after it, and NUL bytes also separate paths
+    let path = dir.join("c.slp");
+    std::fs::write(&path, test_slp()).unwrap();
+    write!(stdin, "{}\0{}\0", dir.join("missing.slp").display(), path.display()).unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("Error: input path '{}' not found", dir.join("missing.slp").display())), "{}", stderr);
+    assert!(stderr.contains("summary: compressed=3 skipped=0 failed=1 "), "{}", stderr);
+    line.clear();
+    stdout.read_to_string(&mut line).unwrap();
+    assert_eq!(line, format!("compressed {}\nremoved {}\n", path.display(), path.display()));
+    for name in ["a", "b", "c"] {
+        assert!(dir.join(name).with_extension("slpz").exists());
+        assert!(!dir.join(name).with_extension("slp").exists());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn requires_direction() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_slpz"))
+        .arg("--stdin-filenames")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires either '-x' or '-d'"));
+}
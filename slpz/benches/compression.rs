@@ -0,0 +1,239 @@
+//! Compression throughput on generated replays, so no replay files are needed.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slpz::*;
+
+const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+
+/// (name, frames). One, four, and eight minute games.
+const FIXTURES: [(&str, u32); 3] = [("short", 60 * 60), ("medium", 60 * 60 * 4), ("long", 60 * 60 * 8)];
+
+const LEVELS: [i32; 3] = [3, 9, 12];
+
+// (command, payload size), sized like slp 3.18
+const EVENTS: [(u8, u16); 6] = [
+    (0x36, 0x1A4), // game start
+    (0x37, 0x40),  // pre-frame update
+    (0x38, 0x54),  // post-frame update
+    (0x39, 0x02),  // game end
+    (0x3A, 0x0C),  // frame start
+    (0x3C, 0x08),  // frame bookend
+];
+
+/// Builds a two player replay. Positions move smoothly and a few bytes are noisy,
+/// which roughly matches how real replays compress.
+fn generate_replay(frames: u32) -> Vec<u8> {
+    let mut rng = 0x2545F491u32;
+    let mut next = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        rng
+    };
+
+    let mut raw = vec![0x35, 1 + 3 * EVENTS.len() as u8];
+    for (command, size) in EVENTS {
+        raw.push(command);
+        raw.extend_from_slice(&size.to_be_bytes());
+    }
+
+    let mut game_start = vec![0u8; 0x1A5];
+    game_start[0] = 0x36;
+    game_start[1..5].copy_from_slice(&[3, 18, 0, 0]);
+    game_start[0x13..0x15].copy_from_slice(&32u16.to_be_bytes());
+    for port in 0..4 {
+        game_start[0x66 + 0x24 * port] = if port < 2 { 0 } else { 3 };
+    }
+    raw.extend_from_slice(&game_start);
+
+    // slp frames start at -123
+    for frame in -123..frames as i32 - 123 {
+        let frame_bytes = frame.to_be_bytes();
+
+        let mut frame_start = [0u8; 0x0D];
+        frame_start[0] = 0x3A;
+        frame_start[1..5].copy_from_slice(&frame_bytes);
+        frame_start[5..9].copy_from_slice(&next().to_be_bytes());
+        raw.extend_from_slice(&frame_start);
+
+        for port in 0..2u8 {
+            let t = frame as f32 / 60.0 + port as f32;
+            let x = (t * 0.7).sin() * 60.0;
+            let y = (t * 1.3).cos().abs() * 30.0;
+            let action = (frame / 20).rem_euclid(40) as u16 + 14;
+
+            let mut pre = [0u8; 0x41];
+            pre[0] = 0x37;
+            pre[1..5].copy_from_slice(&frame_bytes);
+            pre[5] = port;
+            pre[7..11].copy_from_slice(&next().to_be_bytes());
+            pre[11..13].copy_from_slice(&action.to_be_bytes());
+            pre[13..17].copy_from_slice(&x.to_be_bytes());
+            pre[17..21].copy_from_slice(&y.to_be_bytes());
+            pre[25..29].copy_from_slice(&((next() % 3) as f32 - 1.0).to_be_bytes());
+            raw.extend_from_slice(&pre);
+
+            let mut post = [0u8; 0x55];
+            post[0] = 0x38;
+            post[1..5].copy_from_slice(&frame_bytes);
+            post[5] = port;
+            post[7] = if port == 0 { 2 } else { 20 };
+            post[8..10].copy_from_slice(&action.to_be_bytes());
+            post[10..14].copy_from_slice(&x.to_be_bytes());
+            post[14..18].copy_from_slice(&y.to_be_bytes());
+            post[22..26].copy_from_slice(&((frame.max(0) / 300) as f32 * 11.5).to_be_bytes());
+            post[0x21] = frame.rem_euclid(256) as u8;
+            raw.extend_from_slice(&post);
+        }
+
+        let mut bookend = [0u8; 0x09];
+        bookend[0] = 0x3C;
+        bookend[1..5].copy_from_slice(&frame_bytes);
+        bookend[5..9].copy_from_slice(&frame_bytes);
+        raw.extend_from_slice(&bookend);
+    }
+    raw.extend_from_slice(&[0x39, 2, 0]);
+
+    let mut slp = RAW_HEADER.to_vec();
+    slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    slp.extend_from_slice(&raw);
+    slp.extend_from_slice(b"U\x08metadata{}}");
+    slp
+}
+
+fn bench_compress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compress");
+    for (name, frames) in FIXTURES {
+        let slp = generate_replay(frames);
+        group.throughput(Throughput::Bytes(slp.len() as u64));
+        for level in LEVELS {
+            let mut compressor = Compressor::new(level).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, level), &slp, |b, slp| {
+                b.iter(|| compress_unchecked(&mut compressor, slp).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decompress");
+    let mut decompressor = Decompressor::new().unwrap();
+    for (name, frames) in FIXTURES {
+        let slp = generate_replay(frames);
+        group.throughput(Throughput::Bytes(slp.len() as u64));
+        for level in LEVELS {
+            let slpz = compress_unchecked(&mut Compressor::new(level).unwrap(), &slp).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, level), &slpz, |b, slpz| {
+                b.iter(|| decompress(&mut decompressor, slpz).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_reorder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reorder");
+    for (name, frames) in FIXTURES {
+        let slp = generate_replay(frames);
+        let reordered = reorder_slp_events(&slp).unwrap();
+        group.throughput(Throughput::Bytes(reordered.len() as u64));
+        group.bench_with_input(BenchmarkId::new("reorder", name), &slp, |b, slp| {
+            b.iter(|| reorder_slp_events(slp).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("count", name), &slp, |b, slp| {
+            b.iter(|| event_counts(slp).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("restore", name), &reordered, |b, reordered| {
+            b.iter(|| restore_slp_events(&slp, reordered).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Many tiny files, where creating a compressor per file dominates.
+fn bench_pool(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pool");
+    let slp = generate_replay(600);
+    group.throughput(Throughput::Bytes(slp.len() as u64));
+    group.bench_function("new_per_file", |b| {
+        b.iter(|| compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap())
+    });
+    let pool = CompressorPool::prefilled(3, 1).unwrap();
+    group.bench_function("with_compressor", |b| {
+        b.iter(|| pool.with_compressor(3, |c| compress_unchecked(c, &slp).unwrap()).unwrap())
+    });
+    group.finish();
+}
+
+/// compress_all against calling compress in a loop, on many small replays.
+fn bench_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    let replays = (1..=32).map(|i| generate_replay(300 + i * 10)).collect::<Vec<_>>();
+    group.throughput(Throughput::Bytes(replays.iter().map(|r| r.len() as u64).sum()));
+    group.bench_function("compress_loop", |b| {
+        b.iter(|| {
+            replays.iter()
+                .map(|slp| compress_unchecked(&mut Compressor::new(3).unwrap(), slp))
+                .collect::<Vec<_>>()
+        })
+    });
+    let mut compressor = Compressor::new(3).unwrap();
+    group.bench_function("compress_all", |b| {
+        b.iter(|| compress_all(&mut compressor, replays.iter().map(Vec::as_slice)))
+    });
+    group.finish();
+}
+
+/// Wall time of compressing and decompressing a single large replay in segments on a thread each.
+fn bench_segments(c: &mut Criterion) {
+    let mut group = c.benchmark_group("segments");
+    group.sample_size(10);
+    let slp = generate_replay(60 * 60 * 30);
+    group.throughput(Throughput::Bytes(slp.len() as u64));
+    for segments in [1, 2, 4, 8] {
+        let mut compressor = Compressor::new(3).unwrap();
+        compressor.set_segments(segments);
+        group.bench_with_input(BenchmarkId::new("compress", segments), &slp, |b, slp| {
+            b.iter(|| compress_unchecked(&mut compressor, slp).unwrap())
+        });
+
+        let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+        let mut decompressor = Decompressor::new().unwrap();
+        decompressor.set_threads(segments);
+        group.bench_with_input(BenchmarkId::new("decompress", segments), &slpz, |b, slpz| {
+            b.iter(|| decompress(&mut decompressor, slpz).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Decompression throughput to a sink with staging buffers from 4KB to 1MB.
+fn bench_buffer_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("buffer_size");
+    let slp = generate_replay(8 * 60 * 60);
+    let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+    group.throughput(Throughput::Bytes(slp.len() as u64));
+    for buffer_size in [4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20] {
+        let mut decompressor = Decompressor::new().unwrap();
+        decompressor.set_buffer_size(buffer_size);
+        group.bench_with_input(BenchmarkId::new("decompress_to_sink", buffer_size), &slpz, |b, slpz| {
+            b.iter(|| decompress_to_sink(&mut decompressor, slpz, &mut std::io::sink()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_compress,
+    bench_decompress,
+    bench_reorder,
+    bench_pool,
+    bench_batch,
+    bench_segments,
+    bench_buffer_size,
+);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! Compresses and decompresses every slp file in a directory, printing throughput and ratio.
+//!
+//! Usage: cargo run --release --example bench_dir -- <directory> [level]
+
+use slpz::*;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dir = match args.next() {
+        Some(d) => d,
+        None => {
+            eprintln!("Usage: bench_dir <directory> [level]");
+            std::process::exit(1);
+        }
+    };
+    let level = args.next().map(|l| l.parse().expect("level must be a number")).unwrap_or(3);
+
+    let mut replays = Vec::new();
+    for f in std::fs::read_dir(&dir).expect("could not read directory") {
+        let path = f.unwrap().path();
+        if path.extension() != Some(std::ffi::OsStr::new("slp")) { continue }
+        let slp = std::fs::read(&path).unwrap();
+        if is_slp(&slp) { replays.push(slp) }
+    }
+    if replays.is_empty() {
+        eprintln!("No slp files in {}", dir);
+        std::process::exit(1);
+    }
+
+    let mut compressor = Compressor::new(level).expect("invalid level");
+    let mut decompressor = Decompressor::new().unwrap();
+
+    let start = std::time::Instant::now();
+    let compressed = replays.iter()
+        .map(|slp| compress_unchecked(&mut compressor, slp).unwrap())
+        .collect::<Vec<_>>();
+    let compress_time = start.elapsed();
+
+    let start = std::time::Instant::now();
+    for slpz in compressed.iter() {
+        decompress(&mut decompressor, slpz).unwrap();
+    }
+    let decompress_time = start.elapsed();
+
+    let slp_size = replays.iter().map(Vec::len).sum::<usize>();
+    let slpz_size = compressed.iter().map(Vec::len).sum::<usize>();
+    let mb = slp_size as f64 / 1_000_000.0;
+
+    println!("{} replays, {:.1} MB, level {}", replays.len(), mb, level);
+    println!("compress:   {:>8.1} MB/s", mb / compress_time.as_secs_f64());
+    println!("decompress: {:>8.1} MB/s", mb / decompress_time.as_secs_f64());
+    println!("ratio:      {:>8.2}", slp_size as f64 / slpz_size as f64);
+}
@@ -0,0 +1,739 @@
+//! Reading and writing [`Options`] as config files, with [`Options::to_toml`] and `Options::from_str`.
+//!
+//! # Format
+//!
+//! Config files are a subset of TOML: `key = value` lines with `#` comments, no tables.
+//! Keys are the names of the `Options` fields. Values are booleans, integers, floats, strings
+//! or inline tables of those, such as `spot_check = { percent = 5.0, seed = 0 }`.
+//! Enums are lowercase strings, such as `log = "quiet"`. `clip` is `{ frames = N }` or `{ seconds = S }`.
+//! Paths that are not UTF-8 are written lossily.
+//!
+//! Fields missing from a file keep their default, and fields that are unset by default, such as `output_dir`,
+//! are written commented out with an example value. `temp_suffix` and the hooks cannot be set from a file.
+
+use crate::{ClipRange, ExtensionMap, LogLevel, OnConflict, Options, RemovalMode, SpotCheck, Strictness};
+
+/// Why `Options::from_str` rejected a config file. Lines are counted from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConfigError {
+    /// A line that is not a comment or a `key = value` pair this parser understands.
+    Syntax { line: usize },
+    UnknownKey { line: usize, key: String },
+    DuplicateKey { line: usize, key: String },
+    /// The value has the wrong type or is out of range for the key.
+    InvalidValue { line: usize, key: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Syntax { line } => write!(f, "line {}: expected 'key = value'", line),
+            ConfigError::UnknownKey { line, key } => write!(f, "line {}: unknown option '{}'", line, key),
+            ConfigError::DuplicateKey { line, key } => write!(f, "line {}: '{}' is set twice", line, key),
+            ConfigError::InvalidValue { line, key } => write!(f, "line {}: invalid value for '{}'", line, key),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A TOML or JSON value. Also used for [`AnalysisReport::to_json`](crate::AnalysisReport::to_json).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Table(Vec<(String, Value)>),
+    /// Only written, never parsed.
+    List(Vec<Value>),
+}
+
+/// A field of [`Options`] as it is written, with the comment above it. `value` is `Err` with an example if unset.
+struct Entry {
+    key: &'static str,
+    comment: &'static str,
+    value: Result<Value, Value>,
+}
+
+impl Options {
+    /// The options as a config file, with a comment describing each field. See the [`config`](crate::config) module.
+    ///
+    /// `options.to_toml().parse::<Options>()` gives back the same options, except `temp_suffix` and the hooks.
+    pub fn to_toml(&self) -> String {
+        let mut out = String::new();
+        for entry in entries(self) {
+            out.push_str("# ");
+            out.push_str(entry.comment);
+            out.push('\n');
+            match entry.value {
+                Ok(value) => out.push_str(&format!("{} = {}\n\n", entry.key, toml_value(&value))),
+                Err(example) => out.push_str(&format!("# {} = {}\n\n", entry.key, toml_value(&example))),
+            }
+        }
+        out.pop();
+        out
+    }
+
+    /// The options as a JSON object with the same keys and values as [`Options::to_toml`].
+    /// Fields that are unset are `null`.
+    pub fn to_json(&self) -> String {
+        let fields = entries(self).into_iter()
+            .map(|entry| match entry.value {
+                Ok(value) => format!("{}:{}", json_string(entry.key), json_value(&value)),
+                Err(_) => format!("{}:null", json_string(entry.key)),
+            })
+            .collect::<Vec<_>>();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// The fields that `Options`'s `Display` always writes, before the ones that differ from [`Options::DEFAULT`].
+const SUMMARY_KEYS: [&str; 4] = ["level", "keep", "recursive", "log"];
+
+impl std::fmt::Display for Options {
+    /// One line for logs, like `level=3, keep=true, recursive=false, log=normal`,
+    /// followed by the other fields that differ from [`Options::DEFAULT`], such as `, output_dir=compressed`.
+    /// Keys and values are as in [`Options::to_toml`], with strings unquoted if they are a single plain word.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = entries(self);
+        let defaults = entries(&Options::DEFAULT);
+        let summary = SUMMARY_KEYS.iter().filter_map(|&key| fields.iter().find(|e| e.key == key));
+        let changed = fields.iter().zip(&defaults)
+            .filter(|(e, default)| !SUMMARY_KEYS.contains(&e.key) && e.value != default.value)
+            .map(|(e, _)| e);
+        for (i, entry) in summary.chain(changed).enumerate() {
+            if i != 0 { f.write_str(", ")? }
+            match entry.value {
+                Ok(ref value) => write!(f, "{}={}", entry.key, summary_value(value))?,
+                Err(_) => write!(f, "{}=unset", entry.key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&Options> for String {
+    fn from(options: &Options) -> String { options.to_string() }
+}
+
+impl std::fmt::Debug for Options {
+    /// Every field, with values as in [`Options::to_toml`] and `None` for unset fields.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("Options");
+        for entry in entries(self) {
+            let value = entry.value.map_or("None".into(), |v| toml_value(&v));
+            s.field(entry.key, &format_args!("{}", value));
+        }
+        s.field("temp_suffix", &self.temp_suffix)
+            .field("confirm_overwrite", &self.confirm_overwrite)
+            .field("pre_hook", &self.pre_hook)
+            .field("post_hook", &self.post_hook)
+            .finish()
+    }
+}
+
+impl std::str::FromStr for Options {
+    type Err = ConfigError;
+
+    /// Parses a config file written by [`Options::to_toml`] or by hand. Missing fields are [`Options::DEFAULT`].
+    fn from_str(s: &str) -> Result<Options, ConfigError> {
+        let mut options = Options::DEFAULT;
+        let mut seen = Vec::new();
+        for (i, text) in s.lines().enumerate() {
+            let line = i + 1;
+            let mut parser = Parser { s: text, pos: 0 };
+            parser.skip_space();
+            if parser.at_end() { continue }
+
+            let key = parser.key().ok_or(ConfigError::Syntax { line })?;
+            parser.skip_space();
+            if !parser.eat('=') { return Err(ConfigError::Syntax { line }) }
+            let value = parser.value().ok_or(ConfigError::Syntax { line })?;
+            parser.skip_space();
+            if !parser.at_end() { return Err(ConfigError::Syntax { line }) }
+
+            if seen.contains(&key) { return Err(ConfigError::DuplicateKey { line, key }) }
+            set(&mut options, &key, value, line)?;
+            seen.push(key);
+        }
+        Ok(options)
+    }
+}
+
+fn entries(o: &Options) -> Vec<Entry> {
+    use Value::*;
+
+    let unset_or = |value: Option<Value>, example: Value| value.ok_or(example);
+    let path = |p: &Option<std::path::PathBuf>| p.as_ref().map(|p| Str(p.to_string_lossy().into_owned()));
+    let int = |n: u64| Int(n.min(i64::MAX as u64) as i64);
+
+    vec![
+        Entry { key: "keep", comment: "Keep inputs after writing their outputs.", value: Ok(Bool(o.keep)) },
+        Entry {
+            key: "compress",
+            comment: "Compress (true) or decompress (false). Unset decides from the input paths.",
+            value: unset_or(o.compress.map(Bool), Bool(true)),
+        },
+        Entry { key: "recursive", comment: "Process directories recursively.", value: Ok(Bool(o.recursive)) },
+        Entry {
+            key: "skip_hidden",
+            comment: "Skip files and directories whose names start with '.' when processing a directory.",
+            value: Ok(Bool(o.skip_hidden)),
+        },
+        Entry {
+            key: "follow_symlinks",
+            comment: "Follow symlinks when processing a directory. Otherwise they are skipped.",
+            value: Ok(Bool(o.follow_symlinks)),
+        },
+        Entry { key: "threading", comment: "Process files on several threads.", value: Ok(Bool(o.threading)) },
+        Entry { key: "level", comment: "zstd compression level.", value: Ok(Int(o.level as i64)) },
+        Entry {
+            key: "log",
+            comment: "\"verbose\", \"normal\", \"quiet\" or \"silent\". Errors are always printed.",
+            value: Ok(Str(log_name(o.log).into())),
+        },
+        Entry {
+            key: "long_distance_matching",
+            comment: "Enable zstd long distance matching.",
+            value: Ok(Bool(o.long_distance_matching)),
+        },
+        Entry { key: "zstd_checksum", comment: "Add a checksum to the zstd frame.", value: Ok(Bool(o.zstd_checksum)) },
+        Entry {
+            key: "window_log",
+            comment: "zstd window log. Zero uses zstd's default for the level.",
+            value: Ok(Int(o.window_log as i64)),
+        },
+        Entry {
+            key: "zstd_threads",
+            comment: "zstd worker threads for each file. Zero compresses on the calling thread.",
+            value: Ok(Int(o.zstd_threads as i64)),
+        },
+        Entry {
+            key: "verify_after_compress",
+            comment: "Decompress each output and check it matches the input before writing it.",
+            value: Ok(Bool(o.verify_after_compress)),
+        },
+        Entry {
+            key: "spot_check",
+            comment: "Check this percent of outputs, chosen from the seed, before writing them.",
+            value: unset_or(
+                o.spot_check.map(|s| Table(vec![("percent".into(), Float(s.percent)), ("seed".into(), int(s.seed))])),
+                Table(vec![("percent".into(), Float(5.0)), ("seed".into(), Int(0))]),
+            ),
+        },
+        Entry {
+            key: "strip_trailer",
+            comment: "Drop bytes after the end of the slp file's UBJSON object.",
+            value: Ok(Bool(o.strip_trailer)),
+        },
+        Entry {
+            key: "strictness",
+            comment: "\"lenient\", \"normal\" or \"strict\": which slp files needing repair are rejected.",
+            value: Ok(Str(strictness_name(o.strictness).into())),
+        },
+        Entry {
+            key: "clip",
+            comment: "Only compress the start of each replay, in frames or seconds.",
+            value: unset_or(
+                o.clip.map(|c| match c {
+                    ClipRange::Frames(frames) => Table(vec![("frames".into(), Int(frames as i64))]),
+                    ClipRange::Duration(d) => Table(vec![("seconds".into(), Float(d.as_secs_f64()))]),
+                }),
+                Table(vec![("frames".into(), Int(3600))]),
+            ),
+        },
+        Entry {
+            key: "output_extension",
+            comment: "Overrides the output extension.",
+            value: unset_or(
+                o.output_extension.as_ref().map(|e| Str(e.to_string_lossy().into_owned())),
+                Str("slpz".into()),
+            ),
+        },
+        Entry {
+            key: "extensions",
+            comment: "Comma separated slp=slpz extension pairs, checked in order before slp=slpz. Extensions ignore case.",
+            value: unset_or(
+                Some(o.extensions.pairs().map(|(slp, slpz)| format!("{}={}", slp, slpz)).collect::<Vec<_>>().join(", "))
+                    .filter(|pairs| !pairs.is_empty())
+                    .map(Str),
+                Str("slp.zst=slpz".into()),
+            ),
+        },
+        Entry { key: "in_place", comment: "Allow outputs to replace their inputs.", value: Ok(Bool(o.in_place)) },
+        Entry {
+            key: "settle_ms",
+            comment: "Skip files modified within this many milliseconds. Zero disables the check.",
+            value: Ok(int(o.settle_ms)),
+        },
+        Entry {
+            key: "skip_already_compressed",
+            comment: "When compressing, skip files that are already slpz files.",
+            value: Ok(Bool(o.skip_already_compressed)),
+        },
+        Entry {
+            key: "skip_already_decompressed",
+            comment: "When decompressing, skip files that are already slp files.",
+            value: Ok(Bool(o.skip_already_decompressed)),
+        },
+        Entry {
+            key: "skip_if_unchanged",
+            comment: "When compressing, skip files whose output already holds the same replay.",
+            value: Ok(Bool(o.skip_if_unchanged)),
+        },
+        Entry {
+            key: "only_complete",
+            comment: "Only process replays that ended normally (true), or only ones that did not, such as crashes and quit outs (false).",
+            value: unset_or(o.only_complete.map(Bool), Bool(true)),
+        },
+        Entry {
+            key: "on_conflict",
+            comment: "\"skip\", \"overwrite\" or \"rename\": what to do when decompressing over a different replay.",
+            value: Ok(Str(on_conflict_name(o.on_conflict).into())),
+        },
+        Entry {
+            key: "dedupe_names",
+            comment: "Add the replay ID to outputs that would be written to the same path.",
+            value: Ok(Bool(o.dedupe_names)),
+        },
+        Entry {
+            key: "force_recompress",
+            comment: "Replace recompressed files even if they do not get smaller.",
+            value: Ok(Bool(o.force_recompress)),
+        },
+        Entry {
+            key: "output_dir",
+            comment: "Write outputs to this directory instead of next to their inputs.",
+            value: unset_or(path(&o.output_dir), Str("compressed".into())),
+        },
+        Entry {
+            key: "chunk_bytes",
+            comment: "Bytes of events in each chunk of the chunk table.",
+            value: Ok(int(o.chunk_bytes as u64)),
+        },
+        Entry {
+            key: "segments",
+            comment: "Compress each file in this many segments, on a thread each.",
+            value: unset_or(o.segments.map(|n| int(n as u64)), Int(4)),
+        },
+        Entry {
+            key: "progress",
+            comment: "Show a progress bar on stderr for a single file. Requires the 'progress' feature.",
+            value: Ok(Bool(o.progress)),
+        },
+        Entry {
+            key: "launcher_names",
+            comment: "Name decompressed replays like the Slippi Launcher does.",
+            value: Ok(Bool(o.launcher_names)),
+        },
+        Entry {
+            key: "gzip_output",
+            comment: "When decompressing, write gzipped .slp.gz files. Requires the 'gzip' feature.",
+            value: Ok(Bool(o.gzip_output)),
+        },
+        Entry {
+            key: "removal",
+            comment: "\"trash\" or \"permanent\": how inputs are removed when keep is false.",
+            value: Ok(Str(removal_name(o.removal).into())),
+        },
+        Entry {
+            key: "buffer_size",
+            comment: "Size in bytes of the blocks that files are read and written in.",
+            value: Ok(int(o.buffer_size as u64)),
+        },
+        Entry {
+            key: "memory_budget",
+            comment: "Maximum bytes of zstd memory to use at once, by processing fewer files in parallel.",
+            value: unset_or(o.memory_budget.map(int), Int(1 << 30)),
+        },
+        Entry {
+            key: "journal",
+            comment: "Append a record of each stage of each file to this file, for 'slpz audit'.",
+            value: unset_or(path(&o.journal), Str("slpz.journal".into())),
+        },
+        Entry {
+            key: "null_output",
+            comment: "Process files without writing outputs or removing inputs, for benchmarking.",
+            value: Ok(Bool(o.null_output)),
+        },
+        Entry {
+            key: "time_budget_per_file",
+            comment: "Seconds. Compress files that would take longer at a faster level instead.",
+            value: unset_or(o.time_budget_per_file.map(|d| Float(d.as_secs_f64())), Float(10.0)),
+        },
+    ]
+}
+
+fn set(o: &mut Options, key: &str, value: Value, line: usize) -> Result<(), ConfigError> {
+    let invalid = || ConfigError::InvalidValue { line, key: key.into() };
+    let boolean = |v: &Value| match *v { Value::Bool(b) => Ok(b), _ => Err(invalid()) };
+    let string = |v: &Value| match v { Value::Str(s) => Ok(s.clone()), _ => Err(invalid()) };
+    fn int<T: TryFrom<i64>>(v: &Value) -> Option<T> {
+        match *v { Value::Int(n) => n.try_into().ok(), _ => None }
+    }
+    fn float(v: &Value) -> Option<f64> {
+        match *v { Value::Float(f) => Some(f), Value::Int(n) => Some(n as f64), _ => None }
+    }
+    let named = |v: &Value, names: &[&str]| names.iter().position(|&n| Value::Str(n.into()) == *v).ok_or(invalid());
+
+    match key {
+        "keep" => o.keep = boolean(&value)?,
+        "compress" => o.compress = Some(boolean(&value)?),
+        "recursive" => o.recursive = boolean(&value)?,
+        "skip_hidden" => o.skip_hidden = boolean(&value)?,
+        "follow_symlinks" => o.follow_symlinks = boolean(&value)?,
+        "threading" => o.threading = boolean(&value)?,
+        "level" => o.level = int(&value).filter(|&l| crate::is_valid_level(l)).ok_or(invalid())?,
+        "log" => o.log = LOG_LEVELS[named(&value, &LOG_LEVELS.map(log_name))?],
+        "long_distance_matching" => o.long_distance_matching = boolean(&value)?,
+        "zstd_checksum" => o.zstd_checksum = boolean(&value)?,
+        "window_log" => o.window_log = int(&value).ok_or(invalid())?,
+        "zstd_threads" => o.zstd_threads = int(&value).ok_or(invalid())?,
+        "verify_after_compress" => o.verify_after_compress = boolean(&value)?,
+        "spot_check" => {
+            let Value::Table(ref fields) = value else { return Err(invalid()) };
+            let (mut percent, mut seed) = (None, None);
+            for (k, v) in fields {
+                match k.as_str() {
+                    "percent" => percent = float(v).filter(|p| (0.0..=100.0).contains(p)),
+                    "seed" => seed = int(v),
+                    _ => return Err(invalid()),
+                }
+            }
+            o.spot_check = Some(SpotCheck { percent: percent.ok_or(invalid())?, seed: seed.ok_or(invalid())? });
+        }
+        "strip_trailer" => o.strip_trailer = boolean(&value)?,
+        "strictness" => o.strictness = STRICTNESSES[named(&value, &STRICTNESSES.map(strictness_name))?],
+        "clip" => {
+            let clip = match value {
+                Value::Table(ref fields) if fields.len() == 1 => match (fields[0].0.as_str(), &fields[0].1) {
+                    ("frames", v) => int(v).map(ClipRange::Frames),
+                    ("seconds", v) => float(v)
+                        .and_then(|s| std::time::Duration::try_from_secs_f64(s).ok())
+                        .map(ClipRange::Duration),
+                    _ => None,
+                },
+                _ => None,
+            };
+            o.clip = Some(clip.ok_or(invalid())?);
+        }
+        "output_extension" => o.output_extension = Some(string(&value)?.into()),
+        "extensions" => {
+            let mut extensions = ExtensionMap::new();
+            // each pair is checked before the ones added before it
+            for pair in string(&value)?.split(',').rev() {
+                let (slp, slpz) = pair.trim().split_once('=').ok_or(invalid())?;
+                extensions.add(slp, slpz).ok_or(invalid())?;
+            }
+            o.extensions = extensions;
+        }
+        "in_place" => o.in_place = boolean(&value)?,
+        "settle_ms" => o.settle_ms = int(&value).ok_or(invalid())?,
+        "skip_already_compressed" => o.skip_already_compressed = boolean(&value)?,
+        "skip_already_decompressed" => o.skip_already_decompressed = boolean(&value)?,
+        "skip_if_unchanged" => o.skip_if_unchanged = boolean(&value)?,
+        "only_complete" => o.only_complete = Some(boolean(&value)?),
+        "on_conflict" => o.on_conflict = ON_CONFLICTS[named(&value, &ON_CONFLICTS.map(on_conflict_name))?],
+        "dedupe_names" => o.dedupe_names = boolean(&value)?,
+        "force_recompress" => o.force_recompress = boolean(&value)?,
+        "output_dir" => o.output_dir = Some(string(&value)?.into()),
+        "chunk_bytes" => o.chunk_bytes = int(&value).ok_or(invalid())?,
+        "segments" => o.segments = Some(int(&value).filter(|&n| n > 0).ok_or(invalid())?),
+        "progress" => o.progress = boolean(&value)?,
+        "launcher_names" => o.launcher_names = boolean(&value)?,
+        "gzip_output" => o.gzip_output = boolean(&value)?,
+        "removal" => o.removal = REMOVAL_MODES[named(&value, &REMOVAL_MODES.map(removal_name))?],
+        "buffer_size" => o.buffer_size = int(&value).filter(|&n| n > 0).ok_or(invalid())?,
+        "memory_budget" => o.memory_budget = Some(int(&value).ok_or(invalid())?),
+        "journal" => o.journal = Some(string(&value)?.into()),
+        "null_output" => o.null_output = boolean(&value)?,
+        "time_budget_per_file" => {
+            let budget = float(&value).and_then(|s| std::time::Duration::try_from_secs_f64(s).ok());
+            o.time_budget_per_file = Some(budget.ok_or(invalid())?);
+        }
+        _ => return Err(ConfigError::UnknownKey { line, key: key.into() }),
+    }
+    Ok(())
+}
+
+const LOG_LEVELS: [LogLevel; 4] = [LogLevel::Verbose, LogLevel::Normal, LogLevel::Quiet, LogLevel::Silent];
+const STRICTNESSES: [Strictness; 3] = [Strictness::Lenient, Strictness::Normal, Strictness::Strict];
+const ON_CONFLICTS: [OnConflict; 3] = [OnConflict::Skip, OnConflict::Overwrite, OnConflict::Rename];
+const REMOVAL_MODES: [RemovalMode; 2] = [RemovalMode::Trash, RemovalMode::Permanent];
+
+fn log_name(log: LogLevel) -> &'static str {
+    match log {
+        LogLevel::Verbose => "verbose",
+        LogLevel::Normal => "normal",
+        LogLevel::Quiet => "quiet",
+        LogLevel::Silent => "silent",
+    }
+}
+
+fn strictness_name(strictness: Strictness) -> &'static str {
+    match strictness {
+        Strictness::Lenient => "lenient",
+        Strictness::Normal => "normal",
+        Strictness::Strict => "strict",
+    }
+}
+
+fn on_conflict_name(on_conflict: OnConflict) -> &'static str {
+    match on_conflict {
+        OnConflict::Skip => "skip",
+        OnConflict::Overwrite => "overwrite",
+        OnConflict::Rename => "rename",
+    }
+}
+
+fn removal_name(removal: RemovalMode) -> &'static str {
+    match removal {
+        RemovalMode::Trash => "trash",
+        RemovalMode::Permanent => "permanent",
+    }
+}
+
+fn toml_value(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::Str(s) => json_string(s),
+        Value::Table(fields) => {
+            let fields = fields.iter().map(|(k, v)| format!("{} = {}", k, toml_value(v))).collect::<Vec<_>>();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        Value::List(items) => format!("[{}]", items.iter().map(toml_value).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+fn summary_value(value: &Value) -> String {
+    match value {
+        Value::Str(s) if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c)) => s.clone(),
+        value => toml_value(value),
+    }
+}
+
+pub(crate) fn json_value(value: &Value) -> String {
+    match value {
+        Value::Table(fields) => {
+            let fields = fields.iter().map(|(k, v)| format!("{}:{}", json_string(k), json_value(v))).collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::List(items) => format!("[{}]", items.iter().map(json_value).collect::<Vec<_>>().join(",")),
+        // same as TOML for everything else
+        value => toml_value(value),
+    }
+}
+
+/// Quotes a string, valid in both TOML and JSON.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses the values of one line.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> { self.s[self.pos..].chars().next() }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) { self.pos += 1 }
+    }
+
+    /// True after trailing whitespace or a comment.
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some('#'))
+    }
+
+    fn key(&mut self) -> Option<String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') { self.pos += 1 }
+        if self.pos == start { return None }
+        Some(self.s[start..self.pos].to_string())
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_space();
+        match self.peek()? {
+            '"' => self.basic_string().map(Value::Str),
+            '\'' => {
+                self.pos += 1;
+                let len = self.s[self.pos..].find('\'')?;
+                let s = self.s[self.pos..self.pos + len].to_string();
+                self.pos += len + 1;
+                Some(Value::Str(s))
+            }
+            '{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                loop {
+                    self.skip_space();
+                    if fields.is_empty() && self.eat('}') { break }
+                    let key = self.key()?;
+                    self.skip_space();
+                    if !self.eat('=') { return None }
+                    fields.push((key, self.value()?));
+                    self.skip_space();
+                    if self.eat('}') { break }
+                    if !self.eat(',') { return None }
+                }
+                Some(Value::Table(fields))
+            }
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-._".contains(c)) { self.pos += 1 }
+                let word = &self.s[start..self.pos];
+                match word {
+                    "true" => Some(Value::Bool(true)),
+                    "false" => Some(Value::Bool(false)),
+                    _ => {
+                        let digits = word.replace('_', "");
+                        match digits.parse::<i64>() {
+                            Ok(n) => Some(Value::Int(n)),
+                            Err(_) if digits.contains(['.', 'e', 'E']) => digits.parse().ok().map(Value::Float),
+                            Err(_) => None,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Option<String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            match self.bump()? {
+                '"' => return Some(out),
+                '\\' => out.push(match self.bump()? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => self.unicode_escape(4)?,
+                    'U' => self.unicode_escape(8)?,
+                    _ => return None,
+                }),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, len: usize) -> Option<char> {
+        let hex = self.s.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_round_trips() {
+        let toml = Options::DEFAULT.to_toml();
+        let options = toml.parse::<Options>().unwrap();
+        assert_eq!(options.to_toml(), toml);
+        assert!(toml.contains("\n# output_dir = \"compressed\"\n"));
+
+        let options = Options {
+            compress: Some(false),
+            log: LogLevel::Silent,
+            spot_check: Some(SpotCheck { percent: 12.5, seed: 7 }),
+            clip: Some(ClipRange::Duration(std::time::Duration::from_millis(1500))),
+            output_dir: Some("out \"dir\"\\\n".into()),
+            removal: RemovalMode::Permanent,
+            segments: Some(3),
+            time_budget_per_file: Some(std::time::Duration::from_secs(10)),
+            ..Options::DEFAULT
+        };
+        let mut options = options;
+        options.extensions.add("a", "b").unwrap();
+        options.extensions.add("slp", "slp.zst").unwrap();
+        let toml = options.to_toml();
+        assert_eq!(toml.parse::<Options>().unwrap().to_toml(), toml);
+        assert!(toml.contains("\nextensions = \"slp=slp.zst, a=b\"\n"));
+        assert_eq!(toml.parse::<Options>().unwrap().extensions, options.extensions);
+        assert!(toml.contains("\nspot_check = { percent = 12.5, seed = 7 }\n"));
+        assert!(options.to_json().contains(r#""clip":{"seconds":1.5},"#));
+
+        let json: serde_json::Value = serde_json::from_str(&Options::DEFAULT.to_json()).unwrap();
+        assert_eq!(json["level"], 3);
+        assert!(json["output_dir"].is_null());
+    }
+
+    #[test]
+    fn config_errors() {
+        let options = "level = 5 # comment\n\n  keep=false\noutput_dir = 'C:\\replays'\nclip = { frames = 60 }"
+            .parse::<Options>()
+            .unwrap();
+        assert_eq!((options.level, options.keep), (5, false));
+        assert_eq!(options.output_dir, Some("C:\\replays".into()));
+        assert_eq!(options.clip, Some(ClipRange::Frames(60)));
+
+        let error = |s: &str| s.parse::<Options>().unwrap_err();
+        assert_eq!(error("keep = true\n[section]"), ConfigError::Syntax { line: 2 });
+        assert_eq!(error("keep = true false"), ConfigError::Syntax { line: 1 });
+        assert_eq!(error("kep = true"), ConfigError::UnknownKey { line: 1, key: "kep".into() });
+        assert_eq!(error("keep = true\nkeep = false"), ConfigError::DuplicateKey { line: 2, key: "keep".into() });
+        assert_eq!(error("keep = 1"), ConfigError::InvalidValue { line: 1, key: "keep".into() });
+        assert_eq!(error("log = \"loud\""), ConfigError::InvalidValue { line: 1, key: "log".into() });
+        assert_eq!(error("level = 1000"), ConfigError::InvalidValue { line: 1, key: "level".into() });
+    }
+
+    #[test]
+    fn summary_line() {
+        assert_eq!(Options::DEFAULT.to_string(), "level=3, keep=true, recursive=false, log=normal");
+        let options = Options {
+            level: 6,
+            recursive: true,
+            output_dir: Some("out dir".into()),
+            clip: Some(ClipRange::Frames(60)),
+            ..Options::DEFAULT
+        };
+        assert_eq!(
+            String::from(&options),
+            "level=6, keep=true, recursive=true, log=normal, clip={ frames = 60 }, output_dir=\"out dir\"",
+        );
+
+        let debug = format!("{:?}", options);
+        assert!(debug.starts_with("Options { keep: true, compress: None, recursive: true, "), "{}", debug);
+        assert!(debug.ends_with(" temp_suffix: \".tmp\", confirm_overwrite: None, pre_hook: None, post_hook: None }"), "{}", debug);
+    }
+}
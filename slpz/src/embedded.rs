@@ -0,0 +1,626 @@
+//! Header parsing and event restore using only `core` and `alloc`, for targets without std such as consoles.
+//!
+//! This module and [`crate::tables`] are what is built without the default `std` feature.
+//! CI builds `slpz --no-default-features` for `thumbv7em-none-eabihf`.
+//! With `std`, the API such as `decompress` wraps these functions.
+//!
+//! zstd decompression is passed in by the caller. See [`decompress_into`]. Files written with level 0 need none.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub(crate) const EVENT_PAYLOADS: u8 = 0x35;
+pub(crate) const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
+/// The slpz format version written by this version of slpz.
+pub const SLPZ_FORMAT_VERSION: u32 = 2;
+/// The oldest slpz format version this version of slpz reads. See [`format_version`].
+pub const MIN_SUPPORTED_FORMAT_VERSION: u32 = 0;
+/// The slpz header stores a 32 byte replay ID. See [`replay_id`](crate::replay_id).
+pub const FLAG_REPLAY_ID: u32 = 1 << 0;
+/// The events are not compressed. See [`LEVEL_STORE`](crate::LEVEL_STORE).
+pub const FLAG_STORED: u32 = 1 << 1;
+/// The events are compressed in separate chunks, listed in a chunk table. See [`Compressor::set_chunk_bytes`](crate::Compressor::set_chunk_bytes).
+pub const FLAG_CHUNKED: u32 = 1 << 2;
+/// The chunks were compressed on a thread each, and are sized to be decompressed on a thread each. Only set with [`FLAG_CHUNKED`].
+/// See [`Compressor::set_segments`](crate::Compressor::set_segments).
+pub const FLAG_SEGMENTED: u32 = 1 << 3;
+pub(crate) const KNOWN_FLAGS: u32 = FLAG_REPLAY_ID | FLAG_STORED | FLAG_CHUNKED | FLAG_SEGMENTED;
+
+/// Errors from this module. Converts into [`crate::DecompError`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidFile,
+    /// The file does not have an slpz header. See [`is_slpz`].
+    NotSlpz { first_bytes: [u8; 4] },
+    /// The file is an slpz file of a format version this version of slpz does not read. See [`format_version`].
+    UnsupportedVersion { version: u32 },
+    /// The file ends before the end of the slpz header or sections.
+    Truncated,
+    /// The output buffer is smaller than the `needed` bytes.
+    BufferTooSmall { needed: usize },
+    /// The zstd decompression passed to [`decompress_into`] failed.
+    DecompressionFailure,
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecodeError::InvalidFile => write!(f, "File is invalid"),
+            DecodeError::NotSlpz { first_bytes: b } =>
+                write!(f, "Not an slpz file (starts with {:02X} {:02X} {:02X} {:02X})", b[0], b[1], b[2], b[3]),
+            DecodeError::UnsupportedVersion { version } => write!(
+                f, "slpz format version {} is not supported (versions {} to {} are)",
+                version, MIN_SUPPORTED_FORMAT_VERSION, SLPZ_FORMAT_VERSION,
+            ),
+            DecodeError::Truncated => write!(f, "File is truncated"),
+            DecodeError::BufferTooSmall { needed } => write!(f, "Output buffer is too small, {} bytes are needed", needed),
+            DecodeError::DecompressionFailure => write!(f, "Decompression failed"),
+        }
+    }
+}
+
+/// Returns true if the data starts like an slpz file.
+///
+/// The slpz format has no magic bytes.
+/// Instead the version must be supported and the event sizes offset must point to an Event Payloads event.
+pub fn is_slpz(data: &[u8]) -> bool {
+    format_version(data).is_some_and(|v| (MIN_SUPPORTED_FORMAT_VERSION..=SLPZ_FORMAT_VERSION).contains(&v))
+}
+
+/// Returns the format version of an slpz file, reading only the start of the header.
+///
+/// Returns versions newer than [`SLPZ_FORMAT_VERSION`], so they can be refused with a clear error,
+/// as long as their header starts like the current one. Returns None if the data does not start like an slpz file.
+pub fn format_version(data: &[u8]) -> Option<u32> {
+    if data.len() < 24 { return None }
+    let version = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let event_sizes_offset = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+
+    // Without magic bytes, large version numbers are more likely other files than slpz files from the future.
+    if version > SLPZ_FORMAT_VERSION + 16 { return None }
+    (event_sizes_offset >= 24 && data.get(event_sizes_offset) == Some(&EVENT_PAYLOADS)).then_some(version)
+}
+
+
+/// The parsed fixed header of an slpz file.
+///
+/// Headers are ordered as their bytes in the file would be, comparing fields in header layout order.
+/// The start time is in the metadata rather than the header, so to order files by when they were played
+/// use a key such as `(Option<GameTimestamp>, SlpzHeader)`. See [`game_timestamp`](crate::game_timestamp).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SlpzHeader {
+    pub version: u32,
+    /// Always zero for version 0 files.
+    pub flags: u32,
+    pub event_sizes_offset: usize,
+    pub game_start_offset: usize,
+    pub metadata_offset: usize,
+    pub compressed_events_offset: usize,
+    pub decompressed_events_size: usize,
+    /// Present if `flags` contains [`FLAG_REPLAY_ID`]. See [`replay_id`](crate::replay_id).
+    pub replay_id: Option<[u8; 32]>,
+    /// The compression level the file was written with. Not present before version 2.
+    pub level: Option<i32>,
+    /// The number of separately compressed chunks of events. 1 unless `flags` contains [`FLAG_CHUNKED`].
+    pub chunk_count: usize,
+    /// Offset of the first chunk table entry, present if `flags` contains [`FLAG_CHUNKED`]. See [`SlpzHeader::chunks`].
+    pub chunk_table_offset: Option<usize>,
+}
+
+impl PartialOrd for SlpzHeader {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for SlpzHeader {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        let key = |h: &SlpzHeader| (
+            h.version,
+            h.event_sizes_offset,
+            h.game_start_offset,
+            h.metadata_offset,
+            h.compressed_events_offset,
+            h.decompressed_events_size,
+            h.flags,
+            h.level,
+            h.replay_id,
+            h.chunk_count,
+            h.chunk_table_offset,
+        );
+        key(self).cmp(&key(other))
+    }
+}
+
+/// Parses and validates the header of an slpz file. Does not decompress anything.
+///
+/// Same as [`SlpzHeader::parse`], with this module's error type.
+#[rustfmt::skip]
+pub fn parse_header(slpz: &[u8]) -> Result<SlpzHeader, DecodeError> {
+    if let Some(version) = format_version(slpz).filter(|&v| v > SLPZ_FORMAT_VERSION) {
+        return Err(DecodeError::UnsupportedVersion { version })
+    }
+    if !is_slpz(slpz) {
+        let mut first_bytes = [0u8; 4];
+        let n = slpz.len().min(4);
+        first_bytes[..n].copy_from_slice(&slpz[..n]);
+        return Err(DecodeError::NotSlpz { first_bytes })
+    }
+
+    let version                  = u32::from_be_bytes(slpz[0..4].try_into().unwrap());
+    let event_sizes_offset       = u32::from_be_bytes(slpz[4..8].try_into().unwrap()) as usize;
+    let game_start_offset        = u32::from_be_bytes(slpz[8..12].try_into().unwrap()) as usize;
+    let metadata_offset          = u32::from_be_bytes(slpz[12..16].try_into().unwrap()) as usize;
+    let compressed_events_offset = u32::from_be_bytes(slpz[16..20].try_into().unwrap()) as usize;
+    let decompressed_events_size = u32::from_be_bytes(slpz[20..24].try_into().unwrap()) as usize;
+
+    if !(event_sizes_offset <= game_start_offset
+        && game_start_offset <= metadata_offset
+        && metadata_offset <= compressed_events_offset
+        && compressed_events_offset <= slpz.len()
+    ) { return Err(DecodeError::InvalidFile) }
+
+    let mut header = SlpzHeader {
+        version,
+        flags: 0,
+        event_sizes_offset,
+        game_start_offset,
+        metadata_offset,
+        compressed_events_offset,
+        decompressed_events_size,
+        replay_id: None,
+        level: None,
+        chunk_count: 1,
+        chunk_table_offset: None,
+    };
+
+    // update the fields below when the format changes
+    const _: () = assert!(SLPZ_FORMAT_VERSION == 2 && MIN_SUPPORTED_FORMAT_VERSION == 0);
+    if version == 0 { return Ok(header) }
+
+    let fields_offset = if version >= 2 { 32 } else { 28 };
+    if let Some(level) = slpz.get(28..32).filter(|_| version >= 2) {
+        header.level = Some(i32::from_be_bytes(level.try_into().unwrap()));
+    }
+
+    // optional fields are between the fixed header and the event sizes, in flag bit order.
+    let fields = slpz.get(fields_offset..event_sizes_offset).ok_or(DecodeError::InvalidFile)?;
+    header.flags = u32::from_be_bytes(slpz[24..28].try_into().unwrap());
+    if header.flags & !KNOWN_FLAGS != 0 { return Err(DecodeError::InvalidFile) }
+    if header.flags & FLAG_SEGMENTED != 0 && header.flags & FLAG_CHUNKED == 0 { return Err(DecodeError::InvalidFile) }
+
+    let mut fields = fields;
+    let mut take = |len: usize| -> Result<&[u8], DecodeError> {
+        if fields.len() < len { return Err(DecodeError::InvalidFile) }
+        let (field, rest) = fields.split_at(len);
+        fields = rest;
+        Ok(field)
+    };
+
+    if header.flags & FLAG_REPLAY_ID != 0 {
+        header.replay_id = Some(take(32)?.try_into().unwrap());
+    }
+
+    if header.flags & FLAG_CHUNKED != 0 {
+        let count = u32::from_be_bytes(take(4)?.try_into().unwrap()) as usize;
+        let entries = take(count.checked_mul(8).ok_or(DecodeError::InvalidFile)?)?;
+        let (mut compressed, mut reordered) = (0usize, 0usize);
+        for entry in entries.chunks_exact(8) {
+            compressed += u32::from_be_bytes(entry[0..4].try_into().unwrap()) as usize;
+            reordered += u32::from_be_bytes(entry[4..8].try_into().unwrap()) as usize;
+        }
+        if count == 0
+            || compressed != slpz.len() - compressed_events_offset
+            || reordered != decompressed_events_size
+        { return Err(DecodeError::InvalidFile) }
+
+        header.chunk_count = count;
+        header.chunk_table_offset = Some(
+            fields_offset + if header.flags & FLAG_REPLAY_ID != 0 { 32 } else { 0 } + 4
+        );
+    }
+
+    Ok(header)
+}
+
+impl SlpzHeader {
+    /// Returns the compressed and reordered size of each chunk of events, in order.
+    /// Files without [`FLAG_CHUNKED`] have a single chunk.
+    ///
+    /// `slpz` must be the file the header was parsed from.
+    pub fn chunks(&self, slpz: &[u8]) -> Vec<(usize, usize)> {
+        match self.chunk_table_offset {
+            Some(offset) => slpz[offset..][..self.chunk_count * 8]
+                .chunks_exact(8)
+                .map(|entry| (
+                    u32::from_be_bytes(entry[0..4].try_into().unwrap()) as usize,
+                    u32::from_be_bytes(entry[4..8].try_into().unwrap()) as usize,
+                ))
+                .collect(),
+            None => vec![(slpz.len() - self.compressed_events_offset, self.decompressed_events_size)],
+        }
+    }
+}
+
+/// Events per tile in [`PayloadTiles`]. A multiple of 8.
+///
+/// Wider tiles read or write each column row in longer runs, which matters once the columns no longer fit in the TLB.
+const TILE_EVENTS: usize = 128;
+
+/// Moves payloads between whole payloads and byte columns [`TILE_EVENTS`] events at a time.
+///
+/// Each command has a tile of whole payloads.
+/// Tiles are moved to and from the columns in 8x16 byte blocks with [`transpose_8x16`], and the edges byte by byte.
+pub(crate) struct PayloadTiles<'a> {
+    event_sizes: &'a [u16; 256],
+    event_counts: [u32; 256],
+    /// Offset of each command's columns in the reordered payloads.
+    column_offsets: [u32; 256],
+    /// Offset of each command's tile in `tiles`.
+    tile_offsets: [usize; 256],
+    /// Index of each command's next payload.
+    next: [u32; 256],
+    tiles: Vec<u8>,
+}
+
+impl<'a> PayloadTiles<'a> {
+    pub(crate) fn new(event_sizes: &'a [u16; 256], event_counts: [u32; 256], column_offsets: [u32; 256]) -> Self {
+        let mut tile_offsets = [0usize; 256];
+        let mut tiles_len = 0;
+        for event in 0..256 {
+            tile_offsets[event] = tiles_len;
+            tiles_len += (event_counts[event] as usize).min(TILE_EVENTS) * event_sizes[event] as usize;
+        }
+        PayloadTiles {
+            event_sizes,
+            event_counts,
+            column_offsets,
+            tile_offsets,
+            next: [0; 256],
+            tiles: vec![0; tiles_len],
+        }
+    }
+
+    /// Adds the next payload of a command, moving the tile to `columns` when it is full.
+    #[cfg(feature = "std")]
+    pub(crate) fn push(&mut self, event: u8, payload: &[u8], columns: &mut [u8]) {
+        let e = event as usize;
+        let size = payload.len();
+        let slot = self.next[e] as usize % TILE_EVENTS;
+        self.tiles[self.tile_offsets[e] + slot * size..][..size].copy_from_slice(payload);
+        self.next[e] += 1;
+        if slot == TILE_EVENTS - 1 { self.store(e, TILE_EVENTS, columns) }
+    }
+
+    /// Moves the tiles that are not full to `columns`.
+    #[cfg(feature = "std")]
+    pub(crate) fn finish(&mut self, columns: &mut [u8]) {
+        for e in 0..256 {
+            let len = self.next[e] as usize % TILE_EVENTS;
+            if len != 0 { self.store(e, len, columns) }
+        }
+    }
+
+    /// Moves the first `len` payloads of a command's tile to their columns.
+    #[cfg(feature = "std")]
+    fn store(&mut self, e: usize, len: usize, columns: &mut [u8]) {
+        let size = self.event_sizes[e] as usize;
+        let count = self.event_counts[e] as usize;
+        let first = self.next[e] as usize - len;
+        let tile = &self.tiles[self.tile_offsets[e]..];
+        let columns = &mut columns[self.column_offsets[e] as usize..][..size * count];
+
+        let (rows, events) = (size / 16 * 16, len / 8 * 8);
+        for j0 in (0..rows).step_by(16) {
+            for i0 in (0..events).step_by(8) {
+                let mut block = [[0u8; 16]; 8];
+                for (i, payload_part) in block.iter_mut().enumerate() {
+                    *payload_part = tile[(i0 + i) * size + j0..][..16].try_into().unwrap();
+                }
+                for (j, row) in transpose_8x16(&block).iter().enumerate() {
+                    columns[(j0 + j) * count + first + i0..][..8].copy_from_slice(row);
+                }
+            }
+        }
+        for j in 0..size {
+            let i_start = if j < rows { events } else { 0 };
+            for i in i_start..len {
+                columns[j * count + first + i] = tile[i * size + j];
+            }
+        }
+    }
+
+    /// Returns the next payload of a command, moving the next tile from `columns` when needed.
+    fn pop(&mut self, event: u8, columns: &[u8]) -> &[u8] {
+        let e = event as usize;
+        let size = self.event_sizes[e] as usize;
+        let slot = self.next[e] as usize % TILE_EVENTS;
+        if slot == 0 { self.load(e, columns) }
+        self.next[e] += 1;
+        &self.tiles[self.tile_offsets[e] + slot * size..][..size]
+    }
+
+    /// Moves the next payloads of a command from their columns to its tile.
+    fn load(&mut self, e: usize, columns: &[u8]) {
+        let size = self.event_sizes[e] as usize;
+        let count = self.event_counts[e] as usize;
+        let first = self.next[e] as usize;
+        let len = (count - first).min(TILE_EVENTS);
+        let tile = &mut self.tiles[self.tile_offsets[e]..];
+        let columns = &columns[self.column_offsets[e] as usize..][..size * count];
+
+        let (rows, events) = (size / 8 * 8, len / 16 * 16);
+        for j0 in (0..rows).step_by(8) {
+            for i0 in (0..events).step_by(16) {
+                let mut block = [[0u8; 16]; 8];
+                for (j, row) in block.iter_mut().enumerate() {
+                    *row = columns[(j0 + j) * count + first + i0..][..16].try_into().unwrap();
+                }
+                for (i, payload_part) in transpose_8x16(&block).iter().enumerate() {
+                    tile[(i0 + i) * size + j0..][..8].copy_from_slice(payload_part);
+                }
+            }
+        }
+        for j in 0..size {
+            let i_start = if j < rows { events } else { 0 };
+            for i in i_start..len {
+                tile[i * size + j] = columns[j * count + first + i];
+            }
+        }
+    }
+}
+
+/// Transposes 8 rows of 16 bytes into 16 rows of 8 bytes.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn transpose_8x16(rows: &[[u8; 16]; 8]) -> [[u8; 8]; 16] {
+    use core::arch::x86_64::*;
+
+    // SAFETY: SSE2 is always available on x86_64. [u8; 16] and __m128i are both 16 bytes,
+    // so [[u8; 16]; 8], [[u8; 8]; 16] and [__m128i; 8] have the same size.
+    unsafe {
+        let r: [__m128i; 8] = core::mem::transmute(*rows);
+        // interleave bytes, then pairs of bytes, then groups of four, so each 8 byte half is one output row
+        let a = [
+            _mm_unpacklo_epi8(r[0], r[1]), _mm_unpackhi_epi8(r[0], r[1]),
+            _mm_unpacklo_epi8(r[2], r[3]), _mm_unpackhi_epi8(r[2], r[3]),
+            _mm_unpacklo_epi8(r[4], r[5]), _mm_unpackhi_epi8(r[4], r[5]),
+            _mm_unpacklo_epi8(r[6], r[7]), _mm_unpackhi_epi8(r[6], r[7]),
+        ];
+        let b = [
+            _mm_unpacklo_epi16(a[0], a[2]), _mm_unpackhi_epi16(a[0], a[2]),
+            _mm_unpacklo_epi16(a[1], a[3]), _mm_unpackhi_epi16(a[1], a[3]),
+            _mm_unpacklo_epi16(a[4], a[6]), _mm_unpackhi_epi16(a[4], a[6]),
+            _mm_unpacklo_epi16(a[5], a[7]), _mm_unpackhi_epi16(a[5], a[7]),
+        ];
+        let c = [
+            _mm_unpacklo_epi32(b[0], b[4]), _mm_unpackhi_epi32(b[0], b[4]),
+            _mm_unpacklo_epi32(b[1], b[5]), _mm_unpackhi_epi32(b[1], b[5]),
+            _mm_unpacklo_epi32(b[2], b[6]), _mm_unpackhi_epi32(b[2], b[6]),
+            _mm_unpacklo_epi32(b[3], b[7]), _mm_unpackhi_epi32(b[3], b[7]),
+        ];
+        core::mem::transmute(c)
+    }
+}
+
+/// Transposes 8 rows of 16 bytes into 16 rows of 8 bytes.
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn transpose_8x16(rows: &[[u8; 16]; 8]) -> [[u8; 8]; 16] {
+    let mut out = [[0u8; 8]; 16];
+    for half in 0..2 {
+        let mut t = [0u64; 8];
+        for (t, row) in t.iter_mut().zip(rows) {
+            *t = u64::from_le_bytes(row[half * 8..][..8].try_into().unwrap());
+        }
+        transpose_8x8(&mut t);
+        for (out, t) in out[half * 8..].iter_mut().zip(t) {
+            *out = t.to_le_bytes();
+        }
+    }
+    out
+}
+
+/// Transposes an 8x8 matrix of bytes, with row `i` in `t[i]` and column `j` in byte `j` (little endian).
+///
+/// Swaps the off-diagonal 4x4 blocks, then the 2x2 blocks within them, then the single bytes.
+#[cfg(not(target_arch = "x86_64"))]
+fn transpose_8x8(t: &mut [u64; 8]) {
+    for (shift, mask, pairs) in [
+        (32, 0x0000_0000_FFFF_FFFFu64, [0, 1, 2, 3]),
+        (16, 0x0000_FFFF_0000_FFFF, [0, 1, 4, 5]),
+        (8, 0x00FF_00FF_00FF_00FF, [0, 2, 4, 6]),
+    ] {
+        let step = shift / 8;
+        for i in pairs {
+            let swap = ((t[i] >> shift) ^ t[i + step]) & mask;
+            t[i] ^= swap << shift;
+            t[i + step] ^= swap;
+        }
+    }
+}
+
+
+/// Restores reordered events to their original order, one event at a time. See [`restore_events`].
+pub struct EventRestorer<'a> {
+    event_order_list: core::slice::Iter<'a, u8>,
+    events: &'a [u8],
+    tiles: PayloadTiles<'a>,
+    unordered_size: usize,
+}
+
+impl<'a> EventRestorer<'a> {
+    /// `reordered` is one decompressed chunk of events. `event_sizes` is from the file's Event Payloads event.
+    pub fn new(reordered: &'a [u8], event_sizes: &'a [u16; 256]) -> Result<Self, DecodeError> {
+        let ReorderedLayout { event_order_list, events, event_counts, reordered_event_offsets, unordered_size } =
+            reordered_layout(reordered, event_sizes)?;
+        Ok(EventRestorer {
+            event_order_list: event_order_list.iter(),
+            events,
+            tiles: PayloadTiles::new(event_sizes, event_counts, reordered_event_offsets),
+            unordered_size,
+        })
+    }
+
+    /// The size of all events once restored, including their command bytes.
+    pub fn unordered_size(&self) -> usize { self.unordered_size }
+
+    /// Returns the command byte and payload of the next event.
+    pub fn next_event(&mut self) -> Option<(u8, &[u8])> {
+        let &command = self.event_order_list.next()?;
+        Some((command, self.tiles.pop(command, self.events)))
+    }
+}
+
+/// Restores one chunk of reordered events into the start of `out`. Returns the number of bytes written.
+pub fn restore_events(reordered: &[u8], event_sizes: &[u16; 256], out: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut restorer = EventRestorer::new(reordered, event_sizes)?;
+    let needed = restorer.unordered_size();
+    if out.len() < needed { return Err(DecodeError::BufferTooSmall { needed }) }
+
+    let mut written = 0;
+    while let Some((command, payload)) = restorer.next_event() {
+        out[written] = command;
+        out[written + 1..][..payload.len()].copy_from_slice(payload);
+        written += 1 + payload.len();
+    }
+    Ok(written)
+}
+
+/// Returns the size of the slp file that an slpz file decompresses to, read from the header.
+pub fn original_size(slpz: &[u8]) -> Result<u64, DecodeError> {
+    if slpz.len() < 24 { return Err(DecodeError::Truncated) }
+    let version = u32::from_be_bytes(slpz[0..4].try_into().unwrap());
+    let compressed_events_offset = u32::from_be_bytes(slpz[16..20].try_into().unwrap()) as usize;
+    if version <= SLPZ_FORMAT_VERSION && slpz.len() < compressed_events_offset { return Err(DecodeError::Truncated) }
+
+    let header = parse_header(slpz)?;
+
+    // each chunk of reordered events starts with a 4 byte event count, which is not in the slp file
+    let events_size = (header.decompressed_events_size as u64)
+        .checked_sub(4 * header.chunk_count as u64)
+        .ok_or(DecodeError::InvalidFile)?;
+    Ok(RAW_HEADER.len() as u64 + 4
+        + (header.compressed_events_offset - header.event_sizes_offset) as u64
+        + events_size)
+}
+
+/// Decompresses an slpz file into the start of `out`, which must hold [`original_size`] bytes.
+/// Returns the size of the slp file.
+///
+/// `inflate` decompresses one zstd frame, appending it to the `Vec` it is passed, and returns false if it fails.
+/// Each frame is decompressed into `scratch`, which is reused between chunks.
+/// Files written with level 0 do not call `inflate`.
+pub fn decompress_into(
+    slpz: &[u8],
+    out: &mut [u8],
+    scratch: &mut Vec<u8>,
+    mut inflate: impl FnMut(&[u8], &mut Vec<u8>) -> bool,
+) -> Result<usize, DecodeError> {
+    let header = parse_header(slpz)?;
+    let size = usize::try_from(original_size(slpz)?).map_err(|_| DecodeError::InvalidFile)?;
+    if out.len() < size { return Err(DecodeError::BufferTooSmall { needed: size }) }
+
+    let (event_sizes, _) = event_sizes(&slpz[header.event_sizes_offset..header.game_start_offset])
+        .ok_or(DecodeError::InvalidFile)?;
+    let metadata = &slpz[header.metadata_offset..header.compressed_events_offset];
+
+    out[..RAW_HEADER.len()].copy_from_slice(&RAW_HEADER);
+    let raw_len = u32::try_from(size - 15 - metadata.len()).map_err(|_| DecodeError::InvalidFile)?;
+    out[11..15].copy_from_slice(&raw_len.to_be_bytes());
+    let sections = &slpz[header.event_sizes_offset..header.metadata_offset];
+    out[15..][..sections.len()].copy_from_slice(sections);
+    let mut written = 15 + sections.len();
+
+    let stored = header.flags & FLAG_STORED != 0;
+    let mut compressed_events = &slpz[header.compressed_events_offset..];
+    for (compressed_size, reordered_size) in header.chunks(slpz) {
+        // chunk sizes are checked against the section size when parsing the header
+        let (chunk, rest) = compressed_events.split_at(compressed_size);
+        compressed_events = rest;
+
+        let reordered = if stored {
+            chunk
+        } else {
+            scratch.clear();
+            if !inflate(chunk, &mut *scratch) { return Err(DecodeError::DecompressionFailure) }
+            &scratch[..]
+        };
+        if reordered.len() != reordered_size { return Err(DecodeError::InvalidFile) }
+        written += restore_events(reordered, &event_sizes, &mut out[written..size - metadata.len()])
+            .map_err(|_| DecodeError::InvalidFile)?;
+    }
+
+    if written + metadata.len() != size { return Err(DecodeError::InvalidFile) }
+    out[written..size].copy_from_slice(metadata);
+    Ok(size)
+}
+
+/// Where the parts of reordered events are. See [`reorder_events`].
+struct ReorderedLayout<'a> {
+    event_order_list: &'a [u8],
+    /// The reordered payloads.
+    events: &'a [u8],
+    event_counts: [u32; 256],
+    /// Offset of each command's payloads in `events`.
+    reordered_event_offsets: [u32; 256],
+    /// Size of the events once unordered.
+    unordered_size: usize,
+}
+
+fn reordered_layout<'a>(b: &'a [u8], event_sizes: &[u16; 256]) -> Result<ReorderedLayout<'a>, DecodeError> {
+    if b.len() < 4 { return Err(DecodeError::InvalidFile) }
+    let total_events = u32::from_be_bytes(b[0..4].try_into().unwrap()) as usize;
+    if b.len() - 4 < total_events { return Err(DecodeError::InvalidFile) }
+
+    let event_order_list_offset = 4;
+    let reordered_events_offset = event_order_list_offset + total_events;
+
+    let mut event_counts = [0u32; 256];
+    for i in 0..total_events {
+        let event = b[event_order_list_offset + i] as usize;
+        event_counts[event] += 1;
+    }
+
+    let mut reordered_event_offsets = [0u32; 256];
+    for i in 0..255 {
+        let size = event_sizes[i];
+        let count = event_counts[i];
+
+        let event_total_size = size as u32 * count;
+
+        // offset for next event is the end of this event.
+        reordered_event_offsets[i + 1] = reordered_event_offsets[i] + event_total_size;
+    }
+
+    let unordered_size = {
+        let last_size = event_sizes[255];
+        let last_count = event_counts[255];
+        let last_total_size = last_count as usize * last_size as usize;
+        reordered_event_offsets[255] as usize + last_total_size + total_events
+    };
+
+    let event_order_list = &b[event_order_list_offset..reordered_events_offset];
+    let events = &b[reordered_events_offset..];
+
+    if unordered_size != events.len() + total_events { return Err(DecodeError::InvalidFile) }
+
+    Ok(ReorderedLayout { event_order_list, events, event_counts, reordered_event_offsets, unordered_size })
+}
+
+/// Reads the payload sizes and number of commands from an Event Payloads event, starting at its command byte.
+pub(crate) fn event_sizes(events: &[u8]) -> Option<([u16; 256], usize)> {
+    if events.len() < 2 || events[1] == 0 { return None }
+
+    let info_size = events[1] as usize;
+    let event_count = (info_size - 1) / 3;
+
+    if events.len() < 1 + info_size { return None }
+
+    let mut event_payload_sizes = [0; 256];
+    for i in 0..event_count {
+        let offset = i * 3 + 2;
+        let command_byte = events[offset] as usize;
+        let payload_size = u16::from_be_bytes(events[offset + 1..][..2].try_into().unwrap());
+        event_payload_sizes[command_byte] = payload_size;
+    }
+
+    Some((event_payload_sizes, event_count))
+}
+
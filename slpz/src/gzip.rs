@@ -0,0 +1,492 @@
+//! Reading and writing gzip files, for the `.slp.gz` files written by older archive scripts.
+//!
+//! Enabled by the `gzip` feature. Written for slp files rather than speed:
+//! [`encode`] writes a single block with the fixed Huffman codes.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GzipError {
+    /// The data does not start with the gzip magic bytes, or uses a compression method other than deflate.
+    NotGzip,
+    /// The header or the deflate stream is invalid.
+    Corrupt,
+    /// The data ends before the end of the stream or the trailer.
+    Truncated,
+    /// The CRC or size in the trailer does not match the decompressed data.
+    ChecksumMismatch,
+}
+
+impl std::fmt::Display for GzipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            GzipError::NotGzip => "Not a gzip file",
+            GzipError::Corrupt => "Gzip data is invalid",
+            GzipError::Truncated => "Gzip data is truncated",
+            GzipError::ChecksumMismatch => "Gzip checksum does not match",
+        })
+    }
+}
+
+impl std::error::Error for GzipError {}
+
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+const DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 1 << 1;
+const FLAG_EXTRA: u8 = 1 << 2;
+const FLAG_NAME: u8 = 1 << 3;
+const FLAG_COMMENT: u8 = 1 << 4;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/// The order code length code lengths are stored in, in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+
+/// The most [`decode`] writes: the largest raw element an slp raw length describes, and 1MiB for the metadata.
+/// Anything larger is not an slp file, such as a gzip bomb.
+const MAX_OUTPUT: u64 = 15 + u32::MAX as u64 + (1 << 20);
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8))
+}
+
+/// Returns true if the data starts with the gzip magic bytes.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decompresses a gzip file. Files made of several gzip members decompress to their concatenation.
+///
+/// Returns [`GzipError::Corrupt`] if the output would be larger than any slp file can be, a little over 4GiB.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    decode_with_limit(data, usize::try_from(MAX_OUTPUT).unwrap_or(usize::MAX))
+}
+
+fn decode_with_limit(data: &[u8], limit: usize) -> Result<Vec<u8>, GzipError> {
+    if !is_gzip(data) { return Err(GzipError::NotGzip) }
+
+    let mut out = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if !is_gzip(rest) { return Err(GzipError::Corrupt) }
+        let member_start = out.len();
+        let header_len = header_len(rest)?;
+
+        let mut bits = BitReader { data: rest, pos: header_len, buf: 0, count: 0 };
+        inflate(&mut bits, &mut out, member_start, limit)?;
+
+        let trailer = rest.get(bits.pos..bits.pos + 8).ok_or(GzipError::Truncated)?;
+        let crc = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+        let member = &out[member_start..];
+        if crc != crc32(member) || size != member.len() as u32 { return Err(GzipError::ChecksumMismatch) }
+        rest = &rest[bits.pos + 8..];
+    }
+    Ok(out)
+}
+
+/// Compresses data to a gzip file. The header has no file name or modification time,
+/// so the same data always gives the same file.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4 + 32);
+    // no flags or time, and an unknown OS
+    out.extend_from_slice(&[MAGIC[0], MAGIC[1], DEFLATE, 0, 0, 0, 0, 0, 0, 255]);
+
+    let mut bits = BitWriter { out, buf: 0, count: 0 };
+    bits.write(1, 1); // final block
+    bits.write(1, 2); // fixed Huffman codes
+
+    // greedy matching against the most recent position with the same 3 byte hash
+    let hash = |i: usize| ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7FFF;
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut i = 0;
+    while i < data.len() {
+        let mut length = 0;
+        let mut dist = 0;
+        if i + 3 <= data.len() {
+            let candidate = std::mem::replace(&mut head[hash(i)], i);
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                let max = (data.len() - i).min(MAX_MATCH);
+                length = (0..max).take_while(|&k| data[candidate + k] == data[i + k]).count();
+                dist = i - candidate;
+            }
+        }
+
+        if length >= 3 {
+            let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+            write_literal_length(&mut bits, 257 + code);
+            bits.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+            let code = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+            bits.write(reverse_bits(code as u32, 5), 5);
+            bits.write((dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+
+            for j in i + 1..(i + length).min(data.len().saturating_sub(2)) {
+                head[hash(j)] = j;
+            }
+            i += length;
+        } else {
+            write_literal_length(&mut bits, data[i] as usize);
+            i += 1;
+        }
+    }
+    write_literal_length(&mut bits, 256); // end of block
+    bits.flush();
+
+    let mut out = bits.out;
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Returns the length of the gzip member header at the start of `data`.
+fn header_len(data: &[u8]) -> Result<usize, GzipError> {
+    let header = data.get(..10).ok_or(GzipError::Truncated)?;
+    if header[2] != DEFLATE { return Err(GzipError::NotGzip) }
+    let flags = header[3];
+    if flags & 0xE0 != 0 { return Err(GzipError::Corrupt) }
+
+    let mut pos = 10;
+    if flags & FLAG_EXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or(GzipError::Truncated)?;
+        pos += 2 + u16::from_le_bytes(len.try_into().unwrap()) as usize;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let len = data.get(pos..).and_then(|d| d.iter().position(|&b| b == 0)).ok_or(GzipError::Truncated)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 { pos += 2 }
+    if data.len() < pos { return Err(GzipError::Truncated) }
+    Ok(pos)
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    /// Reads `n` bits, at most 16, least significant first.
+    fn bits(&mut self, n: u32) -> Result<u32, GzipError> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(GzipError::Truncated)?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary. Fewer than 8 bits are ever buffered.
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    buf: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `n` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, n: u32) {
+        self.buf |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 { self.out.push(self.buf as u8) }
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// Huffman codes are stored most significant bit first, unlike everything else.
+fn reverse_bits(code: u32, n: u32) -> u32 {
+    code.reverse_bits() >> (32 - n)
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code.
+fn write_literal_length(bits: &mut BitWriter, symbol: usize) {
+    let (code, n) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xC0 + symbol - 280, 8),
+    };
+    bits.write(reverse_bits(code as u32, n), n);
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    /// The number of codes of each length.
+    counts: [u16; 16],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// `lengths` is the code length of each symbol, with zero for unused symbols.
+    /// Incomplete codes are allowed, as a single distance code is valid.
+    fn new(lengths: &[u8]) -> Result<Huffman, GzipError> {
+        let mut counts = [0u16; 16];
+        for &len in lengths { counts[len as usize] += 1; }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 { return Err(GzipError::Corrupt) }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, GzipError> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(GzipError::Corrupt)
+    }
+}
+
+/// Decompresses a deflate stream onto `out`. Matches may not reach before `start`, the start of the member.
+/// `out` may not grow past `limit` bytes.
+fn inflate(bits: &mut BitReader, out: &mut Vec<u8>, start: usize, limit: usize) -> Result<(), GzipError> {
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.data.get(bits.pos..bits.pos + 4).ok_or(GzipError::Truncated)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen { return Err(GzipError::Corrupt) }
+                bits.pos += 4;
+                let stored = bits.data.get(bits.pos..bits.pos + len as usize).ok_or(GzipError::Truncated)?;
+                if limit - out.len() < stored.len() { return Err(GzipError::Corrupt) }
+                out.extend_from_slice(stored);
+                bits.pos += len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(bits, out, start, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                inflate_block(bits, out, start, limit, &literals, &distances)?;
+            }
+            _ => return Err(GzipError::Corrupt),
+        }
+        if last { break }
+    }
+    bits.align();
+    Ok(())
+}
+
+/// Reads the literal/length and distance codes at the start of a dynamic block.
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), GzipError> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 { return Err(GzipError::Corrupt) }
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let (len, repeat) = match code_lengths.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths[..i].last().ok_or(GzipError::Corrupt)?, 3 + bits.bits(2)? as usize),
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        let repeated = lengths.get_mut(i..i + repeat).ok_or(GzipError::Corrupt)?;
+        repeated.fill(len);
+        i += repeat;
+    }
+    if lengths[256] == 0 { return Err(GzipError::Corrupt) }
+
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    start: usize,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), GzipError> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 if out.len() < limit => out.push(symbol as u8),
+            0..=255 => return Err(GzipError::Corrupt),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() { return Err(GzipError::Corrupt) }
+                let length = LENGTH_BASE[code] as usize + bits.bits(LENGTH_EXTRA[code] as u32)? as usize;
+
+                let code = distances.decode(bits)? as usize;
+                if code >= DIST_BASE.len() { return Err(GzipError::Corrupt) }
+                let dist = DIST_BASE[code] as usize + bits.bits(DIST_EXTRA[code] as u32)? as usize;
+                if dist > out.len() - start || limit - out.len() < length { return Err(GzipError::Corrupt) }
+
+                // byte by byte, as matches may overlap their own output
+                let from = out.len() - dist;
+                for k in 0..length {
+                    out.push(out[from + k]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut data = Vec::new();
+        for i in 0..50000u32 {
+            data.extend_from_slice(&(i / 7).to_be_bytes());
+            data.push((i * 31 % 251) as u8);
+        }
+        for input in [&b""[..], b"a", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab", &data] {
+            let encoded = encode(input);
+            assert_eq!(encoded, encode(input));
+            assert_eq!(decode(&encoded).as_deref(), Ok(input));
+        }
+        assert!(encode(&data).len() < data.len() * 3 / 4);
+        assert!(encode(&[7; 100000]).len() < 1000);
+
+        let mut two_members = encode(b"first ");
+        two_members.extend_from_slice(&encode(b"second"));
+        assert_eq!(decode(&two_members).as_deref(), Ok(&b"first second"[..]));
+    }
+
+    #[test]
+    fn decode_gzip_tool_output() {
+        // Python's gzip module at level 9, with the file name "a.slp". A fixed Huffman block.
+        let fixed = b"\x1f\x8b\x08\x08\x00\x00\x00\x00\x02\xffa.slp\x00+\xce)P(\x86\xe3\xcc\x82\x82L\x85\xa2\xd4\x82\x9c\xc4J\x98(\x17\x00\
+            \xf2S}\x13\"\x00\x00\x00";
+        assert_eq!(decode(fixed).as_deref(), Ok(&b"slp slp slp slippi replay slp slp\n"[..]));
+
+        // a dynamic Huffman block
+        let dynamic = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\xff\x5d\xd3\x4b\x0e\xc2\x30\x0c\x45\xd1\xad\x64\x09\xb1\x9d\
+            \x2f\xac\x86\x01\x9d\x21\xaa\xc2\xfe\x45\x85\x93\x17\xbd\x8e\x2a\xdd\x56\x72\x7c\x94\x6e\xc7\xe3\
+            \xf5\x0c\xf1\x16\xf6\xe3\x7c\x84\xfd\xfd\xf9\x86\x78\x0f\xdb\x3f\x8b\xe7\xea\xd9\x66\x56\xcf\xe2\
+            \xb9\xcc\x6c\x9e\x9b\xe7\x3e\x73\xf2\xac\x9e\x65\xe6\xec\xb9\x7b\x4e\x33\x17\xcf\xe6\xb9\xce\x5c\
+            \xc7\xc8\x71\x42\xc1\x11\x9b\xbf\x48\xde\x75\xe6\x3e\xbe\x1f\x67\xcc\xd8\x68\x6c\x9a\xbd\x37\xf4\
+            \xb1\xaa\xe8\x95\x60\x2c\x5b\x2e\x06\x62\x44\x06\x04\x49\x64\x06\x05\xc9\x84\x06\x06\x29\xa4\x06\
+            \x07\xa9\xc4\x06\x08\x69\xe4\xb6\x20\xa4\x93\x1c\x24\x34\x32\x1d\x28\x54\x88\x0e\x14\xaa\x6c\x87\
+            \x09\x6a\x64\xb7\xae\x43\x62\x3b\x58\x68\x26\x3b\x58\x68\x21\x3b\x58\x68\x25\x3b\x58\x68\x23\x3b\
+            \x58\x68\x27\xbb\x65\x61\x91\xf0\x60\x61\x42\x78\xa0\x30\x25\x3b\x50\x98\xb1\xdd\x1a\x90\xc8\x0e\
+            \x14\x96\xd9\x6e\xfd\x1b\x85\xec\x40\x61\x95\xed\x60\x61\x8d\xec\x60\x61\x9d\xec\x4e\x8b\x1f\x8b\
+            \xfb\xa1\x79\xc2\x03\x00\x00";
+        let text = (0..40).map(|i| format!("frame {}: pre {} post {}; ", i, i * 7 % 13, i * 3 % 11)).collect::<String>();
+        assert_eq!(decode(dynamic), Ok(text.into_bytes()));
+
+        let mut bad_crc = fixed.to_vec();
+        let n = bad_crc.len();
+        bad_crc[n - 8] ^= 1;
+        assert_eq!(decode(&bad_crc), Err(GzipError::ChecksumMismatch));
+        assert_eq!(decode(&fixed[..fixed.len() - 3]), Err(GzipError::Truncated));
+        assert_eq!(decode(&dynamic[..100]), Err(GzipError::Truncated));
+        assert_eq!(decode(b"slp"), Err(GzipError::NotGzip));
+    }
+
+    #[test]
+    fn output_limit() {
+        // matches, literals and stored blocks all stop at the limit
+        let zeros = encode(&[0; 100000]);
+        assert!(zeros.len() < 1000);
+        assert_eq!(decode_with_limit(&zeros, 100000).map(|d| d.len()), Ok(100000));
+        assert_eq!(decode_with_limit(&zeros, 99999), Err(GzipError::Corrupt));
+        assert_eq!(decode_with_limit(&encode(b"abc"), 2), Err(GzipError::Corrupt));
+        let stored = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\xff\x01\x03\x00\xfc\xffabc\xc2\x41\x24\x35\x03\x00\x00\x00";
+        assert_eq!(decode(stored).as_deref(), Ok(&b"abc"[..]));
+        assert_eq!(decode_with_limit(stored, 2), Err(GzipError::Corrupt));
+
+        // the limit covers every member
+        let mut two_members = encode(&[0; 600]);
+        two_members.extend_from_slice(&encode(&[0; 600]));
+        assert_eq!(decode_with_limit(&two_members, 1000), Err(GzipError::Corrupt));
+    }
+}
@@ -0,0 +1,339 @@
+//! The journal written with [`Options::journal`](crate::Options::journal), and [`audit`], which checks one against the files on disk
+//! after a crash.
+//!
+//! # Format
+//!
+//! A journal is a UTF-8 text file of lines ending in `\n`, with fields separated by tabs. The first line is `slpz-journal 1`.
+//! Each file processed by [`target_path`](crate::target_path) then adds these records in order, as far as it gets:
+//!
+//! ```text
+//! started   <id> <input> <output>   before the output is written
+//! written   <id> <input> <output>   after the output is renamed into place
+//! verified  <id> <input> <output>   after the output was read back and holds the replay <id>
+//! removed   <id> <input> <output>   after the input was removed with `keep` false
+//! ```
+//!
+//! `<id>` is the [`replay_id`](crate::replay_id) of the replay in hex. Paths are absolute. Backslashes, tabs, carriage returns
+//! and newlines in them are written as `\\`, `\t`, `\r` and `\n`, and paths that are not UTF-8 are written lossily.
+//! Each line is synced to disk before slpz goes on, so a crash loses at most the line being written.
+//!
+//! A run appends to an existing journal. Lines without a newline, cut off by a crash, are ignored, as are records
+//! with an unknown name, which later versions may add.
+
+use crate::display_escaped;
+
+/// The first line of a journal.
+pub const JOURNAL_HEADER: &str = "slpz-journal 1";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JournalStage {
+    Started,
+    Written,
+    Verified,
+    Removed,
+}
+
+impl JournalStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            JournalStage::Started => "started",
+            JournalStage::Written => "written",
+            JournalStage::Verified => "verified",
+            JournalStage::Removed => "removed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<JournalStage> {
+        [JournalStage::Started, JournalStage::Written, JournalStage::Verified, JournalStage::Removed]
+            .into_iter()
+            .find(|s| s.name() == name)
+    }
+}
+
+/// One line of a journal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalRecord {
+    pub stage: JournalStage,
+    pub id: [u8; 32],
+    pub input: std::path::PathBuf,
+    pub output: std::path::PathBuf,
+}
+
+impl JournalRecord {
+    /// The line for the record, without the newline.
+    pub fn to_line(&self) -> String {
+        let id = self.id.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        format!("{}\t{}\t{}\t{}", self.stage.name(), id, escape(&self.input), escape(&self.output))
+    }
+
+    /// Parses a line without its newline. Returns `None` for unknown records and malformed lines.
+    pub fn parse(line: &str) -> Option<JournalRecord> {
+        let mut fields = line.split('\t');
+        let stage = JournalStage::from_name(fields.next()?)?;
+        let id_hex = fields.next()?;
+        if id_hex.len() != 64 || !id_hex.is_ascii() { return None }
+        let mut id = [0; 32];
+        for (i, byte) in id.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&id_hex[i * 2..][..2], 16).ok()?;
+        }
+        let input = unescape(fields.next()?)?;
+        let output = unescape(fields.next()?)?;
+        if fields.next().is_some() { return None }
+        Some(JournalRecord { stage, id, input, output })
+    }
+}
+
+fn escape(path: &std::path::Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Option<std::path::PathBuf> {
+    let mut path = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        path.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'r' => '\r',
+                'n' => '\n',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(path.into())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JournalError {
+    /// The file does not start with [`JOURNAL_HEADER`] or the header of another version.
+    NotJournal,
+    /// The journal was written by a later version of slpz.
+    UnsupportedVersion { version: u32 },
+}
+
+impl std::fmt::Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JournalError::NotJournal => write!(f, "Not an slpz journal"),
+            JournalError::UnsupportedVersion { version } =>
+                write!(f, "Journal version {} is not supported. This version of slpz reads version 1", version),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+/// The records of a journal, read with [`parse_journal`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JournalContents {
+    pub records: Vec<JournalRecord>,
+    /// Complete lines that are not records this version knows.
+    pub unknown_lines: usize,
+    /// Whether the last line was cut off, as by a crash while it was written.
+    pub truncated: bool,
+}
+
+/// Parses a journal. Only fails if it does not start with a journal header.
+pub fn parse_journal(journal: &[u8]) -> Result<JournalContents, JournalError> {
+    let text = String::from_utf8_lossy(journal);
+    let mut lines = text.split_inclusive('\n');
+    match lines.next().map(|l| l.strip_suffix('\n').unwrap_or(l)) {
+        Some(JOURNAL_HEADER) => {}
+        Some(header) => return Err(match header.strip_prefix("slpz-journal ").and_then(|v| v.parse().ok()) {
+            Some(version) => JournalError::UnsupportedVersion { version },
+            None => JournalError::NotJournal,
+        }),
+        None => return Err(JournalError::NotJournal),
+    }
+
+    let mut contents = JournalContents::default();
+    for line in lines {
+        let Some(line) = line.strip_suffix('\n') else {
+            contents.truncated = true;
+            break;
+        };
+        match JournalRecord::parse(line) {
+            Some(record) => contents.records.push(record),
+            None => contents.unknown_lines += 1,
+        }
+    }
+    Ok(contents)
+}
+
+/// An open journal. Records from several threads are written one at a time.
+#[derive(Debug)]
+pub struct Journal {
+    file: std::sync::Mutex<std::fs::File>,
+}
+
+impl Journal {
+    /// Opens a journal to append to, creating it with a header if it is empty or missing.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the file is not a journal. If the last line was cut off by a crash,
+    /// it is ended so that new records start on their own line.
+    pub fn open(path: &std::path::Path) -> std::io::Result<Journal> {
+        use std::io::{Read, Write};
+
+        let mut file = std::fs::OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut existing = Vec::new();
+        file.read_to_end(&mut existing)?;
+        if existing.is_empty() {
+            writeln!(file, "{}", JOURNAL_HEADER)?;
+        } else {
+            parse_journal(&existing).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            if existing.last() != Some(&b'\n') { file.write_all(b"\n")?; }
+        }
+        file.sync_data()?;
+        Ok(Journal { file: std::sync::Mutex::new(file) })
+    }
+
+    /// Appends a record and syncs it to disk.
+    pub fn record(&self, record: &JournalRecord) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(format!("{}\n", record.to_line()).as_bytes())?;
+        file.sync_data()
+    }
+}
+
+/// Something [`audit`] found that needs a look.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditIssue {
+    /// The output was started or written but never verified, so it may be incomplete.
+    Unverified { input: std::path::PathBuf, output: std::path::PathBuf },
+    /// The input is gone, but the journal does not record removing it.
+    InputMissing { input: std::path::PathBuf },
+    /// The input was removed, but its output is gone.
+    OutputMissing { input: std::path::PathBuf, output: std::path::PathBuf },
+    /// A temporary file left by an output that was never renamed into place.
+    TempFile { path: std::path::PathBuf },
+}
+
+impl std::fmt::Display for AuditIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditIssue::Unverified { input, output } =>
+                write!(f, "{} was written from {} but never verified", display_escaped(output), display_escaped(input)),
+            AuditIssue::InputMissing { input } =>
+                write!(f, "{} is missing, but the journal does not record removing it", display_escaped(input)),
+            AuditIssue::OutputMissing { input, output } =>
+                write!(f, "{} was removed, but its output {} is missing", display_escaped(input), display_escaped(output)),
+            AuditIssue::TempFile { path } => write!(f, "{} is a partly written output", display_escaped(path)),
+        }
+    }
+}
+
+/// The result of [`audit`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// The number of inputs in `dir` the journal has records for.
+    pub files: usize,
+    pub issues: Vec<AuditIssue>,
+}
+
+/// Checks the files in `dir` against a journal, for inputs and outputs inside `dir`.
+///
+/// The last record for each input says how far it got. Outputs that were not verified, inputs that are gone without
+/// being removed, and removed inputs whose outputs are gone are reported, along with files inside `dir` ending in
+/// `temp_suffix`. See [`Options::temp_suffix`](crate::Options::temp_suffix).
+pub fn audit(journal: &JournalContents, dir: &std::path::Path, temp_suffix: &str) -> AuditReport {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut report = AuditReport::default();
+
+    let mut last = std::collections::HashMap::new();
+    let mut inputs = Vec::new();
+    for record in journal.records.iter().filter(|r| r.input.starts_with(&dir) || r.output.starts_with(&dir)) {
+        if last.insert(&record.input, record).is_none() { inputs.push(&record.input) }
+    }
+    report.files = inputs.len();
+
+    for input in inputs {
+        let record = last[input];
+        let input = record.input.clone();
+        let output = record.output.clone();
+        match record.stage {
+            JournalStage::Started | JournalStage::Written => {
+                if output.exists() { report.issues.push(AuditIssue::Unverified { input: input.clone(), output }) }
+                if !input.exists() { report.issues.push(AuditIssue::InputMissing { input }) }
+            }
+            JournalStage::Verified if !input.exists() => report.issues.push(AuditIssue::InputMissing { input }),
+            JournalStage::Verified => {}
+            JournalStage::Removed if !output.exists() => report.issues.push(AuditIssue::OutputMissing { input, output }),
+            JournalStage::Removed => {}
+        }
+    }
+
+    if !temp_suffix.is_empty() { find_temp_files(&dir, temp_suffix, &mut report.issues) }
+    report
+}
+
+fn find_temp_files(dir: &std::path::Path, temp_suffix: &str, issues: &mut Vec<AuditIssue>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let mut paths = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        let is_dir = std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+        if is_dir {
+            find_temp_files(&path, temp_suffix, issues);
+        } else if path.file_name().is_some_and(|n| n.to_string_lossy().ends_with(temp_suffix)) {
+            issues.push(AuditIssue::TempFile { path });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip() {
+        let record = JournalRecord {
+            stage: JournalStage::Written,
+            id: [0xAB; 32],
+            input: "/replays/odd\tname\\game.slp".into(),
+            output: "/replays/odd\tname\\game.slpz".into(),
+        };
+        let line = record.to_line();
+        assert!(line.starts_with("written\tabab"));
+        assert_eq!(line.matches('\t').count(), 3);
+        assert_eq!(JournalRecord::parse(&line), Some(record));
+
+        assert_eq!(JournalRecord::parse("renamed\tab\t/a\t/b"), None);
+        assert_eq!(JournalRecord::parse(&line.replacen("abab", "zzzz", 1)), None);
+    }
+
+    #[test]
+    fn truncated_journal() {
+        let record = |stage, input: &str| JournalRecord { stage, id: [1; 32], input: input.into(), output: format!("{}z", input).into() };
+        let mut journal = format!("{}\n", JOURNAL_HEADER);
+        for r in [record(JournalStage::Started, "/a.slp"), record(JournalStage::Written, "/a.slp")] {
+            journal += &format!("{}\n", r.to_line());
+        }
+        journal += "future\tfield\n";
+        let cut = record(JournalStage::Verified, "/a.slp").to_line();
+        journal += &cut[..cut.len() / 2];
+
+        let contents = parse_journal(journal.as_bytes()).unwrap();
+        assert_eq!(contents.records.len(), 2);
+        assert_eq!(contents.unknown_lines, 1);
+        assert!(contents.truncated);
+
+        assert_eq!(parse_journal(b"slpz-journal 2\n"), Err(JournalError::UnsupportedVersion { version: 2 }));
+        assert_eq!(parse_journal(b"{\"not\": 1}"), Err(JournalError::NotJournal));
+    }
+}
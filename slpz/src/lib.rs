@@ -0,0 +1,75 @@
+//! Compresses and decompresses between the slp and slpz formats.
+//!
+//! You can expect slpz files to be around 8x to 12x times smaller than slp files for regular matches.
+//! (~3Mb down to ~300Kb).
+//!
+//! Compression is done with the zstd compression library.
+//! zstd is not required on the user's computer; the library is statically linked at compile time.
+//!
+//! The slpz format is documented in the readme in the repo.
+//! Important information, such as player tags, stages, date, characters, etc. all remain uncompressed in the slpz format.
+//! This allows slp file browsers to easily parse and display this information without needing to decompress the replay.
+
+//!
+//! Without the default `std` feature the crate is `no_std`, and only [`embedded`] and [`tables`] are available,
+//! for decompressing on targets without std.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod config;
+pub mod embedded;
+#[cfg(feature = "gzip")]
+pub mod gzip;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+mod std_api;
+pub mod tables;
+
+#[cfg(feature = "std")]
+pub use config::ConfigError;
+pub use embedded::{
+    format_version, is_slpz, SlpzHeader, FLAG_CHUNKED, FLAG_REPLAY_ID, FLAG_SEGMENTED, FLAG_STORED,
+    MIN_SUPPORTED_FORMAT_VERSION, SLPZ_FORMAT_VERSION,
+};
+#[cfg(feature = "std")]
+pub use journal::{audit, parse_journal, AuditIssue, AuditReport, Journal, JournalError, JournalRecord, JournalStage};
+#[cfg(feature = "std")]
+pub use metadata::{Metadata, MetadataPlayer, UbjsonError, UbjsonValue};
+#[cfg(feature = "std")]
+pub use std_api::*;
+
+/// The changes in each version of slpz.
+///
+/// Also available as text from [`changelog()`], and the breaking changes as [`BREAKING_CHANGES_SINCE_1_0`].
+#[doc = include_str!("../CHANGELOG.md")]
+pub mod changelog {}
+
+/// Returns the text of the changelog, in markdown.
+pub fn changelog() -> &'static str {
+    include_str!("../CHANGELOG.md")
+}
+
+/// (version, description) for each breaking change since 1.0, oldest first.
+/// Unreleased changes have the version "Unreleased". Matches the changelog.
+pub const BREAKING_CHANGES_SINCE_1_0: &[(&str, &str)] = &[
+    ("Unreleased", "Files are written in slpz format version 2, which slpz 1.1.1 and earlier cannot read"),
+    ("Unreleased", "Options is no longer Copy, and has new fields"),
+    ("Unreleased", "CompError, DecompError and TargetPathError have new variants"),
+    ("Unreleased", "Compressor::new accepts levels from COMPRESS_LEVEL_MIN to COMPRESS_LEVEL_MAX, and level 0 stores without compressing"),
+    ("Unreleased", "Directory walks skip hidden files and symlinks by default"),
+    ("Unreleased", "Directory walks skip files that are still being written, or already in the output format"),
+    ("Unreleased", "--fast uses level -1"),
+    ("Unreleased", "--rm and Options::keep = false move files to the trash instead of deleting them"),
+    ("Unreleased", "CompError::InvalidFile is replaced by CompError::Corrupt, and CompError::NotSlp has a detected format"),
+    ("Unreleased", "Options::log is a LogLevel instead of a bool, and --quiet still prints a summary line"),
+    ("Unreleased", "target_path returns a BatchResult, and the slpz program prints the summary line instead"),
+    ("Unreleased", "compress returns a CompressOutput with warnings, and compress_unchecked returns the bytes"),
+    ("Unreleased", "The slpz program is built by the slpz-cli crate, which has the http feature"),
+    ("Unreleased", "Compressor::set_strict and Options::strict are replaced by Compressor::set_strictness and Options::strictness"),
+];
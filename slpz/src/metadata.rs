@@ -0,0 +1,505 @@
+//! The metadata element of a replay, parsed from UBJSON.
+//!
+//! Only the UBJSON that Slippi writes is needed, but every type is read so unknown keys can still be reached with
+//! [`Metadata::get`]. Strings that are not UTF-8, such as names from old Slippi versions, are converted lossily.
+//! The same reader also skips over values without building them, to find where the metadata ends in an slp file.
+
+use crate::GameTimestamp;
+
+/// Containers nested more deeply than this are rejected, so parsing cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UbjsonError {
+    /// The data ends inside a value.
+    Truncated { offset: usize },
+    /// A type marker that is not UBJSON, or not allowed where it is.
+    InvalidMarker { marker: u8, offset: usize },
+    /// A string or container length that is negative or longer than the data.
+    InvalidLength { offset: usize },
+    /// Containers are nested more deeply than 64 levels.
+    TooDeep { offset: usize },
+    /// The metadata is not an object.
+    NotObject,
+}
+
+impl std::fmt::Display for UbjsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UbjsonError::Truncated { offset } => write!(f, "UBJSON ends inside a value at offset {}", offset),
+            UbjsonError::InvalidMarker { marker, offset } =>
+                write!(f, "Invalid UBJSON type marker 0x{:02X} at offset {}", marker, offset),
+            UbjsonError::InvalidLength { offset } => write!(f, "Invalid UBJSON length at offset {}", offset),
+            UbjsonError::TooDeep { offset } => write!(f, "UBJSON nested too deeply at offset {}", offset),
+            UbjsonError::NotObject => write!(f, "Metadata is not a UBJSON object"),
+        }
+    }
+}
+
+impl std::error::Error for UbjsonError {}
+
+impl UbjsonError {
+    /// The offset where parsing stopped, from the start of the parsed bytes.
+    pub(crate) fn offset(&self) -> Option<usize> {
+        match *self {
+            UbjsonError::Truncated { offset }
+            | UbjsonError::InvalidMarker { offset, .. }
+            | UbjsonError::InvalidLength { offset }
+            | UbjsonError::TooDeep { offset } => Some(offset),
+            UbjsonError::NotObject => None,
+        }
+    }
+}
+
+/// A UBJSON value. Objects keep their keys in order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UbjsonValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(u8),
+    String(String),
+    /// A high precision number, as its decimal string.
+    HighPrecision(String),
+    Array(Vec<UbjsonValue>),
+    Object(Vec<(String, UbjsonValue)>),
+}
+
+impl UbjsonValue {
+    /// The value of a key, if this is an object with it.
+    pub fn get(&self, key: &str) -> Option<&UbjsonValue> {
+        self.as_object()?.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            UbjsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            UbjsonValue::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[UbjsonValue]> {
+        match self {
+            UbjsonValue::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, UbjsonValue)]> {
+        match self {
+            UbjsonValue::Object(o) => Some(o),
+            _ => None,
+        }
+    }
+}
+
+/// A player from the metadata's `players` object. See [`Metadata::players`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataPlayer {
+    /// 0 to 3.
+    pub port: u8,
+    /// The netplay display name. None for console recordings, and replays from before Slippi recorded names.
+    pub name: Option<String>,
+    /// The connect code, like `ABCD#123`.
+    pub connect_code: Option<String>,
+    /// (external character ID, frames played) for each character the player used. See [`crate::tables::CHARACTERS`].
+    pub characters: Vec<(u8, u32)>,
+}
+
+/// The metadata of a replay. See [`crate::read_metadata`] to find it in an slp or slpz file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    root: UbjsonValue,
+}
+
+impl Metadata {
+    /// Parses the metadata object, optionally preceded by its `U\x08metadata` key as stored in replays.
+    /// Anything after the object, such as the end of the slp file, is ignored.
+    pub fn parse(bytes: &[u8]) -> Result<Metadata, UbjsonError> {
+        const KEY: &[u8] = b"U\x08metadata";
+        let offset = if bytes.starts_with(KEY) { KEY.len() } else { 0 };
+        let mut reader = Reader { data: bytes, offset, depth: 0, non_utf8: false };
+        match reader.value()? {
+            root @ UbjsonValue::Object(_) => Ok(Metadata { root }),
+            _ => Err(UbjsonError::NotObject),
+        }
+    }
+
+    /// The metadata object.
+    pub fn root(&self) -> &UbjsonValue { &self.root }
+
+    /// Looks up a value by its keys separated by dots, like `players.0.names.code`.
+    /// Array elements are looked up by their index.
+    pub fn get(&self, path: &str) -> Option<&UbjsonValue> {
+        path.split('.').try_fold(&self.root, |value, key| match value {
+            UbjsonValue::Array(a) => a.get(key.parse::<usize>().ok()?),
+            _ => value.get(key),
+        })
+    }
+
+    /// When the game started, from `startAt`. Usually UTC.
+    pub fn start_at(&self) -> Option<GameTimestamp> {
+        GameTimestamp::parse_iso8601(self.get("startAt")?.as_str()?.as_bytes())
+    }
+
+    /// The last frame number, from `lastFrame`.
+    pub fn last_frame(&self) -> Option<i32> {
+        i32::try_from(self.get("lastFrame")?.as_i64()?).ok()
+    }
+
+    /// What recorded the replay, usually `dolphin`, `network` or `nintendont`.
+    pub fn played_on(&self) -> Option<&str> {
+        self.get("playedOn")?.as_str()
+    }
+
+    /// The name of the console, for replays recorded on a console.
+    pub fn console_nick(&self) -> Option<&str> {
+        self.get("consoleNick")?.as_str()
+    }
+
+    /// Each player in `players`, sorted by port. Ports that are not numbers from 0 to 3 are skipped.
+    pub fn players(&self) -> Vec<MetadataPlayer> {
+        let Some(players) = self.get("players").and_then(UbjsonValue::as_object) else { return Vec::new() };
+        let mut players = players.iter()
+            .filter_map(|(port, player)| {
+                let port = port.parse::<u8>().ok().filter(|&p| p < 4)?;
+                let names = player.get("names");
+                let name = |key| names?.get(key)?.as_str().filter(|s| !s.is_empty()).map(str::to_string);
+                let characters = player.get("characters").and_then(UbjsonValue::as_object).unwrap_or_default()
+                    .iter()
+                    .filter_map(|(character, frames)| Some((character.parse().ok()?, u32::try_from(frames.as_i64()?).ok()?)))
+                    .collect();
+                Some(MetadataPlayer { port, name: name("netplay"), connect_code: name("code"), characters })
+            })
+            .collect::<Vec<_>>();
+        players.sort_by_key(|p| p.port);
+        players
+    }
+}
+
+/// Returns the length of the remaining key value pairs of a UBJSON object, including the closing '}',
+/// or where they stop parsing. Used to find the end of the metadata in an slp file, whose root object it is in.
+pub(crate) fn object_rest_len(data: &[u8]) -> Result<usize, UbjsonError> {
+    let mut reader = Reader { data, offset: 0, depth: 1, non_utf8: false };
+    reader.skip_elements(true, None, None)?;
+    Ok(reader.offset)
+}
+
+/// Returns true if a UBJSON value has a string or object key that is not UTF-8.
+/// Stops at the end of the value, or where it is malformed.
+pub(crate) fn has_non_utf8_string(data: &[u8]) -> bool {
+    let mut reader = Reader { data, offset: 0, depth: 0, non_utf8: false };
+    let _ = reader.skip_value();
+    reader.non_utf8
+}
+
+/// Size of the payload of fixed size UBJSON types.
+fn scalar_size(marker: u8) -> Option<usize> {
+    Some(match marker {
+        b'Z' | b'T' | b'F' => 0,
+        b'i' | b'U' | b'C' => 1,
+        b'I' => 2,
+        b'l' | b'd' => 4,
+        b'L' | b'D' => 8,
+        _ => return None,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    depth: usize,
+    /// Set when a skipped string is not UTF-8.
+    non_utf8: bool,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, UbjsonError> {
+        let b = *self.data.get(self.offset).ok_or(UbjsonError::Truncated { offset: self.offset })?;
+        self.offset += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], UbjsonError> {
+        let bytes = self.data.get(self.offset..).and_then(|d| d.get(..len)).ok_or(UbjsonError::Truncated { offset: self.offset })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize { self.data.len().saturating_sub(self.offset) }
+
+    /// Reads a value, skipping no-op markers before it.
+    fn value(&mut self) -> Result<UbjsonValue, UbjsonError> {
+        loop {
+            match self.byte()? {
+                b'N' => continue,
+                marker => return self.typed_value(marker),
+            }
+        }
+    }
+
+    fn typed_value(&mut self, marker: u8) -> Result<UbjsonValue, UbjsonError> {
+        let offset = self.offset - 1;
+        Ok(match marker {
+            b'Z' => UbjsonValue::Null,
+            b'T' => UbjsonValue::Bool(true),
+            b'F' => UbjsonValue::Bool(false),
+            b'i' | b'U' | b'I' | b'l' | b'L' => UbjsonValue::Int(self.int(marker)?),
+            b'd' => UbjsonValue::Float(f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64),
+            b'D' => UbjsonValue::Float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            b'C' => UbjsonValue::Char(self.byte()?),
+            b'S' => UbjsonValue::String(self.string()?),
+            b'H' => UbjsonValue::HighPrecision(self.string()?),
+            b'[' | b'{' => self.container(marker == b'{', offset)?,
+            _ => return Err(UbjsonError::InvalidMarker { marker, offset }),
+        })
+    }
+
+    fn int(&mut self, marker: u8) -> Result<i64, UbjsonError> {
+        Ok(match marker {
+            b'i' => self.byte()? as i8 as i64,
+            b'U' => self.byte()? as i64,
+            b'I' => i16::from_be_bytes(self.take(2)?.try_into().unwrap()) as i64,
+            b'l' => i32::from_be_bytes(self.take(4)?.try_into().unwrap()) as i64,
+            b'L' => i64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err(UbjsonError::InvalidMarker { marker, offset: self.offset - 1 }),
+        })
+    }
+
+    /// Reads a string or container length, which can be at most the bytes left.
+    fn length(&mut self) -> Result<usize, UbjsonError> {
+        let offset = self.offset;
+        let marker = self.byte()?;
+        let len = self.int(marker)?;
+        usize::try_from(len).ok()
+            .filter(|&len| len <= self.remaining())
+            .ok_or(UbjsonError::InvalidLength { offset })
+    }
+
+    /// Reads a string without its 'S' marker, as used for object keys.
+    fn string(&mut self) -> Result<String, UbjsonError> {
+        let len = self.length()?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// Reads the element type and count of an optimized container, if present.
+    fn container_header(&mut self, offset: usize) -> Result<(Option<u8>, Option<usize>), UbjsonError> {
+        if self.depth == MAX_DEPTH { return Err(UbjsonError::TooDeep { offset }) }
+        self.depth += 1;
+
+        let mut element_type = None;
+        if self.data.get(self.offset) == Some(&b'$') {
+            self.offset += 1;
+            element_type = Some(self.byte()?);
+        }
+        let count = match self.data.get(self.offset) {
+            Some(b'#') => {
+                self.offset += 1;
+                Some(self.length()?)
+            }
+            _ if element_type.is_some() => return Err(UbjsonError::InvalidMarker { marker: b'$', offset: offset + 1 }),
+            _ => None,
+        };
+        Ok((element_type, count))
+    }
+
+    fn container(&mut self, is_object: bool, offset: usize) -> Result<UbjsonValue, UbjsonError> {
+        let (element_type, count) = self.container_header(offset)?;
+
+        let end = if is_object { b'}' } else { b']' };
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for _ in 0..count.unwrap_or(usize::MAX) {
+            if count.is_none() {
+                while self.data.get(self.offset) == Some(&b'N') { self.offset += 1; }
+                if self.data.get(self.offset) == Some(&end) {
+                    self.offset += 1;
+                    break;
+                }
+            }
+            if is_object { keys.push(self.string()?); }
+            values.push(match element_type {
+                Some(t) => self.typed_value(t)?,
+                None => self.value()?,
+            });
+        }
+
+        self.depth -= 1;
+        Ok(match is_object {
+            true => UbjsonValue::Object(keys.into_iter().zip(values).collect()),
+            false => UbjsonValue::Array(values),
+        })
+    }
+
+    /// Skips a value like [`Reader::value`] without building it.
+    fn skip_value(&mut self) -> Result<(), UbjsonError> {
+        loop {
+            match self.byte()? {
+                b'N' => continue,
+                marker => return self.skip_typed_value(marker),
+            }
+        }
+    }
+
+    fn skip_typed_value(&mut self, marker: u8) -> Result<(), UbjsonError> {
+        let offset = self.offset - 1;
+        if let Some(size) = scalar_size(marker) {
+            self.take(size)?;
+            return Ok(());
+        }
+        match marker {
+            b'S' | b'H' => self.skip_string(),
+            b'[' | b'{' => {
+                let (element_type, count) = self.container_header(offset)?;
+                self.skip_elements(marker == b'{', element_type, count)?;
+                self.depth -= 1;
+                Ok(())
+            }
+            _ => Err(UbjsonError::InvalidMarker { marker, offset }),
+        }
+    }
+
+    fn skip_string(&mut self) -> Result<(), UbjsonError> {
+        let len = self.length()?;
+        let bytes = self.take(len)?;
+        if std::str::from_utf8(bytes).is_err() { self.non_utf8 = true }
+        Ok(())
+    }
+
+    /// Skips the elements of a container after its header, and the closing marker if it has no count.
+    fn skip_elements(&mut self, is_object: bool, element_type: Option<u8>, count: Option<usize>) -> Result<(), UbjsonError> {
+        // fixed size elements, such as the raw element, are skipped at once
+        if let (false, Some(size), Some(count)) = (is_object, element_type.and_then(scalar_size), count) {
+            let len = count.checked_mul(size).ok_or(UbjsonError::InvalidLength { offset: self.offset })?;
+            self.take(len)?;
+            return Ok(());
+        }
+
+        let end = if is_object { b'}' } else { b']' };
+        for _ in 0..count.unwrap_or(usize::MAX) {
+            if count.is_none() {
+                while self.data.get(self.offset) == Some(&b'N') { self.offset += 1; }
+                if self.data.get(self.offset) == Some(&end) {
+                    self.offset += 1;
+                    break;
+                }
+            }
+            if is_object { self.skip_string()?; }
+            match element_type {
+                Some(t) => self.skip_typed_value(t)?,
+                None => self.skip_value()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETPLAY: &[u8] = b"U\x08metadata{U\x07startAtSU\x142024-01-01T18:30:12ZU\x09lastFramel\x00\x00\x12\xD7\
+        U\x07players{U\x010{U\x0Acharacters{U\x019I\x12\xD7}U\x05names{U\x07netplaySU\x05AitchU\x04codeSU\x08AITC#123}}\
+        U\x011{U\x0Acharacters{U\x0220I\x12\x00U\x012U\x50}U\x05names{U\x07netplaySU\x00U\x04codeSU\x00}}}\
+        U\x08playedOnSU\x07dolphin}}";
+
+    // recorded on a console: there is a console name, and no names blocks
+    const CONSOLE: &[u8] = b"U\x08metadata{U\x07startAtSU\x182019-06-02T21:04:45.137ZU\x09lastFramel\x00\x00\x0B\xB8\
+        U\x07players{U\x012{U\x0Acharacters{U\x0215l\x00\x00\x0C\x73}}U\x013{U\x0Acharacters{U\x012l\x00\x00\x0C\x73}}}\
+        U\x08playedOnSU\x0AnintendontU\x0BconsoleNickSU\x09Couch WiiN}}";
+
+    #[test]
+    fn netplay_metadata() {
+        let metadata = Metadata::parse(NETPLAY).unwrap();
+        assert_eq!(metadata.start_at().unwrap().to_string(), "2024-01-01T18:30:12");
+        assert_eq!(metadata.last_frame(), Some(4823));
+        assert_eq!(metadata.played_on(), Some("dolphin"));
+        assert_eq!(metadata.console_nick(), None);
+        assert_eq!(metadata.players(), [
+            MetadataPlayer { port: 0, name: Some("Aitch".into()), connect_code: Some("AITC#123".into()), characters: vec![(9, 4823)] },
+            MetadataPlayer { port: 1, name: None, connect_code: None, characters: vec![(20, 4608), (2, 80)] },
+        ]);
+        assert_eq!(metadata.get("players.0.names.code").and_then(UbjsonValue::as_str), Some("AITC#123"));
+        assert_eq!(metadata.get("players.4"), None);
+    }
+
+    #[test]
+    fn console_metadata() {
+        let metadata = Metadata::parse(CONSOLE).unwrap();
+        assert_eq!(metadata.start_at().unwrap().to_string(), "2019-06-02T21:04:45");
+        assert_eq!(metadata.played_on(), Some("nintendont"));
+        assert_eq!(metadata.console_nick(), Some("Couch Wii"));
+        let players = metadata.players();
+        assert_eq!(players.iter().map(|p| (p.port, p.name.clone(), p.connect_code.clone())).collect::<Vec<_>>(), [(2, None, None), (3, None, None)]);
+        assert_eq!(players[1].characters, [(2, 3187)]);
+    }
+
+    #[test]
+    fn ubjson_values() {
+        let parse = |bytes: &[u8]| Metadata::parse(bytes).map(|m| m.root().clone());
+        assert_eq!(parse(b"{}"), Ok(UbjsonValue::Object(vec![])));
+        assert_eq!(
+            parse(b"{U\x01a[$i#U\x03\x01\x02\xFFU\x01b[TFZd\x3F\x80\x00\x00CxHU\x02\x31\x30]}").unwrap().get("b"),
+            Some(&UbjsonValue::Array(vec![
+                UbjsonValue::Bool(true),
+                UbjsonValue::Bool(false),
+                UbjsonValue::Null,
+                UbjsonValue::Float(1.0),
+                UbjsonValue::Char(b'x'),
+                UbjsonValue::HighPrecision("10".into()),
+            ])),
+        );
+        assert_eq!(
+            Metadata::parse(b"{U\x01a[$i#U\x03\x01\x02\xFF}").unwrap().get("a.2"),
+            Some(&UbjsonValue::Int(-1)),
+        );
+        assert_eq!(parse(b"{#U\x01U\x01kSU\x01v"), Ok(UbjsonValue::Object(vec![("k".into(), UbjsonValue::String("v".into()))])));
+
+        assert_eq!(parse(b"[]"), Err(UbjsonError::NotObject));
+        assert_eq!(parse(b"{U\x01aSU\x05ab"), Err(UbjsonError::InvalidLength { offset: 5 }));
+        assert_eq!(parse(b"{U\x01aSU\x02ab"), Err(UbjsonError::Truncated { offset: 9 }));
+        assert_eq!(parse(b"{U\x01aX}"), Err(UbjsonError::InvalidMarker { marker: b'X', offset: 4 }));
+        assert_eq!(parse(b"{U\x01a[$i]}"), Err(UbjsonError::InvalidMarker { marker: b'$', offset: 5 }));
+        assert_eq!(parse(b"{U\x01a[#L\x7F\xFF\xFF\xFF\xFF\xFF\xFF\xFF]}"), Err(UbjsonError::InvalidLength { offset: 6 }));
+        let deep = [b"{U\x01a".as_slice(), &[b'['; 64]].concat();
+        assert_eq!(parse(&deep), Err(UbjsonError::TooDeep { offset: 67 }));
+    }
+
+    #[test]
+    fn ubjson_skipping() {
+        let rest = b"U\x08metadata{U\x07startAtSU\x142024-01-01T00:00:00ZU\x09lastFramel\x00\x00\x12\xD7U\x07players{U\x010{U\x05names{U\x07netplaySU\x03abc}}}U\x08playedOnSU\x07dolphin}}";
+        assert_eq!(object_rest_len(rest), Ok(rest.len()));
+        assert_eq!(object_rest_len(b"N}trailer"), Ok(2));
+        assert_eq!(object_rest_len(b"U\x01aSU\x05ab}"), Err(UbjsonError::InvalidLength { offset: 4 }));
+
+        let value_len = |bytes: &[u8]| {
+            let mut reader = Reader { data: bytes, offset: 0, depth: 0, non_utf8: false };
+            reader.skip_value().map(|()| reader.offset)
+        };
+        assert_eq!(value_len(b"[$U#i\x03abc"), Ok(9));
+        assert_eq!(value_len(b"[$U#i\x03ab"), Err(UbjsonError::InvalidLength { offset: 4 }));
+        assert_eq!(value_len(b"{#i\x01U\x01ai\x05"), Ok(9));
+        assert_eq!(value_len(b"[i\x01SU\x01a]"), Ok(8));
+        assert_eq!(value_len(b"[$S#U\x02U\x01aU\x02bc"), Ok(13));
+        // each typed element is skipped once, so long containers in nested ones stay linear
+        let mut nested = b"[$[#U\x02".to_vec();
+        for _ in 0..2 { nested.extend_from_slice(b"$U#I\x10\x00"); nested.extend_from_slice(&[0; 4096]); }
+        assert_eq!(value_len(&nested), Ok(nested.len()));
+        let deep = [&[b'['; 65][..], &[b']'; 65]].concat();
+        assert_eq!(value_len(&deep), Err(UbjsonError::TooDeep { offset: 64 }));
+
+        assert!(!has_non_utf8_string(b"{U\x04name[SU\x02\xC3\xA9]}"));
+        assert!(has_non_utf8_string(b"{U\x04name[SU\x01\xC3]}"));
+        assert!(has_non_utf8_string(b"{U\x01\xE9i\x01}"));
+        assert!(has_non_utf8_string(b"[$S#U\x01U\x01\xE9]"));
+    }
+}
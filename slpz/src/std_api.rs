@@ -0,0 +1,8424 @@
+//! Compression and decompression with zstd, and files and directories, built with the `std` feature.
+//! Everything here is re-exported at the crate root.
+
+use crate::{embedded, tables};
+#[cfg(feature = "gzip")]
+use crate::gzip;
+use crate::embedded::{
+    event_sizes, is_slpz, PayloadTiles, SlpzHeader, EVENT_PAYLOADS, FLAG_CHUNKED, FLAG_REPLAY_ID, FLAG_SEGMENTED,
+    FLAG_STORED, MIN_SUPPORTED_FORMAT_VERSION, RAW_HEADER, SLPZ_FORMAT_VERSION,
+};
+use crate::journal::{Journal, JournalRecord, JournalStage};
+use crate::metadata::{has_non_utf8_string, object_rest_len, Metadata};
+
+// Spans and events for the `tracing` feature. Without the feature they expand to nothing,
+// and their arguments are not evaluated.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($t:tt)*) => { tracing::info_span!($($t)*).entered() };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($t:tt)*) => { () };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! record {
+    ($span:expr, $field:literal, $value:expr) => { $span.record($field, $value) };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! record {
+    ($($t:tt)*) => { () };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($t:tt)*) => { tracing::$level!($($t)*) };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($t:tt)*) => { () };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompError {
+    /// The file does not start with the slp raw element. See [`is_slp`].
+    /// `detected` is the format the file looks like instead, if it is a common one. See [`detect_format`].
+    NotSlp { detected: Option<KnownFormat> },
+    /// The file starts like an slp file, but parsing failed at `offset` bytes from the start of the file.
+    Corrupt { offset: usize, detail: CorruptDetail },
+    CompressionFailure,
+    /// The raw element length is a placeholder (0 or 0xFFFFFFFF) left by a writer that never finalized the file.
+    PlaceholderRawLen,
+    /// The output did not decompress to the input. See [`Compressor::set_verify`].
+    VerificationFailed { first_diff_byte: usize },
+    /// An event's command byte is not a known event type. Only returned with [`Strictness::Strict`].
+    UnknownEvent { command: u8 },
+    /// There are bytes after the end of the UBJSON object. Only returned with [`Strictness::Strict`].
+    TrailingData { len: usize },
+    /// The metadata element is missing or does not parse. Only returned with [`Strictness::Strict`].
+    MissingMetadata,
+    /// The raw length puts the end of the events past `usize::MAX`, at `size` bytes. Only returned on 32-bit targets.
+    ///
+    /// The raw length is 32 bits, so the events of an slp file end within 4GiB, and files are at most that plus the metadata.
+    /// Such files fit in memory on 64-bit targets.
+    FileTooLarge { size: u64 },
+    /// Compressing the events was projected to take longer than the time budget. See [`Compressor::set_time_budget`].
+    TimeBudgetExceeded,
+}
+
+/// How to treat slp files that need inference or repair to compress. See [`Compressor::set_strictness`].
+///
+/// | Condition                            | Lenient   | Normal    | Strict                         |
+/// |--------------------------------------|-----------|-----------|--------------------------------|
+/// | Raw length never written             | inferred  | inferred  | `CompError::PlaceholderRawLen` |
+/// | Raw length past the end of the file  | inferred  | `CompError::Corrupt` | `CompError::Corrupt`  |
+/// | Raw length written little-endian     | inferred  | inferred  | `CompError::Corrupt`           |
+/// | Unknown event types                  | kept      | kept      | `CompError::UnknownEvent`      |
+/// | Bytes after the UBJSON object        | kept      | kept      | `CompError::TrailingData`      |
+/// | Missing or unparsable metadata       | kept      | kept      | `CompError::MissingMetadata`   |
+///
+/// Inferred raw lengths are found by scanning the events, stopping at the metadata element,
+/// an undeclared command byte, or a truncated event.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Strictness {
+    Lenient,
+    #[default]
+    Normal,
+    Strict,
+}
+
+/// Why an slp file could not be parsed. See [`CompError::Corrupt`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CorruptDetail {
+    /// The file ends before the end of the event or section starting at the offset.
+    Truncated,
+    /// The Event Payloads event is missing or invalid.
+    InvalidEventPayloads,
+    /// The Game Start event does not follow the Event Payloads event.
+    MissingGameStart,
+    /// The raw element length ends before the Game Start event does.
+    InvalidRawLen,
+    /// The command byte is not declared in the Event Payloads event.
+    UndeclaredEvent { command: u8 },
+    /// The event's payload extends past the end of the events.
+    TruncatedEvent { command: u8 },
+}
+
+/// Common file formats recognized by [`detect_format`], for error messages about files that are not slp files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KnownFormat {
+    Slp,
+    Slpz,
+    Zip,
+    Gzip,
+    Zstd,
+    Png,
+    Jpeg,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecompError {
+    InvalidFile,
+    /// The file does not have an slpz header. See [`is_slpz`].
+    NotSlpz { first_bytes: [u8; 4] },
+    DecompressionFailure,
+    /// The zstd frame's content size does not match the decompressed events size in the slpz header.
+    FrameSizeMismatch { header: usize, frame: usize },
+    /// The file ends before the end of the slpz header or sections. See [`slpz_original_size`].
+    TruncatedInput,
+    /// Writing to the sink failed. See [`decompress_to_sink`].
+    WriteFailed,
+    /// The file is an slpz file of a format version this version of slpz does not read. See [`format_version`](crate::format_version).
+    UnsupportedVersion { version: u32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TargetPathError {
+    PathNotFound,
+    PathInvalid,
+    CompressOrDecompressAmbiguous,
+    ZstdInitError,
+    InvalidOutputExtension,
+    /// The output directory could not be created.
+    OutputDirInvalid,
+    /// The output directory is the input directory and outputs would be found again as inputs.
+    OutputDirConflict,
+    /// Several inputs would be written to the same output path. See [`Options::dedupe_names`].
+    OutputCollision,
+    /// The [`Options::journal`] could not be opened, or is not a journal.
+    JournalInvalid,
+    /// [`Options::clip`] is set and [`Options::keep`] is not, which would replace each replay with its clip.
+    ClipRemovesInput,
+}
+
+impl std::fmt::Display for CompError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompError::NotSlp { detected: Some(KnownFormat::Slpz) } => write!(f, "This looks like an slpz file, which is already compressed"),
+            CompError::NotSlp { detected: Some(format) } =>
+                write!(f, "This looks like {}, not a Slippi replay", format.description()),
+            CompError::NotSlp { detected: None } => write!(f, "Not a Slippi replay"),
+            CompError::Corrupt { offset, detail } => write!(f, "Replay is corrupt at byte {}: {}", offset, detail),
+            CompError::CompressionFailure => write!(f, "Compression failed"),
+            CompError::PlaceholderRawLen => write!(f, "Raw length was never written"),
+            CompError::VerificationFailed { first_diff_byte } =>
+                write!(f, "Verification failed: output differs from input at byte {}", first_diff_byte),
+            CompError::UnknownEvent { command } => write!(f, "Unknown event 0x{:02X}", command),
+            CompError::TrailingData { len } => write!(f, "{} bytes after the end of the replay", len),
+            CompError::MissingMetadata => write!(f, "Metadata is missing or invalid"),
+            CompError::FileTooLarge { size } => write!(f, "Replay events end at byte {}, too large for this platform", size),
+            CompError::TimeBudgetExceeded => write!(f, "Compressing would take longer than the time budget"),
+        }
+    }
+}
+
+impl std::fmt::Display for CorruptDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorruptDetail::Truncated => write!(f, "the file is truncated"),
+            CorruptDetail::InvalidEventPayloads => write!(f, "the Event Payloads event is missing or invalid"),
+            CorruptDetail::MissingGameStart => write!(f, "the Game Start event is missing"),
+            CorruptDetail::InvalidRawLen => write!(f, "the raw length ends before the Game Start event"),
+            CorruptDetail::UndeclaredEvent { command } => write!(f, "undeclared event 0x{:02X}", command),
+            CorruptDetail::TruncatedEvent { command } => write!(f, "event 0x{:02X} is truncated", command),
+        }
+    }
+}
+
+impl KnownFormat {
+    /// The format with an article, such as "a zip file".
+    pub fn description(self) -> &'static str {
+        match self {
+            KnownFormat::Slp => "an slp file",
+            KnownFormat::Slpz => "an slpz file",
+            KnownFormat::Zip => "a zip file",
+            KnownFormat::Gzip => "a gzip file",
+            KnownFormat::Zstd => "a zstd file",
+            KnownFormat::Png => "a PNG image",
+            KnownFormat::Jpeg => "a JPEG image",
+        }
+    }
+}
+
+impl std::fmt::Display for KnownFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            KnownFormat::Slp => "slp",
+            KnownFormat::Slpz => "slpz",
+            KnownFormat::Zip => "zip",
+            KnownFormat::Gzip => "gzip",
+            KnownFormat::Zstd => "zstd",
+            KnownFormat::Png => "PNG",
+            KnownFormat::Jpeg => "JPEG",
+        })
+    }
+}
+
+impl CompError {
+    /// Moves the offset of [`CompError::Corrupt`] from the start of a section to the start of the file.
+    fn offset_by(self, base: usize) -> CompError {
+        match self {
+            CompError::Corrupt { offset, detail } => CompError::Corrupt { offset: base + offset, detail },
+            e => e,
+        }
+    }
+}
+
+impl From<EventIterError> for CompError {
+    fn from(e: EventIterError) -> Self {
+        match e {
+            EventIterError::UndeclaredEvent { command, offset } =>
+                CompError::Corrupt { offset, detail: CorruptDetail::UndeclaredEvent { command } },
+            EventIterError::TruncatedEvent { command, offset } =>
+                CompError::Corrupt { offset, detail: CorruptDetail::TruncatedEvent { command } },
+        }
+    }
+}
+
+impl From<embedded::DecodeError> for DecompError {
+    fn from(e: embedded::DecodeError) -> Self {
+        use embedded::DecodeError;
+        match e {
+            DecodeError::InvalidFile | DecodeError::BufferTooSmall { .. } => DecompError::InvalidFile,
+            DecodeError::NotSlpz { first_bytes } => DecompError::NotSlpz { first_bytes },
+            DecodeError::UnsupportedVersion { version } => DecompError::UnsupportedVersion { version },
+            DecodeError::Truncated => DecompError::TruncatedInput,
+            DecodeError::DecompressionFailure => DecompError::DecompressionFailure,
+        }
+    }
+}
+
+impl std::fmt::Display for DecompError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompError::InvalidFile => write!(f, "File is invalid"),
+            DecompError::NotSlpz { first_bytes: b } =>
+                write!(f, "Not an slpz file (starts with {:02X} {:02X} {:02X} {:02X})", b[0], b[1], b[2], b[3]),
+            DecompError::DecompressionFailure => write!(f, "Decompression failed"),
+            DecompError::FrameSizeMismatch { header, frame } =>
+                write!(f, "zstd frame holds {} bytes but the header expects {}", frame, header),
+            DecompError::TruncatedInput => write!(f, "File is truncated"),
+            DecompError::WriteFailed => write!(f, "Writing the output failed"),
+            DecompError::UnsupportedVersion { version } => write!(
+                f, "slpz format version {} is not supported (versions {} to {} are)",
+                version, MIN_SUPPORTED_FORMAT_VERSION, SLPZ_FORMAT_VERSION,
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for TargetPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TargetPathError::PathNotFound => "Replay path not found",
+            TargetPathError::PathInvalid => "Replay path invalid",
+            TargetPathError::CompressOrDecompressAmbiguous => "Not a slp or slpz file",
+            TargetPathError::ZstdInitError => "Failed to init zstd",
+            TargetPathError::InvalidOutputExtension => "Output extension is empty or contains a path separator",
+            TargetPathError::OutputDirInvalid => "Output directory could not be created",
+            TargetPathError::OutputDirConflict => "Output directory is the input directory and outputs have the input extension",
+            TargetPathError::OutputCollision => "Several inputs would be written to the same output",
+            TargetPathError::JournalInvalid => "Journal could not be opened or is not an slpz journal",
+            TargetPathError::ClipRemovesInput => "Clipped replays cannot replace their inputs",
+        })
+    }
+}
+
+const GAME_START: u8 = 0x36;
+const PRE_FRAME_UPDATE: u8 = 0x37;
+const POST_FRAME_UPDATE: u8 = 0x38;
+const GAME_END: u8 = 0x39;
+/// Events with the frame number as an i32 at the start of their payload.
+const FRAME_EVENTS: [u8; 8] = [0x37, 0x38, 0x3A, 0x3B, 0x3C, 0x3F, 0x40, 0x41];
+/// The Game End method for games that ended without a winner, such as by quitting out.
+/// See [`Completeness::game_end_method`].
+pub const GAME_END_NO_CONTEST: u8 = 7;
+/// Command bytes defined by the slp spec, as of version 3.18.
+const KNOWN_EVENTS: [u8; 14] = [
+    0x10, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x40, 0x41,
+];
+
+/// Same as [`SLPZ_FORMAT_VERSION`].
+pub const VERSION: u32 = SLPZ_FORMAT_VERSION;
+/// The version of the slpz crate.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Default maximum size of the events compressed in one chunk.
+pub const DEFAULT_CHUNK_BYTES: usize = 64 << 20;
+
+/// Compression level that stores the reordered events without compressing them.
+///
+/// The output is a valid slpz file, marked with [`FLAG_STORED`].
+pub const LEVEL_STORE: i32 = 0;
+
+/// The lowest compression level. Negative levels are zstd's fast modes, which trade compression for speed.
+pub const COMPRESS_LEVEL_MIN: i32 = -(1 << 17);
+/// The highest compression level. Levels above 19 are zstd's ultra modes, which use a lot more memory.
+pub const COMPRESS_LEVEL_MAX: i32 = 22;
+
+/// Returns true for levels accepted by [`Compressor::new`].
+pub fn is_valid_level(level: i32) -> bool {
+    (COMPRESS_LEVEL_MIN..=COMPRESS_LEVEL_MAX).contains(&level)
+}
+
+/// MIME type of slpz files.
+pub const SLPZ_MIME_TYPE: &str = "application/x-slippi-compressed";
+/// MIME type of slp files.
+pub const SLP_MIME_TYPE: &str = "application/x-slippi";
+
+/// A reusable zstd compression context.
+///
+/// Outputs of [`compress`] are owned `Vec<u8>`s and do not borrow from the compressor,
+/// so a compressor can be dropped or reused while its outputs are still alive.
+pub struct Compressor {
+    ctx: zstd::bulk::Compressor<'static>,
+    level: i32,
+    strictness: Strictness,
+    strip_trailer: bool,
+    verify: bool,
+    chunk_bytes: usize,
+    segments: usize,
+    time_budget: Option<std::time::Duration>,
+    /// The zstd parameters set on `ctx`, for creating a context per segment.
+    params: Vec<zstd::stream::raw::CParameter>,
+}
+
+/// zstd's match finding strategies, from fastest to strongest. See [`Compressor::set_strategy`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ZstdStrategy {
+    Fast,
+    Dfast,
+    Greedy,
+    Lazy,
+    Lazy2,
+    Btlazy2,
+    Btopt,
+    Btultra,
+    Btultra2,
+}
+
+impl ZstdStrategy {
+    fn zstd_strategy(self) -> zstd::zstd_safe::Strategy {
+        use zstd::zstd_safe::Strategy;
+
+        match self {
+            ZstdStrategy::Fast => Strategy::ZSTD_fast,
+            ZstdStrategy::Dfast => Strategy::ZSTD_dfast,
+            ZstdStrategy::Greedy => Strategy::ZSTD_greedy,
+            ZstdStrategy::Lazy => Strategy::ZSTD_lazy,
+            ZstdStrategy::Lazy2 => Strategy::ZSTD_lazy2,
+            ZstdStrategy::Btlazy2 => Strategy::ZSTD_btlazy2,
+            ZstdStrategy::Btopt => Strategy::ZSTD_btopt,
+            ZstdStrategy::Btultra => Strategy::ZSTD_btultra,
+            ZstdStrategy::Btultra2 => Strategy::ZSTD_btultra2,
+        }
+    }
+}
+
+pub struct Decompressor {
+    ctx: zstd::bulk::Decompressor<'static>,
+    threads: usize,
+    buffer_size: usize,
+}
+
+/// The zstd parameters set by [`Compressor::new`], besides the level.
+fn default_params() -> Vec<zstd::stream::raw::CParameter> {
+    use zstd::stream::raw::CParameter;
+
+    vec![CParameter::ContentSizeFlag(true), CParameter::ChecksumFlag(true)]
+}
+
+fn zstd_compressor(level: i32, params: &[zstd::stream::raw::CParameter]) -> Option<zstd::bulk::Compressor<'static>> {
+    let mut ctx = zstd::bulk::Compressor::default();
+    ctx.set_parameter(zstd::stream::raw::CParameter::CompressionLevel(level)).ok()?;
+    for &p in params { ctx.set_parameter(p).ok()?; }
+    Some(ctx)
+}
+
+impl Compressor {
+    /// compression_level must be between [`COMPRESS_LEVEL_MIN`] and [`COMPRESS_LEVEL_MAX`],
+    /// where 0 is [`LEVEL_STORE`]. Returns None otherwise. The default is 3.
+    ///
+    /// Frames record their content size and have a zstd checksum. See [`Compressor::set_checksum`].
+    pub fn new(compression_level: i32) -> Option<Compressor> {
+        if !is_valid_level(compression_level) { return None }
+
+        let params = default_params();
+        Some(Compressor {
+            ctx: zstd_compressor(compression_level, &params)?,
+            level: compression_level,
+            strictness: Strictness::Normal,
+            strip_trailer: false,
+            verify: false,
+            chunk_bytes: DEFAULT_CHUNK_BYTES,
+            segments: 1,
+            time_budget: None,
+            params,
+        })
+    }
+
+    /// Restores the settings of [`Compressor::new`], keeping the level and the zstd context.
+    fn reset_settings(&mut self) -> Option<()> {
+        use zstd::stream::raw::CParameter;
+
+        let params = default_params();
+        if self.params != params {
+            self.ctx.context_mut().reset(zstd::zstd_safe::ResetDirective::Parameters).ok()?;
+            self.ctx.set_parameter(CParameter::CompressionLevel(self.level)).ok()?;
+            for &p in &params { self.ctx.set_parameter(p).ok()?; }
+            self.params = params;
+        }
+        self.strictness = Strictness::Normal;
+        self.strip_trailer = false;
+        self.verify = false;
+        self.chunk_bytes = DEFAULT_CHUNK_BYTES;
+        self.segments = 1;
+        self.time_budget = None;
+        Some(())
+    }
+
+    /// Sets a zstd parameter, replacing any earlier value of the same parameter.
+    fn set_parameter(&mut self, param: zstd::stream::raw::CParameter) -> Option<()> {
+        self.ctx.set_parameter(param).ok()?;
+        self.params.retain(|p| std::mem::discriminant(p) != std::mem::discriminant(&param));
+        self.params.push(param);
+        Some(())
+    }
+
+    /// Whether to add a zstd checksum to the compressed events. Set by default.
+    pub fn set_checksum(&mut self, checksum: bool) -> Option<()> {
+        self.set_parameter(zstd::stream::raw::CParameter::ChecksumFlag(checksum))
+    }
+
+    /// A compressor with zstd's target match length and strategy set, overriding the ones picked by the level.
+    /// Returns None if the level is invalid or zstd rejects a parameter.
+    /// See [`Compressor::set_target_length`], [`Compressor::set_strategy`] and [`CompressorBuilder`].
+    pub fn with_params(compression_level: i32, target_length: u32, strategy: ZstdStrategy) -> Option<Compressor> {
+        let mut compressor = Compressor::new(compression_level)?;
+        compressor.set_target_length(target_length)?;
+        compressor.set_strategy(strategy)?;
+        Some(compressor)
+    }
+
+    /// zstd's target match length, overriding the one picked by the level. 0 uses the level's.
+    /// Only the fast and optimal strategies use it: with [`ZstdStrategy::Fast`] larger values are faster and compress worse,
+    /// and with [`ZstdStrategy::Btopt`] and above larger values compress better and slower.
+    ///
+    /// On a generated 8 minute replay this changed the size by about 1% with the optimal strategies,
+    /// and made the output of `Fast` up to 80% larger.
+    pub fn set_target_length(&mut self, target_length: u32) -> Option<()> {
+        self.set_parameter(zstd::stream::raw::CParameter::TargetLength(target_length))
+    }
+
+    /// zstd's match finding strategy, overriding the one picked by the level. Ignored by [`LEVEL_STORE`].
+    /// Stronger strategies use more memory. See [`Compressor::estimated_memory`].
+    ///
+    /// Raising the level does more for replays than changing the strategy, as it also enlarges zstd's tables.
+    /// On a generated 8 minute replay at level 3, [`ZstdStrategy::Btultra2`] was 1% smaller and 4 times slower,
+    /// while level 9 with its own strategy was 29% smaller and 2 times slower.
+    pub fn set_strategy(&mut self, strategy: ZstdStrategy) -> Option<()> {
+        self.set_parameter(zstd::stream::raw::CParameter::Strategy(strategy.zstd_strategy()))
+    }
+
+    pub fn level(&self) -> i32 { self.level }
+
+    /// Creates a pool containing this compressor, so it can be reused rather than dropped.
+    /// Its settings are restored to the defaults, see [`CompressorPool::put`].
+    pub fn into_pool(self) -> CompressorPool {
+        let pool = CompressorPool::new();
+        pool.put(self);
+        pool
+    }
+
+    /// Which slp files that need inference or repair are rejected. See [`Strictness`]. Default is `Normal`.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    /// If set, bytes after the end of the slp file's UBJSON object are dropped. See [`trailer_len`].
+    /// Otherwise they are kept, so decompression reproduces the file exactly. Not set by default.
+    pub fn set_strip_trailer(&mut self, strip_trailer: bool) {
+        self.strip_trailer = strip_trailer;
+    }
+
+    /// If set, [`compress`] decompresses its output and returns `CompError::VerificationFailed`
+    /// if it does not match the input. This doubles the work. Not set by default.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// Events are reordered and compressed in chunks of at most this many bytes,
+    /// so memory use is bounded by the input size plus one chunk.
+    /// Replays smaller than this are written as a single chunk. Default is [`DEFAULT_CHUNK_BYTES`].
+    pub fn set_chunk_bytes(&mut self, chunk_bytes: usize) {
+        self.chunk_bytes = chunk_bytes.max(1);
+    }
+
+    /// Splits the events into this many chunks of about the same size, reordering and compressing them
+    /// on a thread each. Each chunk is at most the chunk size. See [`Compressor::set_chunk_bytes`].
+    ///
+    /// Splitting slightly lowers the compression ratio, so it is only worth it for large replays.
+    /// Default is 1, a single thread.
+    pub fn set_segments(&mut self, segments: usize) {
+        self.segments = segments.max(1);
+    }
+
+    /// If set, [`compress`] returns `CompError::TimeBudgetExceeded` once compressing a file is projected to take longer,
+    /// from the time taken for the events compressed so far. Ignored with more than one segment. Not set by default.
+    pub fn set_time_budget(&mut self, budget: Option<std::time::Duration>) {
+        self.time_budget = budget;
+    }
+
+    /// Runs `f` with the compression level changed and no time budget, then restores them.
+    fn with_level<R>(&mut self, level: i32, f: impl FnOnce(&mut Compressor) -> R) -> Option<R> {
+        use zstd::stream::raw::CParameter;
+
+        self.ctx.set_parameter(CParameter::CompressionLevel(level)).ok()?;
+        // taken after the level is set, so an error above leaves the budget in place
+        let (old_level, budget) = (self.level, self.time_budget.take());
+        self.level = level;
+        let r = f(self);
+        self.level = old_level;
+        self.time_budget = budget;
+        self.ctx.set_parameter(CParameter::CompressionLevel(old_level)).ok()?;
+        Some(r)
+    }
+
+    /// zstd's estimate of the memory in bytes used to compress a file, for the level and zstd parameters.
+    ///
+    /// zstd worker threads and segments each have their own context, so are counted as a context each.
+    /// Does not include the file or its output. Zero for [`LEVEL_STORE`], which does not use zstd.
+    pub fn estimated_memory(&self) -> u64 {
+        use zstd::stream::raw::CParameter;
+        use zstd::zstd_safe::zstd_sys::{self, ZSTD_cParameter};
+
+        if self.level == LEVEL_STORE { return 0 }
+
+        let mut workers = 0;
+        let mut ldm = false;
+        let mut window_log = 27; // zstd's default with long distance matching
+        let mut settings = vec![(ZSTD_cParameter::ZSTD_c_compressionLevel, self.level)];
+        for &p in &self.params {
+            match p {
+                CParameter::WindowLog(log) => {
+                    window_log = log as i32;
+                    settings.push((ZSTD_cParameter::ZSTD_c_windowLog, window_log));
+                }
+                CParameter::EnableLongDistanceMatching(enable) => ldm = enable,
+                CParameter::TargetLength(length) => settings.push((ZSTD_cParameter::ZSTD_c_targetLength, length as i32)),
+                CParameter::Strategy(strategy) => settings.push((ZSTD_cParameter::ZSTD_c_strategy, strategy as i32)),
+                // zstd only estimates single threaded contexts
+                CParameter::NbWorkers(n) => workers = n,
+                _ => (),
+            }
+        }
+        if ldm {
+            // The estimate does not fill in the long distance matching defaults, and divides by the minimum match.
+            // These are the defaults zstd uses when compressing.
+            let hash_log = (window_log - 7).max(6);
+            settings.extend([
+                (ZSTD_cParameter::ZSTD_c_enableLongDistanceMatching, 1),
+                (ZSTD_cParameter::ZSTD_c_ldmHashLog, hash_log),
+                (ZSTD_cParameter::ZSTD_c_ldmMinMatch, 64),
+                (ZSTD_cParameter::ZSTD_c_ldmBucketSizeLog, 3),
+                (ZSTD_cParameter::ZSTD_c_ldmHashRateLog, (window_log - hash_log).max(0)),
+            ]);
+        }
+
+        // SAFETY: params is checked for null before use, only passed to zstd while alive, and freed once.
+        let context = unsafe {
+            let params = zstd_sys::ZSTD_createCCtxParams();
+            if params.is_null() { return 0 }
+            for (param, value) in settings {
+                zstd_sys::ZSTD_CCtxParams_setParameter(params, param, value);
+            }
+            let size = zstd_sys::ZSTD_estimateCCtxSize_usingCCtxParams(params);
+            zstd_sys::ZSTD_freeCCtxParams(params);
+            if zstd_sys::ZSTD_isError(size) != 0 { 0 } else { size }
+        };
+
+        context as u64 * (workers as u64 + 1) * self.segments as u64
+    }
+}
+
+/// Builds a [`Compressor`], checking the level and zstd parameters once in [`CompressorBuilder::build`].
+///
+/// Each setter does the same as the [`Compressor`] setter of the same name. A builder can build any number of compressors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressorBuilder {
+    level: i32,
+    checksum: bool,
+    target_length: Option<u32>,
+    strategy: Option<ZstdStrategy>,
+    strictness: Strictness,
+    strip_trailer: bool,
+    verify: bool,
+    chunk_bytes: usize,
+    segments: usize,
+    time_budget: Option<std::time::Duration>,
+}
+
+/// Why [`CompressorBuilder::build`] could not create a compressor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ZstdInitError {
+    /// The level is not between [`COMPRESS_LEVEL_MIN`] and [`COMPRESS_LEVEL_MAX`].
+    InvalidLevel { level: i32 },
+    /// zstd could not create a context, or rejected a parameter.
+    ParameterRejected,
+}
+
+impl std::fmt::Display for ZstdInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZstdInitError::InvalidLevel { level } => write!(f, "Compression level {} is out of range", level),
+            ZstdInitError::ParameterRejected => write!(f, "Failed to init zstd"),
+        }
+    }
+}
+
+impl Default for CompressorBuilder {
+    fn default() -> Self { CompressorBuilder::new(3) }
+}
+
+impl CompressorBuilder {
+    /// A builder for a compressor at this level, with the defaults of [`Compressor::new`].
+    pub fn new(compression_level: i32) -> CompressorBuilder {
+        CompressorBuilder {
+            level: compression_level,
+            checksum: true,
+            target_length: None,
+            strategy: None,
+            strictness: Strictness::Normal,
+            strip_trailer: false,
+            verify: false,
+            chunk_bytes: DEFAULT_CHUNK_BYTES,
+            segments: 1,
+            time_budget: None,
+        }
+    }
+
+    pub fn level(mut self, compression_level: i32) -> Self { self.level = compression_level; self }
+    pub fn checksum(mut self, checksum: bool) -> Self { self.checksum = checksum; self }
+    pub fn target_length(mut self, target_length: u32) -> Self { self.target_length = Some(target_length); self }
+    pub fn strategy(mut self, strategy: ZstdStrategy) -> Self { self.strategy = Some(strategy); self }
+    pub fn strictness(mut self, strictness: Strictness) -> Self { self.strictness = strictness; self }
+    pub fn strip_trailer(mut self, strip_trailer: bool) -> Self { self.strip_trailer = strip_trailer; self }
+    pub fn verify(mut self, verify: bool) -> Self { self.verify = verify; self }
+    pub fn chunk_bytes(mut self, chunk_bytes: usize) -> Self { self.chunk_bytes = chunk_bytes; self }
+    pub fn segments(mut self, segments: usize) -> Self { self.segments = segments; self }
+    pub fn time_budget(mut self, budget: Option<std::time::Duration>) -> Self { self.time_budget = budget; self }
+
+    pub fn build(&self) -> Result<Compressor, ZstdInitError> {
+        if !is_valid_level(self.level) { return Err(ZstdInitError::InvalidLevel { level: self.level }) }
+
+        let mut compressor = Compressor::new(self.level).ok_or(ZstdInitError::ParameterRejected)?;
+        // the checksum is already set, and leaving the parameters as they are keeps pooling the compressor cheap
+        if !self.checksum { compressor.set_checksum(false).ok_or(ZstdInitError::ParameterRejected)?; }
+        if let Some(target_length) = self.target_length {
+            compressor.set_target_length(target_length).ok_or(ZstdInitError::ParameterRejected)?;
+        }
+        if let Some(strategy) = self.strategy {
+            compressor.set_strategy(strategy).ok_or(ZstdInitError::ParameterRejected)?;
+        }
+        compressor.set_strictness(self.strictness);
+        compressor.set_strip_trailer(self.strip_trailer);
+        compressor.set_verify(self.verify);
+        compressor.set_chunk_bytes(self.chunk_bytes);
+        compressor.set_segments(self.segments);
+        compressor.set_time_budget(self.time_budget);
+        Ok(compressor)
+    }
+}
+
+/// A set of idle compressors.
+///
+/// Initializing a zstd context is expensive, so compressors can be returned here and taken again later.
+pub struct CompressorPool { idle: std::sync::Mutex<Vec<Compressor>> }
+
+impl Default for CompressorPool {
+    fn default() -> Self { CompressorPool::new() }
+}
+
+impl CompressorPool {
+    pub const fn new() -> CompressorPool {
+        CompressorPool { idle: std::sync::Mutex::new(Vec::new()) }
+    }
+
+    /// Creates a pool holding `count` idle compressors with this compression level,
+    /// e.g. one per thread from [`std::thread::available_parallelism`].
+    pub fn prefilled(compression_level: i32, count: usize) -> Option<CompressorPool> {
+        let idle = (0..count).map(|_| Compressor::new(compression_level)).collect::<Option<Vec<_>>>()?;
+        Some(CompressorPool { idle: std::sync::Mutex::new(idle) })
+    }
+
+    /// Runs `f` with an idle compressor, returning it to the pool afterwards.
+    ///
+    /// Creates a new compressor if none with this level are idle. Returns None if that fails.
+    pub fn with_compressor<R>(&self, compression_level: i32, f: impl FnOnce(&mut Compressor) -> R) -> Option<R> {
+        let mut compressor = self.take(compression_level)?;
+        let r = f(&mut compressor);
+        self.put(compressor);
+        Some(r)
+    }
+
+    /// Takes an idle compressor with this compression level, or creates a new one if none are idle.
+    ///
+    /// Compressors have the settings of [`Compressor::new`], whatever was changed before they were returned.
+    pub fn take(&self, compression_level: i32) -> Option<Compressor> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        match idle.iter().position(|c| c.level == compression_level) {
+            Some(i) => Some(idle.swap_remove(i)),
+            None => {
+                drop(idle);
+                Compressor::new(compression_level)
+            }
+        }
+    }
+
+    /// Returns a compressor to the pool, restoring its default settings.
+    /// It is dropped if zstd rejects them.
+    pub fn put(&self, mut compressor: Compressor) {
+        if compressor.reset_settings().is_none() { return }
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).push(compressor);
+    }
+
+    /// The number of idle compressors.
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+std::thread_local! {
+    static COMPRESSOR: std::cell::RefCell<Option<Compressor>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Runs `f` with this thread's compressor, creating it on first use or if its level differs.
+///
+/// Avoids creating a compressor for each task when compressing on a thread pool. See [`compress_batch_parallel`].
+/// Settings changed by `f` (such as [`Compressor::set_strictness`]) are restored to the defaults afterwards,
+/// as [`CompressorPool::put`] does. Calls from within `f` use a separate compressor. Returns None if creating the compressor fails.
+pub fn with_thread_local_compressor<F, R>(compression_level: i32, f: F) -> Option<R>
+    where F: FnOnce(&mut Compressor) -> R
+{
+    let compressor = COMPRESSOR.with(|c| c.borrow_mut().take()).filter(|c| c.level == compression_level);
+    let mut compressor = match compressor {
+        Some(c) => c,
+        None => Compressor::new(compression_level)?,
+    };
+    let r = f(&mut compressor);
+    if compressor.reset_settings().is_some() {
+        COMPRESSOR.with(|c| *c.borrow_mut() = Some(compressor));
+    }
+    Some(r)
+}
+
+/// Compresses slp files that arrive in chunks, such as replays relayed from a live Slippi connection.
+///
+/// Chunks are buffered until they hold a complete slp file, found from its UBJSON framing:
+/// the raw element length, or the events when the length was never written, then the metadata element and closing '}'.
+pub struct ChunkedCompressor {
+    compressor: Compressor,
+    buffer: Vec<u8>,
+    /// How far the events in `buffer` have been scanned when the raw length was never written.
+    scanned: usize,
+}
+
+impl ChunkedCompressor {
+    pub fn new(compressor: Compressor) -> ChunkedCompressor {
+        ChunkedCompressor { compressor, buffer: Vec::new(), scanned: 0 }
+    }
+
+    /// Adds a chunk, returning the compressed file once the buffer holds a complete slp file.
+    ///
+    /// Data after the end of the file is kept as the start of the next file.
+    /// If that holds a complete file too, it is returned by the next call, which can pass an empty chunk.
+    /// If the buffer does not start like an slp file, it is dropped and [`CompError::NotSlp`] is returned.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>, CompError> {
+        self.buffer.extend_from_slice(chunk);
+        if self.buffer.len() >= RAW_HEADER.len() && self.buffer[..RAW_HEADER.len()] != RAW_HEADER {
+            let detected = detect_format(&self.buffer);
+            self.buffer.clear();
+            self.scanned = 0;
+            return Err(CompError::NotSlp { detected });
+        }
+        let Some(len) = complete_slp_len(&self.buffer, &mut self.scanned) else { return Ok(None) };
+        let slp = self.buffer.drain(..len).collect::<Vec<_>>();
+        self.scanned = 0;
+        compress_unchecked(&mut self.compressor, &slp).map(Some)
+    }
+
+    /// Compresses the buffered data even if it is not a complete slp file, such as when a connection drops mid game.
+    ///
+    /// A partial file has [`CompressWarning::PartialFile`] and the warnings [`Strictness::Lenient`] compression finds.
+    /// Returns None if nothing is buffered.
+    pub fn finish(&mut self) -> Result<Option<CompressOutput>, CompError> {
+        if self.buffer.is_empty() { return Ok(None) }
+        if let Some(data) = self.feed(&[])? { return Ok(Some(CompressOutput { data, warnings: Vec::new() })) }
+        if self.buffer.is_empty() { return Ok(None) }
+
+        event!(warn, bytes = self.buffer.len(), "compressing a partial slp file");
+        let strictness = self.compressor.strictness;
+        self.compressor.strictness = Strictness::Lenient;
+        let output = compress(&mut self.compressor, &self.buffer);
+        self.compressor.strictness = strictness;
+        let len = self.buffer.len();
+        self.buffer.clear();
+        self.scanned = 0;
+        let mut output = output?;
+        output.warnings.insert(0, CompressWarning::PartialFile { len });
+        Ok(Some(output))
+    }
+
+    /// The number of bytes buffered towards the next file.
+    pub fn buffered(&self) -> usize { self.buffer.len() }
+
+    pub fn into_compressor(self) -> Compressor { self.compressor }
+}
+
+/// The offset of the end of the raw element with a raw length of `raw_len`, which is where the metadata starts.
+/// `None` on 32-bit targets for raw lengths within 15 bytes of 4GiB. See [`CompError::FileTooLarge`].
+fn raw_element_end(raw_len: u32) -> Option<usize> {
+    usize::try_from(15 + raw_len as u64).ok()
+}
+
+/// The end of the raw element if its length is read little-endian, as some third-party writers store it.
+///
+/// The length is big-endian in the slp spec. Callers only use this when the big-endian length does not fit the file,
+/// and only if it matches where the events end when scanned.
+fn byte_swapped_raw_end(slp: &[u8]) -> Option<usize> {
+    raw_element_end(u32::from_le_bytes(slp[11..15].try_into().unwrap()))
+}
+
+/// The length of the slp file at the start of `data`, or None if it does not hold a complete slp file yet.
+///
+/// If the raw length was never written the events are scanned, resuming from `scanned`,
+/// which is updated so that a buffer growing in small chunks is not scanned again from the start.
+fn complete_slp_len(data: &[u8], scanned: &mut usize) -> Option<usize> {
+    if !is_slp(data) { return None }
+    let raw_len = u32::from_be_bytes(data[11..15].try_into().unwrap());
+    let metadata_offset = if raw_len == 0 || raw_len == u32::MAX {
+        // the events end at the metadata element
+        let info_size = *data.get(16)? as usize;
+        if data[15] != EVENT_PAYLOADS || info_size == 0 || data.len() <= 16 + info_size { return None }
+        let (event_sizes, _) = event_sizes(&data[15..])?;
+        let events_offset = (*scanned).max(15 + 1 + info_size);
+        let events_offset = events_offset + scan_events_len(&data[events_offset..], &event_sizes);
+        *scanned = events_offset;
+        if !data[events_offset..].starts_with(b"U\x08metadata") { return None }
+        events_offset
+    } else {
+        raw_element_end(raw_len)?
+    };
+    Some(metadata_offset + object_rest_len(data.get(metadata_offset..)?).ok()?)
+}
+
+impl Decompressor {
+    pub fn new() -> Option<Decompressor> {
+        Some(Decompressor {
+            ctx: zstd::bulk::Decompressor::new().ok()?,
+            threads: 1,
+            buffer_size: Options::DEFAULT_BUFFER_SIZE,
+        })
+    }
+
+    /// Decompresses the chunks of chunked slpz files on up to this many threads. See [`Compressor::set_segments`].
+    ///
+    /// All of the decompressed chunks are held in memory at once, rather than one at a time. Default is 1.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// [`decompress_to_sink`] writes events in blocks of about this many bytes. See [`Options::buffer_size`].
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size.max(1);
+    }
+
+    /// The estimated memory in bytes used to decompress `slpz`, without the file itself.
+    ///
+    /// Counts the decompressed replay, the reordered events held before they are rebuilt, and zstd's estimate
+    /// for the largest frame's window, a context for each thread. Stored files do not use zstd.
+    pub fn estimated_memory(&self, slpz: &[u8]) -> Result<u64, DecompError> {
+        use zstd::zstd_safe::zstd_sys;
+
+        let header = SlpzHeader::parse(slpz)?;
+        let output = slpz_original_size(slpz)?;
+        let chunks = header.chunks(slpz);
+        let threads = self.threads.min(chunks.len()).max(1);
+        // parallel decompression holds every chunk at once, otherwise only one is held
+        let events = if threads > 1 {
+            header.decompressed_events_size
+        } else {
+            chunks.iter().map(|&(_, reordered)| reordered).max().unwrap_or(0)
+        };
+
+        let mut context = 0;
+        if header.flags & FLAG_STORED == 0 {
+            let mut compressed_events = &slpz[header.compressed_events_offset..];
+            for (compressed_size, _) in chunks {
+                // chunk sizes are checked against the section size when parsing the header
+                let (frame, rest) = compressed_events.split_at(compressed_size);
+                compressed_events = rest;
+                // SAFETY: the pointer and length are from the same live slice, which zstd only reads the frame header from.
+                let size = unsafe { zstd_sys::ZSTD_estimateDStreamSize_fromFrame(frame.as_ptr().cast(), frame.len()) };
+                // SAFETY: only inspects the returned code.
+                if unsafe { zstd_sys::ZSTD_isError(size) } != 0 { return Err(DecompError::InvalidFile) }
+                context = context.max(size as u64);
+            }
+        }
+
+        Ok(output + events as u64 + context * threads as u64)
+    }
+}
+
+/// Returns true if the data starts like an slp file.
+///
+/// Only the first 15 bytes are checked.
+pub fn is_slp(data: &[u8]) -> bool {
+    data.len() >= 15 && data[0..11] == RAW_HEADER
+}
+
+/// Guesses the format of a file from its first bytes. Returns None for unrecognized formats.
+///
+/// Used to explain [`CompError::NotSlp`], so only formats that are likely to be mistaken for replays are recognized.
+pub fn detect_format(data: &[u8]) -> Option<KnownFormat> {
+    const MAGIC: [(&[u8], KnownFormat); 6] = [
+        (b"PK\x03\x04", KnownFormat::Zip),
+        (b"PK\x05\x06", KnownFormat::Zip),
+        (b"\x1F\x8B", KnownFormat::Gzip),
+        (b"\x28\xB5\x2F\xFD", KnownFormat::Zstd),
+        (b"\x89PNG\r\n\x1A\n", KnownFormat::Png),
+        (b"\xFF\xD8\xFF", KnownFormat::Jpeg),
+    ];
+    if is_slp(data) { return Some(KnownFormat::Slp) }
+    if is_slpz(data) { return Some(KnownFormat::Slpz) }
+    MAGIC.iter().find(|(magic, _)| data.starts_with(magic)).map(|&(_, format)| format)
+}
+
+/// The sections of an slp file, borrowed from the file.
+struct SlpParts<'a> {
+    event_sizes: [u16; 256],
+    event_sizes_payload: &'a [u8],
+    game_start_payload: &'a [u8],
+    events: &'a [u8],
+    /// The offset of `events` in the file.
+    events_offset: usize,
+    metadata: &'a [u8],
+}
+
+/// Splits an slp file into its sections.
+///
+/// If the raw element length was never written (0 or 0xFFFFFFFF), the end of the events is found by scanning.
+/// See [`Strictness`] for the files that are rejected.
+fn split_slp(slp: &[u8], strictness: Strictness) -> Result<SlpParts<'_>, CompError> {
+    let corrupt = |offset, detail| CompError::Corrupt { offset, detail };
+    if !slp.starts_with(&RAW_HEADER) { return Err(CompError::NotSlp { detected: detect_format(slp) }) }
+    if slp.len() < 16 { return Err(corrupt(15, CorruptDetail::Truncated)) }
+
+    // get event sizes
+    if slp[15] != EVENT_PAYLOADS { return Err(corrupt(15, CorruptDetail::InvalidEventPayloads)) }
+    let (event_sizes, event_type_count) = event_sizes(&slp[15..]).ok_or(corrupt(15, CorruptDetail::InvalidEventPayloads))?;
+    let event_sizes_size = 2 + event_type_count * 3;
+    let event_sizes_payload = &slp[15..][..event_sizes_size];
+
+    // get game start
+    let game_start_offset = 15 + event_sizes_size;
+    let game_start_size = event_sizes[GAME_START as usize] as usize + 1;
+    if slp.len() <= game_start_offset { return Err(corrupt(game_start_offset, CorruptDetail::Truncated)) }
+    if slp[game_start_offset] != GAME_START { return Err(corrupt(game_start_offset, CorruptDetail::MissingGameStart)) }
+    if slp.len() < game_start_offset+game_start_size { return Err(corrupt(game_start_offset, CorruptDetail::Truncated)) }
+    let game_start_payload = &slp[game_start_offset..][..game_start_size];
+
+    let other_events_offset = game_start_offset+game_start_size;
+
+    // get metadata
+    let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap());
+    let scanned_offset = || other_events_offset + scan_events_len(&slp[other_events_offset..], &event_sizes);
+    let metadata_offset = if raw_len == 0 || raw_len == u32::MAX {
+        if strictness == Strictness::Strict { return Err(CompError::PlaceholderRawLen) }
+        scanned_offset()
+    } else if let Some(end) = raw_element_end(raw_len).filter(|&end| other_events_offset <= end && end <= slp.len()) {
+        end
+    } else if strictness != Strictness::Strict && byte_swapped_raw_end(slp) == Some(scanned_offset()) {
+        scanned_offset()
+    } else {
+        match raw_element_end(raw_len) {
+            Some(end) if strictness == Strictness::Lenient && slp.len() < end => scanned_offset(),
+            Some(end) => end,
+            // past the end of any slice on this target
+            None if strictness == Strictness::Lenient => scanned_offset(),
+            None => return Err(CompError::FileTooLarge { size: 15 + raw_len as u64 }),
+        }
+    };
+    if slp.len() < metadata_offset { return Err(corrupt(15, CorruptDetail::Truncated)) }
+    let metadata = &slp[metadata_offset..];
+
+    if metadata_offset < other_events_offset { return Err(corrupt(11, CorruptDetail::InvalidRawLen)); }
+    let events = &slp[other_events_offset..metadata_offset];
+
+    if strictness == Strictness::Strict {
+        let counts = count_events(events, &event_sizes).map_err(|e| e.offset_by(other_events_offset))?;
+        if let Some(&(command, _)) = unknown_event_counts(&counts).first() {
+            return Err(CompError::UnknownEvent { command });
+        }
+        match object_rest_len(metadata) {
+            Ok(len) if metadata.starts_with(b"U\x08metadata") => if len < metadata.len() {
+                return Err(CompError::TrailingData { len: metadata.len() - len });
+            }
+            _ => return Err(CompError::MissingMetadata),
+        }
+    }
+
+    Ok(SlpParts { event_sizes, event_sizes_payload, game_start_payload, events, events_offset: other_events_offset, metadata })
+}
+
+/// Returns the length of the complete events at the start of `events`.
+///
+/// Stops at the metadata element, an undeclared command byte, or a truncated event.
+fn scan_events_len(events: &[u8], event_sizes: &[u16; 256]) -> usize {
+    const METADATA_START: &[u8] = b"U\x08metadata";
+
+    let mut i = 0;
+    while i < events.len() {
+        if events[i..].starts_with(METADATA_START) { break }
+        let event_size = event_sizes[events[i] as usize] as usize;
+        if event_size == 0 || events.len() < i + 1 + event_size { break }
+        i += 1 + event_size;
+    }
+    i
+}
+
+impl SlpzHeader {
+    /// Parses and validates the header. Does not decompress anything.
+    pub fn parse(slpz: &[u8]) -> Result<SlpzHeader, DecompError> {
+        Ok(embedded::parse_header(slpz)?)
+    }
+}
+
+/// An slpz file and the warnings found while compressing it. Returned by [`compress`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressOutput {
+    pub data: Vec<u8>,
+    pub warnings: Vec<CompressWarning>,
+}
+
+/// Something in an slp file that slpz had to infer, or that other tools may not read back the same.
+/// The slpz file still decompresses to the slp file, except where noted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompressWarning {
+    /// A string in the metadata is not valid UTF-8. It is stored byte for byte.
+    MetadataNotUtf8,
+    /// The raw length points past the end of the file, and the events were recovered with [`Strictness::Lenient`].
+    /// The slpz file decompresses with the recovered raw length.
+    TruncatedRawData { raw_len: u32 },
+    /// A command byte that is not defined by the slp spec. See [`unknown_events`].
+    UnknownEventCode { command: u8, count: u32 },
+    /// The raw length was never written, so the end of the events was found by scanning them.
+    /// The slpz file decompresses with the found raw length.
+    ZeroRawLen,
+    /// Bytes after the end of the replay. See [`trailer_len`].
+    /// They are dropped if `stripped`, see [`Compressor::set_strip_trailer`], and kept otherwise.
+    TrailingBytes { len: usize, stripped: bool },
+    /// The file ended before the replay did, such as when a stream dropped mid game. See [`ChunkedCompressor::finish`].
+    /// `len` bytes were compressed.
+    PartialFile { len: usize },
+    /// The raw length was written little-endian, and `raw_len` is its value read that way.
+    /// The slpz file decompresses with the raw length big-endian, as the slp spec has it.
+    ByteSwappedRawLen { raw_len: u32 },
+}
+
+impl std::fmt::Display for CompressWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressWarning::MetadataNotUtf8 => write!(f, "has metadata strings that are not UTF-8"),
+            CompressWarning::TruncatedRawData { raw_len } =>
+                write!(f, "has a raw length of {} bytes, past the end of the file", raw_len),
+            CompressWarning::UnknownEventCode { command, count } =>
+                write!(f, "has unknown event 0x{:02X} ({})", command, count),
+            CompressWarning::ZeroRawLen => write!(f, "has no raw length, so the end of the events was inferred"),
+            CompressWarning::TrailingBytes { len, stripped: false } =>
+                write!(f, "has {} bytes after the end of the replay", len),
+            CompressWarning::TrailingBytes { len, stripped: true } =>
+                write!(f, "had {} bytes after the end of the replay, which were dropped", len),
+            CompressWarning::PartialFile { len } => write!(f, "is a partial slp file of {} bytes", len),
+            CompressWarning::ByteSwappedRawLen { raw_len } =>
+                write!(f, "has a little-endian raw length of {} bytes, which was rewritten big-endian", raw_len),
+        }
+    }
+}
+
+/// Compresses an slp file to an slpz file, with warnings about anything that had to be inferred.
+///
+/// See [`compress_unchecked`] to skip looking for warnings.
+pub fn compress(compressor: &mut Compressor, slp: &[u8]) -> Result<CompressOutput, CompError> {
+    let data = compress_unchecked(compressor, slp)?;
+    Ok(CompressOutput { data, warnings: compress_warnings(compressor, slp) })
+}
+
+/// The warnings [`compress`] returns for an slp file that compresses.
+fn compress_warnings(compressor: &Compressor, slp: &[u8]) -> Vec<CompressWarning> {
+    let Ok(parts) = split_slp(slp, compressor.strictness) else { return Vec::new() };
+    let mut warnings = Vec::new();
+
+    let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap());
+    if raw_len == 0 || raw_len == u32::MAX {
+        warnings.push(CompressWarning::ZeroRawLen);
+    } else if (slp.len() as u64) < 15 + raw_len as u64 || 15 + (raw_len as u64) < parts.events_offset as u64 {
+        if byte_swapped_raw_end(slp) == Some(parts.events_offset + parts.events.len()) {
+            warnings.push(CompressWarning::ByteSwappedRawLen { raw_len: u32::from_le_bytes(slp[11..15].try_into().unwrap()) });
+        } else {
+            warnings.push(CompressWarning::TruncatedRawData { raw_len });
+        }
+    }
+
+    if let Ok(counts) = count_events(parts.events, &parts.event_sizes) {
+        warnings.extend(unknown_event_counts(&counts)
+            .into_iter()
+            .map(|(command, count)| CompressWarning::UnknownEventCode { command, count }));
+    }
+
+    let metadata = parts.metadata;
+    if let Some(len) = object_rest_len(metadata).ok().filter(|&len| len < metadata.len()) {
+        warnings.push(CompressWarning::TrailingBytes { len: metadata.len() - len, stripped: compressor.strip_trailer });
+    }
+    if metadata.strip_prefix(b"U\x08metadata").is_some_and(has_non_utf8_string) {
+        warnings.push(CompressWarning::MetadataNotUtf8);
+    }
+
+    warnings
+}
+
+/// Compresses an slp file to an slpz file. Same as [`compress`] without the warnings.
+pub fn compress_unchecked(compressor: &mut Compressor, slp: &[u8]) -> Result<Vec<u8>, CompError> {
+    compress_to_tee(compressor, slp, None)
+}
+
+/// Compresses an slp file to an slpz file, mirroring it to `tee` as it is written. See [`write_slpz`].
+fn compress_to_tee(compressor: &mut Compressor, slp: &[u8], mut tee: Option<&mut SlpzTee<'_>>) -> Result<Vec<u8>, CompError> {
+    let _span = span!("slpz.compress", bytes_in = slp.len(), level = compressor.level);
+    let mut parts = split_slp(slp, compressor.strictness)?;
+    let mut trailer = 0;
+    if compressor.strip_trailer {
+        if let Ok(len) = object_rest_len(parts.metadata) {
+            trailer = parts.metadata.len() - len;
+            parts.metadata = &parts.metadata[..len];
+        }
+    }
+    let replay_id = hash_slp_parts(&parts);
+    let mut budget = compressor.time_budget.map(|limit| TimeBudget {
+        start: std::time::Instant::now(),
+        limit,
+        total: parts.events.len(),
+        done: 0,
+    });
+
+    let segments = if compressor.level == LEVEL_STORE { 1 } else { compressor.segments };
+    let chunk_bytes = compressor.chunk_bytes.min(parts.events.len().div_ceil(segments).max(1));
+    let ranges = chunk_ranges(parts.events, &parts.event_sizes, chunk_bytes).map_err(|e| e.offset_by(parts.events_offset))?;
+
+    let slpz = if segments > 1 {
+        let chunks = compress_segments(compressor, parts.events, &parts.event_sizes, &ranges)
+            .map_err(|e| e.offset_by(parts.events_offset))?;
+        write_slpz(
+            compressor,
+            parts.event_sizes_payload,
+            parts.game_start_payload,
+            parts.metadata,
+            &replay_id,
+            ranges.len(),
+            true,
+            chunks.into_iter().map(Ok::<_, CompError>),
+            None,
+            tee.as_deref_mut(),
+        )?
+    } else {
+        // each chunk is reordered just before it is compressed, so only one is in memory at a time
+        let chunks = ranges.iter().map(|r| {
+            let mut reordered_data = Vec::new();
+            reorder_events(&parts.events[r.clone()], &parts.event_sizes, &mut reordered_data)
+                .map_err(|e| e.offset_by(parts.events_offset + r.start))?;
+            Ok::<_, CompError>(SlpzChunk::Reordered(std::borrow::Cow::Owned(reordered_data)))
+        });
+        write_slpz(
+            compressor,
+            parts.event_sizes_payload,
+            parts.game_start_payload,
+            parts.metadata,
+            &replay_id,
+            ranges.len(),
+            false,
+            chunks,
+            budget.as_mut(),
+            tee,
+        )?
+    };
+
+    if compressor.verify {
+        if let Some(first_diff_byte) = first_difference(&slp[..slp.len() - trailer], &slpz)? {
+            event!(error, first_diff_byte, "verification failed");
+            return Err(CompError::VerificationFailed { first_diff_byte });
+        }
+    }
+
+    Ok(slpz)
+}
+
+/// Returns the first byte where the decompressed slpz file differs from the slp file,
+/// or 0 if it does not decompress.
+///
+/// The raw length is not compared, as it is rewritten if it was never written in the slp file.
+fn first_difference(slp: &[u8], slpz: &[u8]) -> Result<Option<usize>, CompError> {
+    let mut decompressor = Decompressor::new().ok_or(CompError::CompressionFailure)?;
+    let mut sink = CompareSink { expected: slp, written: 0, first_diff: None };
+    if decompress_to_sink(&mut decompressor, slpz, &mut sink).is_err() { return Ok(Some(0)) }
+    Ok(match sink.first_diff {
+        Some(i) => Some(i),
+        None if sink.written != slp.len() => Some(sink.written.min(slp.len())),
+        None => None,
+    })
+}
+
+/// Compares the bytes written to `expected`, except for the raw length. See [`first_difference`].
+struct CompareSink<'a> {
+    expected: &'a [u8],
+    written: usize,
+    first_diff: Option<usize>,
+}
+
+impl std::io::Write for CompareSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.first_diff.is_none() {
+            let expected = self.expected.get(self.written..).unwrap_or(&[]);
+            self.first_diff = buf.iter()
+                .zip(expected)
+                .enumerate()
+                .position(|(i, (a, b))| a != b && !(11..15).contains(&(self.written + i)))
+                .map(|i| self.written + i);
+        }
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+/// Decompresses a sample of the files compressed by [`target_path`] and compares them to their inputs,
+/// as a cheaper check than [`Options::verify_after_compress`]. See [`Options::spot_check`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpotCheck {
+    /// The chance of checking each file, from 0 to 100.
+    pub percent: f64,
+    /// Which files are checked only depends on the seed and each file's path relative to the input directory,
+    /// so a rerun with the same seed checks the same files.
+    pub seed: u64,
+}
+
+impl SpotCheck {
+    /// Whether the file at `path`, relative to the input directory, is checked.
+    pub fn samples(&self, path: &std::path::Path) -> bool {
+        use sha2::Digest;
+
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(self.seed.to_le_bytes());
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        let hash = u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap());
+        self.percent >= 100.0 || (hash as f64) < self.percent / 100.0 * u64::MAX as f64
+    }
+}
+
+/// How much of a replay [`compress_clip`] keeps, counted from its first frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClipRange {
+    Frames(u32),
+    /// Converted to frames at 60 frames per second.
+    Duration(std::time::Duration),
+}
+
+impl ClipRange {
+    pub fn frames(self) -> u32 {
+        match self {
+            ClipRange::Frames(frames) => frames,
+            ClipRange::Duration(d) => (d.as_secs_f64() * 60.0).round().min(u32::MAX as f64) as u32,
+        }
+    }
+}
+
+/// Compresses the start of an slp file, such as for preview clips.
+///
+/// Events from the first frame past `range` on are dropped, and replaced by a Game End event
+/// with the No Contest method if the Event Payloads event declares one.
+/// The metadata's `lastFrame` is set to the last frame kept.
+/// Replays that end within `range` are compressed unchanged.
+pub fn compress_clip(compressor: &mut Compressor, slp: &[u8], range: ClipRange) -> Result<Vec<u8>, CompError> {
+    compress_unchecked(compressor, &clip_slp(slp, range, compressor.strictness)?)
+}
+
+/// The slp file cut as described in [`compress_clip`].
+fn clip_slp(slp: &[u8], range: ClipRange, strictness: Strictness) -> Result<std::borrow::Cow<'_, [u8]>, CompError> {
+    let parts = split_slp(slp, strictness)?;
+    let frames = range.frames() as i64;
+
+    let mut first_frame = None;
+    let mut last_frame = None;
+    let mut cut = None;
+    for e in EventIter::new(parts.events, &parts.event_sizes) {
+        let (command, payload, offset) = e.map_err(|e| CompError::from(e).offset_by(parts.events_offset))?;
+        if !FRAME_EVENTS.contains(&command) || payload.len() < 4 { continue }
+        let frame = i32::from_be_bytes(payload[..4].try_into().unwrap());
+        let first = *first_frame.get_or_insert(frame);
+        if frame as i64 - first as i64 >= frames {
+            cut = Some(offset);
+            break;
+        }
+        last_frame = Some(last_frame.map_or(frame, |last: i32| last.max(frame)));
+    }
+    let (Some(cut), Some(first)) = (cut, first_frame) else { return Ok(std::borrow::Cow::Borrowed(slp)) };
+    let last_frame = last_frame.unwrap_or(first - 1);
+
+    let mut raw = Vec::with_capacity(parts.event_sizes_payload.len() + parts.game_start_payload.len() + cut + 16);
+    raw.extend_from_slice(parts.event_sizes_payload);
+    raw.extend_from_slice(parts.game_start_payload);
+    raw.extend_from_slice(&parts.events[..cut]);
+    let game_end_size = parts.event_sizes[GAME_END as usize] as usize;
+    if game_end_size != 0 {
+        // the LRAS initiator and placements are -1 for none
+        let mut game_end = vec![0xFF; 1 + game_end_size];
+        game_end[0] = GAME_END;
+        game_end[1] = GAME_END_NO_CONTEST;
+        raw.extend_from_slice(&game_end);
+    }
+
+    let mut clipped = Vec::with_capacity(15 + raw.len() + parts.metadata.len());
+    clipped.extend_from_slice(&RAW_HEADER);
+    clipped.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    clipped.extend_from_slice(&raw);
+    clipped.extend_from_slice(parts.metadata);
+    if let Some(i) = metadata_last_frame_offset(parts.metadata) {
+        let i = 15 + raw.len() + i;
+        clipped[i..][..4].copy_from_slice(&last_frame.to_be_bytes());
+    }
+    Ok(std::borrow::Cow::Owned(clipped))
+}
+
+/// Splits the events into ranges of at most `chunk_bytes` at event boundaries.
+/// A single event larger than `chunk_bytes` gets its own range. Always returns at least one range.
+fn chunk_ranges(
+    events: &[u8],
+    event_sizes: &[u16; 256],
+    chunk_bytes: usize,
+) -> Result<Vec<std::ops::Range<usize>>, CompError> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    if events.len() > chunk_bytes {
+        for e in EventIter::new(events, event_sizes) {
+            let (_, payload, offset) = e?;
+            if offset > start && offset + 1 + payload.len() - start > chunk_bytes {
+                ranges.push(start..offset);
+                start = offset;
+            }
+        }
+    }
+    ranges.push(start..events.len());
+    Ok(ranges)
+}
+
+/// Reorders and compresses each range of events as a separate zstd frame,
+/// spreading the ranges over a thread per segment with a zstd context each.
+fn compress_segments(
+    compressor: &Compressor,
+    events: &[u8],
+    event_sizes: &[u16; 256],
+    ranges: &[std::ops::Range<usize>],
+) -> Result<Vec<SlpzChunk<'static>>, CompError> {
+    let threads = compressor.segments.min(ranges.len());
+    let (level, params) = (compressor.level, &compressor.params);
+    std::thread::scope(|scope| {
+        let handles = (0..threads).map(|t| scope.spawn(move || {
+            let mut ctx = zstd_compressor(level, params).ok_or(CompError::CompressionFailure)?;
+            ranges.iter().enumerate().skip(t).step_by(threads).map(|(i, r)| {
+                let mut reordered_data = Vec::new();
+                reorder_events(&events[r.clone()], event_sizes, &mut reordered_data).map_err(|e| e.offset_by(r.start))?;
+                let frame = ctx.compress(&reordered_data).map_err(|_| CompError::CompressionFailure)?;
+                Ok((i, SlpzChunk::Compressed { frame, reordered_size: reordered_data.len() }))
+            }).collect::<Result<Vec<_>, CompError>>()
+        })).collect::<Vec<_>>();
+
+        let mut chunks = Vec::with_capacity(ranges.len());
+        for h in handles { chunks.extend(h.join().unwrap()?); }
+        chunks.sort_by_key(|(i, _)| *i);
+        Ok(chunks.into_iter().map(|(_, c)| c).collect())
+    })
+}
+
+/// A chunk of events for [`write_slpz`].
+enum SlpzChunk<'a> {
+    /// Reordered events, compressed when written.
+    Reordered(std::borrow::Cow<'a, [u8]>),
+    /// Reordered events already compressed to a zstd frame.
+    Compressed { frame: Vec<u8>, reordered_size: usize },
+}
+
+/// Writes the header and sections of an slpz file, compressing each chunk of reordered events.
+///
+/// With more than one chunk, the file is marked with [`FLAG_CHUNKED`] and each chunk is a separate zstd frame,
+/// and also with [`FLAG_SEGMENTED`] if `segmented`. Reordered chunks are compressed in blocks within `budget` if given.
+///
+/// The file is also written to `tee` if given. With a single chunk the header is complete before the chunk is compressed,
+/// so the frame is written to `tee` as zstd produces it. Chunked files are written once their chunk table is filled.
+#[allow(clippy::too_many_arguments)]
+fn write_slpz<'a, E: From<CompError>>(
+    compressor: &mut Compressor,
+    event_sizes_payload: &[u8],
+    game_start_payload: &[u8],
+    metadata: &[u8],
+    replay_id: &[u8; 32],
+    chunk_count: usize,
+    segmented: bool,
+    chunks: impl Iterator<Item = Result<SlpzChunk<'a>, E>>,
+    mut budget: Option<&mut TimeBudget>,
+    mut tee: Option<&mut SlpzTee<'_>>,
+) -> Result<Vec<u8>, E> {
+    let chunked = chunk_count > 1;
+    let mut slpz = Vec::with_capacity(
+        68 + 8 * chunk_count + event_sizes_payload.len() + game_start_payload.len() + metadata.len()
+    );
+
+    // header
+    slpz.extend_from_slice(&SLPZ_FORMAT_VERSION.to_be_bytes());
+    slpz.extend_from_slice(&[0u8; 20]); // offsets filled later
+    let store = compressor.level == LEVEL_STORE;
+    let flags = FLAG_REPLAY_ID
+        | if store { FLAG_STORED } else { 0 }
+        | if chunked { FLAG_CHUNKED } else { 0 }
+        | if chunked && segmented { FLAG_SEGMENTED } else { 0 };
+    slpz.extend_from_slice(&flags.to_be_bytes());
+    slpz.extend_from_slice(&compressor.level.to_be_bytes());
+    slpz.extend_from_slice(replay_id);
+
+    let chunk_table_offset = slpz.len() + 4;
+    if chunked {
+        slpz.extend_from_slice(&(chunk_count as u32).to_be_bytes());
+        slpz.resize(chunk_table_offset + 8 * chunk_count, 0); // entries filled later
+    }
+
+    // write event sizes
+    let len = slpz.len() as u32;
+    slpz[4..8].copy_from_slice(&len.to_be_bytes());
+    slpz.extend_from_slice(event_sizes_payload);
+
+    // write game start
+    let len = slpz.len() as u32;
+    slpz[8..12].copy_from_slice(&len.to_be_bytes());
+    slpz.extend_from_slice(game_start_payload);
+
+    // write metadata
+    let len = slpz.len() as u32;
+    slpz[12..16].copy_from_slice(&len.to_be_bytes());
+    slpz.extend_from_slice(metadata);
+
+    // write compressed events
+    let len = slpz.len() as u32;
+    slpz[16..20].copy_from_slice(&len.to_be_bytes());
+
+    let mut reordered_size = 0;
+    for (i, chunk) in chunks.enumerate() {
+        let chunk = chunk?;
+        let mut stream = if chunked { None } else { tee.as_deref_mut() };
+        if let Some(ref mut stream) = stream {
+            let size = match &chunk {
+                SlpzChunk::Reordered(chunk) => chunk.len(),
+                SlpzChunk::Compressed { reordered_size, .. } => *reordered_size,
+            };
+            slpz[20..24].copy_from_slice(&(size as u32).to_be_bytes());
+            stream.write_new(&slpz)?;
+        }
+
+        let start = slpz.len();
+        let chunk_size = match chunk {
+            SlpzChunk::Reordered(chunk) if store => {
+                slpz.extend_from_slice(&chunk);
+                chunk.len()
+            }
+            SlpzChunk::Reordered(chunk) if budget.is_some() || stream.is_some() => {
+                compress_blocks(compressor, &chunk, &mut slpz, budget.as_deref_mut(), stream)?;
+                chunk.len()
+            }
+            SlpzChunk::Reordered(chunk) => {
+                slpz.reserve(zstd::zstd_safe::compress_bound(chunk.len()));
+
+                // wrap in cursor so we don't overwrite previous data
+                let mut slpz_cursor = std::io::Cursor::new(slpz);
+                slpz_cursor.set_position(start as u64);
+                compressor.ctx.compress_to_buffer(&chunk, &mut slpz_cursor).map_err(|_| CompError::CompressionFailure)?;
+                slpz = slpz_cursor.into_inner();
+                chunk.len()
+            }
+            SlpzChunk::Compressed { frame, reordered_size } => {
+                slpz.extend_from_slice(&frame);
+                reordered_size
+            }
+        };
+
+        if chunked {
+            let entry = chunk_table_offset + 8 * i;
+            let compressed_size = (slpz.len() - start) as u32;
+            slpz[entry..][..4].copy_from_slice(&compressed_size.to_be_bytes());
+            slpz[entry + 4..][..4].copy_from_slice(&(chunk_size as u32).to_be_bytes());
+        }
+        reordered_size += chunk_size;
+    }
+    slpz[20..24].copy_from_slice(&(reordered_size as u32).to_be_bytes());
+    if let Some(tee) = tee { tee.write_new(&slpz)?; }
+
+    Ok(slpz)
+}
+
+/// The writer of [`compress_tee`], and how much of the slpz file has been written to it.
+struct SlpzTee<'a> {
+    tee: &'a mut dyn std::io::Write,
+    written: usize,
+    error: Option<std::io::Error>,
+}
+
+impl SlpzTee<'_> {
+    /// Writes the bytes of `slpz` that have not been written yet, keeping the error if it fails.
+    fn write_new(&mut self, slpz: &[u8]) -> Result<(), CompError> {
+        if let Err(e) = self.tee.write_all(&slpz[self.written..]) {
+            self.error = Some(e);
+            return Err(CompError::CompressionFailure);
+        }
+        self.written = slpz.len();
+        Ok(())
+    }
+}
+
+/// The time allowed for compressing a file. See [`Compressor::set_time_budget`].
+struct TimeBudget {
+    start: std::time::Instant,
+    limit: std::time::Duration,
+    /// Bytes of events in the file, and how many have been compressed.
+    total: usize,
+    done: usize,
+}
+
+impl TimeBudget {
+    /// Fails if the time so far, scaled up to all of the events, is over the limit.
+    fn check(&self) -> Result<(), CompError> {
+        let elapsed = self.start.elapsed();
+        let projected = match self.done {
+            0 => elapsed,
+            done => elapsed.mul_f64(self.total as f64 / done as f64),
+        };
+        if projected > self.limit { return Err(CompError::TimeBudgetExceeded) }
+        Ok(())
+    }
+}
+
+/// Bytes of reordered events passed to zstd between checks of the [`TimeBudget`].
+const BUDGET_BLOCK_BYTES: usize = 1 << 20;
+
+/// Compresses reordered events to a zstd frame at the end of `out` like [`zstd::bulk::Compressor::compress_to_buffer`],
+/// a block at a time, checking the budget before each block and writing the output so far to `tee` after it.
+fn compress_blocks(
+    compressor: &mut Compressor,
+    data: &[u8],
+    out: &mut Vec<u8>,
+    mut budget: Option<&mut TimeBudget>,
+    mut tee: Option<&mut SlpzTee<'_>>,
+) -> Result<(), CompError> {
+    use zstd::zstd_safe::{zstd_sys::ZSTD_EndDirective, InBuffer, OutBuffer, ResetDirective};
+
+    let ctx = compressor.ctx.context_mut();
+    ctx.reset(ResetDirective::SessionOnly).map_err(|_| CompError::CompressionFailure)?;
+    ctx.set_pledged_src_size(Some(data.len() as u64)).map_err(|_| CompError::CompressionFailure)?;
+    out.reserve(zstd::zstd_safe::compress_bound(data.len()));
+
+    let block_count = data.len().div_ceil(BUDGET_BLOCK_BYTES).max(1);
+    for i in 0..block_count {
+        if let Some(ref budget) = budget { budget.check()?; }
+        let block = &data[(i * BUDGET_BLOCK_BYTES).min(data.len())..((i + 1) * BUDGET_BLOCK_BYTES).min(data.len())];
+        let last = i + 1 == block_count;
+        let directive = if last { ZSTD_EndDirective::ZSTD_e_end } else { ZSTD_EndDirective::ZSTD_e_continue };
+        let mut input = InBuffer::around(block);
+        loop {
+            let pos = out.len();
+            let mut output = OutBuffer::around_pos(out, pos);
+            let remaining = ctx.compress_stream2(&mut output, &mut input, directive)
+                .map_err(|_| CompError::CompressionFailure)?;
+            let finished = if last { remaining == 0 } else { input.pos == block.len() };
+            if finished { break }
+            // out has room for the compress bound, so zstd should never run out
+            if out.capacity() == out.len() { return Err(CompError::CompressionFailure) }
+        }
+        if let Some(ref mut budget) = budget { budget.done += block.len(); }
+        if let Some(ref mut tee) = tee { tee.write_new(out)?; }
+    }
+    Ok(())
+}
+
+/// Compresses each slp file with the same compressor. The results are in the same order as the inputs.
+pub fn compress_all<'a>(
+    compressor: &mut Compressor,
+    inputs: impl IntoIterator<Item = &'a [u8]>,
+) -> Vec<Result<Vec<u8>, CompError>> {
+    inputs.into_iter().map(|slp| compress_unchecked(compressor, slp)).collect()
+}
+
+/// Compresses slp files on a thread per available core, with [`with_thread_local_compressor`].
+/// The results are in the same order as the inputs.
+pub fn compress_batch_parallel(compression_level: i32, inputs: &[&[u8]]) -> Vec<Result<Vec<u8>, CompError>> {
+    parallel_map(inputs, true, |slp| {
+        with_thread_local_compressor(compression_level, |c| compress_unchecked(c, slp))
+            .unwrap_or(Err(CompError::CompressionFailure))
+    })
+}
+
+/// Decompresses each slpz file with the same decompressor. The results are in the same order as the inputs.
+pub fn decompress_all<'a>(
+    decompressor: &mut Decompressor,
+    inputs: impl IntoIterator<Item = &'a [u8]>,
+) -> Vec<Result<Vec<u8>, DecompError>> {
+    inputs.into_iter().map(|slpz| decompress(decompressor, slpz)).collect()
+}
+
+/// Returns the reordered events of each chunk of an slpz file in order, decompressing them unless they are stored.
+fn read_reordered_chunks<'a, 'd>(
+    decompressor: &'d mut Decompressor,
+    slpz: &'a [u8],
+    header: &SlpzHeader,
+) -> impl Iterator<Item = Result<std::borrow::Cow<'a, [u8]>, DecompError>> + 'd where 'a: 'd {
+    let stored = header.flags & FLAG_STORED != 0;
+    let mut compressed_events = &slpz[header.compressed_events_offset..];
+    header.chunks(slpz).into_iter().map(move |(compressed_size, reordered_size)| {
+        // chunk sizes are checked against the section size when parsing the header
+        let (chunk, rest) = compressed_events.split_at(compressed_size);
+        compressed_events = rest;
+        read_reordered_chunk(decompressor, chunk, reordered_size, stored)
+    })
+}
+
+fn read_reordered_chunk<'a>(
+    decompressor: &mut Decompressor,
+    chunk: &'a [u8],
+    reordered_size: usize,
+    stored: bool,
+) -> Result<std::borrow::Cow<'a, [u8]>, DecompError> {
+    if stored {
+        if chunk.len() != reordered_size { return Err(DecompError::InvalidFile) }
+        return Ok(std::borrow::Cow::Borrowed(chunk));
+    }
+
+    // frames written before the content size was recorded are checked after decompressing
+    if let Ok(Some(frame)) = zstd::zstd_safe::get_frame_content_size(chunk) {
+        if frame != reordered_size as u64 {
+            return Err(DecompError::FrameSizeMismatch { header: reordered_size, frame: frame as usize });
+        }
+    }
+    let b = decompressor.ctx.decompress(chunk, reordered_size)
+        .map_err(|_| DecompError::DecompressionFailure)?;
+    if b.len() != reordered_size {
+        return Err(DecompError::FrameSizeMismatch { header: reordered_size, frame: b.len() });
+    }
+    Ok(std::borrow::Cow::Owned(b))
+}
+
+/// Decompresses every chunk of an slpz file, spreading them over `threads` threads with a zstd context each.
+fn decompress_chunks_parallel(slpz: &[u8], header: &SlpzHeader, threads: usize) -> Result<Vec<Vec<u8>>, DecompError> {
+    let mut chunks = Vec::with_capacity(header.chunk_count);
+    let mut compressed_events = &slpz[header.compressed_events_offset..];
+    for (compressed_size, reordered_size) in header.chunks(slpz) {
+        // chunk sizes are checked against the section size when parsing the header
+        let (chunk, rest) = compressed_events.split_at(compressed_size);
+        compressed_events = rest;
+        chunks.push((chunk, reordered_size));
+    }
+
+    let threads = threads.min(chunks.len());
+    let chunks = &chunks;
+    std::thread::scope(|scope| {
+        let handles = (0..threads).map(|t| scope.spawn(move || {
+            let mut decompressor = Decompressor::new().ok_or(DecompError::DecompressionFailure)?;
+            chunks.iter().enumerate().skip(t).step_by(threads).map(|(i, &(chunk, reordered_size))| {
+                Ok((i, read_reordered_chunk(&mut decompressor, chunk, reordered_size, false)?.into_owned()))
+            }).collect::<Result<Vec<_>, DecompError>>()
+        })).collect::<Vec<_>>();
+
+        let mut reordered = Vec::with_capacity(chunks.len());
+        for h in handles { reordered.extend(h.join().unwrap()?); }
+        reordered.sort_by_key(|(i, _)| *i);
+        Ok(reordered.into_iter().map(|(_, r)| r).collect())
+    })
+}
+
+/// Returns the size of the slp file that an slpz file decompresses to, read from the header without decompressing.
+///
+/// Returns `DecompError::TruncatedInput` if the file ends before the compressed events.
+pub fn slpz_original_size(slpz: &[u8]) -> Result<u64, DecompError> {
+    Ok(embedded::original_size(slpz)?)
+}
+
+/// Returns the payload size of each event type in an slpz file, read from the header without decompressing.
+///
+/// Every slpz format version keeps the Event Payloads event uncompressed, so this never starts a zstd decode.
+pub fn slpz_event_sizes(slpz: &[u8]) -> Result<EventSizes, DecompError> {
+    let header = SlpzHeader::parse(slpz)?;
+    let (sizes, _) = event_sizes(&slpz[header.event_sizes_offset..header.game_start_offset]).ok_or(DecompError::InvalidFile)?;
+    Ok(sizes)
+}
+
+/// An slpz file with its header checked, borrowed without copying, such as from a memory mapped file.
+///
+/// Only the magic bytes and header offsets are checked by [`SlpzView::new`]. The compressed events are checked when decompressed.
+#[derive(Copy, Clone, Debug)]
+pub struct SlpzView<'a> {
+    data: &'a [u8],
+    header: SlpzHeader,
+}
+
+impl<'a> SlpzView<'a> {
+    pub fn new(data: &'a [u8]) -> Result<SlpzView<'a>, DecompError> {
+        Ok(SlpzView { data, header: SlpzHeader::parse(data)? })
+    }
+
+    pub fn header(&self) -> SlpzHeader { self.header }
+
+    /// The whole file.
+    pub fn as_bytes(&self) -> &'a [u8] { self.data }
+
+    /// The metadata element, as returned by [`read_metadata`].
+    pub fn metadata(&self) -> &'a [u8] {
+        &self.data[self.header.metadata_offset..self.header.compressed_events_offset]
+    }
+
+    /// The zstd frames of the events, in chunk order. See [`SlpzHeader::chunks`].
+    pub fn compressed_data(&self) -> &'a [u8] {
+        &self.data[self.header.compressed_events_offset..]
+    }
+
+    pub fn decompress(&self, decompressor: &mut Decompressor) -> Result<Vec<u8>, DecompError> {
+        decompress(decompressor, self.data)
+    }
+}
+
+/// Totals from [`decompress_to_sink`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecompStats {
+    /// Size of the slpz file.
+    pub bytes_in: usize,
+    /// Bytes written to the sink, which is the size of the slp file.
+    pub bytes_out: u64,
+    /// The [`replay_id`] of the slp file, hashed as it was written.
+    /// Compare with [`read_replay_id`] to check the file decompresses to the replay it was compressed from.
+    pub replay_id: [u8; 32],
+}
+
+/// Decompresses an slpz file, writing the slp file to `sink` as it is rebuilt.
+///
+/// Unlike [`decompress`], the slp file is never fully held in memory, only one chunk of reordered events.
+/// Pass [`std::io::sink`] to only check the file and hash it.
+pub fn decompress_to_sink(
+    decompressor: &mut Decompressor,
+    slpz: &[u8],
+    sink: &mut dyn std::io::Write,
+) -> Result<DecompStats, DecompError> {
+    use sha2::Digest;
+
+    let _span = span!("slpz.decompress_to_sink", bytes_in = slpz.len());
+    let header = SlpzHeader::parse(slpz)?;
+    let event_sizes_bytes = &slpz[header.event_sizes_offset..header.game_start_offset];
+    let (event_sizes, _) = event_sizes(event_sizes_bytes).ok_or(DecompError::InvalidFile)?;
+    let metadata = &slpz[header.metadata_offset..header.compressed_events_offset];
+
+    let slp_size = slpz_original_size(slpz)?;
+    let raw_len = slp_size - 15 - metadata.len() as u64;
+    let mut header_bytes = RAW_HEADER.to_vec();
+    header_bytes.extend_from_slice(&u32::try_from(raw_len).map_err(|_| DecompError::InvalidFile)?.to_be_bytes());
+    sink.write_all(&header_bytes).map_err(|_| DecompError::WriteFailed)?;
+
+    // the replay ID hashes everything after the raw length
+    let mut hasher = sha2::Sha256::new();
+    let mut bytes_out = header_bytes.len() as u64;
+    let mut emit = |block: &[u8]| -> Result<(), DecompError> {
+        hasher.update(block);
+        bytes_out += block.len() as u64;
+        sink.write_all(block).map_err(|_| DecompError::WriteFailed)
+    };
+
+    emit(&slpz[header.event_sizes_offset..header.metadata_offset])?;
+    let block_size = decompressor.buffer_size;
+    let mut staging = Vec::with_capacity(block_size + u16::MAX as usize + 1);
+    for reordered in read_reordered_chunks(decompressor, slpz, &header) {
+        unorder_events_to(&reordered?, &event_sizes, block_size, &mut staging, &mut emit)?;
+    }
+    emit(metadata)?;
+
+    if bytes_out != slp_size { return Err(DecompError::InvalidFile) }
+    Ok(DecompStats { bytes_in: slpz.len(), bytes_out, replay_id: hasher.finalize().into() })
+}
+
+/// Decompresses an slpz file to an slp file.
+pub fn decompress(decompressor: &mut Decompressor, slpz: &[u8]) -> Result<Vec<u8>, DecompError> {
+    let _span = span!("slpz.decompress", bytes_in = slpz.len());
+    let header = SlpzHeader::parse(slpz)?;
+
+    let mut slp = Vec::with_capacity(slpz.len() * 32);
+    slp.extend_from_slice(&RAW_HEADER);
+    slp.extend_from_slice(&[0u8; 4]); // raw len. filled in later
+
+    let event_sizes_bytes = &slpz[header.event_sizes_offset..header.game_start_offset];
+    slp.extend_from_slice(event_sizes_bytes);
+    let (event_sizes, _) = event_sizes(event_sizes_bytes).ok_or(DecompError::InvalidFile)?;
+    slp.extend_from_slice(&slpz[header.game_start_offset..header.metadata_offset]);
+
+    if decompressor.threads > 1 && header.chunk_count > 1 && header.flags & FLAG_STORED == 0 {
+        for reordered in decompress_chunks_parallel(slpz, &header, decompressor.threads)? {
+            unorder_events(&reordered, &event_sizes, &mut slp)?;
+        }
+    } else {
+        for reordered in read_reordered_chunks(decompressor, slpz, &header) {
+            unorder_events(&reordered?, &event_sizes, &mut slp)?;
+        }
+    }
+
+    let metadata_offset_in_slp = slp.len();
+    slp.extend_from_slice(&slpz[header.metadata_offset..header.compressed_events_offset]);
+
+    slp[11..15].copy_from_slice(&(metadata_offset_in_slp as u32 - 15).to_be_bytes()); // raw len
+
+    Ok(slp)
+}
+
+/// Compresses an slp file to an slpz file, also writing it to `tee`, such as a hasher or an upload.
+///
+/// The header is written first, then the compressed events as zstd produces them. Chunked files have a chunk table
+/// of sizes only known once every chunk is compressed, so they are written when compression finishes.
+/// If compression fails partway, or [`Compressor::set_verify`] finds a difference, part of the file has been written.
+pub fn compress_tee(compressor: &mut Compressor, slp: &[u8], tee: &mut dyn std::io::Write) -> Result<Vec<u8>, SlpzError> {
+    let mut tee = SlpzTee { tee, written: 0, error: None };
+    match compress_to_tee(compressor, slp, Some(&mut tee)) {
+        Ok(slpz) => Ok(slpz),
+        Err(e) => Err(tee.error.map_or(SlpzError::from(e), SlpzError::Io)),
+    }
+}
+
+/// Decompresses an slpz file to an slp file, also writing it to `tee` as it is rebuilt.
+///
+/// The events are decompressed on one thread, as with [`decompress_to_sink`].
+pub fn decompress_tee(decompressor: &mut Decompressor, slpz: &[u8], tee: &mut dyn std::io::Write) -> Result<Vec<u8>, SlpzError> {
+    let capacity = slpz_original_size(slpz).map_or(0, |size| size as usize);
+    let mut sink = TeeSink { slp: Vec::with_capacity(capacity), tee, error: None };
+    match decompress_to_sink(decompressor, slpz, &mut sink) {
+        Ok(_) => Ok(sink.slp),
+        Err(e) => Err(sink.error.map_or(SlpzError::from(e), SlpzError::Io)),
+    }
+}
+
+/// Collects the slp file for [`decompress_tee`], keeping the error from `tee` if it fails.
+struct TeeSink<'a> {
+    slp: Vec<u8>,
+    tee: &'a mut dyn std::io::Write,
+    error: Option<std::io::Error>,
+}
+
+impl std::io::Write for TeeSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Err(e) = self.tee.write_all(buf) {
+            self.error = Some(e);
+            return Err(std::io::ErrorKind::Other.into());
+        }
+        self.slp.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { self.tee.flush() }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IdError {
+    /// Neither an slp nor an slpz file.
+    UnknownFormat,
+    InvalidFile,
+    DecompressionFailure,
+}
+
+impl std::fmt::Display for IdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            IdError::UnknownFormat => "Not a slp or slpz file",
+            IdError::InvalidFile => "File is invalid",
+            IdError::DecompressionFailure => "Decompression failed",
+        })
+    }
+}
+
+impl std::error::Error for CompError {}
+impl std::error::Error for DecompError {}
+impl std::error::Error for TargetPathError {}
+impl std::error::Error for IdError {}
+impl std::error::Error for ZstdInitError {}
+
+/// Any error returned by slpz. Returned by the file and directory APIs.
+///
+/// The low level functions such as [`compress`] and [`decompress`] return their specific error types,
+/// which convert into this with `?`.
+#[derive(Debug)]
+pub enum SlpzError {
+    Compress(CompError),
+    Decompress(DecompError),
+    TargetPath(TargetPathError),
+    Id(IdError),
+    Dictionary(DictionaryError),
+    Repair(RepairError),
+    ZstdInit(ZstdInitError),
+    Io(std::io::Error),
+    /// An error while processing a specific file.
+    File { path: std::path::PathBuf, error: Box<SlpzError> },
+}
+
+/// Broad categories of [`SlpzError`]. See [`SlpzError::kind`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorKind {
+    /// Reading or writing a file failed, or a path does not exist.
+    Io,
+    /// The input is an slp or slpz file, but it is damaged.
+    CorruptInput,
+    /// The input is not a supported format, or an option is invalid.
+    Unsupported,
+    /// The input exceeds a size limit.
+    Limit,
+    /// A failure inside slpz or zstd that is not caused by the input.
+    Internal,
+}
+
+impl SlpzError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SlpzError::Compress(e) => match e {
+                CompError::Corrupt { .. }
+                    | CompError::PlaceholderRawLen
+                    | CompError::TrailingData { .. }
+                    | CompError::MissingMetadata => ErrorKind::CorruptInput,
+                CompError::NotSlp { .. } | CompError::UnknownEvent { .. } | CompError::FileTooLarge { .. } => ErrorKind::Unsupported,
+                CompError::CompressionFailure | CompError::VerificationFailed { .. } => ErrorKind::Internal,
+                CompError::TimeBudgetExceeded => ErrorKind::Limit,
+            },
+            SlpzError::Decompress(e) => match e {
+                DecompError::InvalidFile
+                    | DecompError::DecompressionFailure
+                    | DecompError::FrameSizeMismatch { .. }
+                    | DecompError::TruncatedInput => ErrorKind::CorruptInput,
+                DecompError::WriteFailed => ErrorKind::Io,
+                DecompError::NotSlpz { .. } | DecompError::UnsupportedVersion { .. } => ErrorKind::Unsupported,
+            },
+            SlpzError::TargetPath(e) => match e {
+                TargetPathError::PathNotFound
+                    | TargetPathError::PathInvalid
+                    | TargetPathError::OutputDirInvalid
+                    | TargetPathError::JournalInvalid => ErrorKind::Io,
+                TargetPathError::CompressOrDecompressAmbiguous
+                    | TargetPathError::InvalidOutputExtension
+                    | TargetPathError::OutputDirConflict
+                    | TargetPathError::OutputCollision
+                    | TargetPathError::ClipRemovesInput => ErrorKind::Unsupported,
+                TargetPathError::ZstdInitError => ErrorKind::Internal,
+            },
+            SlpzError::Id(e) => match e {
+                IdError::UnknownFormat => ErrorKind::Unsupported,
+                IdError::InvalidFile | IdError::DecompressionFailure => ErrorKind::CorruptInput,
+            },
+            SlpzError::Dictionary(e) => match e {
+                DictionaryError::NoSamples => ErrorKind::Unsupported,
+                DictionaryError::InvalidSample { .. } => ErrorKind::CorruptInput,
+                DictionaryError::TrainingFailure => ErrorKind::Internal,
+            },
+            SlpzError::Repair(e) => match e {
+                RepairError::NotSlp | RepairError::VersionMismatch { .. } => ErrorKind::Unsupported,
+                RepairError::Donor(_)
+                    | RepairError::MissingGameStart
+                    | RepairError::Misaligned { .. } => ErrorKind::CorruptInput,
+            },
+            SlpzError::ZstdInit(e) => match e {
+                ZstdInitError::InvalidLevel { .. } => ErrorKind::Unsupported,
+                ZstdInitError::ParameterRejected => ErrorKind::Internal,
+            },
+            SlpzError::Io(_) => ErrorKind::Io,
+            SlpzError::File { error, .. } => error.kind(),
+        }
+    }
+
+    /// Attaches the path of the file being processed.
+    pub fn in_file(self, path: &std::path::Path) -> SlpzError {
+        SlpzError::File { path: path.to_path_buf(), error: Box::new(self) }
+    }
+}
+
+impl std::fmt::Display for SlpzError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SlpzError::Compress(e) => write!(f, "{}", e),
+            SlpzError::Decompress(e) => write!(f, "{}", e),
+            SlpzError::TargetPath(e) => write!(f, "{}", e),
+            SlpzError::Id(e) => write!(f, "{}", e),
+            SlpzError::Dictionary(e) => write!(f, "{}", e),
+            SlpzError::Repair(e) => write!(f, "{}", e),
+            SlpzError::ZstdInit(e) => write!(f, "{}", e),
+            SlpzError::Io(e) => write!(f, "{}", e),
+            SlpzError::File { path, error } => write!(f, "{}: {}", display_escaped(&path), error),
+        }
+    }
+}
+
+impl std::error::Error for SlpzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SlpzError::Compress(e) => Some(e),
+            SlpzError::Decompress(e) => Some(e),
+            SlpzError::TargetPath(e) => Some(e),
+            SlpzError::Id(e) => Some(e),
+            SlpzError::Dictionary(e) => Some(e),
+            SlpzError::Repair(e) => Some(e),
+            SlpzError::ZstdInit(e) => Some(e),
+            SlpzError::Io(e) => Some(e),
+            SlpzError::File { error, .. } => Some(error.as_ref()),
+        }
+    }
+}
+
+impl From<CompError> for SlpzError {
+    fn from(e: CompError) -> Self { SlpzError::Compress(e) }
+}
+
+impl From<DecompError> for SlpzError {
+    fn from(e: DecompError) -> Self { SlpzError::Decompress(e) }
+}
+
+impl From<TargetPathError> for SlpzError {
+    fn from(e: TargetPathError) -> Self { SlpzError::TargetPath(e) }
+}
+
+impl From<IdError> for SlpzError {
+    fn from(e: IdError) -> Self { SlpzError::Id(e) }
+}
+
+impl From<DictionaryError> for SlpzError {
+    fn from(e: DictionaryError) -> Self { SlpzError::Dictionary(e) }
+}
+
+impl From<RepairError> for SlpzError {
+    fn from(e: RepairError) -> Self { SlpzError::Repair(e) }
+}
+
+impl From<ZstdInitError> for SlpzError {
+    fn from(e: ZstdInitError) -> Self { SlpzError::ZstdInit(e) }
+}
+
+impl From<std::io::Error> for SlpzError {
+    fn from(e: std::io::Error) -> Self { SlpzError::Io(e) }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DictionaryError {
+    NoSamples,
+    /// The sample at this index is not a valid slp file.
+    InvalidSample { index: usize },
+    /// zstd could not train a dictionary, usually because there is too little sample data.
+    TrainingFailure,
+}
+
+impl std::fmt::Display for DictionaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DictionaryError::NoSamples => write!(f, "No slp files to train on"),
+            DictionaryError::InvalidSample { index } => write!(f, "Sample {} is not a valid slp file", index),
+            DictionaryError::TrainingFailure => write!(f, "Dictionary training failed"),
+        }
+    }
+}
+
+impl std::error::Error for DictionaryError {}
+
+/// Returns the contents of the raw element of an slp file: the Event Payloads event through the last event.
+///
+/// Does not copy. If the raw length was never written, the end is found as in [`replay_id`].
+pub fn extract_raw_section(slp: &[u8]) -> Result<&[u8], SlpzError> {
+    let parts = split_slp(slp, Strictness::Normal)?;
+    Ok(&slp[15..slp.len() - parts.metadata.len()])
+}
+
+/// Trains a zstd dictionary of at most `dict_size` bytes on the raw sections of slp files.
+/// See [`extract_raw_section`].
+pub fn train_slpz_dictionary(slp_files: &[&[u8]], dict_size: usize) -> Result<Vec<u8>, DictionaryError> {
+    if slp_files.is_empty() { return Err(DictionaryError::NoSamples) }
+
+    let samples = slp_files.iter()
+        .enumerate()
+        .map(|(index, slp)| extract_raw_section(slp).map_err(|_| DictionaryError::InvalidSample { index }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    zstd::dict::from_samples(&samples, dict_size).map_err(|_| DictionaryError::TrainingFailure)
+}
+
+/// Why [`repair_with_donor`] refused to repair a replay.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RepairError {
+    /// The broken file does not start with the slp header and an Event Payloads event.
+    NotSlp,
+    /// The donor is not a valid slp file.
+    Donor(CompError),
+    /// No Game Start event follows the broken file's Event Payloads event.
+    MissingGameStart,
+    /// The replays were recorded by different Slippi versions, as (major, minor, build).
+    VersionMismatch { broken: [u8; 3], donor: [u8; 3] },
+    /// The broken file's events do not parse with the donor's payload sizes.
+    /// The offset in the broken file where parsing stopped.
+    Misaligned { offset: usize },
+}
+
+impl std::fmt::Display for RepairError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepairError::NotSlp => write!(f, "File is not a slp file"),
+            RepairError::Donor(e) => write!(f, "Donor is invalid: {}", e),
+            RepairError::MissingGameStart => write!(f, "No Game Start event after the Event Payloads event"),
+            RepairError::VersionMismatch { broken: [a, b, c], donor: [x, y, z] } =>
+                write!(f, "Replay is from Slippi {}.{}.{} but the donor is from {}.{}.{}", a, b, c, x, y, z),
+            RepairError::Misaligned { offset } =>
+                write!(f, "Events do not match the donor's payload sizes at offset {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for RepairError {}
+
+/// Repairs an slp file whose Event Payloads event is damaged, such as by truncation,
+/// by replacing it with the one from `donor`, a replay from the same Slippi version.
+///
+/// Slippi versions must have the same major and minor version.
+/// Refuses unless every event of `broken`, from the Game Start event to the metadata or the end of the file,
+/// parses with the donor's payload sizes. The raw length is rewritten.
+pub fn repair_with_donor(broken: &[u8], donor: &[u8]) -> Result<Vec<u8>, RepairError> {
+    const METADATA_START: &[u8] = b"U\x08metadata";
+
+    if !broken.starts_with(&RAW_HEADER) || broken.get(15) != Some(&EVENT_PAYLOADS) { return Err(RepairError::NotSlp) }
+    let donor = split_slp(donor, Strictness::Normal).map_err(RepairError::Donor)?;
+    let donor_version: [u8; 3] = donor.game_start_payload[1..4].try_into().unwrap();
+
+    // The damaged event's length cannot be trusted, so try each Game Start command byte
+    // where the largest Event Payloads event could end.
+    let mut error = RepairError::MissingGameStart;
+    let last_start = broken.len().min(17 + 255);
+    for game_start_offset in (17..last_start).filter(|&i| broken[i] == GAME_START) {
+        let events = &broken[game_start_offset..];
+        let events_len = scan_events_len(events, &donor.event_sizes);
+        let rest = &events[events_len..];
+        if events_len == 0 || !(rest.is_empty() || rest.starts_with(METADATA_START)) {
+            let offset = game_start_offset + events_len;
+            match error {
+                RepairError::VersionMismatch { .. } => (),
+                RepairError::Misaligned { offset: furthest } if furthest >= offset => (),
+                _ => error = RepairError::Misaligned { offset },
+            }
+            continue;
+        }
+
+        let version: [u8; 3] = events[1..4].try_into().unwrap();
+        if version[..2] != donor_version[..2] {
+            error = RepairError::VersionMismatch { broken: version, donor: donor_version };
+            continue;
+        }
+
+        let raw_len = donor.event_sizes_payload.len() + events_len;
+        let mut repaired = Vec::with_capacity(15 + raw_len + rest.len());
+        repaired.extend_from_slice(&RAW_HEADER);
+        repaired.extend_from_slice(&(raw_len as u32).to_be_bytes());
+        repaired.extend_from_slice(donor.event_sizes_payload);
+        repaired.extend_from_slice(&events[..events_len]);
+        repaired.extend_from_slice(rest);
+        return Ok(repaired);
+    }
+
+    Err(error)
+}
+
+/// Compresses the slp file at `input`, atomically writing the slpz file to `output`.
+pub fn compress_file(
+    compressor: &mut Compressor,
+    input: &std::path::Path,
+    output: &std::path::Path,
+) -> Result<(), SlpzError> {
+    let _span = span!(
+        "slpz.compress_file",
+        path = %display_escaped(&input),
+        level = compressor.level,
+        bytes_in = tracing::field::Empty,
+        bytes_out = tracing::field::Empty,
+    );
+    let slp = std::fs::read(input).map_err(|e| SlpzError::from(e).in_file(input))?;
+    record!(_span, "bytes_in", slp.len());
+    let slpz = compress_unchecked(compressor, &slp).map_err(|e| SlpzError::from(e).in_file(input))?;
+    record!(_span, "bytes_out", slpz.len());
+    atomic_write(output, &slpz).map_err(|e| SlpzError::from(e).in_file(output))
+}
+
+/// Decompresses the slpz file at `input`, atomically writing the slp file to `output`.
+pub fn decompress_file(
+    decompressor: &mut Decompressor,
+    input: &std::path::Path,
+    output: &std::path::Path,
+) -> Result<(), SlpzError> {
+    let _span = span!(
+        "slpz.decompress_file",
+        path = %display_escaped(&input),
+        bytes_in = tracing::field::Empty,
+        bytes_out = tracing::field::Empty,
+    );
+    let slpz = std::fs::read(input).map_err(|e| SlpzError::from(e).in_file(input))?;
+    record!(_span, "bytes_in", slpz.len());
+    let slp = decompress(decompressor, &slpz).map_err(|e| SlpzError::from(e).in_file(input))?;
+    record!(_span, "bytes_out", slp.len());
+    atomic_write(output, &slp).map_err(|e| SlpzError::from(e).in_file(output))
+}
+
+/// Recompresses an slpz file with a different compressor, such as one with a higher level.
+///
+/// The events are recompressed in their reordered form, so the slp file is not rebuilt.
+/// The replay ID and chunks are carried over. Version 0 files, which have no replay ID, are fully decompressed.
+pub fn recompress(
+    decompressor: &mut Decompressor,
+    compressor: &mut Compressor,
+    slpz: &[u8],
+) -> Result<Vec<u8>, SlpzError> {
+    let header = SlpzHeader::parse(slpz)?;
+    let Some(replay_id) = header.replay_id else {
+        let slp = decompress(decompressor, slpz)?;
+        return Ok(compress_unchecked(compressor, &slp)?);
+    };
+
+    let mut metadata = &slpz[header.metadata_offset..header.compressed_events_offset];
+    if compressor.strip_trailer {
+        if let Ok(len) = object_rest_len(metadata) { metadata = &metadata[..len]; }
+    }
+
+    let chunks = read_reordered_chunks(decompressor, slpz, &header)
+        .map(|c| c.map(SlpzChunk::Reordered).map_err(SlpzError::from));
+    write_slpz(
+        compressor,
+        &slpz[header.event_sizes_offset..header.game_start_offset],
+        &slpz[header.game_start_offset..header.metadata_offset],
+        metadata,
+        &replay_id,
+        header.chunk_count,
+        header.flags & FLAG_SEGMENTED != 0,
+        chunks,
+        None,
+        None,
+    )
+}
+
+/// Totals from [`recompress_path`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecompressSummary {
+    /// The number of slpz files found.
+    pub files: usize,
+    /// The number of files that were replaced.
+    pub replaced: usize,
+    /// Total size of the replaced files before recompressing.
+    pub bytes_before: u64,
+    /// Total size of the replaced files after recompressing.
+    pub bytes_after: u64,
+    /// The number of files that could not be read, recompressed or replaced.
+    pub failed: usize,
+}
+
+/// Recompresses every slpz file in a path with the level and zstd settings in `options`. See [`recompress`].
+///
+/// Each file is atomically replaced only if it gets smaller, or always if `options.force_recompress` is set.
+/// Errors for individual files are printed and counted in [`RecompressSummary::failed`], and the file is left alone.
+pub fn recompress_path(options: &Options, path: &std::path::Path) -> Result<RecompressSummary, SlpzError> {
+    if !matches!(path.try_exists(), Ok(true)) { return Err(TargetPathError::PathNotFound.into()) }
+
+    let mut targets = Vec::new();
+    if path.is_dir() {
+        get_targets(&mut targets, path, options, false, None);
+    } else if path.is_file() {
+        targets.push(path.to_path_buf());
+    } else {
+        return Err(TargetPathError::PathInvalid.into());
+    }
+
+    // (before, after) for replaced files, or None for skipped files
+    let results = parallel_map(&targets, options.threading, |t| -> Result<Option<(u64, u64)>, ()> {
+        let (Some(mut compressor), Some(mut decompressor)) = (options_compressor(options), Decompressor::new()) else {
+            eprintln!("Error: Failed to init zstd");
+            return Err(());
+        };
+        let slpz = match std::fs::read(t) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Error recompressing {}: {}", display_escaped(&t), e);
+                return Err(());
+            }
+        };
+        let new = match recompress(&mut decompressor, &mut compressor, &slpz) {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Error recompressing {}: {}", display_escaped(&t), e);
+                return Err(());
+            }
+        };
+        if new.len() >= slpz.len() && !options.force_recompress {
+            if options.log.prints_files() { println!("skipped {} (not smaller)", display_escaped(&t)); }
+            return Ok(None);
+        }
+        if let Err(e) = atomic_write_with_suffix(t, &new, &options.temp_suffix) {
+            eprintln!("Error recompressing {}: {}", display_escaped(&t), e);
+            return Err(());
+        }
+        if options.log.prints_files() { println!("recompressed {} ({} -> {} bytes)", display_escaped(&t), slpz.len(), new.len()); }
+        Ok(Some((slpz.len() as u64, new.len() as u64)))
+    });
+
+    let mut summary = RecompressSummary { files: targets.len(), ..RecompressSummary::default() };
+    for result in results {
+        match result {
+            Ok(Some((before, after))) => {
+                summary.replaced += 1;
+                summary.bytes_before += before;
+                summary.bytes_after += after;
+            }
+            Ok(None) => {}
+            Err(()) => summary.failed += 1,
+        }
+    }
+    Ok(summary)
+}
+
+/// Returns an identifier for a replay that is the same for its slp and slpz forms, regardless of compression level.
+///
+/// The ID is the SHA-256 hash of the slp file without its first 15 bytes
+/// (the `{U\x03raw[$U#l` prefix and the raw element length).
+/// That is, the Event Payloads event, the Game Start event, all other events, and the rest of the file after the raw element.
+/// If the raw element length was never written, it is inferred before hashing, so unfinalized replays also match their slpz form.
+///
+/// slpz files store the ID in the header, which is returned without decompressing.
+/// Older slpz files are decompressed.
+pub fn replay_id(data: &[u8]) -> Result<[u8; 32], IdError> {
+    if is_slp(data) {
+        let parts = split_slp(data, Strictness::Normal).map_err(|_| IdError::InvalidFile)?;
+        return Ok(hash_slp_parts(&parts));
+    }
+
+    if is_slpz(data) {
+        if let Some(id) = read_replay_id(data) { return Ok(id) }
+
+        let mut decompressor = Decompressor::new().ok_or(IdError::DecompressionFailure)?;
+        let slp = decompress(&mut decompressor, data).map_err(|e| match e {
+            DecompError::DecompressionFailure | DecompError::FrameSizeMismatch { .. } => IdError::DecompressionFailure,
+            _ => IdError::InvalidFile,
+        })?;
+        return Ok(hash_slp_parts(&split_slp(&slp, Strictness::Normal).map_err(|_| IdError::InvalidFile)?));
+    }
+
+    Err(IdError::UnknownFormat)
+}
+
+/// Returns the replay ID stored in an slpz header, if present. See [`replay_id`].
+pub fn read_replay_id(slpz: &[u8]) -> Option<[u8; 32]> {
+    SlpzHeader::parse(slpz).ok()?.replay_id
+}
+
+fn hash_slp_parts(parts: &SlpParts) -> [u8; 32] {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(parts.event_sizes_payload);
+    hasher.update(parts.game_start_payload);
+    hasher.update(parts.events);
+    hasher.update(parts.metadata);
+    hasher.finalize().into()
+}
+
+/// Returns the number of bytes after the end of the slp file's UBJSON object.
+///
+/// These are usually left by a crashed writer and are not part of the replay.
+/// Returns None if the metadata could not be parsed.
+pub fn trailer_len(slp: &[u8]) -> Option<usize> {
+    let metadata = split_slp(slp, Strictness::Normal).ok()?.metadata;
+    Some(metadata.len() - object_rest_len(metadata).ok()?)
+}
+
+/// The last complete event before a problem. See [`SlpIssue`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LastEvent {
+    pub command: u8,
+    /// Offset of the command byte from the start of the file.
+    pub offset: usize,
+}
+
+/// A problem found by [`validate_slp`] or [`validate_slpz`].
+///
+/// Offsets are from the start of the file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SlpIssue {
+    /// The file does not start with the slp raw element.
+    NotSlp,
+    /// The Event Payloads event is missing or truncated.
+    InvalidEventPayloads,
+    /// The Game Start event is missing or truncated.
+    InvalidGameStart,
+    /// The raw element length is 0 or 0xFFFFFFFF. The replay was never finalized.
+    PlaceholderRawLen,
+    /// The raw element length is past the end of the file.
+    /// `computed_end` is where the events end when scanned instead.
+    RawLenOutOfBounds { raw_len: u32, computed_end: usize },
+    /// An event's command byte is not in the Event Payloads event.
+    UndeclaredEvent { command: u8, offset: usize, last_event: Option<LastEvent> },
+    /// An event extends past the end of the raw element.
+    TruncatedEvent { command: u8, offset: usize, last_event: Option<LastEvent> },
+    /// The metadata after the raw element could not be parsed. `offset` is where parsing stopped.
+    InvalidMetadata { offset: usize },
+    /// There are bytes after the end of the replay. See [`trailer_len`].
+    TrailingBytes { len: usize },
+    /// A command byte not defined by the slp spec. See [`unknown_events`].
+    UnknownEvent { command: u8, count: u32 },
+    /// The file does not have an slpz header.
+    NotSlpz,
+    /// The slpz header offsets are out of order or out of bounds, or flags are unknown.
+    InvalidSlpzHeader,
+    /// The compressed events are not a single complete zstd frame.
+    InvalidZstdFrame,
+    /// The raw element length is little-endian rather than big-endian, and `raw_len` is its value read that way.
+    /// It matches where the events end, so the file still compresses.
+    ByteSwappedRawLen { raw_len: u32 },
+    /// The file could not be read. Only returned by [`check_path`].
+    Io(#[cfg_attr(feature = "serde", serde(with = "io_error_kind"))] std::io::ErrorKind),
+}
+
+/// Serializes an [`std::io::ErrorKind`] as its name. Names other than these kinds deserialize as `Other`.
+#[cfg(feature = "serde")]
+mod io_error_kind {
+    use std::io::ErrorKind;
+
+    const KINDS: [ErrorKind; 8] = [
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::IsADirectory,
+        ErrorKind::InvalidData,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::Interrupted,
+        ErrorKind::OutOfMemory,
+        ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: serde::Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", kind))
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let name = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(KINDS.into_iter().find(|k| format!("{:?}", k) == name).unwrap_or(ErrorKind::Other))
+    }
+}
+
+impl SlpIssue {
+    /// The variant name, used to group issues.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlpIssue::NotSlp => "NotSlp",
+            SlpIssue::InvalidEventPayloads => "InvalidEventPayloads",
+            SlpIssue::InvalidGameStart => "InvalidGameStart",
+            SlpIssue::PlaceholderRawLen => "PlaceholderRawLen",
+            SlpIssue::RawLenOutOfBounds { .. } => "RawLenOutOfBounds",
+            SlpIssue::UndeclaredEvent { .. } => "UndeclaredEvent",
+            SlpIssue::TruncatedEvent { .. } => "TruncatedEvent",
+            SlpIssue::InvalidMetadata { .. } => "InvalidMetadata",
+            SlpIssue::TrailingBytes { .. } => "TrailingBytes",
+            SlpIssue::UnknownEvent { .. } => "UnknownEvent",
+            SlpIssue::NotSlpz => "NotSlpz",
+            SlpIssue::InvalidSlpzHeader => "InvalidSlpzHeader",
+            SlpIssue::InvalidZstdFrame => "InvalidZstdFrame",
+            SlpIssue::ByteSwappedRawLen { .. } => "ByteSwappedRawLen",
+            SlpIssue::Io(_) => "Io",
+        }
+    }
+
+    /// The offset in the file where the issue was found, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            SlpIssue::RawLenOutOfBounds { computed_end, .. } => Some(computed_end),
+            SlpIssue::UndeclaredEvent { offset, .. }
+                | SlpIssue::TruncatedEvent { offset, .. }
+                | SlpIssue::InvalidMetadata { offset } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// Warnings do not prevent compression or decompression.
+    pub fn severity(&self) -> Severity {
+        match self {
+            SlpIssue::PlaceholderRawLen
+                | SlpIssue::ByteSwappedRawLen { .. }
+                | SlpIssue::TrailingBytes { .. }
+                | SlpIssue::UnknownEvent { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Returns false for issues that do not prevent compression or decompression.
+    pub fn is_error(&self) -> bool {
+        self.severity() == Severity::Error
+    }
+}
+
+impl std::fmt::Display for SlpIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            SlpIssue::NotSlp => write!(f, "Not an slp file"),
+            SlpIssue::InvalidEventPayloads => write!(f, "Event Payloads event is missing or truncated"),
+            SlpIssue::InvalidGameStart => write!(f, "Game Start event is missing or truncated"),
+            SlpIssue::PlaceholderRawLen => write!(f, "Placeholder raw_len: replay was never finalized"),
+            SlpIssue::RawLenOutOfBounds { raw_len, computed_end } =>
+                write!(f, "raw_len {} is past the end of the file, events end at offset 0x{:X}", raw_len, computed_end),
+            SlpIssue::UndeclaredEvent { command, offset, .. } =>
+                write!(f, "Undeclared event code 0x{:02X} at offset 0x{:X}", command, offset),
+            SlpIssue::TruncatedEvent { command, offset, .. } =>
+                write!(f, "Truncated event 0x{:02X} at offset 0x{:X}", command, offset),
+            SlpIssue::InvalidMetadata { offset } => write!(f, "Invalid metadata at offset 0x{:X}", offset),
+            SlpIssue::TrailingBytes { len } => write!(f, "Trailing bytes: {} bytes after metadata", len),
+            SlpIssue::UnknownEvent { command, count } =>
+                write!(f, "Unknown event code 0x{:02X} ({} events)", command, count),
+            SlpIssue::NotSlpz => write!(f, "Not an slpz file"),
+            SlpIssue::InvalidSlpzHeader => write!(f, "Invalid slpz header"),
+            SlpIssue::InvalidZstdFrame => write!(f, "Compressed events are not a complete zstd frame"),
+            SlpIssue::ByteSwappedRawLen { raw_len } => write!(f, "Byte-swapped raw_len: {} when read little-endian", raw_len),
+            SlpIssue::Io(kind) => write!(f, "Could not read the file: {}", kind),
+        }
+    }
+}
+
+/// How serious an [`SlpIssue`] is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// The file cannot be compressed or decompressed.
+    Error,
+    /// The file can be compressed, but needed inference or has unexpected contents. See [`Strictness::Strict`].
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Severity::Error => "ERROR",
+            Severity::Warning => "WARN",
+        })
+    }
+}
+
+/// The issues found in a file. See [`validate_slp`] and [`validate_slpz`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlpValidationReport {
+    pub issues: Vec<SlpIssue>,
+}
+
+impl SlpValidationReport {
+    /// Returns true if there are no errors. There may be warnings.
+    pub fn is_ok(&self) -> bool {
+        !self.has_errors()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|i| i.severity() == Severity::Error)
+    }
+
+    pub fn has_warnings(&self) -> bool {
+        self.issues.iter().any(|i| i.severity() == Severity::Warning)
+    }
+}
+
+impl std::fmt::Display for SlpValidationReport {
+    /// A line with the number of issues, then a line for each issue with its severity.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SLP Validation Report: {} issue(s)", self.issues.len())?;
+        for issue in self.issues.iter() {
+            let severity = format!("[{}]", issue.severity());
+            write!(f, "\n  {:<7} {}", severity, issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the structure of an slp file without compressing it.
+///
+/// Checks the raw element envelope, the raw length, that every event is declared and complete,
+/// and that the metadata parses.
+pub fn validate_slp(slp: &[u8]) -> SlpValidationReport {
+    let mut issues = Vec::new();
+
+    if !is_slp(slp) {
+        issues.push(SlpIssue::NotSlp);
+        return SlpValidationReport { issues };
+    }
+
+    let event_sizes_payload = match slp.get(15..) {
+        Some(e) if e.first() == Some(&EVENT_PAYLOADS) && e.len() >= 2 => e,
+        _ => {
+            issues.push(SlpIssue::InvalidEventPayloads);
+            return SlpValidationReport { issues };
+        }
+    };
+    let (event_sizes, event_type_count) = match event_sizes(event_sizes_payload) {
+        Some(e) => e,
+        None => {
+            issues.push(SlpIssue::InvalidEventPayloads);
+            return SlpValidationReport { issues };
+        }
+    };
+
+    let game_start_offset = 15 + 2 + event_type_count * 3;
+    let game_start_size = event_sizes[GAME_START as usize] as usize + 1;
+    if slp.get(game_start_offset) != Some(&GAME_START) || slp.len() < game_start_offset + game_start_size {
+        issues.push(SlpIssue::InvalidGameStart);
+        return SlpValidationReport { issues };
+    }
+    let events_offset = game_start_offset + game_start_size;
+
+    let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap());
+    let raw_end = if raw_len == 0 || raw_len == u32::MAX {
+        issues.push(SlpIssue::PlaceholderRawLen);
+        events_offset + scan_events_len(&slp[events_offset..], &event_sizes)
+    } else if (slp.len() as u64) < 15 + raw_len as u64 || 15 + (raw_len as u64) < events_offset as u64 {
+        let computed_end = events_offset + scan_events_len(&slp[events_offset..], &event_sizes);
+        if byte_swapped_raw_end(slp) != Some(computed_end) {
+            issues.push(SlpIssue::RawLenOutOfBounds { raw_len, computed_end });
+            return SlpValidationReport { issues };
+        }
+        issues.push(SlpIssue::ByteSwappedRawLen { raw_len: u32::from_le_bytes(slp[11..15].try_into().unwrap()) });
+        computed_end
+    } else {
+        15 + raw_len as usize
+    };
+
+    let mut counts = [0u32; 256];
+    let mut last_event = None;
+    for e in EventIter::new(&slp[events_offset..raw_end], &event_sizes) {
+        match e {
+            Ok((command, _, offset)) => {
+                counts[command as usize] += 1;
+                last_event = Some(LastEvent { command, offset: events_offset + offset });
+            }
+            Err(EventIterError::UndeclaredEvent { command, offset }) => {
+                issues.push(SlpIssue::UndeclaredEvent { command, offset: events_offset + offset, last_event });
+            }
+            Err(EventIterError::TruncatedEvent { command, offset }) => {
+                issues.push(SlpIssue::TruncatedEvent { command, offset: events_offset + offset, last_event });
+            }
+        }
+    }
+    issues.extend(unknown_event_counts(&counts).into_iter().map(|(command, count)| SlpIssue::UnknownEvent { command, count }));
+
+    let metadata = &slp[raw_end..];
+    match object_rest_len(metadata) {
+        Ok(len) if len < metadata.len() => issues.push(SlpIssue::TrailingBytes { len: metadata.len() - len }),
+        Ok(_) => {}
+        Err(e) => issues.push(SlpIssue::InvalidMetadata { offset: raw_end + e.offset().unwrap_or(0) }),
+    }
+
+    SlpValidationReport { issues }
+}
+
+/// Checks the structure of an slpz file without decompressing it.
+///
+/// Checks the header offsets, the Event Payloads and Game Start sections,
+/// and that the compressed events are a complete zstd frame.
+pub fn validate_slpz(slpz: &[u8]) -> SlpValidationReport {
+    let mut issues = Vec::new();
+
+    let header = match SlpzHeader::parse(slpz) {
+        Ok(h) => h,
+        Err(DecompError::NotSlpz { .. }) => {
+            issues.push(SlpIssue::NotSlpz);
+            return SlpValidationReport { issues };
+        }
+        Err(_) => {
+            issues.push(SlpIssue::InvalidSlpzHeader);
+            return SlpValidationReport { issues };
+        }
+    };
+
+    let event_sizes_payload = &slpz[header.event_sizes_offset..header.game_start_offset];
+    let event_sizes = match event_sizes(event_sizes_payload) {
+        Some((sizes, count)) if event_sizes_payload.len() == 2 + count * 3 => sizes,
+        _ => {
+            issues.push(SlpIssue::InvalidEventPayloads);
+            return SlpValidationReport { issues };
+        }
+    };
+
+    let game_start = &slpz[header.game_start_offset..header.metadata_offset];
+    if game_start.first() != Some(&GAME_START) || game_start.len() != event_sizes[GAME_START as usize] as usize + 1 {
+        issues.push(SlpIssue::InvalidGameStart);
+    }
+
+    let metadata = &slpz[header.metadata_offset..header.compressed_events_offset];
+    match object_rest_len(metadata) {
+        Ok(len) if len < metadata.len() => issues.push(SlpIssue::TrailingBytes { len: metadata.len() - len }),
+        Ok(_) => {}
+        Err(e) => issues.push(SlpIssue::InvalidMetadata { offset: header.metadata_offset + e.offset().unwrap_or(0) }),
+    }
+
+    let mut compressed_events = &slpz[header.compressed_events_offset..];
+    for (compressed_size, reordered_size) in header.chunks(slpz) {
+        let (chunk, rest) = compressed_events.split_at(compressed_size);
+        compressed_events = rest;
+        let valid = if header.flags & FLAG_STORED != 0 {
+            chunk.len() == reordered_size
+        } else {
+            zstd::zstd_safe::find_frame_compressed_size(chunk) == Ok(chunk.len())
+                && !matches!(zstd::zstd_safe::get_frame_content_size(chunk), Ok(Some(frame)) if frame != reordered_size as u64)
+        };
+        if !valid {
+            issues.push(SlpIssue::InvalidZstdFrame);
+            break;
+        }
+    }
+
+    SlpValidationReport { issues }
+}
+
+/// Validates every slp and slpz file in a path in parallel, without writing anything.
+///
+/// Directories are walked according to `options.recursive` and `options.skip_hidden`.
+/// Returns the files and their reports, sorted by path.
+pub fn check_path(
+    options: &Options,
+    path: &std::path::Path,
+) -> Result<Vec<(std::path::PathBuf, SlpValidationReport)>, SlpzError> {
+    if !matches!(path.try_exists(), Ok(true)) { return Err(TargetPathError::PathNotFound.into()) }
+
+    let mut targets = Vec::new();
+    if path.is_dir() {
+        get_targets(&mut targets, path, options, true, None);
+        get_targets(&mut targets, path, options, false, None);
+    } else if path.is_file() {
+        targets.push(path.to_path_buf());
+    } else {
+        return Err(TargetPathError::PathInvalid.into());
+    }
+    targets.sort();
+
+    let reports = parallel_map(&targets, options.threading, |t| check_file(t));
+    Ok(targets.into_iter().zip(reports).collect())
+}
+
+/// Validates an slp or slpz file for [`check_path`].
+fn check_file(path: &std::path::Path) -> SlpValidationReport {
+    match std::fs::read(path) {
+        Ok(data) if is_slpz(&data) => validate_slpz(&data),
+        Ok(data) => validate_slp(&data),
+        Err(e) => SlpValidationReport { issues: vec![SlpIssue::Io(e.kind())] },
+    }
+}
+
+/// Applies `f` to each item, using a thread per available core if `threading` is set.
+/// Results are in the same order as the items.
+fn parallel_map<T: Sync, R: Send>(items: &[T], threading: bool, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    if !threading || threads == 1 || items.len() < 2 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk = items.len().div_ceil(threads);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles = items.chunks(chunk)
+            .map(|c| scope.spawn(move || c.iter().map(f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Basic information about a replay. See [`slp_file_info`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlpFileInfo {
+    /// Stage ID. See [`tables::STAGES`].
+    pub stage: u16,
+    /// External character ID for each port, or None if the port is empty. See [`tables::CHARACTERS`].
+    pub characters: [Option<u8>; 4],
+    /// The last frame number, if known.
+    pub last_frame: Option<i32>,
+    /// Seconds on the timer at the start of the game.
+    pub timer_seconds: u32,
+    pub team_mode: bool,
+    /// Always false before slp 1.5.0.
+    pub is_pal: bool,
+    /// Always false before slp 2.0.0.
+    pub is_frozen_ps: bool,
+    /// Each port's player, or None if the port is empty.
+    pub players: [Option<PlayerStartInfo>; 4],
+}
+
+/// A player from the Game Start event. See [`SlpFileInfo::players`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayerStartInfo {
+    /// External character ID. See [`tables::CHARACTERS`].
+    pub character: u8,
+    /// 0 is human, 1 is CPU, 2 is demo.
+    pub player_type: u8,
+    pub costume: u8,
+}
+
+/// Reads basic information from an slp or slpz file without decompressing.
+///
+/// The last frame is read from the metadata.
+/// If the metadata does not contain it, slp files are scanned for the last frame update, but slpz files are not decompressed.
+pub fn slp_file_info(data: &[u8]) -> Option<SlpFileInfo> {
+    let (game_start, metadata, events) = if is_slp(data) {
+        let parts = split_slp(data, Strictness::Normal).ok()?;
+        (parts.game_start_payload, parts.metadata, Some((parts.events, parts.event_sizes)))
+    } else {
+        let header = SlpzHeader::parse(data).ok()?;
+        let game_start = &data[header.game_start_offset..header.metadata_offset];
+        let metadata = &data[header.metadata_offset..header.compressed_events_offset];
+        (game_start, metadata, None)
+    };
+
+    // offsets from the slp spec, including the command byte
+    let version = game_start.get(1..4)?;
+    let stage = u16::from_be_bytes(game_start.get(0x13..0x15)?.try_into().unwrap());
+    let timer_seconds = u32::from_be_bytes(game_start.get(0x15..0x19)?.try_into().unwrap());
+    let team_mode = *game_start.get(0x0D)? != 0;
+    let mut characters = [None; 4];
+    let mut players = [None; 4];
+    for port in 0..4 {
+        let character = *game_start.get(0x65 + 0x24 * port)?;
+        let player_type = *game_start.get(0x66 + 0x24 * port)?;
+        let costume = *game_start.get(0x68 + 0x24 * port)?;
+        if player_type == 3 { continue } // 3 is empty
+        characters[port] = Some(character);
+        players[port] = Some(PlayerStartInfo { character, player_type, costume });
+    }
+
+    // fields added in later slp versions
+    let flag = |offset: usize| game_start.get(offset).is_some_and(|&b| b != 0);
+    let (is_pal, is_frozen_ps) = match (version[0], version[1], version[2]) {
+        (0, _, _) | (1, 0..=4, _) => (false, false),
+        (1, _, _) => (flag(0x1A1), false),
+        _ => (flag(0x1A1), flag(0x1A2)),
+    };
+
+    let last_frame = metadata_last_frame(metadata)
+        .or_else(|| events.and_then(|(events, event_sizes)| events_last_frame(events, &event_sizes)));
+
+    Some(SlpFileInfo {
+        stage,
+        characters,
+        last_frame,
+        timer_seconds,
+        team_mode,
+        is_pal,
+        is_frozen_ps,
+        players,
+    })
+}
+
+fn metadata_last_frame(metadata: &[u8]) -> Option<i32> {
+    Metadata::parse(metadata).ok()?.last_frame()
+}
+
+/// The offset of the `lastFrame` value in the metadata.
+fn metadata_last_frame_offset(metadata: &[u8]) -> Option<usize> {
+    const LAST_FRAME_KEY: &[u8] = b"U\x09lastFramel";
+    let i = metadata.windows(LAST_FRAME_KEY.len()).position(|w| w == LAST_FRAME_KEY)? + LAST_FRAME_KEY.len();
+    (metadata.len() >= i + 4).then_some(i)
+}
+
+fn events_last_frame(events: &[u8], event_sizes: &[u16; 256]) -> Option<i32> {
+    EventIter::new(events, event_sizes)
+        .map_while(Result::ok)
+        .filter(|(command, payload, _)| {
+            (*command == PRE_FRAME_UPDATE || *command == POST_FRAME_UPDATE) && payload.len() >= 4
+        })
+        .last()
+        .map(|(_, payload, _)| i32::from_be_bytes(payload[..4].try_into().unwrap()))
+}
+
+/// Whether a replay ended normally. See [`completeness`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Completeness {
+    /// The game end method from the Game End event, or None if there is none, as when Dolphin crashed.
+    /// 7 is a no contest, which Slippi records when a player quits out.
+    pub game_end_method: Option<u8>,
+    /// The frame number of the last frame update, if there are any.
+    pub last_frame: Option<i32>,
+    /// False if the raw length was never written or does not match the end of the events.
+    /// Always true for slpz files, as the raw length is rewritten when compressing.
+    pub raw_len_matches: bool,
+}
+
+impl Completeness {
+    /// True if the replay has a Game End event that is not a no contest.
+    pub fn is_complete(&self) -> bool {
+        self.game_end_method.is_some_and(|m| m != GAME_END_NO_CONTEST)
+    }
+}
+
+/// Reads how an slp or slpz file ended.
+///
+/// The events are scanned for the Game End event and the last frame update, reading replays that end partway through an event
+/// as [`Strictness::Lenient`] does. slpz files are decompressed, as the header does not record how the game ended.
+pub fn completeness(data: &[u8]) -> Result<Completeness, SlpzError> {
+    if is_slpz(data) {
+        let mut decompressor = Decompressor::new().ok_or(DecompError::DecompressionFailure)?;
+        return completeness(&decompress(&mut decompressor, data)?);
+    }
+
+    let parts = split_slp(data, Strictness::Lenient)?;
+    let raw_len = u32::from_be_bytes(data[11..15].try_into().unwrap()) as usize;
+    let raw_len_matches = parts.events_offset + parts.events.len() == 15 + raw_len;
+
+    let mut game_end_method = None;
+    let mut last_frame = None;
+    for (command, payload, _) in EventIter::new(parts.events, &parts.event_sizes).map_while(Result::ok) {
+        match command {
+            GAME_END => game_end_method = payload.first().copied(),
+            PRE_FRAME_UPDATE | POST_FRAME_UPDATE if payload.len() >= 4 => {
+                last_frame = Some(i32::from_be_bytes(payload[..4].try_into().unwrap()));
+            }
+            _ => (),
+        }
+    }
+    Ok(Completeness { game_end_method, last_frame, raw_len_matches })
+}
+
+impl std::fmt::Display for SlpFileInfo {
+    /// Formats like `[FD] Fox vs Falco, frame 4823`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match tables::stage_abbreviation(self.stage) {
+            Some(stage) => write!(f, "[{}]", stage)?,
+            None => write!(f, "[stage 0x{:02X}]", self.stage)?,
+        }
+
+        let mut first = true;
+        for &c in self.characters.iter().flatten() {
+            write!(f, "{}", if first { " " } else { " vs " })?;
+            first = false;
+            match tables::character_name(c) {
+                Some(name) => write!(f, "{}", name)?,
+                None => write!(f, "character 0x{:02X}", c)?,
+            }
+        }
+
+        if let Some(frame) = self.last_frame {
+            write!(f, ", frame {}", frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&SlpFileInfo> for String {
+    fn from(info: &SlpFileInfo) -> String { info.to_string() }
+}
+
+/// When a game started, to the second, as recorded in the metadata. Usually UTC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameTimestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl GameTimestamp {
+    /// Parses the start of an ISO 8601 timestamp like `2024-01-01T18:30:12Z`.
+    /// Fractional seconds and the timezone are ignored.
+    pub fn parse_iso8601(s: &[u8]) -> Option<GameTimestamp> {
+        let s = s.get(..19)?;
+        if s[4] != b'-' || s[7] != b'-' || s[10] != b'T' || s[13] != b':' || s[16] != b':' { return None }
+        GameTimestamp::from_digits(&s[0..4], &s[5..7], &s[8..10], &s[11..13], &s[14..16], &s[17..19])
+    }
+
+    /// Parses a Slippi Launcher file name like `Game_20240101T183012.slp`, ignoring anything after the time.
+    pub fn parse_launcher_name(name: &str) -> Option<GameTimestamp> {
+        let s = name.as_bytes().strip_prefix(b"Game_")?.get(..15)?;
+        if s[8] != b'T' { return None }
+        GameTimestamp::from_digits(&s[0..4], &s[4..6], &s[6..8], &s[9..11], &s[11..13], &s[13..15])
+    }
+
+    fn from_digits(year: &[u8], month: &[u8], day: &[u8], hour: &[u8], minute: &[u8], second: &[u8]) -> Option<GameTimestamp> {
+        let num = |digits: &[u8]| -> Option<u16> {
+            digits.iter().try_fold(0u16, |n, &d| d.is_ascii_digit().then(|| n * 10 + (d - b'0') as u16))
+        };
+        let t = GameTimestamp {
+            year: num(year)?,
+            month: num(month)? as u8,
+            day: num(day)? as u8,
+            hour: num(hour)? as u8,
+            minute: num(minute)? as u8,
+            second: num(second)? as u8,
+        };
+        let valid = (1..=12).contains(&t.month) && (1..=31).contains(&t.day)
+            && t.hour < 24 && t.minute < 60 && t.second < 61;
+        valid.then_some(t)
+    }
+
+    /// The file name the Slippi Launcher gives replays, like `Game_20240101T183012.slp`.
+    pub fn launcher_name(&self) -> String {
+        format!(
+            "Game_{:04}{:02}{:02}T{:02}{:02}{:02}.slp",
+            self.year, self.month, self.day, self.hour, self.minute, self.second,
+        )
+    }
+
+    /// The UTC time `seconds` after 1970-01-01T00:00:00. See [`GameTimestamp::unix_seconds`].
+    pub fn from_unix_seconds(seconds: i64) -> GameTimestamp {
+        // civil from days, from Howard Hinnant's date algorithms
+        let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        GameTimestamp {
+            year: year.clamp(0, u16::MAX as i64) as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01T00:00:00, treating the timestamp as UTC.
+    pub fn unix_seconds(&self) -> i64 {
+        // days from civil, from Howard Hinnant's date algorithms
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    /// Returns true if the two timestamps differ by a timezone offset of up to 14 hours, in steps of 15 minutes.
+    ///
+    /// The Launcher names replays in local time, while `startAt` is usually UTC.
+    /// A couple of seconds of difference are allowed.
+    pub fn matches_in_some_timezone(&self, other: &GameTimestamp) -> bool {
+        let diff = self.unix_seconds() - other.unix_seconds();
+        let from_offset = diff.rem_euclid(15 * 60);
+        diff.abs() <= 14 * 3600 + 2 && (from_offset <= 2 || from_offset >= 15 * 60 - 2)
+    }
+}
+
+impl std::fmt::Display for GameTimestamp {
+    /// Formats like `2024-01-01T18:30:12`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second,
+        )
+    }
+}
+
+/// Returns when the game in an slp or slpz file started, from the metadata's `startAt`.
+///
+/// The Game Start event has no timestamp, so there is nothing to fall back to if `startAt` is missing.
+pub fn game_timestamp(data: &[u8]) -> Option<GameTimestamp> {
+    Metadata::parse(read_metadata(data)?).ok()?.start_at()
+}
+
+/// Returns the metadata element of an slp or slpz file, as UBJSON starting with its `U\x08metadata` key.
+/// For slp files this includes the end of the file after it. Parse it with [`Metadata::parse`].
+pub fn read_metadata(data: &[u8]) -> Option<&[u8]> {
+    if is_slp(data) {
+        Some(split_slp(data, Strictness::Normal).ok()?.metadata)
+    } else {
+        let header = SlpzHeader::parse(data).ok()?;
+        Some(&data[header.metadata_offset..header.compressed_events_offset])
+    }
+}
+
+/// Returns the Launcher name for a replay in the output's directory,
+/// adding `_1`, `_2`, ... if the name holds a different replay.
+fn launcher_output_path(out: &std::path::Path, slp: &[u8], timestamp: &GameTimestamp) -> std::path::PathBuf {
+    let name = timestamp.launcher_name();
+    let stem = name.trim_end_matches(".slp");
+    let id = replay_id(slp).ok();
+    let mut n = 0;
+    loop {
+        let candidate = match n {
+            0 => out.with_file_name(&name),
+            n => out.with_file_name(format!("{}_{}.slp", stem, n)),
+        };
+        let taken = match std::fs::read(&candidate) {
+            Ok(existing) => replay_id(&existing).ok() != id,
+            Err(_) => candidate.exists(),
+        };
+        if !taken { return candidate }
+        n += 1;
+    }
+}
+
+/// Compression statistics for a single event type. See [`analyze`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventAnalysis {
+    pub command: u8,
+    /// Number of events with this command byte.
+    pub count: u32,
+    /// Size of this event's reordered column. Does not include command bytes.
+    pub raw_size: usize,
+    /// Size of this event's column when compressed on its own.
+    pub compressed_size: usize,
+}
+
+/// Per-event-type compression statistics for an slp file. See [`analyze`].
+///
+/// Field names are stable across slpz versions.
+/// Changes to the structure increment [`ANALYSIS_SCHEMA_VERSION`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnalysisReport {
+    /// Always [`ANALYSIS_SCHEMA_VERSION`] for reports produced by this version of slpz.
+    pub schema_version: u32,
+    /// Size of the slp file.
+    pub slp_size: usize,
+    /// Size of the slpz file produced with the same compressor.
+    pub slpz_size: usize,
+    /// Size of the reordered events before compression.
+    pub events_raw_size: usize,
+    /// Size of the compressed events section in the slpz file.
+    pub events_compressed_size: usize,
+    /// Size of the event order list (the command bytes).
+    pub order_raw_size: usize,
+    /// Size of the event order list when compressed on its own.
+    pub order_compressed_size: usize,
+    /// Statistics for each event type present in the file, sorted by command byte.
+    pub events: Vec<EventAnalysis>,
+    /// Command bytes in `events` that are not defined by the slp spec. See [`unknown_events`].
+    pub unknown_events: Vec<u8>,
+}
+
+pub const ANALYSIS_SCHEMA_VERSION: u32 = 2;
+
+impl EventAnalysis {
+    pub fn ratio(&self) -> f64 { ratio(self.raw_size, self.compressed_size) }
+}
+
+impl AnalysisReport {
+    pub fn ratio(&self) -> f64 { ratio(self.slp_size, self.slpz_size) }
+    pub fn events_ratio(&self) -> f64 { ratio(self.events_raw_size, self.events_compressed_size) }
+    pub fn order_ratio(&self) -> f64 { ratio(self.order_raw_size, self.order_compressed_size) }
+
+    /// Formats the report as a json object. Identical to the `serde` serialization.
+    pub fn to_json(&self) -> String {
+        use crate::config::Value;
+
+        let size = |n: usize| Value::Int(n as i64);
+        let table = |fields: Vec<(&str, Value)>| Value::Table(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect());
+        let events = self.events.iter()
+            .map(|e| table(vec![
+                ("command", Value::Int(e.command.into())),
+                ("count", Value::Int(e.count.into())),
+                ("raw_size", size(e.raw_size)),
+                ("compressed_size", size(e.compressed_size)),
+            ]))
+            .collect();
+
+        crate::config::json_value(&table(vec![
+            ("schema_version", Value::Int(self.schema_version.into())),
+            ("slp_size", size(self.slp_size)),
+            ("slpz_size", size(self.slpz_size)),
+            ("events_raw_size", size(self.events_raw_size)),
+            ("events_compressed_size", size(self.events_compressed_size)),
+            ("order_raw_size", size(self.order_raw_size)),
+            ("order_compressed_size", size(self.order_compressed_size)),
+            ("events", Value::List(events)),
+            ("unknown_events", Value::List(self.unknown_events.iter().map(|&c| Value::Int(c.into())).collect())),
+        ]))
+    }
+}
+
+fn ratio(raw: usize, compressed: usize) -> f64 {
+    if compressed == 0 { return 0.0 }
+    raw as f64 / compressed as f64
+}
+
+/// Bytes of events sampled by [`slp_to_slpz_ratio_estimate`].
+const ESTIMATE_WINDOW: usize = 4096;
+
+/// Estimates the size of an slp file once compressed, as a fraction of its size from 0.0 to 1.0, without compressing it.
+///
+/// Reordering puts each payload byte next to the same byte of the previous event of the same type,
+/// so this computes the Shannon entropy of the differences between those bytes in a 4KB window from the middle of the events.
+/// Expect it to be within about 0.1 of the real ratio at level 3. Returns 1.0 if the file cannot be parsed.
+pub fn slp_to_slpz_ratio_estimate(slp_data: &[u8]) -> f32 {
+    let Ok(parts) = split_slp(slp_data, Strictness::Normal) else { return 1.0 };
+    let events = parts.events;
+    let start = match events.len().checked_sub(ESTIMATE_WINDOW) {
+        Some(slack) => match event_boundary_after(events, &parts.event_sizes, slack / 2) {
+            Some(start) => start,
+            None => return 1.0,
+        },
+        None => 0,
+    };
+    let window = &events[start..events.len().min(start + ESTIMATE_WINDOW)];
+
+    let mut previous: [Option<&[u8]>; 256] = [None; 256];
+    let mut counts = [0u32; 256];
+    let mut total = 0u32;
+    for (command, payload, _) in EventIter::new(window, &parts.event_sizes).map_while(Result::ok) {
+        if let Some(prev) = previous[command as usize] {
+            for (a, b) in payload.iter().zip(prev) {
+                counts[(a ^ b) as usize] += 1;
+            }
+            total += payload.len() as u32;
+        }
+        previous[command as usize] = Some(payload);
+    }
+    if total == 0 { return 1.0 }
+
+    let entropy = counts.iter()
+        .filter(|&&c| c != 0)
+        .map(|&c| {
+            let p = c as f32 / total as f32;
+            -p * p.log2()
+        })
+        .sum::<f32>();
+    // fitted to generated replays with varying amounts of noise. Constant bytes still cost
+    // some entropy here while zstd removes them almost entirely, hence the offset
+    (entropy * 0.11 - 0.1).clamp(0.0, 1.0)
+}
+
+/// The offset of the first event at or after `from`, found by checking that the next events all have declared commands.
+fn event_boundary_after(events: &[u8], event_sizes: &EventSizes, from: usize) -> Option<usize> {
+    const CHAIN: usize = 16;
+    (from..events.len().min(from + ESTIMATE_WINDOW)).find(|&start| {
+        let mut offset = start;
+        for _ in 0..CHAIN {
+            let Some(&command) = events.get(offset) else { return false };
+            let size = event_sizes[command as usize] as usize;
+            if size == 0 { return false }
+            offset += 1 + size;
+        }
+        offset <= events.len()
+    })
+}
+
+/// Reports how well each event type compresses.
+///
+/// Each command byte's column in the reordered events is compressed independently with the compressor.
+/// These compressions are only measured, never written anywhere.
+pub fn analyze(compressor: &mut Compressor, slp: &[u8]) -> Result<AnalysisReport, CompError> {
+    let slpz = compress_unchecked(compressor, slp)?;
+    let parts = split_slp(slp, compressor.strictness)?;
+
+    let mut reordered_data = Vec::with_capacity(slp.len());
+    let event_counts = count_events(parts.events, &parts.event_sizes).map_err(|e| e.offset_by(parts.events_offset))?;
+    let written = reorder_counted_events(parts.events, &parts.event_sizes, &event_counts, &mut reordered_data)
+        .map_err(|e| e.offset_by(parts.events_offset))?;
+
+    let compressed_events_offset = u32::from_be_bytes(slpz[16..20].try_into().unwrap()) as usize;
+
+    // the 4 byte event count is included with the order list
+    let total_events = u32::from_be_bytes(reordered_data[0..4].try_into().unwrap()) as usize;
+    let order_raw_size = 4 + total_events;
+    let order_compressed_size = compressed_size(compressor, &reordered_data[..order_raw_size])?;
+
+    let mut events = Vec::new();
+    let mut column_offset = order_raw_size;
+    for (command, &count) in event_counts.iter().enumerate() {
+        let raw_size = count as usize * parts.event_sizes[command] as usize;
+        if count == 0 { continue }
+
+        let column = &reordered_data[column_offset..][..raw_size];
+        column_offset += raw_size;
+
+        events.push(EventAnalysis {
+            command: command as u8,
+            count,
+            raw_size,
+            compressed_size: compressed_size(compressor, column)?,
+        });
+    }
+
+    Ok(AnalysisReport {
+        schema_version: ANALYSIS_SCHEMA_VERSION,
+        slp_size: slp.len(),
+        slpz_size: slpz.len(),
+        events_raw_size: written,
+        events_compressed_size: slpz.len() - compressed_events_offset,
+        order_raw_size,
+        order_compressed_size,
+        events,
+        unknown_events: unknown_event_counts(&event_counts).into_iter().map(|(command, _)| command).collect(),
+    })
+}
+
+fn compressed_size(compressor: &mut Compressor, data: &[u8]) -> Result<usize, CompError> {
+    if compressor.level == LEVEL_STORE { return Ok(data.len()) }
+    compressor.ctx.compress(data)
+        .map(|c| c.len())
+        .map_err(|_| CompError::CompressionFailure)
+}
+
+/// Bytes of events sampled by [`compress_level_for_target_ratio`].
+const LEVEL_SAMPLE_SIZE: usize = 256 << 10;
+
+/// Levels searched by [`compress_level_for_target_ratio`].
+const TARGET_RATIO_LEVELS: std::ops::RangeInclusive<i32> = 1..=12;
+
+/// Returns the lowest level from 1 to 12 that compresses an slp file to at least `target_ratio`,
+/// its size divided by the compressed size, such as 3.0. If no level reaches it, returns the level with the best ratio.
+///
+/// Binary searches the levels, compressing the reordered events of a 256KB sample from the middle of the replay
+/// with the compressor's zstd parameters. This takes a few milliseconds per level, where compressing a long replay
+/// at level 12 takes seconds, but the sample leaves out the rest of the events and the uncompressed Game Start and metadata.
+/// On generated replays the ratio of the whole file is within about 15% of the sample's, so compress with the level
+/// and check the ratio when it must be met exactly. The compressor's level is not changed.
+pub fn compress_level_for_target_ratio(compressor: &mut Compressor, data: &[u8], target_ratio: f32) -> Result<i32, CompError> {
+    use zstd::stream::raw::CParameter;
+
+    let parts = split_slp(data, compressor.strictness)?;
+    let events = parts.events;
+    let start = match events.len().checked_sub(LEVEL_SAMPLE_SIZE) {
+        Some(slack) => event_boundary_after(events, &parts.event_sizes, slack / 2).unwrap_or(0),
+        None => 0,
+    };
+    let end = EventIter::new(&events[start..], &parts.event_sizes)
+        .map_while(Result::ok)
+        .map(|(_, payload, offset)| offset + 1 + payload.len())
+        .take_while(|&end| end <= LEVEL_SAMPLE_SIZE)
+        .last()
+        .unwrap_or(0);
+    let sample = &events[start..start + end];
+
+    let mut reordered = Vec::with_capacity(sample.len() + 4);
+    reorder_events(sample, &parts.event_sizes, &mut reordered).map_err(|e| e.offset_by(parts.events_offset + start))?;
+
+    let result = search_target_level(&mut compressor.ctx, &reordered, sample.len(), target_ratio as f64);
+    compressor.ctx.set_parameter(CParameter::CompressionLevel(compressor.level)).map_err(|_| CompError::CompressionFailure)?;
+    result
+}
+
+fn search_target_level(
+    ctx: &mut zstd::bulk::Compressor<'static>,
+    reordered: &[u8],
+    raw_size: usize,
+    target_ratio: f64,
+) -> Result<i32, CompError> {
+    let mut ratios = [None; 1 + *TARGET_RATIO_LEVELS.end() as usize];
+    let mut measure = |level: i32| -> Result<f64, CompError> {
+        ctx.set_parameter(zstd::stream::raw::CParameter::CompressionLevel(level)).map_err(|_| CompError::CompressionFailure)?;
+        let compressed = ctx.compress(reordered).map_err(|_| CompError::CompressionFailure)?;
+        let r = ratio(raw_size, compressed.len());
+        ratios[level as usize] = Some(r);
+        Ok(r)
+    };
+
+    let (mut low, mut high) = (*TARGET_RATIO_LEVELS.start(), *TARGET_RATIO_LEVELS.end());
+    while low < high {
+        let mid = (low + high) / 2;
+        if measure(mid)? >= target_ratio { high = mid } else { low = mid + 1 }
+    }
+    if measure(low)? >= target_ratio { return Ok(low) }
+
+    // the ratio usually rises with the level, but not always, so pick the best level measured
+    let best = TARGET_RATIO_LEVELS
+        .filter_map(|level| Some((level, ratios[level as usize]?)))
+        .max_by(|(a, ra), (b, rb)| ra.total_cmp(rb).then(b.cmp(a)))
+        .map_or(low, |(level, _)| level);
+    Ok(best)
+}
+
+/// Reorders the events of an slp file into byte columns, as stored in an slpz file before compression.
+///
+/// Exposed for benchmarks. Not part of the stable API.
+#[doc(hidden)]
+pub fn reorder_slp_events(slp: &[u8]) -> Result<Vec<u8>, CompError> {
+    let parts = split_slp(slp, Strictness::Normal)?;
+    let mut reordered_data = Vec::with_capacity(slp.len());
+    reorder_events(parts.events, &parts.event_sizes, &mut reordered_data).map_err(|e| e.offset_by(parts.events_offset))?;
+    Ok(reordered_data)
+}
+
+/// Undoes [`reorder_slp_events`]. `slp` provides the event sizes.
+///
+/// Exposed for benchmarks. Not part of the stable API.
+#[doc(hidden)]
+pub fn restore_slp_events(slp: &[u8], reordered: &[u8]) -> Result<Vec<u8>, DecompError> {
+    let parts = split_slp(slp, Strictness::Normal).map_err(|_| DecompError::InvalidFile)?;
+    let mut events = Vec::with_capacity(parts.events.len());
+    unorder_events(reordered, &parts.event_sizes, &mut events)?;
+    Ok(events)
+}
+
+/// Reorders events into byte columns.
+fn reorder_events(
+    events: &[u8],
+    event_sizes: &[u16; 256],
+    buf: &mut Vec<u8>,
+) -> Result<usize, CompError> {
+    let event_counts = count_events(events, event_sizes)?;
+    reorder_counted_events(events, event_sizes, &event_counts, buf)
+}
+
+/// Like [`reorder_events`], with the counts from [`count_events`], so each column is sized before it is filled.
+fn reorder_counted_events(
+    events: &[u8],
+    event_sizes: &[u16; 256],
+    event_counts: &EventCounts,
+    buf: &mut Vec<u8>,
+) -> Result<usize, CompError> {
+    // ---------------------------------------
+    // Build the offset lookup table 'reordered_event_offsets'.
+    // This is the offset of the start of the reordered data for each event in the reordered event data section.
+
+    let mut total_events = 0usize;
+    let mut reordered_event_offsets = [0u32; 256];
+
+    for i in 0..255 {
+        let size = event_sizes[i];
+        let count = event_counts[i];
+        total_events += count as usize;
+
+        let event_total_size = size as u32 * count;
+
+        // offset for next event is the end of this event.
+        reordered_event_offsets[i + 1] = reordered_event_offsets[i] + event_total_size;
+    }
+
+    let reordered_size = {
+        let last_size = event_sizes[255];
+        let last_count = event_counts[255];
+        total_events += last_count as usize;
+        let last_total_size = last_count as usize * last_size as usize;
+
+        reordered_event_offsets[255] as usize + last_total_size
+    };
+
+    if reordered_size != events.len() - total_events {
+        return Err(CompError::Corrupt { offset: 0, detail: CorruptDetail::Truncated })
+    }
+
+    // alloc
+    let data_size = 4 + total_events + reordered_size;
+    let buf_prev = buf.len();
+    buf.resize(buf_prev + data_size, 0u8);
+    let data = &mut buf[buf_prev..];
+
+    // ---------------------------------------
+    // fill event order list and reordered data
+
+    data[0..4].copy_from_slice(&(total_events as u32).to_be_bytes());
+
+    let (order_list, reordered) = data[4..].split_at_mut(total_events);
+
+    let mut tiles = PayloadTiles::new(event_sizes, *event_counts, reordered_event_offsets);
+    for (order, e) in order_list.iter_mut().zip(EventIter::new(events, event_sizes)) {
+        let (event_u8, payload, _) = e?;
+        *order = event_u8;
+        tiles.push(event_u8, payload, reordered);
+    }
+    tiles.finish(reordered);
+
+    Ok(data_size)
+}
+
+/// Undoes the reordering done by 'reorder_events'.
+///
+/// Returns the number of bytes written.
+pub(crate) fn unorder_events(
+    b: &[u8],
+    event_sizes: &[u16; 256],
+    buf: &mut Vec<u8>,
+) -> Result<usize, DecompError> {
+    let mut events = embedded::EventRestorer::new(b, event_sizes)?;
+
+    // writing each event once is faster than zeroing the buffer first
+    buf.reserve(events.unordered_size());
+    while let Some((command, payload)) = events.next_event() {
+        buf.push(command);
+        buf.extend_from_slice(payload);
+    }
+
+    Ok(events.unordered_size())
+}
+
+/// Like [`unorder_events`], passing the events to `emit` in blocks of about `block_size` bytes.
+fn unorder_events_to(
+    b: &[u8],
+    event_sizes: &[u16; 256],
+    block_size: usize,
+    staging: &mut Vec<u8>,
+    emit: &mut dyn FnMut(&[u8]) -> Result<(), DecompError>,
+) -> Result<usize, DecompError> {
+    let mut events = embedded::EventRestorer::new(b, event_sizes)?;
+
+    staging.clear();
+    while let Some((command, payload)) = events.next_event() {
+        staging.push(command);
+        staging.extend_from_slice(payload);
+
+        if staging.len() >= block_size {
+            emit(staging)?;
+            staging.clear();
+        }
+    }
+    if !staging.is_empty() { emit(staging)?; }
+
+    Ok(events.unordered_size())
+}
+
+/// Counts the events of each command byte in an slp file, after the Game Start event.
+///
+/// Compression makes this pass first, to size each column of the reordered events before filling it.
+pub fn event_counts(slp: &[u8]) -> Result<EventCounts, CompError> {
+    let parts = split_slp(slp, Strictness::Normal)?;
+    count_events(parts.events, &parts.event_sizes).map_err(|e| e.offset_by(parts.events_offset))
+}
+
+/// Returns each command byte in an slp file that is not defined by the slp spec, with its count, sorted by command byte.
+///
+/// These events are still compressed faithfully, as their sizes are declared in the Event Payloads event.
+pub fn unknown_events(slp: &[u8]) -> Result<Vec<(u8, u32)>, CompError> {
+    Ok(unknown_event_counts(&event_counts(slp)?))
+}
+
+fn unknown_event_counts(counts: &EventCounts) -> Vec<(u8, u32)> {
+    counts.iter()
+        .enumerate()
+        .filter(|&(command, &count)| count != 0 && !KNOWN_EVENTS.contains(&(command as u8)))
+        .map(|(command, &count)| (command as u8, count))
+        .collect()
+}
+
+fn count_events(events: &[u8], event_sizes: &[u16; 256]) -> Result<EventCounts, CompError> {
+    let mut counts = [0u32; 256];
+    for e in EventIter::new(events, event_sizes) {
+        let (command, _, _) = e?;
+        counts[command as usize] += 1;
+    }
+    Ok(counts)
+}
+
+/// Payload size for each command byte, as declared by the Event Payloads event. Zero is undeclared.
+pub type EventSizes = [u16; 256];
+
+/// Number of events for each command byte. See [`event_counts`].
+pub type EventCounts = [u32; 256];
+
+/// Where an [`EventIter`] stopped early. Offsets are from the start of the iterated data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventIterError {
+    /// The command byte is not declared in the Event Payloads event.
+    UndeclaredEvent { command: u8, offset: usize },
+    /// The event's payload extends past the end of the data.
+    TruncatedEvent { command: u8, offset: usize },
+}
+
+impl std::fmt::Display for EventIterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventIterError::UndeclaredEvent { command, offset } =>
+                write!(f, "Undeclared event 0x{:02X} at offset {}", command, offset),
+            EventIterError::TruncatedEvent { command, offset } =>
+                write!(f, "Truncated event 0x{:02X} at offset {}", command, offset),
+        }
+    }
+}
+
+impl std::error::Error for EventIterError {}
+
+/// Walks a stream of events, yielding `(command, payload, offset)` for each.
+///
+/// If the stream ends with an undeclared or truncated event, that is yielded as the final `Err` item.
+#[derive(Clone, Debug)]
+pub struct EventIter<'a> {
+    events: &'a [u8],
+    event_sizes: EventSizes,
+    offset: usize,
+}
+
+impl<'a> EventIter<'a> {
+    /// `events` must start at a command byte.
+    pub fn new(events: &'a [u8], event_sizes: &EventSizes) -> EventIter<'a> {
+        EventIter { events, event_sizes: *event_sizes, offset: 0 }
+    }
+
+    /// Iterates over the events of an slp file after the Game Start event.
+    pub fn from_slp(slp: &'a [u8]) -> Result<EventIter<'a>, CompError> {
+        let parts = split_slp(slp, Strictness::Normal)?;
+        Ok(EventIter::new(parts.events, &parts.event_sizes))
+    }
+}
+
+impl<'a> Iterator for EventIter<'a> {
+    type Item = Result<(u8, &'a [u8], usize), EventIterError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let command = *self.events.get(offset)?;
+        let size = self.event_sizes[command as usize] as usize;
+
+        if size == 0 {
+            self.offset = self.events.len();
+            return Some(Err(EventIterError::UndeclaredEvent { command, offset }));
+        }
+
+        match self.events.get(offset + 1..offset + 1 + size) {
+            Some(payload) => {
+                self.offset += 1 + size;
+                Some(Ok((command, payload, offset)))
+            }
+            None => {
+                self.offset = self.events.len();
+                Some(Err(EventIterError::TruncatedEvent { command, offset }))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for EventIter<'_> {}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Options {
+    pub keep: bool,
+    pub compress: Option<bool>,
+    pub recursive: bool,
+    /// Skip files and directories whose names start with '.' when processing a directory.
+    pub skip_hidden: bool,
+    /// Follow symlinks when processing a directory. Otherwise symlinked files and directories are skipped.
+    /// Symlinked directories that loop back to a parent directory are never walked twice.
+    pub follow_symlinks: bool,
+    pub threading: bool,
+    /// See [`Compressor::new`].
+    pub level: i32,
+    /// Which messages [`target_path`] and [`recompress_path`] print. Errors are always printed to stderr.
+    pub log: LogLevel,
+    /// Enable zstd long distance matching. Improves compression of long replays at higher levels.
+    pub long_distance_matching: bool,
+    /// Add a checksum to the zstd frame.
+    pub zstd_checksum: bool,
+    /// zstd window log. Zero uses zstd's default for the level.
+    pub window_log: u32,
+    /// Number of zstd worker threads used for each file. Zero compresses on the calling thread.
+    pub zstd_threads: u32,
+    /// Decompress each compressed file and check it matches the input before writing it.
+    /// See [`Compressor::set_verify`].
+    pub verify_after_compress: bool,
+    /// Check a sample of the compressed files before writing them. If one does not match its input,
+    /// [`target_path`] stops and lists it in [`BatchResult::spot_check_failed`]. Ignored with [`Options::clip`].
+    pub spot_check: Option<SpotCheck>,
+    /// Drop bytes after the end of the slp file's UBJSON object. See [`trailer_len`].
+    pub strip_trailer: bool,
+    /// Which slp files that need inference or repair are rejected. See [`Strictness`].
+    pub strictness: Strictness,
+    /// Only compress the start of each replay, written as `<name>.clip.slpz` unless [`Options::output_extension`] is set.
+    /// Requires [`Options::keep`]. See [`compress_clip`].
+    pub clip: Option<ClipRange>,
+    /// Appended to the output file name while it is being written. See [`atomic_write_with_suffix`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub temp_suffix: std::borrow::Cow<'static, str>,
+    /// Overrides the output extension ("slpz" when compressing, "slp" when decompressing).
+    /// Must not be empty or contain path separators.
+    pub output_extension: Option<std::ffi::OsString>,
+    /// The file extensions of inputs, and the extensions of their outputs. See [`ExtensionMap`].
+    pub extensions: ExtensionMap,
+    /// Allow the output file to replace the input file.
+    pub in_place: bool,
+    /// When compressing a directory, skip files modified within this many milliseconds,
+    /// and replays that are still being written. See [`is_probably_in_progress`].
+    /// Zero disables both checks.
+    pub settle_ms: u64,
+    /// When compressing, skip files that are already slpz files. See [`is_slpz`].
+    pub skip_already_compressed: bool,
+    /// When decompressing, skip files that are already slp files. See [`is_slp`].
+    pub skip_already_decompressed: bool,
+    /// When compressing, skip files whose output already exists and holds the same replay, by [`read_replay_id`],
+    /// like `make` skips targets that are up to date. Changing only a file's modification time does not recompress it.
+    pub skip_if_unchanged: bool,
+    /// If `Some(true)`, skip replays that did not end normally, such as after a crash or a quit out.
+    /// If `Some(false)`, skip the ones that did. See [`Completeness::is_complete`].
+    pub only_complete: Option<bool>,
+    /// What to do when decompressing would replace an existing slp file with a different replay.
+    pub on_conflict: OnConflict,
+    /// Run by [`target_path`] before an output replaces an existing file, with the output path.
+    /// If it fails the file is skipped. When decompressing it is asked instead of following
+    /// [`OnConflict::Skip`], and never for existing slp files holding the same replay. See [`interactive_overwrite`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub confirm_overwrite: Option<Hook<std::path::Path>>,
+    /// When several inputs would be written to the same output path, add the start of their [`replay_id`]
+    /// to their output names (`game-1a2b3c4d.slpz`) instead of failing with [`TargetPathError::OutputCollision`].
+    /// Paths differing only in case collide on Windows and macOS.
+    pub dedupe_names: bool,
+    /// Replace files in [`recompress_path`] even if they do not get smaller.
+    pub force_recompress: bool,
+    /// Write outputs to this directory instead of next to their inputs,
+    /// keeping their path relative to the input directory.
+    /// The output directory is never walked, even if it is inside the input directory.
+    pub output_dir: Option<std::path::PathBuf>,
+    /// See [`Compressor::set_chunk_bytes`].
+    pub chunk_bytes: usize,
+    /// Compress each file in this many segments on a thread each, and decompress chunked files on this many threads.
+    /// None writes a single segment. See [`Compressor::set_segments`] and [`Decompressor::set_threads`].
+    pub segments: Option<usize>,
+    /// Show a byte progress bar on stderr when [`target_path`] processes a single file.
+    /// Requires the `progress` feature, otherwise ignored.
+    pub progress: bool,
+    /// Name decompressed replays like the Slippi Launcher does, from [`game_timestamp`].
+    /// When compressing, warn about replays whose names do not match their timestamp.
+    pub launcher_names: bool,
+    /// When decompressing, write gzipped slp files named `.slp.gz`, for tools that only read those.
+    /// Launcher names are not used. Requires the `gzip` feature, otherwise ignored.
+    /// Compressing always reads `.slp.gz` files with the feature.
+    pub gzip_output: bool,
+    /// Run by [`target_path`] before each file is read. If it fails the file is skipped.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub pre_hook: Option<Hook<std::path::Path>>,
+    /// Run by [`target_path`] after each output is written, before the input is removed.
+    /// If it fails the file is reported as failed and the input is kept, even without [`Options::keep`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub post_hook: Option<Hook<FileResult>>,
+    /// How inputs are removed when `keep` is false.
+    pub removal: RemovalMode,
+    /// Size in bytes of the blocks that files are read and written in, and of the staging buffer of [`decompress_to_sink`].
+    /// Smaller buffers use less memory, larger buffers make fewer write calls. See [`Options::DEFAULT_BUFFER_SIZE`].
+    pub buffer_size: usize,
+    /// Maximum bytes of memory [`target_path`] should use at once. Files are processed on fewer threads so that their
+    /// [`Compressor::estimated_memory`], or the [`Decompressor::estimated_memory`] of the largest file, fits, down to one.
+    pub memory_budget: Option<u64>,
+    /// Append a record of each stage of each file to this file, synced to disk, so a run with `keep` false can be checked
+    /// with [`audit`](crate::audit) after a crash. Each output is also read back and checked against its replay ID before the input is
+    /// removed, which costs a decompression per file. See the [`journal`](crate::journal) module for the format.
+    pub journal: Option<std::path::PathBuf>,
+    /// Compress or decompress each file without writing the output or removing the input, to time slpz without disk writes.
+    /// The files are still counted in [`BatchResult::written`], with the sizes their outputs would have had.
+    pub null_output: bool,
+    /// If compressing a file at `level` is projected to take longer than this, from the time taken so far,
+    /// [`target_path`] stops and compresses it at [`TIME_BUDGET_FALLBACK_LEVEL`] instead, recording it in
+    /// [`FileResult::fallback_level`]. Ignored at lower levels and with more than one segment.
+    pub time_budget_per_file: Option<std::time::Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self { Options::DEFAULT }
+}
+
+impl Options {
+    pub const DEFAULT: Self = Options {
+        keep: true,
+        compress: None,
+        recursive: false,
+        skip_hidden: true,
+        follow_symlinks: false,
+        threading: true,
+        level: 3,
+        log: LogLevel::Normal,
+        long_distance_matching: false,
+        zstd_checksum: true,
+        window_log: 0,
+        zstd_threads: 0,
+        verify_after_compress: false,
+        spot_check: None,
+        strip_trailer: false,
+        strictness: Strictness::Normal,
+        clip: None,
+        temp_suffix: std::borrow::Cow::Borrowed(DEFAULT_TEMP_SUFFIX),
+        output_extension: None,
+        extensions: ExtensionMap::new(),
+        in_place: false,
+        settle_ms: 2000,
+        skip_already_compressed: true,
+        skip_already_decompressed: true,
+        skip_if_unchanged: false,
+        only_complete: None,
+        on_conflict: OnConflict::Skip,
+        confirm_overwrite: None,
+        dedupe_names: false,
+        force_recompress: false,
+        output_dir: None,
+        chunk_bytes: DEFAULT_CHUNK_BYTES,
+        segments: None,
+        progress: false,
+        launcher_names: false,
+        gzip_output: false,
+        pre_hook: None,
+        post_hook: None,
+        removal: RemovalMode::Trash,
+        buffer_size: Options::DEFAULT_BUFFER_SIZE,
+        memory_budget: None,
+        journal: None,
+        null_output: false,
+        time_budget_per_file: None,
+    };
+
+    pub const DEFAULT_BUFFER_SIZE: usize = 64 << 10;
+
+    /// The default options, with the level taken from the `SLPZ_LEVEL` environment variable if set.
+    ///
+    /// Prints a warning and uses the default level if `SLPZ_LEVEL` is not a valid level. See [`is_valid_level`].
+    pub fn from_env() -> Options {
+        Options::DEFAULT.with_env()
+    }
+
+    /// The options with the level taken from `SLPZ_LEVEL` if set, such as over options read from a config file.
+    /// See [`Options::from_env`].
+    pub fn with_env(self) -> Options {
+        let value = std::env::var_os("SLPZ_LEVEL");
+        self.with_level_var(value.as_ref().map(|v| v.to_string_lossy()).as_deref())
+    }
+
+    /// [`Options::with_env`] with the value of `SLPZ_LEVEL`.
+    fn with_level_var(self, value: Option<&str>) -> Options {
+        let mut options = self;
+        if let Some(value) = value {
+            match value.trim().parse() {
+                Ok(level) if is_valid_level(level) => options.level = level,
+                _ => eprintln!(
+                    "Warning: ignoring SLPZ_LEVEL '{}'. Expected a level between {} and {}",
+                    value, COMPRESS_LEVEL_MIN, COMPRESS_LEVEL_MAX,
+                ),
+            }
+        }
+        options
+    }
+
+    /// Applies the settings of a profile. Fields not set by the profile are unchanged.
+    pub fn profile(self, profile: Profile) -> Options {
+        let options = Options { level: profile.level(), ..self };
+        match profile {
+            Profile::Fast | Profile::Small => options,
+            Profile::Archive => Options {
+                long_distance_matching: true,
+                zstd_checksum: true,
+                verify_after_compress: true,
+                ..options
+            },
+            Profile::Realtime => Options {
+                long_distance_matching: false,
+                zstd_checksum: false,
+                window_log: 18,
+                ..options
+            },
+        }
+    }
+}
+
+/// A callback run for each file. See [`Options::pre_hook`] and [`Options::post_hook`].
+///
+/// With threading, hooks run on the worker threads, so at most one hook per thread runs at a time.
+pub struct Hook<T: ?Sized>(std::sync::Arc<HookFn<T>>);
+
+type HookFn<T> = dyn Fn(&T) -> Result<(), HookError> + Send + Sync;
+
+impl<T: ?Sized> Clone for Hook<T> {
+    fn clone(&self) -> Self { Hook(self.0.clone()) }
+}
+
+impl<T: ?Sized> Hook<T> {
+    pub fn new(f: impl Fn(&T) -> Result<(), HookError> + Send + Sync + 'static) -> Hook<T> {
+        Hook(std::sync::Arc::new(f))
+    }
+
+    pub fn run(&self, arg: &T) -> Result<(), HookError> { (self.0)(arg) }
+}
+
+impl<T: ?Sized> std::fmt::Debug for Hook<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "Hook") }
+}
+
+/// Writes `<path> already exists. Overwrite? [y/N] ` to `prompt` and reads the answer from `input`, like `cp -i`.
+///
+/// Returns true for "y" or "yes" in any case. An empty line or the end of input is no.
+pub fn ask_overwrite(
+    path: &std::path::Path,
+    input: &mut dyn std::io::BufRead,
+    prompt: &mut dyn std::io::Write,
+) -> std::io::Result<bool> {
+    write!(prompt, "{} already exists. Overwrite? [y/N] ", display_escaped(&path))?;
+    prompt.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// An [`Options::confirm_overwrite`] hook that asks on stderr and stdin with [`ask_overwrite`].
+///
+/// If stdin is not a terminal, such as piped input, it never overwrites instead of waiting for an answer.
+/// Files processed on several threads are asked about one at a time.
+pub fn interactive_overwrite() -> Hook<std::path::Path> {
+    use std::io::IsTerminal;
+
+    static PROMPT: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    Hook::new(|out: &std::path::Path| {
+        let not_overwritten = || HookError::Other(format!("{} exists", display_escaped(&out)));
+        let stdin = std::io::stdin();
+        if !stdin.is_terminal() { return Err(not_overwritten()) }
+
+        let _prompt = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+        match ask_overwrite(out, &mut stdin.lock(), &mut std::io::stderr()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(not_overwritten()),
+            Err(e) => Err(HookError::Other(format!("could not read the answer: {}", e))),
+        }
+    })
+}
+
+/// A file written by [`target_path`]. See [`Options::post_hook`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileResult {
+    pub input: std::path::PathBuf,
+    pub output: std::path::PathBuf,
+    /// True if the input was compressed, false if it was decompressed.
+    pub compressed: bool,
+    pub input_size: usize,
+    pub output_size: usize,
+    /// The level the file was compressed at instead of [`Options::level`],
+    /// because compressing it would have taken longer than [`Options::time_budget_per_file`].
+    pub fallback_level: Option<i32>,
+}
+
+impl FileResult {
+    /// Uncompressed size over compressed size.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed {
+            ratio(self.input_size, self.output_size)
+        } else {
+            ratio(self.output_size, self.input_size)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HookError {
+    /// A command exited with a non-zero status, or None if it was killed by a signal.
+    ExitStatus(Option<i32>),
+    /// A command could not be started.
+    Spawn(String),
+    Other(String),
+}
+
+impl std::fmt::Display for HookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookError::ExitStatus(Some(code)) => write!(f, "Hook command exited with status {}", code),
+            HookError::ExitStatus(None) => write!(f, "Hook command was killed by a signal"),
+            HookError::Spawn(e) => write!(f, "Could not run hook command: {}", e),
+            HookError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HookError {}
+
+/// What to do when the output slp file already exists and holds a different replay. See [`Options::on_conflict`].
+///
+/// Existing outputs with the same [`replay_id`] are always left alone.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OnConflict {
+    /// Keep the existing file and report an error. The default.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Write to the first free `<name>.conflict-<n>.<ext>` instead.
+    Rename,
+}
+
+/// How much [`target_path`] prints. See [`Options::log`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LogLevel {
+    /// Like [`LogLevel::Normal`], also printing the sizes and ratio of each file.
+    Verbose,
+    /// A line on stdout for each file processed or skipped, and warnings. The default.
+    Normal,
+    /// No lines for each file. The slpz program still prints the summary.
+    Quiet,
+    /// Nothing but errors.
+    Silent,
+}
+
+impl LogLevel {
+    /// Whether messages about each file are printed.
+    pub fn prints_files(self) -> bool { self <= LogLevel::Normal }
+
+    /// Whether the slpz program prints [`BatchResult::summary`] to stderr at the end, when stderr is a terminal.
+    pub fn prints_summary(self) -> bool { self <= LogLevel::Quiet }
+}
+
+/// How [`target_path`] removes inputs when [`Options::keep`] is false. See [`Options::removal`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RemovalMode {
+    /// Move inputs to the trash. If that fails the input is kept and an error is printed. The default.
+    /// See [`trash_file`].
+    Trash,
+    /// Delete inputs permanently.
+    Permanent,
+}
+
+/// Pairs of slp file extensions and the slpz file extensions they are compressed to, such as `slp.zst=slpz`.
+/// See [`Options::extensions`].
+///
+/// Names match an extension ignoring ASCII case, and the longest matching extension wins.
+/// Added pairs are checked before `slp=slpz` (and `slp.gz=slpz` with the 'gzip' feature), which are always included.
+///
+/// Serialized as the list of added pairs, most recent first. Deserializing checks them as [`ExtensionMap::add`] does.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "Vec<(String, String)>", into = "Vec<(String, String)>"))]
+pub struct ExtensionMap {
+    pairs: Vec<(String, String)>,
+}
+
+impl TryFrom<Vec<(String, String)>> for ExtensionMap {
+    type Error = String;
+
+    /// Adds the pairs, most recent first, as returned by [`ExtensionMap::pairs`].
+    fn try_from(pairs: Vec<(String, String)>) -> Result<ExtensionMap, String> {
+        let mut extensions = ExtensionMap::new();
+        for (slp, slpz) in pairs.iter().rev() {
+            extensions.add(slp, slpz).ok_or_else(|| format!("invalid extension pair {}={}", slp, slpz))?;
+        }
+        Ok(extensions)
+    }
+}
+
+impl From<ExtensionMap> for Vec<(String, String)> {
+    fn from(extensions: ExtensionMap) -> Vec<(String, String)> {
+        extensions.pairs
+    }
+}
+
+const DEFAULT_EXTENSIONS: &[(&str, &str)] = if cfg!(feature = "gzip") { &[("slp", "slpz"), ("slp.gz", "slpz")] } else { &[("slp", "slpz")] };
+
+impl ExtensionMap {
+    /// Only the default pairs.
+    pub const fn new() -> ExtensionMap {
+        ExtensionMap { pairs: Vec::new() }
+    }
+
+    /// Adds a pair, checked before the ones added earlier. A leading '.' is ignored.
+    /// Returns None if either extension is not valid as for [`Options::output_extension`], or they are the same.
+    pub fn add(&mut self, slp: &str, slpz: &str) -> Option<()> {
+        let clean = |ex: &str| -> Option<String> {
+            let ex = ex.strip_prefix('.').unwrap_or(ex);
+            (valid_extension(ex.as_ref()) && !ex.split('.').any(str::is_empty)).then(|| ex.to_string())
+        };
+        let (slp, slpz) = (clean(slp)?, clean(slpz)?);
+        if slp.eq_ignore_ascii_case(&slpz) { return None }
+        self.pairs.insert(0, (slp, slpz));
+        Some(())
+    }
+
+    /// The added pairs, most recent first, without the defaults.
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(slp, slpz)| (slp.as_str(), slpz.as_str()))
+    }
+
+    fn all_pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs().chain(DEFAULT_EXTENSIONS.iter().copied())
+    }
+
+    /// The longest extension the file name ends with, as (is an slp extension, extension, the other extension of its pair).
+    fn find(&self, path: &std::path::Path) -> Option<(bool, &str, &str)> {
+        let name = path.file_name()?.as_encoded_bytes();
+        let matches = |ex: &str| {
+            // the name must have a stem, as Path::extension requires
+            name.len() > ex.len() + 1
+                && name[name.len() - ex.len() - 1] == b'.'
+                && name[name.len() - ex.len()..].eq_ignore_ascii_case(ex.as_bytes())
+        };
+        let mut found: Option<(bool, &str, &str)> = None;
+        for (slp, slpz) in self.all_pairs() {
+            for (is_slp, ex, other) in [(true, slp, slpz), (false, slpz, slp)] {
+                if matches(ex) && found.is_none_or(|(_, f, _)| ex.len() > f.len()) { found = Some((is_slp, ex, other)) }
+            }
+        }
+        found
+    }
+
+    /// Some(true) if the file name ends in an slp extension, so would be compressed,
+    /// Some(false) if it ends in an slpz extension, and None otherwise.
+    pub fn direction(&self, path: &std::path::Path) -> Option<bool> {
+        self.find(path).map(|(is_slp, _, _)| is_slp)
+    }
+
+    /// `path` without the extension it is recognized by, and the extension of its output.
+    /// Paths that are not recognized are returned as they are, with `slpz` or `slp`.
+    fn split(&self, path: &std::path::Path, compress: bool) -> (std::path::PathBuf, &str) {
+        let mut stem = path.to_path_buf();
+        let found = self.find(path);
+        if let Some((_, ex, _)) = found {
+            for _ in 0..ex.split('.').count() { stem.set_extension(""); }
+        }
+        let out_ex = match found {
+            Some((is_slp, _, other)) if is_slp == compress => other,
+            _ => if compress { DEFAULT_EXTENSIONS[0].1 } else { DEFAULT_EXTENSIONS[0].0 },
+        };
+        (stem, out_ex)
+    }
+
+    /// The name of the output of `path`, replacing the whole extension it is recognized by,
+    /// like `game.SLP` to `game.slpz`, or `game.slp.zst` to `game.slp` with the pair `slp=slp.zst`.
+    /// Other paths get `slpz` or `slp` in place of their last extension, if any.
+    pub fn output_name(&self, path: &std::path::Path, compress: bool) -> std::path::PathBuf {
+        let (mut out, ex) = self.split(path, compress);
+        out.set_extension(ex);
+        out
+    }
+}
+
+/// Named sets of compression settings. See [`Options::profile`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Profile {
+    /// level -1, zstd's fastest mode that still compresses well.
+    Fast,
+    /// level 12.
+    Small,
+    /// level 19, long distance matching, zstd checksum, verify after compress.
+    Archive,
+    /// level 1, no long distance matching, no zstd checksum, 256KiB window (window log 18).
+    Realtime,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [Profile::Fast, Profile::Small, Profile::Archive, Profile::Realtime];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Fast => "fast",
+            Profile::Small => "small",
+            Profile::Archive => "archive",
+            Profile::Realtime => "realtime",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Profile::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    pub fn level(self) -> i32 {
+        match self {
+            Profile::Fast => -1,
+            Profile::Small => 12,
+            Profile::Archive => 19,
+            Profile::Realtime => 1,
+        }
+    }
+
+    /// The exact settings applied by the profile.
+    pub fn description(self) -> &'static str {
+        match self {
+            Profile::Fast => "level=-1",
+            Profile::Small => "level=12",
+            Profile::Archive => "level=19 long_distance_matching=true zstd_checksum=true verify_after_compress=true",
+            Profile::Realtime => "level=1 long_distance_matching=false zstd_checksum=false window_log=18",
+        }
+    }
+}
+
+/// Library access to slpz program functionality.
+///
+/// If Some, the sender will first send the number of targets.
+/// After that, the sender will send '1' for each target completed.
+/// If the sender cannot send, it will panic.
+///
+/// - Threaded directory compression/decompression.
+/// - Compression/decompression autodetection.
+/// - Deletion of old files.
+///
+/// Returns what happened to each file. Files that fail do not stop the others,
+/// so check [`BatchResult::failed`]. Print [`BatchResult::summary`] for a one line report.
+pub fn target_path(
+    options: &Options,
+    path: &std::path::Path,
+    sender: Option<std::sync::mpsc::Sender<usize>>,
+) -> Result<BatchResult, TargetPathError> {
+    if !matches!(path.try_exists(), Ok(true)) { return Err(TargetPathError::PathNotFound) }
+    if options.clip.is_some() && !options.keep { return Err(TargetPathError::ClipRemovesInput) }
+    if let Some(ref ex) = options.output_extension {
+        if !valid_extension(ex) { return Err(TargetPathError::InvalidOutputExtension) }
+    }
+
+    let mut targets = Vec::new();
+    let mut should_compress = options.compress;
+    let mut unsettled = Vec::new();
+    let mut unreadable_dirs = Vec::new();
+
+    // outputs keep their path relative to this
+    let root = if path.is_dir() { path } else { path.parent().unwrap_or(std::path::Path::new("")) };
+    let out_root = match options.output_dir {
+        Some(ref dir) => {
+            std::fs::create_dir_all(dir).map_err(|_| TargetPathError::OutputDirInvalid)?;
+            Some(std::fs::canonicalize(dir).map_err(|_| TargetPathError::OutputDirInvalid)?)
+        }
+        None => None,
+    };
+
+    if path.is_dir() {
+        let c = match should_compress {
+            Some(c) => c,
+            None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
+        };
+        if let Some(ref out_root) = out_root {
+            let input = std::path::Path::new(if c { "x.slp" } else { "x.slpz" });
+            let out = output_path(options, root, input, c);
+            if out.is_some_and(|out| options.extensions.direction(&out) == Some(c))
+                && std::fs::canonicalize(path).ok().as_ref() == Some(out_root)
+            {
+                return Err(TargetPathError::OutputDirConflict);
+            }
+        }
+        unreadable_dirs = get_targets(&mut targets, path, options, c, out_root.as_deref());
+        if c { unsettled = skip_unsettled(options, &mut targets) }
+    } else if path.is_file() {
+        targets.push(path.to_path_buf());
+        if should_compress.is_none() {
+            let ex_compress = options.extensions.direction(path);
+
+            // content is more reliable than the extension
+            should_compress = match content_should_compress(path) {
+                Some(c) => {
+                    if options.log.prints_files() && ex_compress == Some(!c) {
+                        if c {
+                            println!("warning: {} appears to be uncompressed; compressing it", display_escaped(&path));
+                        } else {
+                            println!("warning: {} appears to already be compressed; decompressing it", display_escaped(&path));
+                        }
+                    }
+                    Some(c)
+                }
+                None => ex_compress,
+            };
+        }
+    } else {
+        return Err(TargetPathError::PathInvalid);
+    }
+
+    let will_compress = match should_compress {
+        Some(n) => n,
+        None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
+    };
+    let _span = span!("slpz.target_path", path = %display_escaped(&path), compress = will_compress, files = targets.len());
+
+    let dedupe = plan_dedupe(options, root, &targets, will_compress, CASE_INSENSITIVE_PATHS)?;
+    let mut batch_state = Batch::new(root, dedupe, will_compress);
+    if let Some(ref journal) = options.journal {
+        batch_state.journal = Some(Journal::open(journal).map_err(|_| TargetPathError::JournalInvalid)?);
+    }
+    *batch_state.result() = BatchResult { skipped: unsettled, failed: unreadable_dirs, ..BatchResult::new(will_compress) };
+    let batch = &batch_state;
+
+    // progress bars for many files at once would overwrite each other
+    let single_file_options;
+    let options = if options.progress && targets.len() != 1 {
+        single_file_options = Options { progress: false, ..options.clone() };
+        &single_file_options
+    } else {
+        options
+    };
+
+    if let Some(ref sender) = sender {
+        sender.send(targets.len()).expect("Sending failed");
+    }
+
+    let threads = batch_threads(options, will_compress, &targets);
+    if threads == 1 {
+        if will_compress {
+            let mut compressor = options_compressor(options).ok_or(TargetPathError::ZstdInitError)?;
+            for t in &targets {
+                if batch.stopped() { break }
+                batch.record(t, compress_target(&mut compressor, options, batch, t));
+                if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
+            }
+        } else {
+            let mut decompressor = options_decompressor(options).ok_or(TargetPathError::ZstdInitError)?;
+            for t in &targets {
+                batch.record(t, decompress_target(&mut decompressor, options, batch, t));
+                if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
+            }
+        }
+    } else {
+        // split into approximately equal slices (why is this so annoying?)
+        let chunk = targets.len() / threads;
+        let split = (chunk + 1) * (targets.len() % threads);
+        let slices = targets[..split].chunks(chunk+1).chain(targets[split..].chunks(chunk)).collect::<Vec<_>>();
+
+        let sender_ref = sender.as_ref();
+        #[cfg(feature = "tracing")]
+        let parent = tracing::Span::current();
+
+        std::thread::scope(|scope| {
+            if will_compress {
+                for s in slices {
+                    #[cfg(feature = "tracing")]
+                    let parent = parent.clone();
+                    scope.spawn(move || {
+                        #[cfg(feature = "tracing")]
+                        let _parent = parent.entered();
+                        let mut compressor = match options_compressor(options) {
+                            Some(c) => c,
+                            None => {
+                                eprintln!("Error: Failed to init zstd compressor");
+                                return;
+                            }
+                        };
+                        for t in s {
+                            if batch.stopped() { break }
+                            batch.record(t, compress_target(&mut compressor, options, batch, t));
+                            if let Some(sender) = sender_ref { sender.send(1).expect("Sending failed"); }
+                        }
+                    });
+                }
+            } else {
+                for s in slices {
+                    #[cfg(feature = "tracing")]
+                    let parent = parent.clone();
+                    scope.spawn(move || {
+                        #[cfg(feature = "tracing")]
+                        let _parent = parent.entered();
+                        let mut decompressor = match options_decompressor(options) {
+                            Some(d) => d,
+                            None => {
+                                eprintln!("Error: Failed to init zstd decompressor");
+                                return;
+                            }
+                        };
+                        for t in s {
+                            batch.record(t, decompress_target(&mut decompressor, options, batch, t));
+                            if let Some(sender) = sender_ref { sender.send(1).expect("Sending failed"); }
+                        }
+                    });
+                }
+            };
+        })
+    }
+
+    Ok(batch_state.finish())
+}
+
+/// The number of threads [`target_path`] processes `targets` on, within [`Options::memory_budget`].
+///
+/// Decompression is estimated from the largest file, which is read to find its window size.
+/// Prints the estimated memory with [`LogLevel::Verbose`] when zstd threads, segments or a budget are set,
+/// and when the budget lowers the number of threads.
+fn batch_threads(options: &Options, will_compress: bool, targets: &[std::path::PathBuf]) -> usize {
+    let threads = if options.threading && targets.len() >= 8 { 8 } else { 1 };
+    if options.zstd_threads == 0 && options.segments.is_none() && options.memory_budget.is_none() { return threads }
+
+    let per_thread = if will_compress {
+        options_compressor(options).map_or(0, |c| c.estimated_memory())
+    } else {
+        let largest = targets.iter().max_by_key(|t| std::fs::metadata(t).map_or(0, |m| m.len()));
+        match (options_decompressor(options), largest.and_then(|t| std::fs::read(t).ok())) {
+            (Some(d), Some(slpz)) => d.estimated_memory(&slpz).unwrap_or(0),
+            _ => 0,
+        }
+    };
+    let fitting = match options.memory_budget {
+        Some(budget) if per_thread > 0 => threads.min((budget / per_thread).max(1) as usize),
+        _ => threads,
+    };
+
+    if fitting < threads && options.log.prints_files() {
+        println!("memory budget of {} bytes allows {} threads instead of {}", options.memory_budget.unwrap(), fitting, threads);
+    }
+    if options.log == LogLevel::Verbose {
+        println!("estimated zstd memory {} bytes ({} threads of {} bytes)", per_thread * fitting as u64, fitting, per_thread);
+    }
+    fitting
+}
+
+/// Creates a compressor with the level and zstd settings from `options`.
+pub fn options_compressor(options: &Options) -> Option<Compressor> {
+    use zstd::stream::raw::CParameter;
+
+    let mut compressor = Compressor::new(options.level)?;
+    compressor.set_strictness(options.strictness);
+    compressor.set_strip_trailer(options.strip_trailer);
+    compressor.set_verify(options.verify_after_compress);
+    compressor.set_chunk_bytes(options.chunk_bytes);
+    compressor.set_segments(options.segments.unwrap_or(1));
+    compressor.set_checksum(options.zstd_checksum)?;
+    if options.level > TIME_BUDGET_FALLBACK_LEVEL { compressor.set_time_budget(options.time_budget_per_file); }
+
+    compressor.set_parameter(CParameter::EnableLongDistanceMatching(options.long_distance_matching))?;
+    if options.window_log != 0 { compressor.set_parameter(CParameter::WindowLog(options.window_log))?; }
+    if options.zstd_threads != 0 { compressor.set_parameter(CParameter::NbWorkers(options.zstd_threads))?; }
+
+    Some(compressor)
+}
+
+/// The level [`target_path`] compresses a file at when compressing it at [`Options::level`]
+/// would take longer than [`Options::time_budget_per_file`].
+pub const TIME_BUDGET_FALLBACK_LEVEL: i32 = 1;
+
+/// Compresses an slp file, clipped if [`Options::clip`] is set.
+pub fn options_compress(compressor: &mut Compressor, options: &Options, slp: &[u8]) -> Result<Vec<u8>, CompError> {
+    match options.clip {
+        Some(range) => compress_clip(compressor, slp, range),
+        None => compress_unchecked(compressor, slp),
+    }
+}
+
+/// Creates a decompressor with the threads from `options`.
+pub fn options_decompressor(options: &Options) -> Option<Decompressor> {
+    let mut decompressor = Decompressor::new()?;
+    decompressor.set_threads(options.segments.unwrap_or(1));
+    decompressor.set_buffer_size(options.buffer_size);
+    Some(decompressor)
+}
+
+/// Returns Some(true) if the file starts like an slp file, Some(false) if it starts like an slpz file.
+fn content_should_compress(path: &std::path::Path) -> Option<bool> {
+    use std::io::Read;
+
+    // enough for the slpz header and optional fields
+    let mut start = Vec::with_capacity(1024);
+    std::fs::File::open(path).ok()?.take(1024).read_to_end(&mut start).ok()?;
+    if is_slp(&start) || (cfg!(feature = "gzip") && start.starts_with(b"\x1F\x8B")) {
+        Some(true)
+    } else if is_slpz(&start) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// How recently a replay with an unwritten raw length must have been modified to be considered in progress.
+const IN_PROGRESS_WINDOW: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Returns true if the file looks like a replay that Slippi is still writing.
+///
+/// Slippi writes the raw length after the game ends,
+/// so a recently modified replay whose raw length is still zero is probably in progress.
+pub fn is_probably_in_progress(path: &std::path::Path) -> std::io::Result<bool> {
+    use std::io::Read;
+
+    let mut header = [0u8; 15];
+    let mut file = std::fs::File::open(path)?;
+    if file.read_exact(&mut header).is_err() { return Ok(false) }
+    if !is_slp(&header) || header[11..15] != [0; 4] { return Ok(false) }
+
+    Ok(modified_age(&file.metadata()?) < IN_PROGRESS_WINDOW)
+}
+
+fn modified_age(metadata: &std::fs::Metadata) -> std::time::Duration {
+    metadata.modified().ok()
+        .and_then(|m| m.elapsed().ok())
+        .unwrap_or(std::time::Duration::ZERO)
+}
+
+/// Removes targets that were modified too recently or are still being written.
+/// Removes files that are still being written from `targets`, returning them.
+fn skip_unsettled(options: &Options, targets: &mut Vec<std::path::PathBuf>) -> Vec<std::path::PathBuf> {
+    if options.settle_ms == 0 { return Vec::new() }
+    let settle = std::time::Duration::from_millis(options.settle_ms);
+
+    let mut unsettled = Vec::new();
+    targets.retain(|t| {
+        let recently_modified = match std::fs::metadata(t) {
+            Ok(m) => modified_age(&m) < settle,
+            Err(_) => false, // error is reported when compressing
+        };
+        if recently_modified || matches!(is_probably_in_progress(t), Ok(true)) {
+            event!(info, path = %display_escaped(&t), "skipped (in progress)");
+            if options.log.prints_files() { println!("skipped {} (in progress)", display_escaped(&t)); }
+            unsettled.push(t.clone());
+            return false;
+        }
+        true
+    });
+    unsettled
+}
+
+/// Returns false for extensions that would be unsafe to append to a file name.
+fn valid_extension(ex: &std::ffi::OsStr) -> bool {
+    let ex = ex.to_string_lossy();
+    let ex = ex.strip_prefix('.').unwrap_or(&ex);
+    !ex.is_empty() && ex != "." && !ex.chars().any(std::path::is_separator)
+}
+
+/// `root` is the directory being processed. With [`Options::output_dir`], outputs keep their path relative to it.
+///
+/// The extension the input is recognized by in [`Options::extensions`] is replaced, so `game.slp.gz` becomes `game.slpz`.
+fn output_path(
+    options: &Options,
+    root: &std::path::Path,
+    t: &std::path::Path,
+    compress: bool,
+) -> Option<std::path::PathBuf> {
+    let out = match options.output_dir {
+        Some(ref dir) => dir.join(t.strip_prefix(root).ok().or_else(|| t.file_name().map(std::path::Path::new))?),
+        None => t.to_path_buf(),
+    };
+    let (mut out, default_ex) = options.extensions.split(&out, compress);
+    let ex = match options.output_extension {
+        Some(ref ex) => {
+            let ex = ex.to_string_lossy();
+            ex.strip_prefix('.').unwrap_or(&ex).to_string()
+        }
+        None if !compress && options.gzip_output && cfg!(feature = "gzip") => format!("{}.gz", default_ex),
+        None if compress && options.clip.is_some() => format!("clip.{}", default_ex),
+        None => default_ex.to_string(),
+    };
+    if !out.set_extension(ex) { return None }
+    Some(out)
+}
+
+/// Whether output paths differing only in case are the same file, as on the default file systems of Windows and macOS.
+const CASE_INSENSITIVE_PATHS: bool = cfg!(any(windows, target_os = "macos"));
+
+/// Finds inputs whose outputs would have the same path, before anything is written.
+///
+/// Returns the inputs whose outputs need [`deduped_path`] with [`Options::dedupe_names`],
+/// or prints the collisions and fails without it.
+fn plan_dedupe(
+    options: &Options,
+    root: &std::path::Path,
+    targets: &[std::path::PathBuf],
+    compress: bool,
+    case_insensitive: bool,
+) -> Result<std::collections::HashSet<std::path::PathBuf>, TargetPathError> {
+    // launcher names are chosen per replay and avoid collisions themselves
+    if targets.len() < 2 || (!compress && options.launcher_names) { return Ok(Default::default()) }
+
+    let mut outputs = std::collections::BTreeMap::<String, Vec<&std::path::PathBuf>>::new();
+    for t in targets {
+        let Some(out) = output_path(options, root, t, compress) else { continue };
+        let out = out.to_string_lossy().into_owned();
+        let key = if case_insensitive { out.to_lowercase() } else { out };
+        outputs.entry(key).or_default().push(t);
+    }
+    let collisions = outputs.into_iter().filter(|(_, inputs)| inputs.len() > 1).collect::<Vec<_>>();
+
+    if !collisions.is_empty() && !options.dedupe_names {
+        for (out, inputs) in &collisions {
+            let inputs = inputs.iter().map(|t| display_escaped(&t).to_string()).collect::<Vec<_>>().join(", ");
+            eprintln!("Error: {} would be written to the same file {}", inputs, out);
+        }
+        return Err(TargetPathError::OutputCollision);
+    }
+    Ok(collisions.into_iter().flat_map(|(_, inputs)| inputs).cloned().collect())
+}
+
+/// State shared by the files of one [`target_path`] call.
+struct Batch<'a> {
+    root: &'a std::path::Path,
+    /// Inputs whose outputs collide, named with [`deduped_path`].
+    dedupe: std::collections::HashSet<std::path::PathBuf>,
+    result: std::sync::Mutex<BatchResult>,
+    /// Set when a spot check fails, to stop starting files.
+    stopped: std::sync::atomic::AtomicBool,
+    journal: Option<Journal>,
+}
+
+/// The journal records of one file of a [`Batch`]. See [`Options::journal`].
+struct FileJournal<'a> {
+    journal: &'a Journal,
+    /// The replay the output should hold. If unknown the output cannot be verified.
+    id: Option<[u8; 32]>,
+    input: std::path::PathBuf,
+    output: std::path::PathBuf,
+}
+
+impl FileJournal<'_> {
+    fn record(&self, stage: JournalStage) -> std::io::Result<()> {
+        let record = JournalRecord { stage, id: self.id.unwrap_or_default(), input: self.input.clone(), output: self.output.clone() };
+        self.journal.record(&record)
+    }
+
+    /// Records the output as written, then reads it back and records it as verified if it holds the replay.
+    fn written(&self, compressed: bool) -> Result<(), String> {
+        let cannot_record = |e: std::io::Error| format!("cannot write the journal: {}", io_reason(&e));
+        self.record(JournalStage::Written).map_err(cannot_record)?;
+        if self.id.is_none() || output_replay_id(&self.output, compressed) != self.id {
+            return Err(format!("{} does not hold the replay that was written when read back", display_escaped(&self.output)));
+        }
+        self.record(JournalStage::Verified).map_err(cannot_record)
+    }
+
+    /// Records the input as removed, printing an error if that fails. The input is already gone, so the file still counts as written.
+    fn removed(&self) {
+        if let Err(e) = self.record(JournalStage::Removed) {
+            eprintln!("Error: cannot write the journal after removing {}: {}", display_escaped(&self.input), io_reason(&e));
+        }
+    }
+}
+
+/// The [`replay_id`] of the replay in a written output, found by decompressing it rather than from its header.
+fn output_replay_id(out: &std::path::Path, compressed: bool) -> Option<[u8; 32]> {
+    let data = std::fs::read(out).ok()?;
+    if !compressed { return gunzipped_replay_id(&data) }
+    let slp = decompress(&mut Decompressor::new()?, &data).ok()?;
+    replay_id(&slp).ok()
+}
+
+/// What happened to one input of a [`Batch`].
+enum Outcome {
+    Written(FileResult),
+    Skipped,
+    Failed,
+}
+
+impl<'a> Batch<'a> {
+    fn new(root: &'a std::path::Path, dedupe: std::collections::HashSet<std::path::PathBuf>, compressed: bool) -> Self {
+        Batch {
+            root,
+            dedupe,
+            result: std::sync::Mutex::new(BatchResult::new(compressed)),
+            stopped: Default::default(),
+            journal: None,
+        }
+    }
+
+    /// Records that the output `out` of `t` holding the replay `id` is about to be written, if there is a journal.
+    /// Prints an error and returns `Err` if the journal cannot be written.
+    fn start_journal(&self, t: &std::path::Path, out: &std::path::Path, id: Option<[u8; 32]>, action: &str) -> Result<Option<FileJournal<'_>>, ()> {
+        let Some(ref journal) = self.journal else { return Ok(None) };
+        let absolute = |p: &std::path::Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+        let file = FileJournal { journal, id, input: absolute(t), output: absolute(out) };
+        match file.record(JournalStage::Started) {
+            Ok(()) => Ok(Some(file)),
+            Err(e) => {
+                eprintln!("Error {} {}: cannot write the journal: {}", action, display_escaped(&t), io_reason(&e));
+                Err(())
+            }
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.stopped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn result(&self) -> std::sync::MutexGuard<'_, BatchResult> {
+        self.result.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, input: &std::path::Path, outcome: Outcome) {
+        let mut result = self.result();
+        match outcome {
+            Outcome::Written(file) => result.written.push(file),
+            Outcome::Skipped => result.skipped.push(input.to_path_buf()),
+            Outcome::Failed => result.failed.push(input.to_path_buf()),
+        }
+    }
+
+    /// Sorts the results by input path, as threads finish files in any order.
+    fn finish(self) -> BatchResult {
+        let mut result = self.result.into_inner().unwrap_or_else(|e| e.into_inner());
+        result.written.sort_by(|a, b| a.input.cmp(&b.input));
+        result.skipped.sort();
+        result.failed.sort();
+        result.not_removed.sort();
+        result.spot_check_failed.sort();
+        result
+    }
+}
+
+/// What [`target_path`] did with each file.
+///
+/// Field names are stable across slpz versions.
+/// Changes to the structure increment [`BATCH_SCHEMA_VERSION`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatchResult {
+    /// Always [`BATCH_SCHEMA_VERSION`] for results produced by this version of slpz.
+    pub schema_version: u32,
+    /// True if the files were compressed, false if they were decompressed.
+    pub compressed: bool,
+    /// The files written, sorted by input path like the other lists.
+    pub written: Vec<FileResult>,
+    /// Inputs that were skipped, such as files still being written, or outputs that were not overwritten.
+    pub skipped: Vec<std::path::PathBuf>,
+    /// Inputs that could not be processed, and directories that could not be read. An error was printed for each.
+    pub failed: Vec<std::path::PathBuf>,
+    /// Inputs that were written but could not be removed with [`Options::keep`] false, so both files exist.
+    /// These are also in `written`.
+    pub not_removed: Vec<std::path::PathBuf>,
+    /// The number of files checked with [`Options::spot_check`], including failures.
+    pub spot_checked: usize,
+    /// Files that did not match their input when spot checked. These are also in `failed`.
+    /// If not empty, the run stopped early and later files were not processed.
+    pub spot_check_failed: Vec<std::path::PathBuf>,
+}
+
+pub const BATCH_SCHEMA_VERSION: u32 = 1;
+
+impl Default for BatchResult {
+    fn default() -> Self {
+        BatchResult {
+            schema_version: BATCH_SCHEMA_VERSION,
+            compressed: false,
+            written: Vec::new(),
+            skipped: Vec::new(),
+            failed: Vec::new(),
+            not_removed: Vec::new(),
+            spot_checked: 0,
+            spot_check_failed: Vec::new(),
+        }
+    }
+}
+
+impl BatchResult {
+    pub fn new(compressed: bool) -> Self {
+        BatchResult { compressed, ..Default::default() }
+    }
+
+    /// Total size of the slp files read or written.
+    pub fn slp_bytes(&self) -> u64 {
+        self.written.iter().map(|f| if f.compressed { f.input_size } else { f.output_size } as u64).sum()
+    }
+
+    /// Total size of the slpz files read or written.
+    pub fn slpz_bytes(&self) -> u64 {
+        self.written.iter().map(|f| if f.compressed { f.output_size } else { f.input_size } as u64).sum()
+    }
+
+    /// Uncompressed size over compressed size, over all written files.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.slp_bytes() as usize, self.slpz_bytes() as usize)
+    }
+
+    /// The line printed by the slpz program at the end, like
+    /// `summary: compressed=120 skipped=3 failed=0 not_removed=0 slp_bytes=... slpz_bytes=... ratio=10.12`.
+    /// Ends with `spot_checked=... spot_check_failed=...` if any files were spot checked,
+    /// and `over_time_budget=...` if any were compressed at [`TIME_BUDGET_FALLBACK_LEVEL`].
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "summary: {}={} skipped={} failed={} not_removed={} slp_bytes={} slpz_bytes={} ratio={:.2}",
+            if self.compressed { "compressed" } else { "decompressed" },
+            self.written.len(),
+            self.skipped.len(),
+            self.failed.len(),
+            self.not_removed.len(),
+            self.slp_bytes(),
+            self.slpz_bytes(),
+            self.ratio(),
+        );
+        if self.spot_checked != 0 {
+            summary += &format!(" spot_checked={} spot_check_failed={}", self.spot_checked, self.spot_check_failed.len());
+        }
+        let fallbacks = self.written.iter().filter(|f| f.fallback_level.is_some()).count();
+        if fallbacks != 0 {
+            summary += &format!(" over_time_budget={}", fallbacks);
+        }
+        summary
+    }
+
+    /// [`BatchResult::summary`] followed by the time the run took and the throughput in megabytes of slp files per second,
+    /// like `seconds=2.310 throughput_mbs=142.3`.
+    pub fn summary_with_time(&self, elapsed: std::time::Duration) -> String {
+        format!(
+            "{} seconds={:.3} throughput_mbs={:.1}",
+            self.summary(), elapsed.as_secs_f64(), throughput_mbs(self.slp_bytes(), elapsed),
+        )
+    }
+
+    /// Adds the files of another result to the end of each list, for reporting several runs as one.
+    /// `compressed` is kept from `self`.
+    pub fn merge(&mut self, other: BatchResult) {
+        self.written.extend(other.written);
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+        self.not_removed.extend(other.not_removed);
+        self.spot_checked += other.spot_checked;
+        self.spot_check_failed.extend(other.spot_check_failed);
+    }
+}
+
+/// Adds the first 4 bytes of the replay's [`replay_id`] to a file name, `game.slpz` to `game-1a2b3c4d.slpz`.
+///
+/// `data` is the slp or slpz file, which have the same ID.
+fn deduped_path(out: &std::path::Path, data: &[u8]) -> std::path::PathBuf {
+    let id = replay_id(data).unwrap_or_default();
+    let mut name = out.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{:02x}{:02x}{:02x}{:02x}", id[0], id[1], id[2], id[3]));
+    if let Some(ex) = out.extension() {
+        name.push(".");
+        name.push(ex);
+    }
+    out.with_file_name(name)
+}
+
+/// Creates the directories above an output file when writing to [`Options::output_dir`].
+fn create_output_dir(options: &Options, out: &std::path::Path) -> std::io::Result<()> {
+    match (&options.output_dir, out.parent()) {
+        (Some(_), Some(parent)) => std::fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Returns true, after logging it, if [`Options::only_complete`] skips this replay.
+/// Replays that cannot be parsed are not skipped, so that they fail when processed.
+fn skip_by_completeness(options: &Options, t: &std::path::Path, data: &[u8]) -> bool {
+    let Some(only_complete) = options.only_complete else { return false };
+    match completeness(data) {
+        Ok(c) if c.is_complete() != only_complete => {
+            let reason = if only_complete { "incomplete" } else { "complete" };
+            event!(info, reason, "skipped (completeness)");
+            if options.log.prints_files() { println!("skipped {} ({})", display_escaped(&t), reason); }
+            true
+        }
+        _ => false,
+    }
+}
+
+fn compress_target(
+    c: &mut Compressor,
+    options: &Options,
+    batch: &Batch,
+    t: &std::path::PathBuf,
+) -> Outcome {
+    let _span = span!(
+        "slpz.compress_file",
+        path = %display_escaped(&t),
+        level = c.level,
+        bytes_in = tracing::field::Empty,
+        bytes_out = tracing::field::Empty,
+    );
+    let start = std::time::Instant::now();
+    if let Some(Err(e)) = options.pre_hook.as_ref().map(|h| h.run(t)) {
+        event!(error, error = %e, "pre hook failed");
+        eprintln!("Error compressing {}: {}", display_escaped(&t), e);
+        return Outcome::Failed;
+    }
+    let (slp, progress) = match read_with_progress(options, t) {
+        Ok(s) => s,
+        Err(e) => {
+            event!(error, error = %e, "read failed");
+            eprintln!("Error compressing {}: cannot read input: {}", display_escaped(&t), io_reason(&e));
+            return Outcome::Failed;
+        }
+    };
+    record!(_span, "bytes_in", slp.len());
+
+    #[cfg(feature = "gzip")]
+    let slp = match gzip::is_gzip(&slp) {
+        true => match gzip::decode(&slp) {
+            Ok(slp) => slp,
+            Err(e) => {
+                event!(error, error = %e, "gunzip failed");
+                eprintln!("Error compressing {}: {}", display_escaped(&t), e);
+                return Outcome::Failed;
+            }
+        },
+        false => slp,
+    };
+
+    if options.skip_already_compressed && is_slpz(&slp) {
+        event!(info, "skipped (already compressed)");
+        if options.log.prints_files() { println!("skipped {} (already compressed)", display_escaped(&t)); }
+        return Outcome::Skipped;
+    }
+    if skip_by_completeness(options, t, &slp) { return Outcome::Skipped }
+
+    if options.launcher_names && options.log.prints_files() {
+        let name = t.file_name().unwrap_or_default().to_string_lossy();
+        match (game_timestamp(&slp), GameTimestamp::parse_launcher_name(&name)) {
+            (Some(timestamp), Some(named)) if timestamp.matches_in_some_timezone(&named) => {}
+            (Some(timestamp), _) => println!(
+                "warning: {} does not match its start time {}. The Slippi Launcher would name it {}",
+                display_escaped(&t), timestamp, timestamp.launcher_name(),
+            ),
+            (None, _) => {}
+        }
+    }
+
+    let out = match output_path(options, batch.root, t, true) {
+        Some(out) if batch.dedupe.contains(t) => deduped_path(&out, &slp),
+        // a misnamed file, such as an slp file named .slpz, would overwrite itself
+        Some(out) if out == *t && !options.in_place => {
+            let out = deduped_path(&out, &slp);
+            if options.log.prints_files() {
+                println!("warning: {} would overwrite itself, writing {}", display_escaped(&t), display_escaped(&out));
+            }
+            out
+        }
+        Some(out) => out,
+        None => {
+            eprintln!("Error creating new filename for {}", display_escaped(&t));
+            return Outcome::Failed;
+        }
+    };
+    if options.skip_if_unchanged && is_up_to_date(&out, &slp) {
+        event!(info, "skipped (up to date)");
+        if options.log.prints_files() { println!("skipped {} ({} is up to date)", display_escaped(&t), display_escaped(&out)); }
+        return Outcome::Skipped;
+    }
+
+    let mut fallback_level = None;
+    let compressed = match options_compress(c, options, &slp) {
+        Err(CompError::TimeBudgetExceeded) => {
+            event!(info, level = TIME_BUDGET_FALLBACK_LEVEL, "over time budget, compressing at a faster level");
+            if options.log.prints_files() {
+                println!(
+                    "warning: {} would take over {:?} to compress at level {}, compressing at level {}",
+                    display_escaped(&t), options.time_budget_per_file.unwrap_or_default(), c.level, TIME_BUDGET_FALLBACK_LEVEL,
+                );
+            }
+            fallback_level = Some(TIME_BUDGET_FALLBACK_LEVEL);
+            c.with_level(TIME_BUDGET_FALLBACK_LEVEL, |c| options_compress(c, options, &slp))
+                .unwrap_or(Err(CompError::CompressionFailure))
+        }
+        r => r,
+    };
+    match compressed {
+        Ok(slpz) => {
+            record!(_span, "bytes_out", slpz.len());
+            if !spot_check(options, batch, t, &slp, &slpz) { return Outcome::Failed }
+            if options.log.prints_files() || cfg!(feature = "tracing") {
+                // dropping the trailer was asked for
+                let warnings = compress_warnings(c, &slp).into_iter()
+                    .filter(|w| !matches!(w, CompressWarning::TrailingBytes { stripped: true, .. }));
+                for warning in warnings {
+                    event!(warn, warning = %warning, "compress warning");
+                    if options.log.prints_files() { println!("warning: {} {}", display_escaped(&t), warning); }
+                }
+            }
+            if options.null_output {
+                progress.finish(&format!("{}: {} -> {} bytes ({:.2}x)", display_escaped(&t), slp.len(), slpz.len(), ratio(slp.len(), slpz.len())));
+                let result = FileResult {
+                    input: t.clone(),
+                    output: out,
+                    compressed: true,
+                    input_size: slp.len(),
+                    output_size: slpz.len(),
+                    fallback_level,
+                };
+                return discard_output(options, result, start);
+            }
+            if let Err(e) = create_output_dir(options, &out) {
+                eprintln!("Error compressing {}: cannot create output directory: {}", display_escaped(&t), io_reason(&e));
+                return Outcome::Failed;
+            }
+            if !confirm_overwrite(options, t, &out) {
+                return Outcome::Skipped;
+            }
+            let id = batch.journal.as_ref().and_then(|_| read_replay_id(&slpz).or_else(|| replay_id(&slp).ok()));
+            let Ok(journal) = batch.start_journal(t, &out, id, "compressing") else { return Outcome::Failed };
+            match atomic_write_with_suffix(&out, &slpz, &options.temp_suffix) {
+                Ok(_) => {
+                    progress.finish(&format!("{}: {} -> {} bytes ({:.2}x)", display_escaped(&t), slp.len(), slpz.len(), ratio(slp.len(), slpz.len())));
+                    if let Some(Err(e)) = journal.as_ref().map(|j| j.written(true)) {
+                        event!(error, error = %e, "journal verification failed");
+                        eprintln!("Error compressing {}: {}", display_escaped(&t), e);
+                        return Outcome::Failed;
+                    }
+                    let result = FileResult {
+                        input: t.clone(),
+                        output: out.clone(),
+                        compressed: true,
+                        input_size: slp.len(),
+                        output_size: slpz.len(),
+                        fallback_level,
+                    };
+                    if let Some(Err(e)) = options.post_hook.as_ref().map(|h| h.run(&result)) {
+                        event!(error, error = %e, "post hook failed");
+                        eprintln!("Error compressing {}: {}", display_escaped(&t), e);
+                        return Outcome::Failed;
+                    }
+                    match options.log {
+                        LogLevel::Verbose => println!(
+                            "compressed {} ({} -> {} bytes, {:.2}x, {})",
+                            display_escaped(&t), slp.len(), slpz.len(), ratio(slp.len(), slpz.len()),
+                            throughput(slp.len(), start.elapsed()),
+                        ),
+                        LogLevel::Normal => println!("compressed {}", display_escaped(&t)),
+                        _ => {}
+                    }
+                    if !options.keep && out != *t && remove_input(options, batch, t, &out) {
+                        if let Some(ref journal) = journal { journal.removed() }
+                    }
+                    Outcome::Written(result)
+                }
+                Err(e) => {
+                    event!(error, error = %e, "write failed");
+                    eprintln!("Error compressing {}: cannot write output {}: {}", display_escaped(&t), display_escaped(&out), io_reason(&e));
+                    Outcome::Failed
+                }
+            }
+        }
+        Err(e) => {
+            event!(error, error = %e, "compression failed");
+            eprintln!("Error compressing {}: {}", display_escaped(&t), e);
+            Outcome::Failed
+        }
+    }
+}
+
+/// Whether `out` is an slpz file of the replay `slp`, for [`Options::skip_if_unchanged`].
+fn is_up_to_date(out: &std::path::Path, slp: &[u8]) -> bool {
+    let Some(existing) = std::fs::read(out).ok().and_then(|existing| read_replay_id(&existing)) else { return false };
+    replay_id(slp).is_ok_and(|id| id == existing)
+}
+
+/// Reports a file processed with [`Options::null_output`] like a written one, without writing `out`.
+fn discard_output(options: &Options, result: FileResult, start: std::time::Instant) -> Outcome {
+    let action = if result.compressed { "compressed" } else { "decompressed" };
+    let t = &result.input;
+    match options.log {
+        LogLevel::Verbose => println!(
+            "{} {} ({} -> {} bytes, {:.2}x, {}, discarded)",
+            action, display_escaped(t), result.input_size, result.output_size, result.ratio(),
+            throughput(if result.compressed { result.input_size } else { result.output_size }, start.elapsed()),
+        ),
+        LogLevel::Normal => println!("{} {} (discarded)", action, display_escaped(t)),
+        _ => {}
+    }
+    Outcome::Written(result)
+}
+
+/// Describes the speed of processing a file, like `142.3 MB/s in 29 ms`, from the size of the slp file
+/// and the time since the file was started, including reading and writing it.
+fn throughput(slp_size: usize, elapsed: std::time::Duration) -> String {
+    format!("{:.1} MB/s in {} ms", throughput_mbs(slp_size as u64, elapsed), elapsed.as_millis())
+}
+
+/// Megabytes of slp files processed per second.
+fn throughput_mbs(slp_bytes: u64, elapsed: std::time::Duration) -> f64 {
+    slp_bytes as f64 / elapsed.as_secs_f64().max(1e-6) / 1e6
+}
+
+/// Runs [`Options::spot_check`] on a compressed file if it is sampled. Returns false if the check failed,
+/// after stopping the batch.
+fn spot_check(options: &Options, batch: &Batch, t: &std::path::Path, slp: &[u8], slpz: &[u8]) -> bool {
+    let Some(spot_check) = options.spot_check else { return true };
+    if options.clip.is_some() || !spot_check.samples(t.strip_prefix(batch.root).unwrap_or(t)) { return true }
+
+    let trailer = if options.strip_trailer { trailer_len(slp).unwrap_or(0) } else { 0 };
+    let difference = first_difference(&slp[..slp.len() - trailer], slpz);
+    let mut result = batch.result();
+    result.spot_checked += 1;
+    let first_diff_byte = match difference {
+        Ok(None) => return true,
+        Ok(Some(i)) => i,
+        Err(_) => 0,
+    };
+
+    event!(error, first_diff_byte, "spot check failed");
+    eprintln!(
+        "Error compressing {}: spot check failed, the output differs from the input at byte {}. \
+        Stopping, as this is likely a bug in slpz. The input was kept and nothing was written for it.",
+        display_escaped(&t), first_diff_byte,
+    );
+    result.spot_check_failed.push(t.to_path_buf());
+    batch.stopped.store(true, std::sync::atomic::Ordering::Relaxed);
+    false
+}
+
+fn decompress_target(
+    d: &mut Decompressor,
+    options: &Options,
+    batch: &Batch,
+    t: &std::path::PathBuf,
+) -> Outcome {
+    let _span = span!(
+        "slpz.decompress_file",
+        path = %display_escaped(&t),
+        bytes_in = tracing::field::Empty,
+        bytes_out = tracing::field::Empty,
+    );
+    let start = std::time::Instant::now();
+    if let Some(Err(e)) = options.pre_hook.as_ref().map(|h| h.run(t)) {
+        event!(error, error = %e, "pre hook failed");
+        eprintln!("Error decompressing {}: {}", display_escaped(&t), e);
+        return Outcome::Failed;
+    }
+    let slpz = match std::fs::read(t) {
+        Ok(s) => s,
+        Err(e) => {
+            event!(error, error = %e, "read failed");
+            eprintln!("Error decompressing {}: cannot read input: {}", display_escaped(&t), io_reason(&e));
+            return Outcome::Failed;
+        }
+    };
+    record!(_span, "bytes_in", slpz.len());
+
+    if options.skip_already_decompressed && is_slp(&slpz) {
+        event!(info, "skipped (already decompressed)");
+        if options.log.prints_files() { println!("skipped {} (already decompressed)", display_escaped(&t)); }
+        return Outcome::Skipped;
+    }
+
+    match decompress(d, &slpz) {
+        Ok(slp) => {
+            record!(_span, "bytes_out", slp.len());
+            if skip_by_completeness(options, t, &slp) { return Outcome::Skipped }
+            let out = match output_path(options, batch.root, t, false) {
+                Some(out) if batch.dedupe.contains(t) => deduped_path(&out, &slpz),
+                // a misnamed file, such as an slpz file named .slp, would overwrite itself
+                Some(out) if out == *t && !options.in_place => {
+                    let out = deduped_path(&out, &slpz);
+                    if options.log.prints_files() {
+                        println!("warning: {} would overwrite itself, writing {}", display_escaped(&t), display_escaped(&out));
+                    }
+                    out
+                }
+                Some(out) => out,
+                None => {
+                    eprintln!("Error creating new filename for {}", display_escaped(&t));
+                    return Outcome::Failed;
+                }
+            };
+            #[cfg(feature = "gzip")]
+            let (slp, launcher_names) = match options.gzip_output {
+                true => (gzip::encode(&slp), false),
+                false => (slp, options.launcher_names),
+            };
+            #[cfg(not(feature = "gzip"))]
+            let launcher_names = options.launcher_names;
+            if options.null_output {
+                let result = FileResult {
+                    input: t.clone(),
+                    output: out,
+                    compressed: false,
+                    input_size: slpz.len(),
+                    output_size: slp.len(),
+                    fallback_level: None,
+                };
+                return discard_output(options, result, start);
+            }
+            if let Err(e) = create_output_dir(options, &out) {
+                eprintln!("Error decompressing {}: cannot create output directory: {}", display_escaped(&t), io_reason(&e));
+                return Outcome::Failed;
+            }
+            let out = match game_timestamp(&slp) {
+                Some(timestamp) if launcher_names => launcher_output_path(&out, &slp, &timestamp),
+                None if launcher_names => {
+                    if options.log.prints_files() { println!("warning: {} has no start time, keeping its name", display_escaped(&t)); }
+                    out
+                }
+                _ => out,
+            };
+            let out = if out != *t && out.exists() {
+                match resolve_conflict(options, &out, &slp) {
+                    Ok(Some(out)) => out,
+                    Ok(None) => {
+                        event!(info, "skipped (up to date)");
+                        if options.log.prints_files() { println!("skipped {} ({} is up to date)", display_escaped(&t), display_escaped(&out)); }
+                        return Outcome::Skipped;
+                    }
+                    Err(e) => {
+                        event!(error, error = %e, "conflicting output");
+                        eprintln!("Error decompressing {}: {}", display_escaped(&t), e);
+                        return Outcome::Failed;
+                    }
+                }
+            } else {
+                out
+            };
+            if !confirm_overwrite(options, t, &out) {
+                return Outcome::Skipped;
+            }
+            let id = batch.journal.as_ref().and_then(|_| gunzipped_replay_id(&slp));
+            let Ok(journal) = batch.start_journal(t, &out, id, "decompressing") else { return Outcome::Failed };
+            let progress = Progress::new(options, slp.len(), t);
+            let written = atomic_write_with(&out, &options.temp_suffix, |file| {
+                use std::io::Write;
+                for block in slp.chunks(options.buffer_size.max(1)) {
+                    file.write_all(block)?;
+                    progress.inc(block.len());
+                }
+                Ok(())
+            });
+            match written {
+                Ok(_) => {
+                    progress.finish(&format!("{}: {} -> {} bytes ({:.2}x)", display_escaped(&t), slpz.len(), slp.len(), ratio(slp.len(), slpz.len())));
+                    if let Some(Err(e)) = journal.as_ref().map(|j| j.written(false)) {
+                        event!(error, error = %e, "journal verification failed");
+                        eprintln!("Error decompressing {}: {}", display_escaped(&t), e);
+                        return Outcome::Failed;
+                    }
+                    let result = FileResult {
+                        input: t.clone(),
+                        output: out.clone(),
+                        compressed: false,
+                        input_size: slpz.len(),
+                        output_size: slp.len(),
+                        fallback_level: None,
+                    };
+                    if let Some(Err(e)) = options.post_hook.as_ref().map(|h| h.run(&result)) {
+                        event!(error, error = %e, "post hook failed");
+                        eprintln!("Error decompressing {}: {}", display_escaped(&t), e);
+                        return Outcome::Failed;
+                    }
+                    match options.log {
+                        LogLevel::Verbose => println!(
+                            "decompressed {} ({} -> {} bytes, {:.2}x, {})",
+                            display_escaped(&t), slpz.len(), slp.len(), ratio(slp.len(), slpz.len()),
+                            throughput(slp.len(), start.elapsed()),
+                        ),
+                        LogLevel::Normal => println!("decompressed {}", display_escaped(&t)),
+                        _ => {}
+                    }
+                    if !options.keep && out != *t && remove_input(options, batch, t, &out) {
+                        if let Some(ref journal) = journal { journal.removed() }
+                    }
+                    Outcome::Written(result)
+                }
+                Err(e) => {
+                    event!(error, error = %e, "write failed");
+                    eprintln!("Error decompressing {}: cannot write output {}: {}", display_escaped(&t), display_escaped(&out), io_reason(&e));
+                    Outcome::Failed
+                }
+            }
+        }
+        Err(e) => {
+            event!(error, error = %e, "decompression failed");
+            eprintln!("Error decompressing {}: {}", display_escaped(&t), e);
+            Outcome::Failed
+        }
+    }
+}
+
+/// A byte progress bar on stderr, shown if [`Options::progress`] is set. Does nothing without the `progress` feature.
+struct Progress {
+    #[cfg(feature = "progress")]
+    bar: Option<indicatif::ProgressBar>,
+}
+
+impl Progress {
+    fn new(options: &Options, len: usize, path: &std::path::Path) -> Progress {
+        #[cfg(feature = "progress")]
+        {
+            let bar = options.progress.then(|| {
+                let bar = indicatif::ProgressBar::with_draw_target(Some(len as u64), indicatif::ProgressDrawTarget::stderr());
+                if let Ok(style) = indicatif::ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} {percent}%") {
+                    bar.set_style(style.progress_chars("=> "));
+                }
+                bar.set_message(display_escaped(&path).to_string());
+                bar
+            });
+            Progress { bar }
+        }
+        #[cfg(not(feature = "progress"))]
+        {
+            let _ = (options, len, path);
+            Progress {}
+        }
+    }
+
+    fn inc(&self, n: usize) {
+        #[cfg(feature = "progress")]
+        if let Some(ref bar) = self.bar { bar.inc(n as u64); }
+        #[cfg(not(feature = "progress"))]
+        let _ = n;
+    }
+
+    /// Replaces the bar with a line on stderr.
+    fn finish(&self, message: &str) {
+        #[cfg(feature = "progress")]
+        if let Some(ref bar) = self.bar {
+            bar.finish_and_clear();
+            eprintln!("{}", message);
+        }
+        #[cfg(not(feature = "progress"))]
+        let _ = message;
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(ref bar) = self.bar { bar.finish_and_clear(); }
+    }
+}
+
+/// Reads a file, with progress based on the bytes read if [`Options::progress`] is set.
+/// Progress is updated after each block of [`Options::buffer_size`] bytes.
+fn read_with_progress(options: &Options, path: &std::path::Path) -> std::io::Result<(Vec<u8>, Progress)> {
+    use std::io::Read;
+
+    if !options.progress { return Ok((std::fs::read(path)?, Progress::new(options, 0, path))) }
+
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let progress = Progress::new(options, len, path);
+    let mut data = Vec::with_capacity(len);
+    loop {
+        let n = (&mut file).take(options.buffer_size.max(1) as u64).read_to_end(&mut data)?;
+        if n == 0 { break }
+        progress.inc(n);
+    }
+    Ok((data, progress))
+}
+
+/// The [`replay_id`] of an slp file, which may be gzipped with the `gzip` feature.
+fn gunzipped_replay_id(slp: &[u8]) -> Option<[u8; 32]> {
+    #[cfg(feature = "gzip")]
+    if gzip::is_gzip(slp) { return replay_id(&gzip::decode(slp).ok()?).ok() }
+    replay_id(slp).ok()
+}
+
+/// Decides where to write an slp file whose output path already exists, following [`Options::on_conflict`].
+///
+/// Returns None if the existing file is the same replay.
+fn resolve_conflict(
+    options: &Options,
+    out: &std::path::Path,
+    slp: &[u8],
+) -> Result<Option<std::path::PathBuf>, String> {
+    let existing = std::fs::read(out).map_err(|e| format!("could not read {}: {}", display_escaped(&out), e))?;
+    let id = gunzipped_replay_id(slp);
+    if id.is_some() && gunzipped_replay_id(&existing) == id { return Ok(None) }
+
+    match options.on_conflict {
+        OnConflict::Skip if options.confirm_overwrite.is_none() => Err(format!(
+            "{} exists with different content. Pass --force to overwrite it or --rename-conflicts to keep both",
+            display_escaped(&out),
+        )),
+        // asked before writing
+        OnConflict::Skip => Ok(Some(out.to_path_buf())),
+        OnConflict::Overwrite => {
+            if options.log.prints_files() && options.confirm_overwrite.is_none() {
+                println!("overwriting {} (different content)", display_escaped(&out));
+            }
+            Ok(Some(out.to_path_buf()))
+        }
+        OnConflict::Rename => {
+            let ex = out.extension().unwrap_or_default().to_string_lossy().into_owned();
+            let renamed = (1..)
+                .map(|n| out.with_extension(format!("conflict-{}.{}", n, ex)))
+                .find(|p| !p.exists())
+                .unwrap();
+            if options.log.prints_files() { println!("{} exists with different content, writing {}", display_escaped(&out), display_escaped(&renamed)); }
+            Ok(Some(renamed))
+        }
+    }
+}
+
+/// Runs [`Options::confirm_overwrite`] if writing `out` would replace an existing file other than the input `t`.
+///
+/// Returns false if the file should be skipped.
+fn confirm_overwrite(options: &Options, t: &std::path::Path, out: &std::path::Path) -> bool {
+    let Some(confirm) = &options.confirm_overwrite else { return true };
+    if out == t || !out.exists() { return true }
+    match confirm.run(out) {
+        Ok(()) => true,
+        Err(e) => {
+            event!(info, reason = %e, "skipped (not overwritten)");
+            if options.log.prints_files() { println!("skipped {} ({})", display_escaped(&t), e); }
+            false
+        }
+    }
+}
+
+pub const DEFAULT_TEMP_SUFFIX: &str = ".tmp";
+
+/// Writes a file so that it is either fully written or not written at all.
+///
+/// Uses [`DEFAULT_TEMP_SUFFIX`]. See [`atomic_write_with_suffix`].
+pub fn atomic_write(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    atomic_write_with_suffix(path, data, DEFAULT_TEMP_SUFFIX)
+}
+
+/// Writes a file so that it is either fully written or not written at all.
+///
+/// The data is written and synced to a temporary file in the same directory,
+/// named by appending `temp_suffix` to the file name, then renamed over `path`.
+/// Being in the same directory keeps the rename on one filesystem.
+/// The rename is atomic on POSIX. On Windows it replaces any existing file (MoveFileExW with MOVEFILE_REPLACE_EXISTING).
+/// If the existing file is open in another process, Windows refuses the rename,
+/// so the temporary file is copied over it instead, which is not atomic.
+pub fn atomic_write_with_suffix(
+    path: &std::path::Path,
+    data: &[u8],
+    temp_suffix: &str,
+) -> std::io::Result<()> {
+    use std::io::Write;
+    atomic_write_with(path, temp_suffix, |file| file.write_all(data))
+}
+
+/// Like [`atomic_write_with_suffix`], with the data written to the temporary file by `write`.
+fn atomic_write_with(
+    path: &std::path::Path,
+    temp_suffix: &str,
+    write_data: impl FnOnce(&mut std::fs::File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let temp = same_dir_temp_path(path, temp_suffix)?;
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&temp)?;
+        write_data(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+        rename_or_copy(&temp, path)
+    };
+
+    write().inspect_err(|_| { let _ = std::fs::remove_file(&temp); })
+}
+
+/// The temporary file for `dest`, next to it. A path without a parent, such as `game.slp`, is in the current directory.
+/// Returns an error for paths without a file name, such as `/` or `..`.
+fn same_dir_temp_path(dest: &std::path::Path, temp_suffix: &str) -> std::io::Result<std::path::PathBuf> {
+    let mut temp_name = dest.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_os_string();
+    temp_name.push(temp_suffix);
+    Ok(dest.with_file_name(temp_name))
+}
+
+/// Windows error when a file is open in another process.
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+/// Renames `from` over `to`, falling back to copying on Windows if `to` is open in another process.
+fn rename_or_copy(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if cfg!(windows) && e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => {
+            std::fs::copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        r => r,
+    }
+}
+
+/// Removes an input file following [`Options::removal`], printing the result.
+/// Removes an input after its output `out` was written. Failures are listed in [`BatchResult::not_removed`].
+/// Returns whether it was removed.
+fn remove_input(options: &Options, batch: &Batch, path: &std::path::Path, out: &std::path::Path) -> bool {
+    let removed = match options.removal {
+        RemovalMode::Trash => trash_file(path).map(|_| ()),
+        RemovalMode::Permanent => std::fs::remove_file(path),
+    };
+    match &removed {
+        Ok(()) if !options.log.prints_files() => {}
+        Ok(()) if options.removal == RemovalMode::Trash => println!("moved {} to the trash", display_escaped(&path)),
+        Ok(()) => println!("removed {}", display_escaped(&path)),
+        Err(e) => {
+            event!(error, path = %display_escaped(&path), error = %e, "cannot remove source");
+            batch.result().not_removed.push(path.to_path_buf());
+            let hint = match options.removal {
+                RemovalMode::Trash => ". Pass --rm-permanent to delete it instead",
+                RemovalMode::Permanent => "",
+            };
+            eprintln!(
+                "Error: cannot remove source {}: {}. {} was written, so both files exist{}",
+                display_escaped(&path), io_reason(e), display_escaped(&out), hint,
+            );
+        }
+    }
+    removed.is_ok()
+}
+
+/// Moves a file to the trash.
+///
+/// - Linux and other unix systems: the XDG trash, `$XDG_DATA_HOME/Trash` or `~/.local/share/Trash`.
+///   Returns the path of the file in the trash.
+///   Fails for files on another filesystem from the trash, rather than copying them.
+/// - macOS: `~/.Trash`. Returns the path of the file in the trash. "Put Back" in Finder is not supported.
+/// - Windows: the Recycle Bin. Returns the original path.
+///
+/// Returns an error with `ErrorKind::Unsupported` on other platforms.
+pub fn trash_file(path: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let trash = xdg_trash_dir(std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME"))?;
+        trash_file_xdg(path, &trash)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let trash = home_dir(std::env::var_os("HOME"))?.join(".Trash");
+        let name = path.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let dest = trash_names(name).map(|n| trash.join(n)).find(|p| !p.exists()).unwrap();
+        std::fs::rename(path, &dest)?;
+        Ok(dest)
+    }
+    #[cfg(windows)]
+    {
+        recycle(path).map(|_| path.to_path_buf())
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no trash on this platform"))
+    }
+}
+
+/// The home directory from the value of `HOME`.
+#[cfg(unix)]
+fn home_dir(home: Option<std::ffi::OsString>) -> std::io::Result<std::path::PathBuf> {
+    home
+        .filter(|h| !h.is_empty())
+        .map(std::path::PathBuf::from)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set"))
+}
+
+/// The XDG trash directory from the values of `XDG_DATA_HOME` and `HOME`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn xdg_trash_dir(data_home: Option<std::ffi::OsString>, home: Option<std::ffi::OsString>) -> std::io::Result<std::path::PathBuf> {
+    match data_home.filter(|d| !d.is_empty()) {
+        Some(data) => Ok(std::path::PathBuf::from(data).join("Trash")),
+        None => Ok(home_dir(home)?.join(".local/share/Trash")),
+    }
+}
+
+/// `name`, then `name.2`, `name.3`, ... with the number before the extension.
+#[cfg(unix)]
+fn trash_names(name: &std::ffi::OsStr) -> impl Iterator<Item = std::ffi::OsString> + '_ {
+    let path = std::path::Path::new(name);
+    (1..).map(move |n| {
+        if n == 1 { return name.to_os_string() }
+        let mut numbered = path.file_stem().unwrap_or(name).to_os_string();
+        numbered.push(format!(".{}", n));
+        if let Some(ext) = path.extension() {
+            numbered.push(".");
+            numbered.push(ext);
+        }
+        numbered
+    })
+}
+
+/// Moves a file into an XDG trash directory, following the FreeDesktop.org Trash specification.
+///
+/// The `.trashinfo` file is created first to reserve the name.
+/// The deletion date is written in UTC, as there is no local timezone without a dependency.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_file_xdg(path: &std::path::Path, trash: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+
+    let original = std::fs::canonicalize(path)?;
+    let name = original.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let files = trash.join("files");
+    let info = trash.join("info");
+    std::fs::create_dir_all(&files)?;
+    std::fs::create_dir_all(&info)?;
+
+    let mut encoded = String::new();
+    for &b in original.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let contents = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", encoded, GameTimestamp::from_unix_seconds(now));
+
+    for trash_name in trash_names(name) {
+        let dest = files.join(&trash_name);
+        let mut info_name = trash_name;
+        info_name.push(".trashinfo");
+        let info_path = info.join(info_name);
+        let mut info_file = match std::fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        if dest.exists() {
+            drop(info_file);
+            let _ = std::fs::remove_file(&info_path);
+            continue;
+        }
+
+        let moved = info_file.write_all(contents.as_bytes()).and_then(|_| std::fs::rename(path, &dest));
+        return match moved {
+            Ok(_) => Ok(dest),
+            Err(e) => {
+                let _ = std::fs::remove_file(&info_path);
+                Err(e)
+            }
+        };
+    }
+    unreachable!()
+}
+
+/// Sends a file to the Recycle Bin with SHFileOperationW.
+#[cfg(windows)]
+fn recycle(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+
+    // 32 bit Windows packs this struct to 1 byte
+    #[cfg_attr(target_pointer_width = "32", repr(C, packed(1)))]
+    #[cfg_attr(not(target_pointer_width = "32"), repr(C))]
+    #[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+    struct SHFILEOPSTRUCTW {
+        hwnd: *mut std::ffi::c_void,
+        wFunc: u32,
+        pFrom: *const u16,
+        pTo: *const u16,
+        fFlags: u16,
+        fAnyOperationsAborted: i32,
+        hNameMappings: *mut std::ffi::c_void,
+        lpszProgressTitle: *const u16,
+    }
+
+    #[link(name = "shell32")]
+    extern "system" {
+        fn SHFileOperationW(op: *mut SHFILEOPSTRUCTW) -> i32;
+    }
+
+    const FO_DELETE: u32 = 3;
+    const FOF_SILENT: u16 = 0x4;
+    const FOF_NOCONFIRMATION: u16 = 0x10;
+    const FOF_ALLOWUNDO: u16 = 0x40;
+    const FOF_NOERRORUI: u16 = 0x400;
+
+    // a list of paths ending with two nulls
+    let full = std::path::absolute(path)?;
+    let mut from = full.as_os_str().encode_wide().collect::<Vec<u16>>();
+    from.extend_from_slice(&[0, 0]);
+
+    let mut op = SHFILEOPSTRUCTW {
+        hwnd: std::ptr::null_mut(),
+        wFunc: FO_DELETE,
+        pFrom: from.as_ptr(),
+        pTo: std::ptr::null(),
+        fFlags: FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_SILENT | FOF_NOERRORUI,
+        fAnyOperationsAborted: 0,
+        hNameMappings: std::ptr::null_mut(),
+        lpszProgressTitle: std::ptr::null(),
+    };
+    // SAFETY: op is fully initialized and `from` outlives the call.
+    let result = unsafe { SHFileOperationW(&mut op) };
+    if result != 0 {
+        return Err(std::io::Error::other(format!("SHFileOperationW failed with {:#X}", result)))
+    }
+    if op.fAnyOperationsAborted != 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "moving to the Recycle Bin was cancelled"))
+    }
+    Ok(())
+}
+
+/// `exclude` is a canonicalized directory that is not walked, such as the output directory.
+fn get_targets(
+    targets: &mut Vec<std::path::PathBuf>,
+    path: &std::path::Path,
+    options: &Options,
+    compress: bool,
+    exclude: Option<&std::path::Path>,
+) -> Vec<std::path::PathBuf> {
+    let mut unreadable = Vec::new();
+    get_targets_in(targets, path, options, compress, exclude, &mut Vec::new(), &mut unreadable);
+    unreadable
+}
+
+/// Adds the directories that could not be read to `unreadable`, after printing an error for each.
+///
+/// `ancestors` holds the canonical paths of the directories being walked when following symlinks.
+fn get_targets_in(
+    targets: &mut Vec<std::path::PathBuf>,
+    path: &std::path::Path,
+    options: &Options,
+    compress: bool,
+    exclude: Option<&std::path::Path>,
+    ancestors: &mut Vec<std::path::PathBuf>,
+    unreadable: &mut Vec<std::path::PathBuf>,
+) {
+    if options.follow_symlinks {
+        let Ok(canonical) = std::fs::canonicalize(path) else { return };
+        if ancestors.contains(&canonical) { return }
+        ancestors.push(canonical);
+    }
+
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            event!(error, path = %display_escaped(&path), error = %e, "cannot read directory");
+            eprintln!("Error: cannot read directory {}: {}", display_escaped(&path), io_reason(&e));
+            if options.follow_symlinks { ancestors.pop(); }
+            unreadable.push(path.to_path_buf());
+            return;
+        }
+    };
+    for f in entries {
+        let f = match f {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+
+        // filter before descending so hidden trees are never walked
+        let name = f.file_name();
+        if options.skip_hidden && name.as_encoded_bytes().starts_with(b".") { continue }
+        // partially written outputs. See atomic_write_with_suffix
+        if !options.temp_suffix.is_empty() && name.as_encoded_bytes().ends_with(options.temp_suffix.as_bytes()) { continue }
+
+        let path = f.path();
+
+        if !options.follow_symlinks && f.file_type().is_ok_and(|t| t.is_symlink()) {
+            event!(debug, path = %display_escaped(&path), "skipped (symlink)");
+            continue;
+        }
+
+        if options.recursive && path.is_dir() {
+            if exclude.is_some() && std::fs::canonicalize(&path).ok().as_deref() == exclude { continue }
+            get_targets_in(targets, &path, options, compress, exclude, ancestors, unreadable);
+        }
+        if path.is_file() && options.extensions.direction(&path) == Some(compress) { targets.push(path) }
+    }
+
+    if options.follow_symlinks { ancestors.pop(); }
+}
+
+/// Displays a path in messages like [`std::path::Path::display`], with control characters escaped.
+///
+/// Newlines, tabs and escape sequences in file names are written as `\n`, `\t` and `\u{1b}`, so walking a directory
+/// with unusual names cannot split a log line or control the terminal. Invalid UTF-8 is replaced with U+FFFD.
+pub fn display_escaped<P: AsRef<std::path::Path> + ?Sized>(path: &P) -> EscapedPath<'_> {
+    EscapedPath(path.as_ref())
+}
+
+/// A path displayed with control characters escaped. See [`display_escaped`].
+#[derive(Copy, Clone, Debug)]
+pub struct EscapedPath<'a>(&'a std::path::Path);
+
+impl std::fmt::Display for EscapedPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        for c in self.0.to_string_lossy().chars() {
+            if c.is_control() {
+                write!(f, "{}", c.escape_debug())?;
+            } else {
+                f.write_char(c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes an io error briefly, like "permission denied", for messages that already name the operation.
+fn io_reason(e: &std::io::Error) -> String {
+    use std::io::ErrorKind::*;
+    match e.kind() {
+        kind @ (PermissionDenied | ReadOnlyFilesystem | StorageFull) => kind.to_string(),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    /// Counts the bytes allocated by each thread, for measuring peak memory use.
+    struct CountingAllocator;
+
+    thread_local! {
+        /// (allocated, peak allocated)
+        static ALLOCATED: std::cell::Cell<(usize, usize)> = const { std::cell::Cell::new((0, 0)) };
+    }
+
+    fn track_allocation(change: isize) {
+        let _ = ALLOCATED.try_with(|a| {
+            let (allocated, peak) = a.get();
+            let allocated = allocated.saturating_add_signed(change);
+            a.set((allocated, peak.max(allocated)));
+        });
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let ptr = unsafe { std::alloc::System.alloc(layout) };
+            if !ptr.is_null() { track_allocation(layout.size() as isize); }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) };
+            track_allocation(-(layout.size() as isize));
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = unsafe { std::alloc::System.realloc(ptr, layout, new_size) };
+            if !new_ptr.is_null() { track_allocation(new_size as isize - layout.size() as isize); }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Returns the peak bytes allocated on this thread while running `f`, above what was allocated before.
+    fn peak_allocated<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let start = ALLOCATED.with(|a| {
+            let (allocated, _) = a.get();
+            a.set((allocated, allocated));
+            allocated
+        });
+        let r = f();
+        (r, ALLOCATED.with(|a| a.get().1) - start)
+    }
+
+    /// Builds a Game Start event. Fox vs Falco on Final Destination.
+    fn test_game_start() -> Vec<u8> {
+        let mut game_start = vec![0u8; TEST_GAME_START_SIZE as usize + 1];
+        game_start[0] = 0x36;
+        game_start[1..5].copy_from_slice(&[3, 18, 0, 0]); // version
+        game_start[0x13..0x15].copy_from_slice(&32u16.to_be_bytes());
+        for port in 0..4 {
+            game_start[0x66 + 0x24 * port] = 3;
+        }
+        game_start[0x65] = 2;
+        game_start[0x66] = 0;
+        game_start[0x65 + 0x24] = 20;
+        game_start[0x66 + 0x24] = 0;
+        game_start
+    }
+
+    const TEST_GAME_START_SIZE: u16 = 0x1A4;
+
+    fn round_trips(slp: &[u8], slpz: &[u8]) -> bool {
+        first_difference(slp, slpz) == Ok(None)
+    }
+
+    /// Builds a small but valid slp file.
+    fn test_slp() -> Vec<u8> {
+        test_slp_with(&[], &[])
+    }
+
+    /// `extra_events` is placed before the game end event, with its sizes added to the Event Payloads event.
+    fn test_slp_with(extra_sizes: &[(u8, u16)], extra_events: &[u8]) -> Vec<u8> {
+        let mut sizes: Vec<(u8, u16)> = vec![(0x36, TEST_GAME_START_SIZE), (0x37, 4), (0x38, 6), (0x39, 2)];
+        sizes.extend_from_slice(extra_sizes);
+
+        let mut raw = vec![EVENT_PAYLOADS, 1 + 3 * sizes.len() as u8];
+        for (command, size) in sizes {
+            raw.push(command);
+            raw.extend_from_slice(&size.to_be_bytes());
+        }
+
+        raw.extend_from_slice(&test_game_start());
+        for frame in 0..100u8 {
+            raw.extend_from_slice(&[0x37, 0, 0, 0, frame]);
+            raw.extend_from_slice(&[0x38, 0, 0, 0, frame, 20, 8]);
+        }
+        raw.extend_from_slice(extra_events);
+        raw.extend_from_slice(&[0x39, 2, 0]);
+
+        let mut slp = RAW_HEADER.to_vec();
+        slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+        slp.extend_from_slice(&raw);
+        slp.extend_from_slice(&[0x55, 0x08]);
+        slp.extend_from_slice(b"metadata");
+        slp.extend_from_slice(b"{}}");
+        slp
+    }
+
+    #[test]
+    fn target_ratio_levels() {
+        let slp = test_slp();
+        let mut compressor = Compressor::new(7).unwrap();
+        assert_eq!(compress_level_for_target_ratio(&mut compressor, &slp, 1.0), Ok(1));
+        let best = compress_level_for_target_ratio(&mut compressor, &slp, 1e9).unwrap();
+        assert!(TARGET_RATIO_LEVELS.contains(&best));
+        assert_eq!(compressor.level(), 7);
+        let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+        assert_eq!(SlpzHeader::parse(&slpz).unwrap().level, Some(7));
+        assert_eq!(compress_level_for_target_ratio(&mut compressor, b"not slp", 2.0), Err(CompError::NotSlp { detected: None }));
+    }
+
+    #[test]
+    fn event_counts_of_test_slp() {
+        let counts = event_counts(&test_slp()).unwrap();
+        assert_eq!((counts[0x36], counts[0x37], counts[0x38], counts[0x39]), (0, 100, 100, 1));
+        assert_eq!(counts.iter().sum::<u32>(), 201);
+        assert_eq!(event_counts(b"not slp"), Err(CompError::NotSlp { detected: None }));
+    }
+
+    #[test]
+    fn compress_all_in_order() {
+        let slp = test_slp();
+        let inputs = [slp.as_slice(), b"not slp", slp.as_slice()];
+        let compressed = compress_all(&mut Compressor::new(3).unwrap(), inputs);
+        assert_eq!(compressed[1], Err(CompError::NotSlp { detected: None }));
+
+        let slpzs = compressed.iter().flatten().map(Vec::as_slice).collect::<Vec<_>>();
+        let decompressed = decompress_all(&mut Decompressor::new().unwrap(), slpzs);
+        assert_eq!(decompressed, [Ok(slp.clone()), Ok(slp)]);
+    }
+
+    #[test]
+    fn thread_local_compressors() {
+        let slps = (0..8u8).map(|i| test_slp_with(&[], &[0x39, i, 0])).collect::<Vec<_>>();
+        let expected = slps.iter().map(|slp| compress_unchecked(&mut Compressor::new(5).unwrap(), slp).unwrap()).collect::<Vec<_>>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| for (slp, expected) in slps.iter().zip(&expected) {
+                    for level in [5, 3, 5] {
+                        let slpz = with_thread_local_compressor(level, |c| {
+                            assert_eq!(c.level, level);
+                            compress_unchecked(c, slp).unwrap()
+                        });
+                        if level == 5 { assert_eq!(slpz.as_ref(), Some(expected)) }
+                    }
+                });
+            }
+        });
+        assert_eq!(with_thread_local_compressor(COMPRESS_LEVEL_MAX + 1, |_| ()), None);
+
+        // settings changed by one caller are not seen by the next on the same thread
+        with_thread_local_compressor(5, |c| {
+            c.set_strategy(ZstdStrategy::Btultra2).unwrap();
+            c.set_target_length(999).unwrap();
+            c.set_checksum(false).unwrap();
+            c.set_verify(true);
+            c.set_segments(4);
+            c.set_chunk_bytes(100);
+            c.set_strip_trailer(true);
+            c.set_strictness(Strictness::Strict);
+            c.set_time_budget(Some(std::time::Duration::ZERO));
+        }).unwrap();
+        with_thread_local_compressor(5, |c| {
+            assert_eq!(c.params, default_params());
+            assert_eq!(c.strictness, Strictness::Normal);
+            assert!(!c.strip_trailer && !c.verify);
+            assert_eq!((c.chunk_bytes, c.segments, c.time_budget), (DEFAULT_CHUNK_BYTES, 1, None));
+            assert_eq!(&compress_unchecked(c, &slps[0]).unwrap(), &expected[0]);
+        }).unwrap();
+
+        let inputs = slps.iter().map(Vec::as_slice).chain([b"not slp".as_slice()]).collect::<Vec<_>>();
+        let compressed = compress_batch_parallel(5, &inputs);
+        assert_eq!(compressed[..8], expected.into_iter().map(Ok).collect::<Vec<_>>());
+        assert_eq!(compressed[8], Err(CompError::NotSlp { detected: None }));
+    }
+
+    #[test]
+    fn round_trip() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let decompressed = decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap();
+        assert_eq!(slp, decompressed);
+    }
+
+    #[test]
+    fn placeholder_raw_len() {
+        let slp = test_slp();
+
+        for placeholder in [0u32, u32::MAX] {
+            let mut unfinalized = slp.clone();
+            unfinalized[11..15].copy_from_slice(&placeholder.to_be_bytes());
+
+            let mut compressor = Compressor::new(3).unwrap();
+            let slpz = compress_unchecked(&mut compressor, &unfinalized).unwrap();
+            let decompressed = decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap();
+            assert_eq!(slp, decompressed);
+
+            compressor.set_strictness(Strictness::Strict);
+            assert_eq!(compress_unchecked(&mut compressor, &unfinalized), Err(CompError::PlaceholderRawLen));
+        }
+    }
+
+    #[test]
+    fn event_sizes_of_slpz() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let sizes = slpz_event_sizes(&slpz).unwrap();
+        assert_eq!(sizes, split_slp(&slp, Strictness::Normal).unwrap().event_sizes);
+        // without the compressed events
+        let header = SlpzHeader::parse(&slpz).unwrap();
+        assert_eq!(slpz_event_sizes(&slpz[..header.compressed_events_offset]), Ok(sizes));
+    }
+
+    #[test]
+    fn slpz_view() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let view = SlpzView::new(&slpz).unwrap();
+        let header = view.header();
+        assert_eq!(header, SlpzHeader::parse(&slpz).unwrap());
+        assert_eq!(Some(view.metadata()), read_metadata(&slpz));
+        assert_eq!(view.compressed_data().len(), slpz.len() - header.compressed_events_offset);
+        assert_eq!(view.decompress(&mut Decompressor::new().unwrap()).unwrap(), slp);
+
+        // the events are not checked until decompressed
+        let view = SlpzView::new(&slpz[..header.compressed_events_offset + 1]).unwrap();
+        assert!(view.decompress(&mut Decompressor::new().unwrap()).is_err());
+        assert!(SlpzView::new(&slp).is_err());
+    }
+
+    #[test]
+    fn raw_len_near_4gib() {
+        let slp = test_slp();
+        let mut huge = slp.clone();
+        let raw_len = u32::MAX - 1;
+        huge[11..15].copy_from_slice(&raw_len.to_be_bytes());
+
+        let mut compressor = Compressor::new(3).unwrap();
+        #[cfg(target_pointer_width = "32")]
+        assert_eq!(compress_unchecked(&mut compressor, &huge), Err(CompError::FileTooLarge { size: 15 + raw_len as u64 }));
+        #[cfg(target_pointer_width = "64")]
+        assert!(matches!(compress_unchecked(&mut compressor, &huge), Err(CompError::Corrupt { detail: CorruptDetail::Truncated, .. })));
+
+        // past the end of the file on any target
+        assert!(validate_slp(&huge).issues.iter().any(|i| matches!(i, SlpIssue::RawLenOutOfBounds { .. })));
+        compressor.set_strictness(Strictness::Lenient);
+        let slpz = compress_unchecked(&mut compressor, &huge).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+    }
+
+    #[test]
+    fn compression_levels() {
+        assert_eq!(COMPRESS_LEVEL_MIN, zstd::zstd_safe::min_c_level());
+        assert_eq!(COMPRESS_LEVEL_MAX, zstd::zstd_safe::max_c_level());
+
+        let slp = test_slp();
+        for level in [COMPRESS_LEVEL_MIN, -1, LEVEL_STORE, 1, 19, COMPRESS_LEVEL_MAX] {
+            let slpz = compress_unchecked(&mut Compressor::new(level).unwrap(), &slp).unwrap();
+            assert_eq!(SlpzHeader::parse(&slpz).unwrap().level, Some(level));
+            assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+        }
+        for level in [i32::MIN, COMPRESS_LEVEL_MIN - 1, COMPRESS_LEVEL_MAX + 1, i32::MAX] {
+            assert!(Compressor::new(level).is_none());
+            assert!(options_compressor(&Options { level, ..Options::DEFAULT }).is_none());
+        }
+    }
+
+    #[test]
+    fn strategy_and_target_length() {
+        let slp = test_slp();
+        let (mut memory, mut sizes) = (Vec::new(), Vec::new());
+        for strategy in [ZstdStrategy::Fast, ZstdStrategy::Lazy2, ZstdStrategy::Btultra2] {
+            let mut compressor = Compressor::with_params(3, 64, strategy).unwrap();
+            let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+            assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+            memory.push(compressor.estimated_memory());
+            sizes.push(slpz.len());
+
+            let built = CompressorBuilder::new(3).target_length(64).strategy(strategy).build().unwrap();
+            assert_eq!(built.params, compressor.params);
+
+            // segments use the same parameters
+            compressor.set_segments(2);
+            compressor.set_chunk_bytes(slp.len() / 4);
+            let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+            assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+        }
+        assert!(memory[0] < memory[2], "{:?}", memory);
+        // stronger strategies compress better, as documented on set_strategy and set_target_length
+        assert!(sizes[0] > sizes[1] && sizes[1] >= sizes[2], "{:?}", sizes);
+        assert!(Compressor::with_params(COMPRESS_LEVEL_MAX + 1, 0, ZstdStrategy::Fast).is_none());
+    }
+
+    #[test]
+    fn compressor_builder() {
+        let slp = test_slp();
+        let builder = CompressorBuilder::new(19).checksum(false).verify(true).segments(2).chunk_bytes(slp.len() / 4);
+        let mut compressor = builder.build().unwrap();
+        assert_eq!(compressor.level(), 19);
+        assert!((compressor.verify, compressor.segments, compressor.chunk_bytes) == (true, 2, slp.len() / 4));
+        let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+
+        assert_eq!(CompressorBuilder::default().build().unwrap().params, default_params());
+        let invalid = builder.level(COMPRESS_LEVEL_MAX + 1).build().err();
+        assert_eq!(invalid, Some(ZstdInitError::InvalidLevel { level: COMPRESS_LEVEL_MAX + 1 }));
+        assert_eq!(SlpzError::from(invalid.unwrap()).kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn strictness() {
+        let slp = test_slp();
+
+        let mut placeholder = slp.clone();
+        placeholder[11..15].copy_from_slice(&0u32.to_be_bytes());
+        let mut out_of_bounds = slp.clone();
+        out_of_bounds[11..15].copy_from_slice(&(slp.len() as u32).to_be_bytes());
+        let truncated = CompError::Corrupt { offset: 15, detail: CorruptDetail::Truncated };
+        let unknown = test_slp_with(&[(0x7F, 2)], &[0x7F, 1, 2]);
+        let mut trailing = slp.clone();
+        trailing.extend_from_slice(b"junk");
+        let missing = slp[..slp.len() - 13].to_vec();
+
+        use Strictness::*;
+        let cases = [
+            (placeholder.as_slice(), [Ok(()), Ok(()), Err(CompError::PlaceholderRawLen)]),
+            (&out_of_bounds[..], [Ok(()), Err(truncated), Err(truncated)]),
+            (&unknown[..], [Ok(()), Ok(()), Err(CompError::UnknownEvent { command: 0x7F })]),
+            (&trailing[..], [Ok(()), Ok(()), Err(CompError::TrailingData { len: 4 })]),
+            (&missing[..], [Ok(()), Ok(()), Err(CompError::MissingMetadata)]),
+        ];
+        for (input, expected) in cases {
+            for (strictness, expected) in [Lenient, Normal, Strict].into_iter().zip(expected) {
+                let mut compressor = Compressor::new(3).unwrap();
+                compressor.set_strictness(strictness);
+                compressor.set_verify(true);
+                assert_eq!(compress_unchecked(&mut compressor, input).map(|_| ()), expected, "{:?}", strictness);
+            }
+        }
+        assert_eq!(compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).map(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn compressor_pool_reuse() {
+        let pool = Compressor::new(5).unwrap().into_pool();
+        assert_eq!(pool.idle_count(), 1);
+
+        let other = pool.take(3).unwrap();
+        assert_eq!(other.level(), 3);
+        assert_eq!(pool.idle_count(), 1);
+
+        let reused = pool.take(5).unwrap();
+        assert_eq!(reused.level(), 5);
+        assert_eq!(pool.idle_count(), 0);
+
+        pool.put(reused);
+        pool.put(other);
+        assert_eq!(pool.idle_count(), 2);
+
+        let pool = CompressorPool::prefilled(3, 4).unwrap();
+        let slpz = pool.with_compressor(3, |c| compress_unchecked(c, &test_slp()).unwrap()).unwrap();
+        assert_eq!(pool.idle_count(), 4);
+        assert!(round_trips(&test_slp(), &slpz));
+        pool.with_compressor(9, |c| assert_eq!(c.level(), 9)).unwrap();
+        assert_eq!(pool.idle_count(), 5);
+
+        // settings changed by one caller are not seen by the next
+        let pool = CompressorPool::new();
+        let default = pool.with_compressor(3, |c| compress_unchecked(c, &test_slp()).unwrap()).unwrap();
+        pool.with_compressor(3, |c| {
+            c.set_strategy(ZstdStrategy::Btultra2).unwrap();
+            c.set_target_length(999).unwrap();
+            c.set_checksum(false).unwrap();
+            c.set_verify(true);
+            c.set_segments(4);
+            c.set_chunk_bytes(100);
+            c.set_strip_trailer(true);
+            c.set_strictness(Strictness::Strict);
+            c.set_time_budget(Some(std::time::Duration::ZERO));
+        }).unwrap();
+        assert_eq!(pool.idle_count(), 1);
+        pool.with_compressor(3, |c| {
+            assert_eq!(c.params, default_params());
+            assert_eq!(c.strictness, Strictness::Normal);
+            assert!(!c.strip_trailer && !c.verify);
+            assert_eq!((c.chunk_bytes, c.segments, c.time_budget), (DEFAULT_CHUNK_BYTES, 1, None));
+            assert_eq!(compress_unchecked(c, &test_slp()).unwrap(), default);
+        }).unwrap();
+    }
+
+    #[test]
+    fn chunked_compression() {
+        let mut frames = Vec::new();
+        for frame in 0..200_000u32 {
+            let f = frame.to_be_bytes();
+            frames.extend_from_slice(&[0x37, f[0], f[1], f[2], f[3]]);
+            frames.extend_from_slice(&[0x38, f[0], f[1], f[2], f[3], 20, (frame % 7) as u8]);
+        }
+        let slp = test_slp_with(&[], &frames);
+
+        let mut compressor = Compressor::new(3).unwrap();
+        let (single, single_peak) = peak_allocated(|| compress_unchecked(&mut compressor, &slp).unwrap());
+        assert_eq!(SlpzHeader::parse(&single).unwrap().flags & FLAG_CHUNKED, 0);
+        assert!(single_peak > slp.len());
+
+        compressor.set_chunk_bytes(64 << 10);
+        let (chunked, chunked_peak) = peak_allocated(|| compress_unchecked(&mut compressor, &slp).unwrap());
+        assert!(chunked_peak < slp.len() / 4, "peak {} for {} byte input", chunked_peak, slp.len());
+
+        let header = SlpzHeader::parse(&chunked).unwrap();
+        assert_eq!(header.flags & (FLAG_CHUNKED | FLAG_SEGMENTED), FLAG_CHUNKED);
+        assert_eq!(header.chunk_count, header.chunks(&chunked).len());
+        assert!(header.chunk_count > 30);
+        assert!(validate_slpz(&chunked).is_ok());
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &chunked).unwrap(), slp);
+        assert_eq!(replay_id(&chunked), replay_id(&single));
+
+        // stored chunks, and recompressing keeps the chunks
+        let mut store = Compressor::new(LEVEL_STORE).unwrap();
+        store.set_chunk_bytes(64 << 10);
+        let stored = compress_unchecked(&mut store, &slp).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &stored).unwrap(), slp);
+        let recompressed = recompress(&mut Decompressor::new().unwrap(), &mut Compressor::new(5).unwrap(), &stored).unwrap();
+        assert_eq!(SlpzHeader::parse(&recompressed).unwrap().chunk_count, header.chunk_count);
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &recompressed).unwrap(), slp);
+
+        // chunk table sizes must add up to the section sizes
+        let mut corrupt = chunked.clone();
+        let entry = header.chunk_table_offset.unwrap();
+        corrupt[entry + 7] ^= 1;
+        assert_eq!(SlpzHeader::parse(&corrupt), Err(DecompError::InvalidFile));
+
+        // small replays are unchanged
+        assert_eq!(compress_unchecked(&mut compressor, &test_slp()), compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()));
+    }
+
+    #[test]
+    fn progress_single_file() {
+        let dir = test_dir("progress");
+        let mut slp = test_slp();
+        slp.resize(3 * Options::DEFAULT_BUFFER_SIZE + 17, 0);
+        std::fs::write(dir.join("game.slp"), &slp).unwrap();
+
+        let options = Options { log: LogLevel::Silent, progress: true, settle_ms: 0, ..Options::DEFAULT };
+        let (read, _) = read_with_progress(&options, &dir.join("game.slp")).unwrap();
+        assert_eq!(read, slp);
+
+        target_path(&Options { compress: Some(true), ..options.clone() }, &dir.join("game.slp"), None).unwrap();
+        std::fs::remove_file(dir.join("game.slp")).unwrap();
+        target_path(&Options { compress: Some(false), ..options }, &dir.join("game.slpz"), None).unwrap();
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), slp);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn original_size() {
+        let mut slp = test_slp();
+        slp.extend_from_slice(b"trailer");
+        for level in [LEVEL_STORE, 3] {
+            let slpz = compress_unchecked(&mut Compressor::new(level).unwrap(), &slp).unwrap();
+            assert_eq!(slpz_original_size(&slpz), Ok(slp.len() as u64));
+            assert_eq!(slpz_original_size(&slpz[..40]), Err(DecompError::TruncatedInput));
+        }
+
+        let mut compressor = Compressor::new(3).unwrap();
+        compressor.set_chunk_bytes(100);
+        let chunked = compress_unchecked(&mut compressor, &slp).unwrap();
+        assert!(SlpzHeader::parse(&chunked).unwrap().chunk_count > 1);
+        assert_eq!(slpz_original_size(&chunked), Ok(slp.len() as u64));
+
+        assert_eq!(slpz_original_size(&[0; 10]), Err(DecompError::TruncatedInput));
+        assert!(matches!(slpz_original_size(&slp), Err(DecompError::NotSlpz { .. })));
+    }
+
+    #[test]
+    fn segments() {
+        let slp = test_slp();
+        let single = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let mut decompressor = Decompressor::new().unwrap();
+        decompressor.set_threads(3);
+
+        for segments in [1, 3, 7, 1000] {
+            let mut compressor = Compressor::new(3).unwrap();
+            compressor.set_segments(segments);
+            compressor.set_checksum(false).unwrap();
+            compressor.set_verify(true);
+            let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+            let header = SlpzHeader::parse(&slpz).unwrap();
+            if segments == 1 {
+                assert_eq!(header.chunk_count, 1);
+            } else {
+                assert!(header.chunk_count >= segments.min(201));
+                assert_eq!(header.flags & (FLAG_CHUNKED | FLAG_SEGMENTED), FLAG_CHUNKED | FLAG_SEGMENTED);
+            }
+            assert_eq!(decompress(&mut decompressor, &slpz).unwrap(), slp);
+            assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+            assert_eq!(validate_slpz(&slpz), SlpValidationReport::default());
+        }
+
+        // the default stays a single segment, and per-segment contexts keep the zstd settings
+        let mut compressor = options_compressor(&Options::DEFAULT).unwrap();
+        assert_eq!(compress_unchecked(&mut compressor, &slp).unwrap(), single);
+        compressor.set_segments(2);
+        compressor.set_checksum(false).unwrap();
+        let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+        let header = SlpzHeader::parse(&slpz).unwrap();
+        let (first_size, _) = header.chunks(&slpz)[0];
+        let first = &slpz[header.compressed_events_offset..][..first_size];
+        assert_eq!(first[4] & 0x04, 0); // checksum flag in the frame header descriptor
+        assert_eq!(header.flags & FLAG_SEGMENTED, FLAG_SEGMENTED);
+        let recompressed = recompress(&mut decompressor, &mut Compressor::new(1).unwrap(), &slpz).unwrap();
+        assert_eq!(SlpzHeader::parse(&recompressed).unwrap().flags, header.flags);
+
+        // only chunked files are segmented
+        let mut unchunked = single.clone();
+        let flags = u32::from_be_bytes(single[24..28].try_into().unwrap()) | FLAG_SEGMENTED;
+        unchunked[24..28].copy_from_slice(&flags.to_be_bytes());
+        assert_eq!(SlpzHeader::parse(&unchunked), Err(DecompError::InvalidFile));
+    }
+
+    #[test]
+    fn decompress_sink() {
+        let mut frames = Vec::new();
+        for frame in 0..20_000u32 {
+            frames.push(0x37);
+            frames.extend_from_slice(&frame.to_be_bytes());
+        }
+        let large = test_slp_with(&[], &frames);
+        let mut chunked = Compressor::new(3).unwrap();
+        chunked.set_chunk_bytes(16 << 10);
+        let mut decompressor = Decompressor::new().unwrap();
+
+        for (slp, slpz) in [
+            (test_slp(), compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap()),
+            (test_slp(), compress_unchecked(&mut Compressor::new(LEVEL_STORE).unwrap(), &test_slp()).unwrap()),
+            (large.clone(), compress_unchecked(&mut chunked, &large).unwrap()),
+        ] {
+            let stats = decompress_to_sink(&mut decompressor, &slpz, &mut std::io::sink()).unwrap();
+            let decompressed = decompress(&mut decompressor, &slpz).unwrap();
+            assert_eq!(stats.replay_id, replay_id(&decompressed).unwrap());
+            assert_eq!(Some(stats.replay_id), read_replay_id(&slpz));
+            assert_eq!(stats.bytes_out, slp.len() as u64);
+            assert_eq!(stats.bytes_in, slpz.len());
+
+            for buffer_size in [1, 100, Options::DEFAULT_BUFFER_SIZE] {
+                let mut written = Vec::new();
+                decompressor.set_buffer_size(buffer_size);
+                decompress_to_sink(&mut decompressor, &slpz, &mut written).unwrap();
+                assert_eq!(written, decompressed);
+            }
+        }
+
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap();
+        let mut full = [0u8; 20];
+        assert_eq!(decompress_to_sink(&mut decompressor, &slpz, &mut &mut full[..]), Err(DecompError::WriteFailed));
+        assert!(decompress_to_sink(&mut decompressor, &slpz[..slpz.len() - 1], &mut std::io::sink()).is_err());
+    }
+
+    #[test]
+    fn tee_output() {
+        use sha2::Digest;
+
+        let slp = test_slp();
+        let mut teed = WriteLog::default();
+        let slpz = compress_tee(&mut Compressor::new(3).unwrap(), &slp, &mut teed).unwrap();
+        assert_eq!(teed.data, slpz);
+        // the header is written before the events are compressed
+        assert_eq!(teed.writes[0], SlpzHeader::parse(&slpz).unwrap().compressed_events_offset);
+
+        for level in [LEVEL_STORE, 3] {
+            let mut compressor = CompressorBuilder::new(level).chunk_bytes(slp.len() / 4).build().unwrap();
+            let mut teed = WriteLog::default();
+            let slpz = compress_tee(&mut compressor, &slp, &mut teed).unwrap();
+            assert!(SlpzHeader::parse(&slpz).unwrap().chunk_count > 1);
+            assert_eq!((teed.data, teed.writes.len()), (slpz, 1));
+        }
+
+        let mut decompressor = Decompressor::new().unwrap();
+        decompressor.set_buffer_size(100);
+        let mut teed = Vec::new();
+        let decompressed = decompress_tee(&mut decompressor, &slpz, &mut teed).unwrap();
+        assert_eq!(decompressed, slp);
+        assert_eq!(teed, decompressed);
+
+        let mut hasher = sha2::Sha256::new();
+        let slpz = compress_tee(&mut Compressor::new(3).unwrap(), &slp, &mut hasher).unwrap();
+        assert_eq!(hasher.finalize(), sha2::Sha256::digest(&slpz));
+
+        let mut full = [0u8; 20];
+        let e = compress_tee(&mut Compressor::new(3).unwrap(), &slp, &mut &mut full[..]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Io);
+        let e = decompress_tee(&mut decompressor, &slpz, &mut &mut full[..]).unwrap_err();
+        assert!(matches!(e, SlpzError::Io(_)), "{:?}", e);
+        let e = decompress_tee(&mut decompressor, &slpz[..slpz.len() - 1], &mut Vec::new()).unwrap_err();
+        assert!(matches!(e, SlpzError::Decompress(_)), "{:?}", e);
+    }
+
+    /// Records what is written, and the length of each write.
+    #[derive(Default)]
+    struct WriteLog {
+        data: Vec<u8>,
+        writes: Vec<usize>,
+    }
+
+    impl std::io::Write for WriteLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            self.writes.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn header_ordering() {
+        let small = SlpzHeader::parse(&compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap()).unwrap();
+        let large = SlpzHeader::parse(&compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp_with(&[], &[0x39, 0, 0])).unwrap()).unwrap();
+        assert!(small < large);
+
+        let mut index = std::collections::BTreeMap::new();
+        index.insert((game_timestamp(&with_start_at("2024-03-09T18:30:12Z")), large), "b");
+        index.insert((game_timestamp(&with_start_at("2023-01-01T00:00:00Z")), large), "a");
+        index.insert((None, small), "c");
+        assert_eq!(index.values().copied().collect::<Vec<_>>(), ["c", "a", "b"]);
+
+        let set = [small, large, small].into_iter().collect::<std::collections::HashSet<_>>();
+        assert_eq!(set.len(), 2);
+    }
+
+    fn with_start_at(start_at: &str) -> Vec<u8> {
+        let mut slp = test_slp();
+        slp.truncate(slp.len() - 3);
+        slp.extend_from_slice(b"{U\x07startAtSU");
+        slp.push(start_at.len() as u8);
+        slp.extend_from_slice(start_at.as_bytes());
+        slp.extend_from_slice(b"}}");
+        slp
+    }
+
+    #[test]
+    fn launcher_names() {
+        let slp = with_start_at("2024-03-09T18:30:12.504Z");
+        let timestamp = game_timestamp(&slp).unwrap();
+        assert_eq!(timestamp.to_string(), "2024-03-09T18:30:12");
+        assert_eq!(timestamp.launcher_name(), "Game_20240309T183012.slp");
+        assert_eq!(GameTimestamp::parse_launcher_name("Game_20240309T183012_1.slp"), Some(timestamp));
+        assert_eq!(GameTimestamp::parse_launcher_name("replay.slp"), None);
+        assert_eq!(game_timestamp(&test_slp()), None);
+
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(game_timestamp(&slpz), Some(timestamp));
+
+        // named in UTC-5, and across midnight in UTC+5:45
+        let local = GameTimestamp::parse_iso8601(b"2024-03-09T13:30:12").unwrap();
+        assert!(local.matches_in_some_timezone(&timestamp));
+        let local = GameTimestamp::parse_iso8601(b"2024-03-10T00:15:13").unwrap();
+        assert!(local.matches_in_some_timezone(&timestamp));
+        let other = GameTimestamp::parse_iso8601(b"2024-03-09T18:37:12").unwrap();
+        assert!(!other.matches_in_some_timezone(&timestamp));
+        assert_eq!(GameTimestamp::parse_iso8601(b"1970-01-02T00:00:01Z").unwrap().unix_seconds(), 86401);
+
+        let dir = test_dir("launcher_names");
+        std::fs::write(dir.join("mangled (1).slpz"), &slpz).unwrap();
+        std::fs::write(dir.join("Game_20240309T183012.slp"), test_slp()).unwrap();
+        let options = Options { compress: Some(false), launcher_names: true, log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        target_path(&options, &dir, None).unwrap();
+        assert_eq!(std::fs::read(dir.join("Game_20240309T183012.slp")).unwrap(), test_slp());
+        assert_eq!(std::fs::read(dir.join("Game_20240309T183012_1.slp")).unwrap(), slp);
+
+        // decompressing again finds the same replay
+        target_path(&options, &dir, None).unwrap();
+        assert!(!dir.join("Game_20240309T183012_2.slp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_after_compress() {
+        let mut slp = test_slp();
+        slp.extend_from_slice(b"trailer");
+        let mut compressor = options_compressor(&Options { verify_after_compress: true, ..Options::DEFAULT }).unwrap();
+        assert!(compress_unchecked(&mut compressor, &slp).is_ok());
+        compressor.set_strip_trailer(true);
+        assert!(compress_unchecked(&mut compressor, &slp).is_ok());
+
+        let slpz = compress_unchecked(&mut compressor, &test_slp()).unwrap();
+        let mut changed = test_slp();
+        let metadata_start = changed.len() - 3;
+        changed[metadata_start] = b'[';
+        assert_eq!(first_difference(&changed, &slpz), Ok(Some(metadata_start)));
+        assert_eq!(first_difference(&changed[..100], &slpz), Ok(Some(100)));
+        assert_eq!(first_difference(&test_slp(), &slpz[..40]), Ok(Some(0)));
+        assert_eq!(
+            CompError::VerificationFailed { first_diff_byte: 7 }.to_string(),
+            "Verification failed: output differs from input at byte 7",
+        );
+    }
+
+    #[test]
+    fn unknown_events_round_trip() {
+        let slp = test_slp_with(&[(0x7F, 3)], &[0x7F, 1, 2, 3, 0x37, 0, 0, 0, 100, 0x7F, 4, 5, 6]);
+        assert_eq!(unknown_events(&slp), Ok(vec![(0x7F, 2)]));
+        assert_eq!(unknown_events(&test_slp()), Ok(vec![]));
+
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+
+        let report = validate_slp(&slp);
+        assert!(report.is_ok());
+        assert_eq!(report.issues, [SlpIssue::UnknownEvent { command: 0x7F, count: 2 }]);
+
+        let report = analyze(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(report.unknown_events, [0x7F]);
+        let value: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(value["unknown_events"][0], 0x7F);
+    }
+
+    #[test]
+    fn analysis_json() {
+        let report = analyze(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(value["schema_version"], ANALYSIS_SCHEMA_VERSION);
+        assert_eq!(value["events"][0]["count"], 100);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let report = analyze(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap();
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(json, report.to_json());
+        assert_eq!(serde_json::from_str::<AnalysisReport>(&json).unwrap(), report);
+
+        let file = FileResult {
+            input: "game.slp".into(),
+            output: "game.slpz".into(),
+            compressed: true,
+            input_size: 1000,
+            output_size: 100,
+            fallback_level: None,
+        };
+        let json = serde_json::to_string(&file).unwrap();
+        assert!(json.contains("\"input\":\"game.slp\""), "{}", json);
+        assert_eq!(serde_json::from_str::<FileResult>(&json).unwrap(), file);
+
+        let batch = BatchResult {
+            written: vec![file],
+            failed: vec!["bad.slp".into()],
+            spot_checked: 1,
+            ..BatchResult::new(true)
+        };
+        let json = serde_json::to_string(&batch).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], BATCH_SCHEMA_VERSION);
+        assert_eq!(value["written"][0]["output_size"], 100);
+        assert_eq!(serde_json::from_str::<BatchResult>(&json).unwrap(), batch);
+
+        let options = Options { level: 9, recursive: true, ..Options::DEFAULT };
+        let json = serde_json::to_string(&options).unwrap();
+        let de = serde_json::from_str::<Options>(&json).unwrap();
+        assert_eq!(de.level, 9);
+        assert!(de.recursive);
+        assert_eq!(de.temp_suffix, DEFAULT_TEMP_SUFFIX);
+
+        let mut extensions = ExtensionMap::new();
+        extensions.add("slp", "slp.zst").unwrap();
+        extensions.add("replay", "replayz").unwrap();
+        let json = serde_json::to_string(&extensions).unwrap();
+        assert_eq!(json, "[[\"replay\",\"replayz\"],[\"slp\",\"slp.zst\"]]");
+        assert_eq!(serde_json::from_str::<ExtensionMap>(&json).unwrap(), extensions);
+        for json in ["[[\"slp\",\"a/b\"]]", "[[\"\",\"x\"]]"] {
+            assert!(serde_json::from_str::<ExtensionMap>(json).is_err(), "{}", json);
+        }
+
+        let json = serde_json::to_string(&CompError::PlaceholderRawLen).unwrap();
+        assert_eq!(json, "\"PlaceholderRawLen\"");
+        assert_eq!(serde_json::from_str::<CompError>(&json).unwrap(), CompError::PlaceholderRawLen);
+
+        let issue = SlpIssue::Io(std::io::ErrorKind::PermissionDenied);
+        let json = serde_json::to_string(&issue).unwrap();
+        assert_eq!(json, "{\"Io\":\"PermissionDenied\"}");
+        assert_eq!(serde_json::from_str::<SlpIssue>(&json).unwrap(), issue);
+    }
+
+    #[test]
+    fn format_detection() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+
+        assert!(is_slp(&slp) && !is_slpz(&slp));
+        assert!(is_slpz(&slpz) && !is_slp(&slpz));
+
+        let mut decompressor = Decompressor::new().unwrap();
+        assert_eq!(decompress(&mut decompressor, &slp), Err(DecompError::NotSlpz { first_bytes: *b"{U\x03r" }));
+        assert_eq!(decompress(&mut decompressor, b"ab"), Err(DecompError::NotSlpz { first_bytes: *b"ab\0\0" }));
+        assert_eq!(compress_unchecked(&mut Compressor::new(3).unwrap(), &slpz), Err(CompError::NotSlp { detected: Some(KnownFormat::Slpz) }));
+    }
+
+    #[test]
+    fn not_slp_or_corrupt() {
+        let slp = test_slp();
+        let mut c = Compressor::new(3).unwrap();
+
+        let zip = b"PK\x03\x04\x14\x00\x00\x00\x08\x00game.slp";
+        let zstd = zstd::bulk::compress(&slp, 3).unwrap();
+        let random = (0..256u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<_>>();
+        assert_eq!(compress_unchecked(&mut c, zip), Err(CompError::NotSlp { detected: Some(KnownFormat::Zip) }));
+        assert_eq!(compress_unchecked(&mut c, &zstd), Err(CompError::NotSlp { detected: Some(KnownFormat::Zstd) }));
+        assert_eq!(compress_unchecked(&mut c, &random), Err(CompError::NotSlp { detected: None }));
+        assert_eq!(
+            CompError::NotSlp { detected: Some(KnownFormat::Zip) }.to_string(),
+            "This looks like a zip file, not a Slippi replay",
+        );
+
+        let game_start_offset = 15 + 1 + slp[16] as usize;
+        assert_eq!(compress_unchecked(&mut c, &slp[..100]), Err(CompError::Corrupt { offset: game_start_offset, detail: CorruptDetail::Truncated }));
+        assert_eq!(compress_unchecked(&mut c, &slp[..slp.len() - 40]), Err(CompError::Corrupt { offset: 15, detail: CorruptDetail::Truncated }));
+
+        let mut undeclared = slp.clone();
+        let events = split_slp(&slp, Strictness::Normal).unwrap().events_offset;
+        undeclared[events] = 0xF0;
+        assert_eq!(
+            compress_unchecked(&mut c, &undeclared),
+            Err(CompError::Corrupt { offset: events, detail: CorruptDetail::UndeclaredEvent { command: 0xF0 } }),
+        );
+        assert_eq!(
+            SlpzError::from(compress_unchecked(&mut c, &undeclared).unwrap_err()).kind(),
+            ErrorKind::CorruptInput,
+        );
+        assert_eq!(SlpzError::from(compress_unchecked(&mut c, zip).unwrap_err()).kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn output_extension() {
+        let mut options = Options::DEFAULT;
+        let input = std::path::Path::new("dir/game.slp");
+        let root = std::path::Path::new("dir");
+        assert_eq!(output_path(&options, root, input, true).unwrap(), std::path::Path::new("dir/game.slpz"));
+
+        options.output_extension = Some(".slpz.lz".into());
+        assert_eq!(output_path(&options, root, input, true).unwrap(), std::path::Path::new("dir/game.slpz.lz"));
+
+        options.output_dir = Some("out".into());
+        assert_eq!(output_path(&options, root, input, true).unwrap(), std::path::Path::new("out/game.slpz.lz"));
+
+        for ex in ["slpz.lz", ".replay"] { assert!(valid_extension(ex.as_ref())) }
+        for ex in ["", ".", "..", "a/b", "/"] { assert!(!valid_extension(ex.as_ref())) }
+    }
+
+    #[test]
+    fn extension_map() {
+        let path = std::path::Path::new;
+        let mut options = Options::DEFAULT;
+        let root = path("dir");
+        assert_eq!(options.extensions.direction(path("dir/GAME.SLP")), Some(true));
+        assert_eq!(output_path(&options, root, path("dir/GAME.SLP"), true).unwrap(), path("dir/GAME.slpz"));
+        assert_eq!(output_path(&options, root, path("dir/game.SlpZ"), false).unwrap(), path("dir/game.slp"));
+        // extensionless and unknown files get an extension, replacing the last one
+        assert_eq!(options.extensions.direction(path("dir/game")), None);
+        assert_eq!(output_path(&options, root, path("dir/game"), true).unwrap(), path("dir/game.slpz"));
+        assert_eq!(output_path(&options, root, path("dir/game.replay"), false).unwrap(), path("dir/game.slp"));
+        assert_eq!(options.extensions.direction(path("dir/.slp")), None);
+
+        options.extensions.add(".slp", "slp.zst").unwrap();
+        assert_eq!(options.extensions.direction(path("dir/game.slp.zst")), Some(false));
+        assert_eq!(options.extensions.direction(path("dir/game.SLP.ZST")), Some(false));
+        assert_eq!(output_path(&options, root, path("dir/game.slp"), true).unwrap(), path("dir/game.slp.zst"));
+        assert_eq!(output_path(&options, root, path("dir/game.slp.zst"), false).unwrap(), path("dir/game.slp"));
+        // slpz files still decompress
+        assert_eq!(output_path(&options, root, path("dir/game.slpz"), false).unwrap(), path("dir/game.slp"));
+        options.gzip_output = true;
+        let gzipped = if cfg!(feature = "gzip") { "dir/game.slp.gz" } else { "dir/game.slp" };
+        assert_eq!(output_path(&options, root, path("dir/game.slp.zst"), false).unwrap(), path(gzipped));
+
+        assert_eq!(options.extensions.pairs().collect::<Vec<_>>(), [("slp", "slp.zst")]);
+        for (slp, slpz) in [("", "slpz"), ("slp", "SLP"), ("a/b", "slpz"), ("slp..zst", "slpz")] {
+            assert!(ExtensionMap::new().add(slp, slpz).is_none(), "{}={}", slp, slpz);
+        }
+    }
+
+    #[test]
+    fn extension_map_targets() {
+        let dir = test_dir("extension_map_targets");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("A.SLP"), test_slp()).unwrap();
+        std::fs::write(dir.join("sub/b.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("notes"), test_slp()).unwrap();
+
+        let mut extensions = ExtensionMap::new();
+        extensions.add("slp", "slp.zst").unwrap();
+        let options = Options { compress: Some(true), recursive: true, extensions, log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.written.len(), 2);
+        assert!(dir.join("A.slp.zst").exists() && dir.join("sub/b.slp.zst").exists());
+        assert!(!dir.join("notes.slp.zst").exists());
+
+        std::fs::remove_file(dir.join("A.SLP")).unwrap();
+        std::fs::remove_file(dir.join("sub/b.slp")).unwrap();
+        let result = target_path(&Options { compress: Some(false), ..options }, &dir, None).unwrap();
+        assert_eq!(result.written.len(), 2);
+        assert_eq!(std::fs::read(dir.join("A.slp")).unwrap(), test_slp());
+        assert_eq!(std::fs::read(dir.join("sub/b.slp")).unwrap(), test_slp());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Creates an empty directory for a test.
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("slpz_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzipped_replays() {
+        let dir = test_dir("gzipped");
+        let slp = test_slp();
+        std::fs::write(dir.join("old.slp.gz"), gzip::encode(&slp)).unwrap();
+        std::fs::write(dir.join("notes.txt.gz"), gzip::encode(b"notes")).unwrap();
+
+        let options = Options {
+            compress: Some(true),
+            keep: false,
+            removal: RemovalMode::Permanent,
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            ..Options::DEFAULT
+        };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.written.len(), 1);
+        assert_eq!(result.written[0].input_size, slp.len());
+        assert_eq!(result.written[0].output, dir.join("old.slpz"));
+        assert!(!dir.join("old.slp.gz").exists());
+        assert!(dir.join("notes.txt.gz").exists());
+
+        let options = Options { compress: Some(false), keep: true, gzip_output: true, ..options };
+        target_path(&options, &dir, None).unwrap();
+        let gzipped = std::fs::read(dir.join("old.slp.gz")).unwrap();
+        assert_eq!(gzip::decode(&gzipped), Ok(slp.clone()));
+        assert!(!dir.join("old.slp").exists());
+        assert_eq!(target_path(&options, &dir, None).unwrap().skipped, [dir.join("old.slpz")]);
+
+        // slp -> gz -> slpz -> slp
+        let options = Options { compress: None, gzip_output: false, ..options };
+        std::fs::remove_file(dir.join("old.slpz")).unwrap();
+        target_path(&options, &dir.join("old.slp.gz"), None).unwrap();
+        std::fs::remove_file(dir.join("old.slp.gz")).unwrap();
+        target_path(&options, &dir.join("old.slpz"), None).unwrap();
+        assert_eq!(std::fs::read(dir.join("old.slp")).unwrap(), slp);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_progress_replays_skipped() {
+        let dir = test_dir("in_progress");
+        let mut in_progress = test_slp();
+        in_progress[11..15].copy_from_slice(&[0; 4]);
+        std::fs::write(dir.join("finished.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("in_progress.slp"), &in_progress).unwrap();
+
+        assert!(is_probably_in_progress(&dir.join("in_progress.slp")).unwrap());
+        assert!(!is_probably_in_progress(&dir.join("finished.slp")).unwrap());
+
+        // a replay still growing in the background is skipped by the settle time
+        let growing = dir.join("growing.slp");
+        std::fs::write(&growing, test_slp()).unwrap();
+        let writer = std::thread::spawn(move || {
+            use std::io::Write;
+            let mut f = std::fs::OpenOptions::new().append(true).open(growing).unwrap();
+            for _ in 0..10 {
+                f.write_all(&[0x39, 0, 0]).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+        });
+
+        let options = Options { compress: Some(true), log: LogLevel::Silent, settle_ms: 60_000, ..Options::DEFAULT };
+        target_path(&options, &dir, None).unwrap();
+        writer.join().unwrap();
+        assert!(!dir.join("growing.slpz").exists());
+        assert!(!dir.join("in_progress.slpz").exists());
+        assert!(!dir.join("finished.slpz").exists());
+
+        let options = Options { settle_ms: 0, ..options };
+        target_path(&options, &dir, None).unwrap();
+        assert!(dir.join("finished.slpz").exists());
+        assert!(dir.join("in_progress.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_ids_match() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let slpz_small = compress_unchecked(&mut Compressor::new(12).unwrap(), &slp).unwrap();
+
+        let id = replay_id(&slp).unwrap();
+        assert_eq!(read_replay_id(&slpz), Some(id));
+        assert_eq!(replay_id(&slpz_small).unwrap(), id);
+
+        let mut unfinalized = slp.clone();
+        unfinalized[11..15].copy_from_slice(&[0; 4]);
+        assert_eq!(replay_id(&unfinalized).unwrap(), id);
+
+        use sha2::Digest;
+        assert_eq!(id, <[u8; 32]>::from(sha2::Sha256::digest(&slp[15..])));
+
+        assert_eq!(replay_id(b"not a replay"), Err(IdError::UnknownFormat));
+    }
+
+    #[test]
+    fn version_0_decompresses() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let header = SlpzHeader::parse(&slpz).unwrap();
+
+        // strip flags and optional fields to make a version 0 file
+        let removed = header.event_sizes_offset - 24;
+        let mut v0 = vec![0u8; 24];
+        for (i, offset) in (4..20).step_by(4).enumerate() {
+            let shifted = [header.event_sizes_offset, header.game_start_offset, header.metadata_offset, header.compressed_events_offset][i] - removed;
+            v0[offset..offset + 4].copy_from_slice(&(shifted as u32).to_be_bytes());
+        }
+        v0[20..24].copy_from_slice(&slpz[20..24]);
+        v0.extend_from_slice(&slpz[header.event_sizes_offset..]);
+
+        assert_eq!(format_version(&v0), Some(0));
+        let v0_header = SlpzHeader::parse(&v0).unwrap();
+        assert_eq!((v0_header.version, v0_header.flags, v0_header.replay_id), (0, 0, None));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &v0).unwrap(), slp);
+        assert_eq!(replay_id(&v0).unwrap(), replay_id(&slp).unwrap());
+    }
+
+    #[test]
+    fn changelog_lists_breaking_changes() {
+        let breaking = changelog().split("### Breaking").nth(1).unwrap().split("###").next().unwrap();
+        assert_eq!(breaking.lines().filter(|l| l.starts_with("- ")).count(), BREAKING_CHANGES_SINCE_1_0.len());
+        assert!(changelog().contains(&format!("## {}", CRATE_VERSION)));
+    }
+
+    #[test]
+    fn future_format_version() {
+        let slp = test_slp();
+        let mut slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(format_version(&slpz), Some(SLPZ_FORMAT_VERSION));
+        assert_eq!(format_version(&slp), None);
+        assert_eq!(format_version(&slpz[..20]), None);
+
+        slpz[0..4].copy_from_slice(&(SLPZ_FORMAT_VERSION + 1).to_be_bytes());
+        assert_eq!(format_version(&slpz), Some(SLPZ_FORMAT_VERSION + 1));
+        assert!(!is_slpz(&slpz));
+        let err = decompress(&mut Decompressor::new().unwrap(), &slpz);
+        assert_eq!(err, Err(DecompError::UnsupportedVersion { version: SLPZ_FORMAT_VERSION + 1 }));
+        assert_eq!(SlpzError::from(err.unwrap_err()).kind(), ErrorKind::Unsupported);
+
+        slpz[0..4].copy_from_slice(&0x7B55_0372u32.to_be_bytes());
+        assert_eq!(format_version(&slpz), None);
+    }
+
+    #[test]
+    fn header_level() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(7).unwrap(), &slp).unwrap();
+        let header = SlpzHeader::parse(&slpz).unwrap();
+        assert_eq!((header.version, header.level), (2, Some(7)));
+
+        // remove the level field to make a version 1 file
+        let mut v1 = slpz[..28].to_vec();
+        v1[0..4].copy_from_slice(&1u32.to_be_bytes());
+        for offset in (4..20).step_by(4) {
+            let shifted = u32::from_be_bytes(slpz[offset..offset + 4].try_into().unwrap()) - 4;
+            v1[offset..offset + 4].copy_from_slice(&shifted.to_be_bytes());
+        }
+        v1.extend_from_slice(&slpz[32..]);
+
+        assert_eq!(format_version(&v1), Some(1));
+        let v1_header = SlpzHeader::parse(&v1).unwrap();
+        assert_eq!((v1_header.version, v1_header.level), (1, None));
+        assert_eq!(v1_header.replay_id, header.replay_id);
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &v1).unwrap(), slp);
+    }
+
+    #[test]
+    fn hidden_directories_skipped() {
+        let dir = test_dir("hidden");
+        std::fs::create_dir_all(dir.join(".git/objects")).unwrap();
+        std::fs::create_dir_all(dir.join("replays")).unwrap();
+        std::fs::write(dir.join(".git/objects/game.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join(".hidden.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("replays/game.slp"), test_slp()).unwrap();
+
+        let options = Options { compress: Some(true), recursive: true, log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        target_path(&options, &dir, None).unwrap();
+        assert!(dir.join("replays/game.slpz").exists());
+        assert!(!dir.join(".git/objects/game.slpz").exists());
+        assert!(!dir.join(".hidden.slpz").exists());
+
+        let options = Options { skip_hidden: false, ..options };
+        target_path(&options, &dir, None).unwrap();
+        assert!(dir.join(".git/objects/game.slpz").exists());
+        assert!(dir.join(".hidden.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_level() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(LEVEL_STORE).unwrap(), &slp).unwrap();
+
+        let header = SlpzHeader::parse(&slpz).unwrap();
+        assert_ne!(header.flags & FLAG_STORED, 0);
+        assert_eq!(slpz.len() - header.compressed_events_offset, header.decompressed_events_size);
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+
+        let compressed = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(SlpzHeader::parse(&compressed).unwrap().flags & FLAG_STORED, 0);
+    }
+
+    #[test]
+    fn profile_options_compress() {
+        let slp = test_slp();
+        for p in Profile::ALL {
+            let options = Options::DEFAULT.profile(p);
+            assert_eq!(options.level, p.level());
+            let slpz = compress_unchecked(&mut options_compressor(&options).unwrap(), &slp).unwrap();
+            assert!(round_trips(&slp, &slpz));
+        }
+    }
+
+    #[test]
+    fn file_info_display() {
+        let slp = test_slp();
+        let info = slp_file_info(&slp).unwrap();
+        assert_eq!(info.to_string(), "[FD] Fox vs Falco, frame 99");
+
+        // slpz files only read the last frame from metadata
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(slp_file_info(&slpz).unwrap().to_string(), "[FD] Fox vs Falco");
+
+        let mut info = SlpFileInfo {
+            stage: 0xFF,
+            characters: [Some(9), None, Some(0x40), None],
+            last_frame: Some(-123),
+            ..SlpFileInfo::default()
+        };
+        assert_eq!(info.to_string(), "[stage 0xFF] Marth vs character 0x40, frame -123");
+
+        info.stage = 63;
+        assert_eq!(info.to_string(), "[BTT G&W] Marth vs character 0x40, frame -123");
+
+        assert_eq!(metadata_last_frame(b"U\x08metadata{U\x09lastFramel\x00\x00\x12\xD7}"), Some(4823));
+    }
+
+    #[test]
+    fn game_start_fields() {
+        let with_game_start = |game_start: &[u8]| {
+            let mut slp = test_slp();
+            slp[15 + 14..15 + 14 + game_start.len()].copy_from_slice(game_start);
+            slp_file_info(&slp).unwrap()
+        };
+
+        let mut ntsc = test_game_start();
+        ntsc[0x15..0x19].copy_from_slice(&480u32.to_be_bytes());
+        ntsc[0x68 + 0x24] = 2;
+        let info = with_game_start(&ntsc);
+        assert_eq!((info.timer_seconds, info.team_mode, info.is_pal, info.is_frozen_ps), (480, false, false, false));
+        assert_eq!(info.players, [
+            Some(PlayerStartInfo { character: 2, player_type: 0, costume: 0 }),
+            Some(PlayerStartInfo { character: 20, player_type: 0, costume: 2 }),
+            None,
+            None,
+        ]);
+
+        let mut pal = test_game_start();
+        pal[0x0D] = 1;
+        pal[0x1A1] = 1;
+        pal[0x1A2] = 1;
+        let info = with_game_start(&pal);
+        assert_eq!((info.team_mode, info.is_pal, info.is_frozen_ps), (true, true, true));
+
+        // frozen PS was added in 2.0.0, PAL in 1.5.0
+        pal[1..5].copy_from_slice(&[1, 5, 0, 0]);
+        assert_eq!((with_game_start(&pal).is_pal, with_game_start(&pal).is_frozen_ps), (true, false));
+        pal[1..5].copy_from_slice(&[1, 4, 0, 0]);
+        assert_eq!((with_game_start(&pal).is_pal, with_game_start(&pal).is_frozen_ps), (false, false));
+    }
+
+    #[test]
+    fn trailing_junk() {
+        let slp = test_slp();
+        assert_eq!(trailer_len(&slp), Some(0));
+
+        let mut junk = slp.clone();
+        junk.extend_from_slice(&[0xAB; 17]);
+        assert_eq!(trailer_len(&junk), Some(17));
+
+        let mut compressor = Compressor::new(3).unwrap();
+        let slpz = compress_unchecked(&mut compressor, &junk).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), junk);
+
+        compressor.set_strip_trailer(true);
+        let slpz = compress_unchecked(&mut compressor, &junk).unwrap();
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+    }
+
+    #[test]
+    fn event_payloads_cut_inside_entry() {
+        let mut slp = RAW_HEADER.to_vec();
+        slp.extend_from_slice(&[0, 0, 0, 0, EVENT_PAYLOADS, 4, GAME_START, 0]);
+        assert_eq!(validate_slp(&slp).issues, [SlpIssue::InvalidEventPayloads]);
+        assert_eq!(
+            compress(&mut Compressor::new(3).unwrap(), &slp),
+            Err(CompError::Corrupt { offset: 15, detail: CorruptDetail::InvalidEventPayloads }),
+        );
+    }
+
+    #[test]
+    fn memory_estimates() {
+        let compressor = Compressor::new(3).unwrap();
+        assert!(compressor.estimated_memory() > 0);
+        assert_eq!(Compressor::new(LEVEL_STORE).unwrap().estimated_memory(), 0);
+
+        let long = Options { level: 19, long_distance_matching: true, window_log: 27, ..Options::DEFAULT };
+        let long = options_compressor(&long).unwrap().estimated_memory();
+        assert!(long > compressor.estimated_memory());
+        let threaded = Options { level: 19, long_distance_matching: true, window_log: 27, zstd_threads: 2, ..Options::DEFAULT };
+        assert_eq!(options_compressor(&threaded).unwrap().estimated_memory(), long * 3);
+
+        let slp = test_slp();
+        let mut decompressor = Decompressor::new().unwrap();
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &slp).unwrap().data;
+        let single = decompressor.estimated_memory(&slpz).unwrap();
+        // the output and the window are both at least the size of the replay
+        assert!(single > 2 * slp.len() as u64);
+        decompressor.set_threads(4);
+        assert_eq!(decompressor.estimated_memory(&slpz).unwrap(), single);
+        let stored = compress(&mut Compressor::new(LEVEL_STORE).unwrap(), &slp).unwrap().data;
+        assert!(decompressor.estimated_memory(&stored).unwrap() < single);
+        let mut corrupt = slpz.clone();
+        corrupt[SlpzHeader::parse(&slpz).unwrap().compressed_events_offset] ^= 0xFF;
+        assert_eq!(decompressor.estimated_memory(&corrupt), Err(DecompError::InvalidFile));
+
+        let mut segmented = Compressor::new(3).unwrap();
+        segmented.set_segments(4);
+        let chunked = compress(&mut segmented, &slp).unwrap().data;
+        assert!(SlpzHeader::parse(&chunked).unwrap().chunk_count > 1);
+        decompressor.set_threads(1);
+        let one = decompressor.estimated_memory(&chunked).unwrap();
+        decompressor.set_threads(4);
+        assert!(decompressor.estimated_memory(&chunked).unwrap() > one);
+
+        let dir = test_dir("memory_estimates");
+        std::fs::write(dir.join("a.slpz"), &slpz).unwrap();
+        let targets = vec![dir.join("a.slpz"); 100];
+        let options = Options { log: LogLevel::Silent, ..Options::DEFAULT };
+        assert_eq!(batch_threads(&options, true, &targets), 8);
+        assert_eq!(batch_threads(&options, true, &targets[..7]), 1);
+        let tiny = Options { memory_budget: Some(1), ..options.clone() };
+        assert_eq!(batch_threads(&tiny, true, &targets), 1);
+        assert_eq!(batch_threads(&tiny, false, &targets), 1);
+        let three = Options { memory_budget: Some(compressor.estimated_memory() * 3), ..options.clone() };
+        assert_eq!(batch_threads(&three, true, &targets), 3);
+        let two = Options { memory_budget: Some(single * 2), ..options };
+        assert_eq!(batch_threads(&two, false, &targets), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repair_truncated_event_payloads() {
+        let slp = test_slp();
+        // drop the 0x38 and 0x39 payload sizes, leaving the declared size and raw length
+        let mut broken = slp[..23].to_vec();
+        broken.extend_from_slice(&slp[29..]);
+        assert!(compress_unchecked(&mut Compressor::new(3).unwrap(), &broken).is_err());
+
+        let mut donor = test_slp_with(&[], &[0x39, 1, 0]);
+        donor[32] = 1; // a different build of the same version
+        assert_eq!(repair_with_donor(&broken, &donor), Ok(slp.clone()));
+        assert_eq!(repair_with_donor(&slp, &donor), Ok(slp.clone()));
+
+        let mut no_metadata = broken.clone();
+        no_metadata.truncate(broken.len() - 13);
+        assert_eq!(repair_with_donor(&no_metadata, &donor), Ok(slp[..slp.len() - 13].to_vec()));
+
+        donor[31] = 17;
+        assert_eq!(repair_with_donor(&broken, &donor), Err(RepairError::VersionMismatch { broken: [3, 18, 0], donor: [3, 17, 1] }));
+
+        let resized = test_slp_with(&[(0x38, 7)], &[]);
+        assert_eq!(repair_with_donor(&broken, &resized), Err(RepairError::Misaligned { offset: 23 + 1 + 0x1A4 + 5 + 8 }));
+
+        assert_eq!(repair_with_donor(b"not slp", &slp), Err(RepairError::NotSlp));
+        assert_eq!(repair_with_donor(&broken, b"not slp"), Err(RepairError::Donor(CompError::NotSlp { detected: None })));
+        assert_eq!(repair_with_donor(&broken[..17], &slp), Err(RepairError::MissingGameStart));
+    }
+
+    #[test]
+    fn compress_warnings_found() {
+        use CompressWarning::*;
+
+        let mut c = Compressor::new(3).unwrap();
+        let slp = test_slp();
+        let data = compress_unchecked(&mut c, &slp).unwrap();
+        assert_eq!(compress(&mut c, &slp), Ok(CompressOutput { data, warnings: Vec::new() }));
+
+        let mut unwritten = test_slp_with(&[(0x42, 1)], &[0x42, 0, 0x42, 1]);
+        unwritten[11..15].copy_from_slice(&[0; 4]);
+        unwritten.extend_from_slice(&[0xAB; 3]);
+        assert_eq!(compress(&mut c, &unwritten).unwrap().warnings, [
+            ZeroRawLen,
+            UnknownEventCode { command: 0x42, count: 2 },
+            TrailingBytes { len: 3, stripped: false },
+        ]);
+        c.set_strip_trailer(true);
+        assert_eq!(compress(&mut c, &unwritten).unwrap().warnings[2], TrailingBytes { len: 3, stripped: true });
+
+        let mut latin1 = slp[..slp.len() - 3].to_vec();
+        latin1.extend_from_slice(b"{U\x04nameSU\x02\xE9t}}");
+        assert_eq!(compress(&mut c, &latin1).unwrap().warnings, [MetadataNotUtf8]);
+
+        let mut past_end = slp.clone();
+        let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap()) + 1000;
+        past_end[11..15].copy_from_slice(&raw_len.to_be_bytes());
+        c.set_strictness(Strictness::Lenient);
+        assert_eq!(compress(&mut c, &past_end).unwrap().warnings, [TruncatedRawData { raw_len }]);
+
+        let mut swapped = slp.clone();
+        swapped[11..15].reverse();
+        let raw_len = u32::from_le_bytes(swapped[11..15].try_into().unwrap());
+        assert_eq!(compress(&mut c, &swapped).unwrap().warnings, [ByteSwappedRawLen { raw_len }]);
+    }
+
+    #[test]
+    fn level_from_env() {
+        let level = |value| Options::DEFAULT.with_level_var(value).level;
+        assert_eq!(level(Some("12")), 12);
+        assert_eq!(level(Some(" -5\n")), -5);
+        assert_eq!(level(Some("23")), Options::DEFAULT.level);
+        assert_eq!(level(Some("fast")), Options::DEFAULT.level);
+        assert_eq!(level(None), Options::DEFAULT.level);
+        // over a config file
+        let config = Options { level: 9, keep: false, ..Options::DEFAULT };
+        assert_eq!(config.clone().with_level_var(None).level, 9);
+        let options = config.with_level_var(Some("12"));
+        assert_eq!((options.level, options.keep), (12, false));
+    }
+
+    #[test]
+    fn file_errors() {
+        let dir = test_dir("file_errors");
+        let slp_path = dir.join("game.slp");
+        let slpz_path = dir.join("game.slpz");
+        std::fs::write(&slp_path, test_slp()).unwrap();
+
+        compress_file(&mut Compressor::new(3).unwrap(), &slp_path, &slpz_path).unwrap();
+        decompress_file(&mut Decompressor::new().unwrap(), &slpz_path, &dir.join("out.slp")).unwrap();
+        assert_eq!(std::fs::read(dir.join("out.slp")).unwrap(), test_slp());
+
+        let e = compress_file(&mut Compressor::new(3).unwrap(), &slpz_path, &dir.join("x.slpz")).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+        assert_eq!(e.to_string(), format!("{}: This looks like an slpz file, which is already compressed", slpz_path.display()));
+
+        let missing = dir.join("missing.slpz");
+        let e = decompress_file(&mut Decompressor::new().unwrap(), &missing, &dir.join("x.slp")).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Io);
+        assert!(e.to_string().starts_with(&missing.display().to_string()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn already_compressed_skipped() {
+        let dir = test_dir("already_compressed");
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap();
+        std::fs::write(dir.join("a.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("b.slpz"), &slpz).unwrap();
+        // misnamed, already compressed
+        std::fs::write(dir.join("c.slp"), &slpz).unwrap();
+
+        let options = Options { compress: Some(true), log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        target_path(&options, &dir, None).unwrap();
+
+        let mut names = std::fs::read_dir(&dir).unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.slp", "a.slpz", "b.slpz", "c.slp"]);
+        assert_eq!(std::fs::read(dir.join("b.slpz")).unwrap(), slpz);
+        assert_eq!(std::fs::read(dir.join("c.slp")).unwrap(), slpz);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decompress_conflicts() {
+        let dir = test_dir("conflicts");
+        let slp = test_slp();
+        let mut rerecorded = slp.clone();
+        *rerecorded.iter_mut().rev().nth(10).unwrap() ^= 1;
+        std::fs::write(dir.join("game.slpz"), compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap()).unwrap();
+
+        let run = |on_conflict| {
+            let options = Options { compress: Some(false), log: LogLevel::Silent, on_conflict, ..Options::DEFAULT };
+            target_path(&options, &dir, None).unwrap();
+        };
+
+        // identical: up to date
+        let mut placeholder = slp.clone();
+        placeholder[11..15].copy_from_slice(&[0; 4]);
+        std::fs::write(dir.join("game.slp"), &placeholder).unwrap();
+        run(OnConflict::Overwrite);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), placeholder);
+
+        // different: kept unless forced
+        std::fs::write(dir.join("game.slp"), &rerecorded).unwrap();
+        run(OnConflict::Skip);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), rerecorded);
+
+        run(OnConflict::Rename);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), rerecorded);
+        assert_eq!(std::fs::read(dir.join("game.conflict-1.slp")).unwrap(), slp);
+
+        run(OnConflict::Overwrite);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), slp);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn null_output() {
+        let dir = test_dir("null_output");
+        let slp = test_slp();
+        std::fs::write(dir.join("game.slp"), &slp).unwrap();
+        let options = Options { log: LogLevel::Silent, settle_ms: 0, keep: false, null_output: true, ..Options::DEFAULT };
+
+        let result = target_path(&Options { compress: Some(true), ..options.clone() }, &dir, None).unwrap();
+        assert_eq!(result.written.len(), 1);
+        assert_eq!(result.slp_bytes(), slp.len() as u64);
+        assert!(result.ratio() > 1.0);
+        assert!(!dir.join("game.slpz").exists());
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), slp);
+
+        std::fs::remove_file(dir.join("game.slp")).unwrap();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        std::fs::write(dir.join("game.slpz"), &slpz).unwrap();
+        let result = target_path(&Options { compress: Some(false), ..options }, &dir, None).unwrap();
+        assert_eq!(result.written[0].output_size, slp.len());
+        assert!(!dir.join("game.slp").exists());
+        assert!(dir.join("game.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn time_budget() {
+        // more than one block
+        let mut events = Vec::new();
+        for i in 0..200_000u32 {
+            events.push(0x38);
+            events.extend_from_slice(&i.to_be_bytes());
+            events.extend_from_slice(&[20, 8]);
+        }
+        let slp = test_slp_with(&[], &events);
+        let mut compressor = Compressor::new(12).unwrap();
+        compressor.set_time_budget(Some(std::time::Duration::ZERO));
+        assert_eq!(compress_unchecked(&mut compressor, &slp), Err(CompError::TimeBudgetExceeded));
+        compressor.set_time_budget(Some(std::time::Duration::from_secs(3600)));
+        let slpz = compress_unchecked(&mut compressor, &slp).unwrap();
+        assert_eq!(SlpzHeader::parse(&slpz).unwrap().level, Some(12));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+
+        let dir = test_dir("time_budget");
+        std::fs::write(dir.join("game.slp"), &slp).unwrap();
+        let options = Options {
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            compress: Some(true),
+            level: 12,
+            time_budget_per_file: Some(std::time::Duration::ZERO),
+            ..Options::DEFAULT
+        };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.written[0].fallback_level, Some(TIME_BUDGET_FALLBACK_LEVEL));
+        assert!(result.summary().ends_with(" over_time_budget=1"), "{}", result.summary());
+        let slpz = std::fs::read(dir.join("game.slpz")).unwrap();
+        assert_eq!(SlpzHeader::parse(&slpz).unwrap().level, Some(TIME_BUDGET_FALLBACK_LEVEL));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz).unwrap(), slp);
+        // nothing left from the attempt at level 12
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip_if_unchanged() {
+        let dir = test_dir("skip_if_unchanged");
+        let slp = test_slp();
+        let mut changed = slp.clone();
+        *changed.iter_mut().rev().nth(10).unwrap() ^= 1;
+        std::fs::write(dir.join("a.slp"), &slp).unwrap();
+        std::fs::write(dir.join("b.slp"), &slp).unwrap();
+        let options = Options { compress: Some(true), log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        assert_eq!(target_path(&options, &dir, None).unwrap().written.len(), 2);
+
+        // a is only touched, b holds a different replay
+        std::fs::write(dir.join("a.slp"), &slp).unwrap();
+        std::fs::write(dir.join("b.slp"), &changed).unwrap();
+        let options = Options { skip_if_unchanged: true, on_conflict: OnConflict::Overwrite, ..options };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.written.iter().map(|f| f.input.clone()).collect::<Vec<_>>(), [dir.join("b.slp")]);
+        assert!(result.skipped.contains(&dir.join("a.slp")));
+        let b = std::fs::read(dir.join("b.slpz")).unwrap();
+        assert_eq!(read_replay_id(&b), replay_id(&changed).ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_completeness() {
+        let slp = test_slp();
+        let raw_end = 15 + u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize;
+        let complete = Completeness { game_end_method: Some(2), last_frame: Some(99), raw_len_matches: true };
+        assert_eq!(completeness(&slp).unwrap(), complete);
+        assert!(complete.is_complete());
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(completeness(&slpz).unwrap(), complete);
+
+        let mut quit_out = slp.clone();
+        quit_out[raw_end - 2] = GAME_END_NO_CONTEST;
+        assert!(!completeness(&quit_out).unwrap().is_complete());
+
+        // crashed partway through a frame, before the raw length was written
+        let mut crashed = slp[..raw_end - 6].to_vec();
+        crashed[11..15].copy_from_slice(&[0; 4]);
+        let c = completeness(&crashed).unwrap();
+        assert_eq!(c, Completeness { game_end_method: None, last_frame: Some(99), raw_len_matches: false });
+        assert!(completeness(b"not a replay").is_err());
+
+        let dir = test_dir("only_complete");
+        std::fs::write(dir.join("complete.slp"), &slp).unwrap();
+        std::fs::write(dir.join("quit_out.slp"), &quit_out).unwrap();
+        let options = Options { compress: Some(true), only_complete: Some(true), log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.written.iter().map(|f| f.input.clone()).collect::<Vec<_>>(), [dir.join("complete.slp")]);
+        assert_eq!(result.skipped, [dir.join("quit_out.slp")]);
+
+        let options = Options { compress: Some(false), only_complete: Some(false), output_dir: Some(dir.join("out")), ..options };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!(result.skipped, [dir.join("complete.slpz")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn journal_and_audit() {
+        let dir = test_dir("journal_and_audit");
+        let replays = dir.join("replays");
+        std::fs::create_dir_all(&replays).unwrap();
+        let slp = test_slp();
+        std::fs::write(replays.join("a.slp"), &slp).unwrap();
+        std::fs::write(replays.join("b.slp"), &slp).unwrap();
+        let journal_path = dir.join("run.log");
+        let options = Options {
+            compress: Some(true),
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            keep: false,
+            removal: RemovalMode::Permanent,
+            journal: Some(journal_path.clone()),
+            // one file at a time, so the records are in order
+            threading: false,
+            ..Options::DEFAULT
+        };
+        assert_eq!(target_path(&options, &replays, None).unwrap().written.len(), 2);
+
+        let journal = std::fs::read(&journal_path).unwrap();
+        let contents = parse_journal(&journal).unwrap();
+        let stages = contents.records.iter().map(|r| r.stage).collect::<Vec<_>>();
+        use JournalStage::*;
+        assert_eq!(stages, [Started, Written, Verified, Removed, Started, Written, Verified, Removed]);
+        assert_eq!(contents.records[0].id, replay_id(&slp).unwrap());
+        assert!(contents.records[0].input.is_absolute());
+        let report = audit(&contents, &replays, DEFAULT_TEMP_SUFFIX);
+        assert_eq!(report, AuditReport { files: 2, issues: Vec::new() });
+
+        // a crash while recording that the second file was removed, which left a temp file behind
+        let second = contents.records[7].input.clone();
+        let cut = journal.len() - contents.records[7].to_line().len() / 2;
+        std::fs::write(&journal_path, &journal[..cut]).unwrap();
+        std::fs::write(replays.join("c.slpz.tmp"), b"partial").unwrap();
+        let contents = parse_journal(&journal[..cut]).unwrap();
+        assert!(contents.truncated);
+        assert_eq!(contents.records.len(), 7);
+        assert_eq!(audit(&contents, &replays, DEFAULT_TEMP_SUFFIX).issues, [
+            AuditIssue::InputMissing { input: second.clone() },
+            AuditIssue::TempFile { path: std::path::absolute(replays.join("c.slpz.tmp")).unwrap() },
+        ]);
+
+        // the next run starts on a new line
+        std::fs::write(&second, &slp).unwrap();
+        std::fs::remove_file(replays.join("c.slpz.tmp")).unwrap();
+        target_path(&options, &replays, None).unwrap();
+        let contents = parse_journal(&std::fs::read(&journal_path).unwrap()).unwrap();
+        assert_eq!((contents.records.len(), contents.unknown_lines, contents.truncated), (11, 1, false));
+        assert!(audit(&contents, &replays, DEFAULT_TEMP_SUFFIX).issues.is_empty());
+
+        std::fs::write(dir.join("notes.txt"), "not a journal").unwrap();
+        let not_journal = Options { journal: Some(dir.join("notes.txt")), ..options };
+        assert_eq!(target_path(&not_journal, &replays, None).unwrap_err(), TargetPathError::JournalInvalid);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn confirm_overwrite() {
+        let ask = |answer: &str| ask_overwrite("game.slp".as_ref(), &mut answer.as_bytes(), &mut Vec::new()).unwrap();
+        assert!(ask("y\n") && ask("Y\n") && ask(" yes\r\n"));
+        assert!(!ask("\n") && !ask("n\n") && !ask("") && !ask("yep\n"));
+        let mut prompt = Vec::new();
+        ask_overwrite("game.slp".as_ref(), &mut "\n".as_bytes(), &mut prompt).unwrap();
+        assert_eq!(prompt, b"game.slp already exists. Overwrite? [y/N] ");
+
+        let dir = test_dir("confirm_overwrite");
+        let slp = test_slp();
+        let mut rerecorded = slp.clone();
+        *rerecorded.iter_mut().rev().nth(10).unwrap() ^= 1;
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+
+        // answers stdin would give, one line per prompt
+        let run = |compress, answers: &'static str| {
+            let stdin = std::sync::Mutex::new(answers.as_bytes());
+            let asked = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let counter = asked.clone();
+            let options = Options {
+                compress: Some(compress),
+                log: LogLevel::Silent,
+                settle_ms: 0,
+                confirm_overwrite: Some(Hook::new(move |out: &std::path::Path| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    match ask_overwrite(out, &mut *stdin.lock().unwrap(), &mut std::io::sink()).unwrap() {
+                        true => Ok(()),
+                        false => Err(HookError::Other("not overwritten".into())),
+                    }
+                })),
+                ..Options::DEFAULT
+            };
+            target_path(&options, &dir, None).unwrap();
+            asked.load(std::sync::atomic::Ordering::Relaxed)
+        };
+
+        // compressing over an existing slpz file
+        std::fs::write(dir.join("game.slp"), &slp).unwrap();
+        std::fs::write(dir.join("game.slpz"), b"old").unwrap();
+        assert_eq!(run(true, "n\n"), 1);
+        assert_eq!(std::fs::read(dir.join("game.slpz")).unwrap(), b"old");
+        assert_eq!(run(true, "y\n"), 1);
+        assert_eq!(std::fs::read(dir.join("game.slpz")).unwrap(), slpz);
+
+        // decompressing over a different replay, which is otherwise an error without --force
+        std::fs::write(dir.join("game.slp"), &rerecorded).unwrap();
+        assert_eq!(run(false, "\n"), 1);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), rerecorded);
+        assert_eq!(run(false, "y\n"), 1);
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), slp);
+
+        // the same replay is up to date, so nothing is asked
+        assert_eq!(run(false, ""), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_collisions() {
+        let dir = test_dir("output_collisions");
+        let slp = test_slp();
+        let mut rerecorded = slp.clone();
+        *rerecorded.iter_mut().rev().nth(10).unwrap() ^= 1;
+        std::fs::write(dir.join("Game.slp"), &slp).unwrap();
+        std::fs::write(dir.join("game.slp"), &rerecorded).unwrap();
+        std::fs::write(dir.join("other.slp"), &slp).unwrap();
+        let targets = ["Game.slp", "game.slp", "other.slp"].map(|name| dir.join(name));
+
+        let options = Options { compress: Some(true), log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        assert_eq!(plan_dedupe(&options, &dir, &targets, true, false), Ok(Default::default()));
+        assert_eq!(plan_dedupe(&options, &dir, &targets, true, true), Err(TargetPathError::OutputCollision));
+
+        // mirrored into an output directory
+        let mirrored = [dir.join("a/game.slp"), dir.join("A/game.slp")];
+        let out_dir = Options { output_dir: Some(dir.join("out")), ..options.clone() };
+        assert_eq!(plan_dedupe(&out_dir, &dir, &mirrored, true, true), Err(TargetPathError::OutputCollision));
+
+        let options = Options { dedupe_names: true, ..options };
+        let dedupe = plan_dedupe(&options, &dir, &targets, true, true).unwrap();
+        assert_eq!(dedupe, targets[..2].iter().cloned().collect());
+
+        let mut c = Compressor::new(3).unwrap();
+        let batch = Batch::new(&dir, dedupe, true);
+        for t in &targets { batch.record(t, compress_target(&mut c, &options, &batch, t)) }
+        let short_id = |slp| replay_id(slp).unwrap()[..4].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let game = dir.join(format!("Game-{}.slpz", short_id(&slp)));
+        let rerecorded_game = dir.join(format!("game-{}.slpz", short_id(&rerecorded)));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &std::fs::read(game).unwrap()).unwrap(), slp);
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &std::fs::read(rerecorded_game).unwrap()).unwrap(), rerecorded);
+        assert!(dir.join("other.slpz").exists() && !dir.join("game.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn batch_summary() {
+        let dir = test_dir("batch_summary");
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        std::fs::write(dir.join("a.slp"), &slp).unwrap();
+        std::fs::write(dir.join("b.slp"), &slpz).unwrap();
+        let targets = ["a.slp", "b.slp", "missing.slp"].map(|name| dir.join(name));
+
+        let options = Options { compress: Some(true), log: LogLevel::Silent, ..Options::DEFAULT };
+        let batch = Batch::new(&dir, Default::default(), true);
+        let mut c = Compressor::new(3).unwrap();
+        for t in &targets { batch.record(t, compress_target(&mut c, &options, &batch, t)) }
+        let result = batch.finish();
+        let summary = format!(
+            "summary: compressed=1 skipped=1 failed=1 not_removed=0 slp_bytes={} slpz_bytes={} ratio={:.2}",
+            slp.len(), slpz.len(), ratio(slp.len(), slpz.len()),
+        );
+        assert_eq!(result.summary(), summary);
+        assert_eq!((result.skipped, result.failed), (vec![targets[1].clone()], vec![targets[2].clone()]));
+
+        std::fs::remove_file(dir.join("a.slpz")).unwrap();
+        let result = target_path(&Options { settle_ms: 0, ..options }, &dir, None).unwrap();
+        assert_eq!(result.written.len(), 1);
+        assert_eq!((&result.written[0].input, result.written[0].output_size), (&targets[0], slpz.len()));
+        assert_eq!(result.summary(), summary.replace("failed=1", "failed=0"));
+
+        assert!(LogLevel::Normal.prints_files() && LogLevel::Quiet.prints_summary());
+        assert!(!LogLevel::Quiet.prints_files() && !LogLevel::Silent.prints_summary());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn spot_checks() {
+        let paths = (0..400).map(|i| std::path::PathBuf::from(format!("dir/{}.slp", i))).collect::<Vec<_>>();
+        let sampled = |percent, seed| paths.iter().filter(|p| SpotCheck { percent, seed }.samples(p)).cloned().collect::<Vec<_>>();
+        assert!(sampled(0.0, 1).is_empty());
+        assert_eq!(sampled(100.0, 1).len(), paths.len());
+        assert!((150..250).contains(&sampled(50.0, 1).len()));
+        assert_eq!(sampled(50.0, 1), sampled(50.0, 1));
+        assert_ne!(sampled(50.0, 1), sampled(50.0, 2));
+
+        let dir = test_dir("spot_checks");
+        for i in 0..3 { std::fs::write(dir.join(format!("{}.slp", i)), test_slp()).unwrap() }
+        let options = Options {
+            compress: Some(true),
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            spot_check: Some(SpotCheck { percent: 100.0, seed: 0 }),
+            ..Options::DEFAULT
+        };
+        let result = target_path(&options, &dir, None).unwrap();
+        assert_eq!((result.written.len(), result.spot_checked), (3, 3));
+        assert!(result.summary().ends_with(" spot_checked=3 spot_check_failed=0"), "{}", result.summary());
+
+        // a mismatch stops the batch
+        let batch = Batch::new(&dir, Default::default(), true);
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap();
+        let mut changed = test_slp();
+        let middle = changed.len() / 2;
+        changed[middle] ^= 1;
+        assert!(spot_check(&options, &batch, &dir.join("1.slp"), &test_slp(), &slpz));
+        assert!(!spot_check(&options, &batch, &dir.join("0.slp"), &changed, &slpz));
+        assert!(batch.stopped());
+        assert_eq!(batch.finish().spot_check_failed, [dir.join("0.slp")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clips() {
+        let mut slp = test_slp();
+        slp.truncate(slp.len() - 3);
+        slp.extend_from_slice(b"{U\x09lastFramel\x00\x00\x00\x63}}");
+        let raw_end = 15 + u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize;
+        let mut no_game_end = slp[..raw_end - 3].to_vec();
+        no_game_end[11..15].copy_from_slice(&(raw_end as u32 - 18).to_be_bytes());
+        no_game_end.extend_from_slice(&slp[raw_end..]);
+
+        let mut c = Compressor::new(3).unwrap();
+        let mut d = Decompressor::new().unwrap();
+        let mut clip = |slp: &[u8], range| decompress(&mut d, &compress_clip(&mut c, slp, range).unwrap()).unwrap();
+        for (input, frames, pre_frames, last_frame) in [(&slp, 10, 10, 9), (&slp, 0, 0, -1), (&no_game_end, 10, 10, 9)] {
+            let clipped = clip(input, ClipRange::Frames(frames));
+            assert!(!validate_slp(&clipped).has_errors());
+            let events = EventIter::from_slp(&clipped).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+            assert_eq!(events.iter().filter(|(command, _, _)| *command == PRE_FRAME_UPDATE).count(), pre_frames);
+            assert_eq!(events.last().map(|&(command, payload, _)| (command, payload)), Some((GAME_END, [7, 0xFF].as_slice())));
+            assert_eq!(metadata_last_frame(split_slp(&clipped, Strictness::Normal).unwrap().metadata), Some(last_frame));
+        }
+
+        assert_eq!(clip(&slp, ClipRange::Frames(1000)), slp);
+        assert_eq!(clip(&no_game_end, ClipRange::Frames(100)), no_game_end);
+        assert_eq!(clip(&slp, ClipRange::Duration(std::time::Duration::from_secs(1))), clip(&slp, ClipRange::Frames(60)));
+
+        // clips never replace their input or a full compressed replay
+        let dir = test_dir("clips");
+        std::fs::write(dir.join("game.slp"), &slp).unwrap();
+        let options = Options {
+            compress: Some(true),
+            clip: Some(ClipRange::Frames(10)),
+            keep: false,
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            ..Options::DEFAULT
+        };
+        assert_eq!(target_path(&options, &dir, None).unwrap_err(), TargetPathError::ClipRemovesInput);
+        let result = target_path(&Options { keep: true, ..options }, &dir, None).unwrap();
+        assert_eq!(result.written[0].output, dir.join("game.clip.slpz"));
+        assert_eq!(std::fs::read(dir.join("game.slp")).unwrap(), slp);
+        assert!(!dir.join("game.slpz").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A two player replay whose pre-frame and post-frame updates have `noisy_bytes` random bytes each,
+    /// with smoothly moving positions and constant bytes otherwise.
+    fn generated_replay(frames: i32, noisy_bytes: usize) -> Vec<u8> {
+        let mut rng = 0x2545F491u32;
+        let mut next = move || {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            rng
+        };
+
+        let sizes = [(0x36, TEST_GAME_START_SIZE), (0x37, 0x40), (0x38, 0x54), (0x39, 2)];
+        let mut raw = vec![EVENT_PAYLOADS, 1 + 3 * sizes.len() as u8];
+        for (command, size) in sizes {
+            raw.push(command);
+            raw.extend_from_slice(&size.to_be_bytes());
+        }
+        raw.extend_from_slice(&test_game_start());
+        for frame in -123..frames - 123 {
+            for port in 0..2u8 {
+                let t = frame as f32 / 60.0 + port as f32;
+                for (command, size) in [(0x37u8, 0x40), (0x38, 0x54)] {
+                    let mut event = vec![0u8; 1 + size];
+                    event[0] = command;
+                    event[1..5].copy_from_slice(&frame.to_be_bytes());
+                    event[5] = port;
+                    event[7] = 2 + 18 * port;
+                    event[9..13].copy_from_slice(&((t * 0.7).sin() * 60.0).to_be_bytes());
+                    event[13..17].copy_from_slice(&((t * 1.3).cos().abs() * 30.0).to_be_bytes());
+                    for b in &mut event[20..20 + noisy_bytes] { *b = next() as u8 }
+                    raw.extend_from_slice(&event);
+                }
+            }
+        }
+        raw.extend_from_slice(&[0x39, 2, 0]);
+
+        let mut slp = RAW_HEADER.to_vec();
+        slp.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+        slp.extend_from_slice(&raw);
+        slp.extend_from_slice(b"U\x08metadata{}}");
+        slp
+    }
+
+    #[test]
+    fn ratio_estimates() {
+        let mut c = Compressor::new(3).unwrap();
+        for noisy_bytes in (0..40).step_by(4) {
+            let slp = generated_replay(3600, noisy_bytes);
+            let actual = compress_unchecked(&mut c, &slp).unwrap().len() as f32 / slp.len() as f32;
+            let estimate = slp_to_slpz_ratio_estimate(&slp);
+            assert!((estimate - actual).abs() < 0.1, "{} noisy bytes: estimated {}, was {}", noisy_bytes, estimate, actual);
+        }
+
+        assert!((0.0..=1.0).contains(&slp_to_slpz_ratio_estimate(&test_slp())));
+        assert_eq!(slp_to_slpz_ratio_estimate(b"not an slp file"), 1.0);
+    }
+
+    #[test]
+    fn permission_errors() {
+        let dir = test_dir("permission_errors");
+        let options = Options {
+            compress: Some(true),
+            log: LogLevel::Silent,
+            recursive: true,
+            keep: false,
+            removal: RemovalMode::Permanent,
+            output_dir: Some(dir.join("out")),
+            ..Options::DEFAULT
+        };
+        let batch = Batch::new(&dir, Default::default(), true);
+        remove_input(&options, &batch, &dir.join("missing.slp"), &dir.join("missing.slpz"));
+        assert_eq!(batch.finish().not_removed, [dir.join("missing.slp")]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let set_mode = |path: &str, mode| {
+                std::fs::set_permissions(dir.join(path), std::fs::Permissions::from_mode(mode)).unwrap()
+            };
+
+            let input = dir.join("in");
+            std::fs::create_dir_all(input.join("locked")).unwrap();
+            std::fs::create_dir_all(input.join("secret")).unwrap();
+            for name in ["a.slp", "unreadable.slp", "locked/b.slp", "secret/c.slp"] {
+                std::fs::write(input.join(name), test_slp()).unwrap();
+            }
+            set_mode("in/unreadable.slp", 0o000);
+            set_mode("in/locked", 0o555);
+            set_mode("in/secret", 0o000);
+
+            // permissions do not apply to root
+            if std::fs::read(input.join("unreadable.slp")).is_err() {
+                let mut targets = Vec::new();
+                let unreadable_dirs = get_targets(&mut targets, &input, &options, true, None);
+                assert_eq!(unreadable_dirs, [input.join("secret")]);
+
+                let batch = Batch::new(&input, Default::default(), true);
+                *batch.result() = BatchResult { failed: unreadable_dirs, ..BatchResult::new(true) };
+                let mut c = Compressor::new(3).unwrap();
+                for t in &targets { batch.record(t, compress_target(&mut c, &options, &batch, t)) }
+                let summary = batch.finish().summary();
+                assert!(summary.starts_with("summary: compressed=2 skipped=0 failed=2 not_removed=1 "), "{}", summary);
+                assert!(!input.join("a.slp").exists() && input.join("locked/b.slp").exists());
+                assert!(dir.join("out/locked/b.slpz").exists());
+            }
+
+            set_mode("in/unreadable.slp", 0o644);
+            set_mode("in/locked", 0o755);
+            set_mode("in/secret", 0o755);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunked_compressor() {
+        let slp = test_slp();
+        let mut placeholder = slp.clone();
+        placeholder[11..15].copy_from_slice(&[0; 4]);
+        let expected = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let expected_placeholder = compress_unchecked(&mut Compressor::new(3).unwrap(), &placeholder).unwrap();
+
+        // two games back to back, in chunks that split them anywhere
+        let stream = [slp.as_slice(), &placeholder].concat();
+        let mut chunked = ChunkedCompressor::new(Compressor::new(3).unwrap());
+        let mut outputs = Vec::new();
+        for chunk in stream.chunks(7) {
+            outputs.extend(chunked.feed(chunk).unwrap());
+        }
+        assert_eq!(outputs, [expected, expected_placeholder]);
+        assert_eq!(chunked.buffered(), 0);
+        assert_eq!(chunked.finish(), Ok(None));
+
+        // a game cut off mid stream, without a raw length
+        let partial = &placeholder[..placeholder.len() / 2];
+        for chunk in partial.chunks(7) {
+            assert_eq!(chunked.feed(chunk), Ok(None));
+            // the events after Game Start are scanned as they arrive, up to the one still being received
+            if chunked.buffered() > 30 + TEST_GAME_START_SIZE as usize {
+                assert!(chunked.buffered() - chunked.scanned < 7, "{} {}", chunked.buffered(), chunked.scanned);
+            }
+        }
+        let output = chunked.finish().unwrap().unwrap();
+        assert_eq!(output.warnings, [CompressWarning::PartialFile { len: partial.len() }, CompressWarning::ZeroRawLen]);
+        // the raw length is repaired to the events received
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &output.data).unwrap()[15..], partial[15..]);
+        assert_eq!((chunked.buffered(), chunked.scanned), (0, 0));
+
+        assert_eq!(chunked.feed(b"not an slp file"), Err(CompError::NotSlp { detected: None }));
+        assert_eq!(chunked.feed(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"), Err(CompError::NotSlp { detected: Some(KnownFormat::Png) }));
+        assert_eq!(chunked.buffered(), 0);
+    }
+
+    #[test]
+    fn dictionary_training() {
+        let slp = test_slp();
+        let raw = extract_raw_section(&slp).unwrap();
+        assert_eq!(raw.len(), u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize);
+        assert_eq!(raw.as_ptr(), slp[15..].as_ptr());
+
+        let mut placeholder = slp.clone();
+        placeholder[11..15].copy_from_slice(&[0; 4]);
+        assert_eq!(extract_raw_section(&placeholder).unwrap(), raw);
+
+        assert_eq!(train_slpz_dictionary(&[], 1024), Err(DictionaryError::NoSamples));
+        assert_eq!(train_slpz_dictionary(&[&slp, b"not slp"], 1024), Err(DictionaryError::InvalidSample { index: 1 }));
+
+        // vary the samples so zstd has something to learn from
+        let samples = (0..64u8)
+            .map(|i| {
+                let mut s = slp.clone();
+                let n = s.len();
+                s[n - 20] = i;
+                s
+            })
+            .collect::<Vec<_>>();
+        let samples = samples.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let dict = train_slpz_dictionary(&samples, 1024).unwrap();
+        assert!(!dict.is_empty() && dict.len() <= 1024);
+    }
+
+    #[test]
+    fn zstd_frame_flags() {
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let header = SlpzHeader::parse(&slpz).unwrap();
+        let frame = &slpz[header.compressed_events_offset..];
+        assert_eq!(zstd::zstd_safe::get_frame_content_size(frame).ok(), Some(Some(header.decompressed_events_size as u64)));
+        assert_ne!(frame[4] & 0x04, 0); // checksum flag in the frame header descriptor
+
+        let mut wrong_size = slpz.clone();
+        wrong_size[20..24].copy_from_slice(&(header.decompressed_events_size as u32 + 1).to_be_bytes());
+        assert_eq!(
+            decompress(&mut Decompressor::new().unwrap(), &wrong_size),
+            Err(DecompError::FrameSizeMismatch { header: header.decompressed_events_size + 1, frame: header.decompressed_events_size })
+        );
+        assert_eq!(validate_slpz(&wrong_size).issues, [SlpIssue::InvalidZstdFrame]);
+
+        // older files have neither flag
+        let mut old = Compressor::new(3).unwrap();
+        old.set_checksum(false).unwrap();
+        old.ctx.set_parameter(zstd::stream::raw::CParameter::ContentSizeFlag(false)).unwrap();
+        let old_slpz = compress_unchecked(&mut old, &slp).unwrap();
+        let old_frame = &old_slpz[SlpzHeader::parse(&old_slpz).unwrap().compressed_events_offset..];
+        assert_eq!(zstd::zstd_safe::get_frame_content_size(old_frame).ok(), Some(None));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &old_slpz).unwrap(), slp);
+    }
+
+    #[test]
+    fn content_detection() {
+        let dir = test_dir("content_detection");
+        let slp = test_slp();
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        std::fs::write(dir.join("replay.bin"), &slp).unwrap();
+        std::fs::write(dir.join("misnamed.slp"), &slpz).unwrap();
+
+        assert_eq!(content_should_compress(&dir.join("replay.bin")), Some(true));
+        assert_eq!(content_should_compress(&dir.join("misnamed.slp")), Some(false));
+
+        let options = Options { log: LogLevel::Silent, ..Options::DEFAULT };
+        target_path(&options, &dir.join("replay.bin"), None).unwrap();
+        assert_eq!(std::fs::read(dir.join("replay.slpz")).unwrap(), slpz);
+
+        let options = Options { log: LogLevel::Silent, output_extension: Some("out".into()), ..Options::DEFAULT };
+        target_path(&options, &dir.join("misnamed.slp"), None).unwrap();
+        assert_eq!(std::fs::read(dir.join("misnamed.out")).unwrap(), slp);
+
+        // the output would overwrite the input, so it is named like a deduped output
+        let id = replay_id(&slp).unwrap();
+        let deduped = dir.join(format!("misnamed-{:02x}{:02x}{:02x}{:02x}.slp", id[0], id[1], id[2], id[3]));
+        let options = Options { log: LogLevel::Silent, ..Options::DEFAULT };
+        let result = target_path(&options, &dir.join("misnamed.slp"), None).unwrap();
+        assert!(result.failed.is_empty());
+        assert_eq!(std::fs::read(&deduped).unwrap(), slp);
+        assert_eq!(std::fs::read(dir.join("misnamed.slp")).unwrap(), slpz);
+
+        std::fs::remove_file(&deduped).unwrap();
+        target_path(&Options { compress: Some(false), ..options.clone() }, &dir.join("misnamed.slp"), None).unwrap();
+        assert_eq!(std::fs::read(&deduped).unwrap(), slp);
+
+        std::fs::write(dir.join("raw.slpz"), &slp).unwrap();
+        target_path(&options, &dir.join("raw.slpz"), None).unwrap();
+        assert_eq!(std::fs::read(dir.join(format!("raw-{:02x}{:02x}{:02x}{:02x}.slpz", id[0], id[1], id[2], id[3]))).unwrap(), slpz);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        /// Records the names of spans as they are created, and the messages of events.
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Recorder {
+            fn on_new_span(
+                &self,
+                attrs: &tracing::span::Attributes<'_>,
+                _: &tracing::span::Id,
+                _: tracing_subscriber::layer::Context<'_, S>,
+            ) {
+                self.0.lock().unwrap().push(attrs.metadata().name().to_string());
+            }
+
+            fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+                struct Message<'a>(&'a mut Vec<String>);
+                impl tracing::field::Visit for Message<'_> {
+                    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                        if field.name() == "message" { self.0.push(format!("{:?}", value)); }
+                    }
+                }
+                event.record(&mut Message(&mut self.0.lock().unwrap()));
+            }
+        }
+
+        let dir = test_dir("tracing");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/game.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("compressed.slp"), compress_unchecked(&mut Compressor::new(3).unwrap(), &test_slp()).unwrap()).unwrap();
+
+        let recorded = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Recorder(recorded.clone()));
+        let options = Options { compress: Some(true), recursive: true, threading: false, log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        tracing::subscriber::with_default(subscriber, || target_path(&options, &dir, None).unwrap());
+
+        let mut recorded = recorded.lock().unwrap().clone();
+        recorded.sort();
+        assert_eq!(recorded, [
+            "skipped (already compressed)",
+            "slpz.compress",
+            "slpz.compress_file",
+            "slpz.compress_file",
+            "slpz.target_path",
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recompression() {
+        let slp = test_slp();
+        let mut decompressor = Decompressor::new().unwrap();
+        let fast = compress_unchecked(&mut Compressor::new(1).unwrap(), &slp).unwrap();
+        let stored = compress_unchecked(&mut Compressor::new(LEVEL_STORE).unwrap(), &slp).unwrap();
+
+        for input in [&fast, &stored] {
+            let small = recompress(&mut decompressor, &mut Compressor::new(19).unwrap(), input).unwrap();
+            let header = SlpzHeader::parse(&small).unwrap();
+            assert_eq!((header.level, header.flags), (Some(19), FLAG_REPLAY_ID));
+            assert_eq!(header.replay_id, SlpzHeader::parse(input).unwrap().replay_id);
+            assert_eq!(decompress(&mut decompressor, &small).unwrap(), slp);
+        }
+
+        let restored = recompress(&mut decompressor, &mut Compressor::new(LEVEL_STORE).unwrap(), &fast).unwrap();
+        assert_eq!(restored, stored);
+
+        let dir = test_dir("recompress");
+        std::fs::write(dir.join("stored.slpz"), &stored).unwrap();
+        std::fs::write(dir.join("fast.slpz"), &fast).unwrap();
+
+        // same level again is not smaller
+        let options = Options { level: 1, log: LogLevel::Silent, ..Options::DEFAULT };
+        let summary = recompress_path(&options, &dir).unwrap();
+        assert_eq!((summary.files, summary.replaced), (2, 1));
+        assert!(summary.bytes_after < summary.bytes_before);
+        assert_eq!(std::fs::read(dir.join("fast.slpz")).unwrap(), fast);
+        assert_ne!(std::fs::read(dir.join("stored.slpz")).unwrap(), stored);
+
+        let summary = recompress_path(&Options { force_recompress: true, ..options.clone() }, &dir).unwrap();
+        assert_eq!((summary.replaced, summary.failed), (2, 0));
+
+        std::fs::write(dir.join("broken.slpz"), &fast[..fast.len() - 1]).unwrap();
+        let summary = recompress_path(&options, &dir).unwrap();
+        assert_eq!((summary.files, summary.failed), (3, 1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nested_output_dir() {
+        let dir = test_dir("nested_output");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::create_dir_all(dir.join("compressed")).unwrap();
+        std::fs::write(dir.join("a.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("sub/b.slp"), test_slp()).unwrap();
+        // left over from an earlier run, and a partially written file
+        std::fs::write(dir.join("compressed/stale.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("c.slp.tmp"), test_slp()).unwrap();
+
+        let files = |root: &std::path::Path| {
+            let mut files = Vec::new();
+            get_targets(&mut files, root, &Options { recursive: true, ..Options::DEFAULT }, false, None);
+            let mut files = files.iter()
+                .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        let options = Options {
+            compress: Some(true),
+            recursive: true,
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            output_dir: Some(dir.join("compressed")),
+            ..Options::DEFAULT
+        };
+        target_path(&options, &dir, None).unwrap();
+        assert_eq!(files(&dir), ["compressed/a.slpz", "compressed/sub/b.slpz"]);
+
+        // decompressing into a directory inside the compressed tree
+        let options = Options {
+            compress: Some(false),
+            output_dir: Some(dir.join("compressed/slp")),
+            ..options
+        };
+        target_path(&options, &dir.join("compressed"), None).unwrap();
+        assert!(dir.join("compressed/slp/a.slp").is_file() && dir.join("compressed/slp/sub/b.slp").is_file());
+        assert_eq!(files(&dir.join("compressed/slp")), Vec::<String>::new());
+
+        let options = Options { output_dir: Some(dir.clone()), output_extension: Some("slpz".into()), ..options };
+        assert_eq!(target_path(&options, &dir, None), Err(TargetPathError::OutputDirConflict));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hooks() {
+        let dir = test_dir("hooks");
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.slp", "b.slp", "c.slp"] {
+            std::fs::write(dir.join(name), test_slp()).unwrap();
+        }
+
+        let results = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let post_results = results.clone();
+        let options = Options {
+            compress: Some(true),
+            recursive: true,
+            keep: false,
+            removal: RemovalMode::Permanent,
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            pre_hook: Some(Hook::new(|path: &std::path::Path| match path.ends_with("c.slp") {
+                true => Err(HookError::Other("infected".into())),
+                false => Ok(()),
+            })),
+            post_hook: Some(Hook::new(move |result: &FileResult| {
+                post_results.lock().unwrap().push(result.clone());
+                match result.input.ends_with("b.slp") {
+                    true => Err(HookError::ExitStatus(Some(1))),
+                    false => Ok(()),
+                }
+            })),
+            ..Options::DEFAULT
+        };
+        target_path(&options, &dir, None).unwrap();
+
+        let mut results = results.lock().unwrap().clone();
+        results.sort_by(|a, b| a.input.cmp(&b.input));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].output, dir.join("a.slpz"));
+        assert!(results[0].compressed && results[0].input_size == test_slp().len());
+        assert_eq!(results[0].output_size, std::fs::metadata(dir.join("a.slpz")).unwrap().len() as usize);
+        assert!(results[0].ratio() > 1.0);
+
+        // a.slp succeeded, b.slp failed its post hook, c.slp was skipped by its pre hook
+        assert!(!dir.join("a.slp").exists());
+        assert!(dir.join("b.slp").exists() && dir.join("b.slpz").exists());
+        assert!(dir.join("c.slp").exists() && !dir.join("c.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn trash() {
+        let dir = test_dir("trash");
+        let home = dir.join("home");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("a/game 1.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("b/game 1.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("d.slp"), test_slp()).unwrap();
+
+        let trash = xdg_trash_dir(Some("".into()), Some(home.clone().into())).unwrap();
+        assert_eq!(trash, home.join(".local/share/Trash"));
+        assert_eq!(xdg_trash_dir(Some("/data".into()), None).unwrap(), std::path::Path::new("/data/Trash"));
+        assert_eq!(xdg_trash_dir(None, None).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        assert_eq!(trash_file_xdg(&dir.join("a/game 1.slp"), &trash).unwrap(), trash.join("files/game 1.slp"));
+        assert_eq!(trash_file_xdg(&dir.join("b/game 1.slp"), &trash).unwrap(), trash.join("files/game 1.2.slp"));
+        assert!(!dir.join("a/game 1.slp").exists());
+        assert_eq!(std::fs::read(trash.join("files/game 1.2.slp")).unwrap(), test_slp());
+        let info = std::fs::read_to_string(trash.join("info/game 1.2.slp.trashinfo")).unwrap();
+        let path = std::fs::canonicalize(&dir).unwrap().join("b/game%201.slp");
+        assert!(info.starts_with(&format!("[Trash Info]\nPath={}\nDeletionDate=", path.display())), "{}", info);
+
+        // the default trash is not used here, as it depends on the environment
+        let options = Options {
+            compress: Some(true),
+            keep: false,
+            removal: RemovalMode::Permanent,
+            log: LogLevel::Silent,
+            settle_ms: 0,
+            ..Options::DEFAULT
+        };
+        target_path(&options, &dir.join("d.slp"), None).unwrap();
+        assert!(!dir.join("d.slp").exists() && !trash.join("files/d.slp").exists());
+
+        assert_eq!(GameTimestamp::from_unix_seconds(0).to_string(), "1970-01-01T00:00:00");
+        let t = GameTimestamp::parse_iso8601(b"2024-02-29T23:59:58").unwrap();
+        assert_eq!(GameTimestamp::from_unix_seconds(t.unix_seconds()), t);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let dir = test_dir("symlinks");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.slp"), test_slp()).unwrap();
+        std::fs::write(dir.join("sub/b.slp"), test_slp()).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("a.slp"), dir.join("link.slp")).unwrap();
+
+        let files = |options: &Options| {
+            let mut files = Vec::new();
+            get_targets(&mut files, &dir, options, true, None);
+            let mut files = files.iter()
+                .map(|f| f.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        let options = Options { recursive: true, log: LogLevel::Silent, settle_ms: 0, ..Options::DEFAULT };
+        assert_eq!(files(&options), ["a.slp", "sub/b.slp"]);
+        let follow = Options { follow_symlinks: true, ..options.clone() };
+        assert_eq!(files(&follow), ["a.slp", "link.slp", "sub/b.slp"]);
+
+        target_path(&Options { compress: Some(true), ..options }, &dir, None).unwrap();
+        assert!(dir.join("a.slpz").is_file() && dir.join("sub/b.slpz").is_file());
+        assert!(!dir.join("link.slpz").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validation() {
+        let slp = test_slp();
+        assert_eq!(validate_slp(&slp), SlpValidationReport::default());
+
+        let slpz = compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(validate_slpz(&slpz), SlpValidationReport::default());
+        assert_eq!(validate_slp(&slpz).issues, [SlpIssue::NotSlp]);
+        assert_eq!(validate_slpz(&slp).issues, [SlpIssue::NotSlpz]);
+
+        let events_offset = 15 + 2 + 4 * 3 + TEST_GAME_START_SIZE as usize + 1;
+        let mut undeclared = slp.clone();
+        undeclared[events_offset + 5] = 0x40;
+        assert_eq!(validate_slp(&undeclared).issues, [SlpIssue::UndeclaredEvent {
+            command: 0x40,
+            offset: events_offset + 5,
+            last_event: Some(LastEvent { command: 0x37, offset: events_offset }),
+        }]);
+
+        let mut truncated = slp.clone();
+        truncated.truncate(events_offset + 7);
+        let raw_len = (truncated.len() - 15) as u32;
+        truncated[11..15].copy_from_slice(&raw_len.to_be_bytes());
+        assert_eq!(validate_slp(&truncated).issues[0], SlpIssue::TruncatedEvent {
+            command: 0x38,
+            offset: events_offset + 5,
+            last_event: Some(LastEvent { command: 0x37, offset: events_offset }),
+        });
+
+        let mut out_of_bounds = slp.clone();
+        out_of_bounds[11..15].copy_from_slice(&(slp.len() as u32).to_be_bytes());
+        assert_eq!(validate_slp(&out_of_bounds).issues, [SlpIssue::RawLenOutOfBounds {
+            raw_len: slp.len() as u32,
+            computed_end: slp.len() - 13,
+        }]);
+
+        let mut swapped = slp.clone();
+        swapped[11..15].reverse();
+        let report = validate_slp(&swapped);
+        let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap());
+        assert_eq!(report.issues, [SlpIssue::ByteSwappedRawLen { raw_len }]);
+        assert!(report.is_ok());
+
+        // a bad marker inside the metadata object
+        let metadata_offset = slp.len() - 13;
+        let mut bad_metadata = with_start_at("2024-03-09T18:30:12Z");
+        bad_metadata[metadata_offset + 20] = b'X';
+        let report = validate_slp(&bad_metadata);
+        assert_eq!(report.issues, [SlpIssue::InvalidMetadata { offset: metadata_offset + 20 }]);
+        assert_eq!(report.issues[0].offset(), Some(metadata_offset + 20));
+
+        let mut truncated_zstd = slpz.clone();
+        truncated_zstd.pop();
+        assert_eq!(validate_slpz(&truncated_zstd).issues, [SlpIssue::InvalidZstdFrame]);
+
+        let mut junk = slp.clone();
+        junk.extend_from_slice(&[0; 3]);
+        let report = validate_slp(&junk);
+        assert_eq!(report.issues, [SlpIssue::TrailingBytes { len: 3 }]);
+        assert!(report.is_ok());
+        assert!(!report.has_errors() && report.has_warnings());
+
+        let report = SlpValidationReport { issues: vec![
+            SlpIssue::InvalidGameStart,
+            SlpIssue::TrailingBytes { len: 16 },
+            SlpIssue::UnknownEvent { command: 0xFF, count: 2 },
+        ] };
+        assert!(report.has_errors() && !report.is_ok());
+        assert_eq!(report.to_string(), "SLP Validation Report: 3 issue(s)\n  \
+            [ERROR] Game Start event is missing or truncated\n  \
+            [WARN]  Trailing bytes: 16 bytes after metadata\n  \
+            [WARN]  Unknown event code 0xFF (2 events)");
+        assert_eq!(SlpValidationReport::default().to_string(), "SLP Validation Report: 0 issue(s)");
+    }
+
+    #[test]
+    fn check_directory() {
+        let dir = test_dir("check");
+        let slp = test_slp();
+        std::fs::write(dir.join("good.slp"), &slp).unwrap();
+        std::fs::write(dir.join("bad.slp"), &slp[..slp.len() - 40]).unwrap();
+        std::fs::write(dir.join("good.slpz"), compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap()).unwrap();
+
+        let results = check_path(&Options::DEFAULT, &dir).unwrap();
+        let names = results.iter()
+            .map(|(p, r)| (p.file_name().unwrap().to_str().unwrap(), r.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("bad.slp", false), ("good.slp", true), ("good.slpz", true)]);
+
+        // a directory cannot be read as a file, as with a file without read permission
+        let unreadable = check_file(&dir);
+        assert!(matches!(unreadable.issues[..], [SlpIssue::Io(kind)] if kind != std::io::ErrorKind::NotFound), "{:?}", unreadable);
+        assert!(unreadable.has_errors());
+        assert_eq!(check_file(&dir.join("missing.slp")).issues, [SlpIssue::Io(std::io::ErrorKind::NotFound)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_replaces() {
+        let dir = test_dir("atomic_write");
+        let path = dir.join("game.slpz");
+
+        std::fs::write(&path, b"old").unwrap();
+        atomic_write_with_suffix(&path, b"new", ".slpz.tmp").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!dir.join("game.slpz.slpz.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let temp = |dest: &str| same_dir_temp_path(dest.as_ref(), ".tmp").map_err(|e| e.kind());
+        assert_eq!(temp("replays/game.slpz"), Ok("replays/game.slpz.tmp".into()));
+        assert_eq!(temp("game.slpz"), Ok("game.slpz.tmp".into()));
+        assert_eq!(temp("/"), Err(std::io::ErrorKind::InvalidInput));
+        assert_eq!(temp("replays/.."), Err(std::io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn analyze_counts_events() {
+        let slp = test_slp();
+        let report = analyze(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+
+        let counts = report.events.iter().map(|e| (e.command, e.count)).collect::<Vec<_>>();
+        assert_eq!(counts, [(0x37, 100), (0x38, 100), (0x39, 1)]);
+        assert_eq!(report.events[1].raw_size, 600);
+
+        let columns_size = report.events.iter().map(|e| e.raw_size).sum::<usize>();
+        assert_eq!(report.order_raw_size + columns_size, report.events_raw_size);
+        assert_eq!(report.slp_size, slp.len());
+    }
+
+    #[test]
+    fn event_iter() {
+        let slp = test_slp();
+        let events = EventIter::from_slp(&slp).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(events.len(), 201);
+        assert_eq!(events[0], (0x37, [0, 0, 0, 0].as_slice(), 0));
+        assert_eq!(events[1], (0x38, [0, 0, 0, 0, 20, 8].as_slice(), 5));
+        assert_eq!(events[200], (0x39, [2, 0].as_slice(), 1200));
+
+        // every event type in slp 3.18, with distinct payload sizes
+        let known = [0x10, 0x37, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F, 0x40];
+        let mut event_sizes = [0u16; 256];
+        let mut stream = Vec::new();
+        for (i, &command) in known.iter().enumerate() {
+            event_sizes[command as usize] = i as u16 + 1;
+            stream.push(command);
+            stream.extend(std::iter::repeat_n(command, i + 1));
+        }
+        let commands = EventIter::new(&stream, &event_sizes)
+            .map(|e| { let (c, p, _) = e.unwrap(); assert!(p.iter().all(|&b| b == c)); c })
+            .collect::<Vec<_>>();
+        assert_eq!(commands, known);
+
+        let mut reordered = Vec::new();
+        reorder_events(&stream, &event_sizes, &mut reordered).unwrap();
+        let mut restored = Vec::new();
+        unorder_events(&reordered, &event_sizes, &mut restored).unwrap();
+        assert_eq!(restored, stream);
+
+        // truncated inside the last event
+        let truncated = &stream[..stream.len() - 3];
+        assert_eq!(EventIter::new(truncated, &event_sizes).filter(Result::is_ok).count(), known.len() - 1);
+        let last_offset = stream.len() - 1 - known.len();
+        let mut iter = EventIter::new(truncated, &event_sizes).skip(known.len() - 1);
+        assert_eq!(iter.next(), Some(Err(EventIterError::TruncatedEvent { command: 0x40, offset: last_offset })));
+        assert_eq!(iter.next(), None);
+
+        let undeclared = [0x37, 0, 0, 0x41, 0, 0];
+        let items = EventIter::new(&undeclared, &event_sizes).collect::<Vec<_>>();
+        assert_eq!(items[1], Err(EventIterError::UndeclaredEvent { command: 0x41, offset: 3 }));
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn reorder_round_trip() {
+        #[rustfmt::skip]
+        let events = [
+            3, 1, 2, 3, 4, 5,
+            1, 0, 1, 2,
+            1, 10, 11, 12,
+            2, 1,
+            2, 2,
+            3, 1, 2, 3, 4, 5,
+            1, 20, 21, 22
+        ];
+        let mut event_sizes = [0u16; 256];
+        event_sizes[..4].copy_from_slice(&[0, 3, 1, 5]);
+
+        let mut reordered = Vec::new();
+        reorder_events(&events, &event_sizes, &mut reordered).unwrap();
+        println!("{:?}", reordered);
+
+        let mut unordered = Vec::new();
+        unorder_events(&reordered, &event_sizes, &mut unordered).unwrap();
+
+        assert_eq!(events.as_slice(), &unordered);
+
+        // enough events for whole tiles and partial tiles, with sizes that leave edges
+        let sizes = [(0x10, 1u16), (0x20, 7), (0x30, 16), (0x40, 21), (0x50, 40)];
+        let mut event_sizes = [0u16; 256];
+        for (command, size) in sizes { event_sizes[command as usize] = size }
+        let mut rng = 0x2545F491u32;
+        let mut next = move || { rng ^= rng << 13; rng ^= rng >> 17; rng ^= rng << 5; rng };
+        let mut events = Vec::new();
+        let mut payloads = vec![Vec::new(); 256];
+        for _ in 0..1500 {
+            let (command, size) = sizes[next() as usize % sizes.len()];
+            let payload = (0..size).map(|_| next() as u8).collect::<Vec<_>>();
+            events.push(command);
+            events.extend_from_slice(&payload);
+            payloads[command as usize].push(payload);
+        }
+
+        // the format: event count, order list, then each command's payloads byte by byte
+        let mut expected = 1500u32.to_be_bytes().to_vec();
+        expected.extend(EventIter::new(&events, &event_sizes).map(|e| e.unwrap().0));
+        for (command, size) in sizes {
+            for j in 0..size as usize {
+                expected.extend(payloads[command as usize].iter().map(|p| p[j]));
+            }
+        }
+
+        let mut reordered = Vec::new();
+        reorder_events(&events, &event_sizes, &mut reordered).unwrap();
+        assert!(reordered == expected);
+
+        let mut unordered = Vec::new();
+        unorder_events(&reordered, &event_sizes, &mut unordered).unwrap();
+        assert!(unordered == events);
+
+        let mut streamed = Vec::new();
+        let mut emit = |b: &[u8]| { streamed.extend_from_slice(b); Ok(()) };
+        unorder_events_to(&reordered, &event_sizes, 100, &mut Vec::new(), &mut emit).unwrap();
+        assert!(streamed == events);
+    }
+
+    #[test]
+    fn transpose() {
+        use crate::embedded::transpose_8x16;
+        let rows: [[u8; 16]; 8] = std::array::from_fn(|j| std::array::from_fn(|i| (j * 16 + i) as u8));
+        let t = transpose_8x16(&rows);
+        for (i, j) in (0..16).flat_map(|i| (0..8).map(move |j| (i, j))) {
+            assert_eq!(t[i][j], rows[j][i]);
+        }
+    }
+    #[test]
+    fn embedded_decompress() {
+        let inflate = |frame: &[u8], out: &mut Vec<u8>| zstd::stream::copy_decode(frame, out).is_ok();
+        let slp = test_slp();
+
+        let mut chunked = Compressor::new(3).unwrap();
+        chunked.set_chunk_bytes(100);
+        let slpzs = [
+            compress_unchecked(&mut Compressor::new(3).unwrap(), &slp).unwrap(),
+            compress_unchecked(&mut Compressor::new(LEVEL_STORE).unwrap(), &slp).unwrap(),
+            compress_unchecked(&mut chunked, &slp).unwrap(),
+        ];
+        for slpz in &slpzs {
+            let size = embedded::original_size(slpz).unwrap();
+            assert_eq!(size, slp.len() as u64);
+            let mut out = vec![0xAA; slp.len() + 10];
+            let mut scratch = Vec::new();
+            assert_eq!(embedded::decompress_into(slpz, &mut out, &mut scratch, inflate), Ok(slp.len()));
+            assert!(out[..slp.len()] == slp[..]);
+
+            assert_eq!(
+                embedded::decompress_into(slpz, &mut out[..slp.len() - 1], &mut scratch, inflate),
+                Err(embedded::DecodeError::BufferTooSmall { needed: slp.len() }),
+            );
+        }
+        assert_eq!(
+            embedded::decompress_into(&slpzs[0], &mut vec![0; slp.len()], &mut Vec::new(), |_, _| false),
+            Err(embedded::DecodeError::DecompressionFailure),
+        );
+        assert!(SlpzHeader::parse(&slpzs[2]).unwrap().chunk_count > 1);
+        assert_eq!(
+            embedded::parse_header(&slp).map(|_| ()),
+            Err(embedded::DecodeError::NotSlpz { first_bytes: [0x7B, 0x55, 0x03, 0x72] }),
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Melee stage and character IDs, as stored in the Game Start event.
+
+/// (stage ID, abbreviation, name) for the versus stages, including the unused Test and Akaneia stages,
+/// and the Target Tests and Home-Run Contest. Adventure and event stages are not listed.
+pub const STAGES: [(u16, &str, &str); 59] = [
+    (1, "Test", "Test Stage"),
+    (2, "FoD", "Fountain of Dreams"),
+    (3, "PS", "Pokémon Stadium"),
+    (4, "PC", "Princess Peach's Castle"),
+    (5, "KJ", "Kongo Jungle"),
+    (6, "Brin", "Brinstar"),
+    (7, "Corn", "Corneria"),
+    (8, "YS", "Yoshi's Story"),
+    (9, "Onett", "Onett"),
+    (10, "MC", "Mute City"),
+    (11, "RC", "Rainbow Cruise"),
+    (12, "JJ", "Jungle Japes"),
+    (13, "GB", "Great Bay"),
+    (14, "HT", "Hyrule Temple"),
+    (15, "BD", "Brinstar Depths"),
+    (16, "YI", "Yoshi's Island"),
+    (17, "GG", "Green Greens"),
+    (18, "Four", "Fourside"),
+    (19, "MK1", "Mushroom Kingdom I"),
+    (20, "MK2", "Mushroom Kingdom II"),
+    (21, "Akaneia", "Akaneia"),
+    (22, "Venom", "Venom"),
+    (23, "PF", "Poké Floats"),
+    (24, "BB", "Big Blue"),
+    (25, "IM", "Icicle Mountain"),
+    (26, "Icetop", "Icetop"),
+    (27, "FZ", "Flat Zone"),
+    (28, "DL", "Dream Land N64"),
+    (29, "YI64", "Yoshi's Island N64"),
+    (30, "KJ64", "Kongo Jungle N64"),
+    (31, "BF", "Battlefield"),
+    (32, "FD", "Final Destination"),
+    (40, "BTT Mario", "Target Test (Mario)"),
+    (41, "BTT Falcon", "Target Test (Captain Falcon)"),
+    (42, "BTT YL", "Target Test (Young Link)"),
+    (43, "BTT DK", "Target Test (Donkey Kong)"),
+    (44, "BTT Doc", "Target Test (Dr. Mario)"),
+    (45, "BTT Falco", "Target Test (Falco)"),
+    (46, "BTT Fox", "Target Test (Fox)"),
+    (47, "BTT ICs", "Target Test (Ice Climbers)"),
+    (48, "BTT Kirby", "Target Test (Kirby)"),
+    (49, "BTT Bowser", "Target Test (Bowser)"),
+    (50, "BTT Link", "Target Test (Link)"),
+    (51, "BTT Luigi", "Target Test (Luigi)"),
+    (52, "BTT Marth", "Target Test (Marth)"),
+    (53, "BTT Mewtwo", "Target Test (Mewtwo)"),
+    (54, "BTT Ness", "Target Test (Ness)"),
+    (55, "BTT Peach", "Target Test (Peach)"),
+    (56, "BTT Pichu", "Target Test (Pichu)"),
+    (57, "BTT Pikachu", "Target Test (Pikachu)"),
+    (58, "BTT Puff", "Target Test (Jigglypuff)"),
+    (59, "BTT Samus", "Target Test (Samus)"),
+    (60, "BTT Sheik", "Target Test (Sheik)"),
+    (61, "BTT Yoshi", "Target Test (Yoshi)"),
+    (62, "BTT Zelda", "Target Test (Zelda)"),
+    (63, "BTT G&W", "Target Test (Mr. Game & Watch)"),
+    (64, "BTT Roy", "Target Test (Roy)"),
+    (65, "BTT Ganon", "Target Test (Ganondorf)"),
+    (84, "HRC", "Home-Run Contest"),
+];
+
+/// Character names indexed by external character ID.
+pub const CHARACTERS: [&str; 33] = [
+    "Captain Falcon",
+    "Donkey Kong",
+    "Fox",
+    "Mr. Game & Watch",
+    "Kirby",
+    "Bowser",
+    "Link",
+    "Luigi",
+    "Mario",
+    "Marth",
+    "Mewtwo",
+    "Ness",
+    "Peach",
+    "Pikachu",
+    "Ice Climbers",
+    "Jigglypuff",
+    "Samus",
+    "Yoshi",
+    "Zelda",
+    "Sheik",
+    "Falco",
+    "Young Link",
+    "Dr. Mario",
+    "Roy",
+    "Pichu",
+    "Ganondorf",
+    "Master Hand",
+    "Wireframe Male",
+    "Wireframe Female",
+    "Giga Bowser",
+    "Crazy Hand",
+    "Sandbag",
+    "Popo",
+];
+
+pub fn stage_name(stage: u16) -> Option<&'static str> {
+    STAGES.iter().find(|s| s.0 == stage).map(|s| s.2)
+}
+
+pub fn stage_abbreviation(stage: u16) -> Option<&'static str> {
+    STAGES.iter().find(|s| s.0 == stage).map(|s| s.1)
+}
+
+pub fn character_name(character: u8) -> Option<&'static str> {
+    CHARACTERS.get(character as usize).copied()
+}
@@ -0,0 +1,36 @@
+//! Replays whose raw length was written little-endian by a third-party tool. The slp spec has it big-endian.
+//!
+//! `byte_swapped_raw_len.slp` is `complete.slp` with the four bytes of the raw length reversed.
+//!
+//! Run with `cargo test --features test-fixtures --test byte_order`.
+#![cfg(feature = "test-fixtures")]
+
+use slpz::*;
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+#[test]
+fn byte_swapped_raw_len_is_normalized() {
+    let slp = fixture("byte_swapped_raw_len.slp");
+    let complete = fixture("complete.slp");
+    let raw_len = u32::from_be_bytes(complete[11..15].try_into().unwrap());
+    assert_eq!(u32::from_le_bytes(slp[11..15].try_into().unwrap()), raw_len);
+
+    let report = validate_slp(&slp);
+    assert_eq!(report.issues, [SlpIssue::ByteSwappedRawLen { raw_len }]);
+    assert!(validate_slp(&complete).issues.is_empty());
+
+    let mut compressor = Compressor::new(3).unwrap();
+    let output = compress(&mut compressor, &slp).unwrap();
+    assert_eq!(output.warnings, [CompressWarning::ByteSwappedRawLen { raw_len }]);
+    assert_eq!(decompress(&mut Decompressor::new().unwrap(), &output.data).unwrap(), complete);
+}
+
+#[test]
+fn byte_swapped_raw_len_is_rejected_when_strict() {
+    let mut compressor = Compressor::new(3).unwrap();
+    compressor.set_strictness(Strictness::Strict);
+    assert!(matches!(compress(&mut compressor, &fixture("byte_swapped_raw_len.slp")), Err(CompError::Corrupt { .. })));
+}
@@ -0,0 +1,34 @@
+//! How replays ended, for archivers that keep crashed and quit out replays for review.
+//!
+//! The fixtures are generated from `zero_raw_len_with_metadata.slp`, with the raw length written:
+//! - `complete.slp`: the game ended normally.
+//! - `quit_out.slp`: a player quit out, so the Game End event is a no contest.
+//! - `zero_raw_len_in_progress.slp`: Dolphin crashed, so there is no Game End event.
+//!
+//! Run with `cargo test --features test-fixtures --test completeness`.
+#![cfg(feature = "test-fixtures")]
+
+use slpz::*;
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+#[test]
+fn fixture_completeness() {
+    for (name, game_end_method, raw_len_matches) in [
+        ("complete.slp", Some(2), true),
+        ("quit_out.slp", Some(7), true),
+        ("zero_raw_len_in_progress.slp", None, false),
+    ] {
+        let slp = fixture(name);
+        let c = completeness(&slp).unwrap();
+        assert_eq!((c.game_end_method, c.raw_len_matches), (game_end_method, raw_len_matches), "{}", name);
+        assert_eq!(c.is_complete(), name == "complete.slp", "{}", name);
+        assert!(c.last_frame.is_some(), "{}", name);
+
+        // the same from the slpz file, whose raw length is rewritten
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &slp).unwrap().data;
+        assert_eq!(completeness(&slpz).unwrap(), Completeness { raw_len_matches: true, ..c }, "{}", name);
+    }
+}
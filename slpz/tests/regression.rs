@@ -0,0 +1,45 @@
+//! Replays whose raw length is 0, the placeholder Slippi writes until the game ends, which used to panic.
+//!
+//! The fixtures are generated to match the replays in the bug report:
+//! - `zero_raw_len_in_progress.slp`: Dolphin closed mid-game. There is no metadata,
+//!   and the file ends partway through a post-frame update.
+//! - `zero_raw_len_with_metadata.slp`: the game ended and the metadata was written, but the raw length was not.
+//!
+//! Run with `cargo test --features test-fixtures --test regression`.
+#![cfg(feature = "test-fixtures")]
+
+use slpz::*;
+
+const FIXTURES: [&str; 2] = ["zero_raw_len_in_progress.slp", "zero_raw_len_with_metadata.slp"];
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+#[test]
+fn zero_raw_len_is_inferred() {
+    for name in FIXTURES {
+        let slp = fixture(name);
+        assert_eq!(slp[11..15], [0; 4], "{}", name);
+
+        let mut compressor = Compressor::new(3).unwrap();
+        let output = compress(&mut compressor, &slp).unwrap();
+        assert!(output.warnings.contains(&CompressWarning::ZeroRawLen), "{}: {:?}", name, output.warnings);
+        assert!(validate_slp(&slp).issues.contains(&SlpIssue::PlaceholderRawLen), "{}", name);
+
+        // everything but the raw length decompresses as it was
+        let decompressed = decompress(&mut Decompressor::new().unwrap(), &output.data).unwrap();
+        assert_eq!(decompressed.len(), slp.len(), "{}", name);
+        assert_eq!(decompressed[..11], slp[..11], "{}", name);
+        assert_eq!(decompressed[15..], slp[15..], "{}", name);
+    }
+}
+
+#[test]
+fn zero_raw_len_is_rejected_when_strict() {
+    for name in FIXTURES {
+        let mut compressor = Compressor::new(3).unwrap();
+        compressor.set_strictness(Strictness::Strict);
+        assert_eq!(compress(&mut compressor, &fixture(name)), Err(CompError::PlaceholderRawLen), "{}", name);
+    }
+}